@@ -0,0 +1,66 @@
+#!/usr/bin/env bash
+
+# This script generates a client certificate authority, two client certificates issued by it and a
+# revocation list revoking the first of them, for testing client certificate revocation. It also
+# issues a workload certificate holding a SPIFFE ID, for testing SPIFFE trust domains.
+
+set -e
+
+WORKDIR=$(mktemp -d)
+trap 'rm -rf "$WORKDIR"' EXIT
+
+cat > "$WORKDIR/ca.cnf" <<CONFIG
+[ca]
+default_ca = clients
+
+[clients]
+database = $WORKDIR/index.txt
+crlnumber = $WORKDIR/crlnumber
+default_md = sha256
+default_crl_days = 3650
+crl_extensions = revocation
+
+[revocation]
+authorityKeyIdentifier = keyid:always
+
+[authority]
+basicConstraints = critical,CA:true
+keyUsage = critical,keyCertSign,cRLSign
+subjectKeyIdentifier = hash
+
+[client]
+basicConstraints = CA:false
+keyUsage = critical,digitalSignature
+extendedKeyUsage = clientAuth
+
+[workload]
+basicConstraints = CA:false
+keyUsage = critical,digitalSignature
+extendedKeyUsage = clientAuth
+subjectAltName = URI:spiffe://example.com/billing
+CONFIG
+
+touch "$WORKDIR/index.txt"
+echo 01 > "$WORKDIR/crlnumber"
+
+openssl req -x509 -newkey rsa:2048 -nodes -keyout "$WORKDIR/client-ca.key" -out client-ca.crt -days 3650 \
+  -subj "/C=GB/O=Example/CN=Example Client Authority" -config "$WORKDIR/ca.cnf" -extensions authority
+
+for CLIENT in revoked trusted; do
+  openssl req -newkey rsa:2048 -nodes -keyout "$WORKDIR/$CLIENT.key" -out "$WORKDIR/$CLIENT.csr" \
+    -subj "/C=GB/O=Example/CN=$CLIENT.example.com"
+  openssl x509 -req -in "$WORKDIR/$CLIENT.csr" -CA client-ca.crt -CAkey "$WORKDIR/client-ca.key" \
+    -CAcreateserial -days 3650 -extfile "$WORKDIR/ca.cnf" -extensions client -out "$CLIENT-client.crt"
+done
+
+openssl req -newkey rsa:2048 -nodes -keyout "$WORKDIR/workload.key" -out "$WORKDIR/workload.csr" \
+  -subj "/C=GB/O=Example/CN=billing"
+openssl x509 -req -in "$WORKDIR/workload.csr" -CA client-ca.crt -CAkey "$WORKDIR/client-ca.key" \
+  -CAcreateserial -days 3650 -extfile "$WORKDIR/ca.cnf" -extensions workload -out workload-client.crt
+
+openssl ca -config "$WORKDIR/ca.cnf" -keyfile "$WORKDIR/client-ca.key" -cert client-ca.crt \
+  -revoke revoked-client.crt
+openssl ca -config "$WORKDIR/ca.cnf" -keyfile "$WORKDIR/client-ca.key" -cert client-ca.crt \
+  -gencrl -out client-ca.crl
+
+rm -f client-ca.srl
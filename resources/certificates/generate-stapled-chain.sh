@@ -0,0 +1,18 @@
+#!/usr/bin/env bash
+
+# This script generates a certificate issued by a throwaway certificate authority, along with the
+# OCSP request for it, for testing OCSP stapling.
+
+set -e
+
+openssl req -x509 -newkey rsa:2048 -nodes -keyout issuer.key -out issuer.crt -days 3650 -subj "/C=GB/O=Example/CN=Example Issuer"
+
+openssl req -newkey rsa:2048 -nodes -keyout stapled.key -out stapled.csr -subj "/C=GB/O=Example/CN=stapled.example.com"
+openssl x509 -req -in stapled.csr -CA issuer.crt -CAkey issuer.key -set_serial 0x1f2e3d4c5b6a -days 3650 \
+  -extfile <(printf "authorityInfoAccess=OCSP;URI:http://ocsp.example.com\nsubjectAltName=DNS:stapled.example.com") \
+  -out leaf.crt
+
+cat leaf.crt issuer.crt > stapled.crt
+openssl ocsp -issuer issuer.crt -cert leaf.crt -no_nonce -reqout stapled.ocsp-request.der
+
+rm issuer.key stapled.csr leaf.crt issuer.crt
@@ -7,6 +7,13 @@ use crate::config::ExternalBytes;
 
 pub struct Args {
     pub config_location: ExternalBytes,
+    /// Serves self-signed certificates generated at startup, for testing HTTPS locally.
+    pub dev_tls: bool,
+    /// Where to reach the Docker daemon, overriding the `DOCKER_HOST` environment variable.
+    pub docker_host: Option<String>,
+    /// The directory holding the certificates for a daemon reached over TLS, overriding the
+    /// `DOCKER_CERT_PATH` environment variable.
+    pub docker_cert_path: Option<PathBuf>,
 }
 
 impl Args {
@@ -22,6 +29,9 @@ impl TryFrom<pico_args::Arguments> for Args {
 
     fn try_from(mut args: pico_args::Arguments) -> Result<Self> {
         let config: String = args.value_from_str("--config")?;
+        let dev_tls = args.contains("--dev-tls");
+        let docker_host = args.opt_value_from_str("--docker-host")?;
+        let docker_cert_path = args.opt_value_from_str("--docker-cert-path")?;
 
         let config_location = match config.strip_prefix("s3://") {
             Some(bucket_and_key) => {
@@ -39,7 +49,12 @@ impl TryFrom<pico_args::Arguments> for Args {
             },
         };
 
-        Ok(Self { config_location })
+        Ok(Self {
+            config_location,
+            dev_tls,
+            docker_host,
+            docker_cert_path,
+        })
     }
 }
 
@@ -65,6 +80,50 @@ mod tests {
         };
 
         assert_eq!(parsed.config_location, expected);
+        assert!(!parsed.dev_tls);
+        assert_eq!(parsed.docker_host, None);
+
+        Ok(())
+    }
+
+    #[test]
+    fn can_override_the_docker_host() -> Result<()> {
+        let raw_args = vec![
+            OsString::from("--config"),
+            OsString::from("f2.yaml"),
+            OsString::from("--docker-host"),
+            OsString::from("tcp://docker.example.com:2376"),
+            OsString::from("--docker-cert-path"),
+            OsString::from("/etc/f2/docker"),
+        ];
+
+        let args = pico_args::Arguments::from_vec(raw_args);
+        let parsed = Args::try_from(args)?;
+
+        assert_eq!(
+            parsed.docker_host.as_deref(),
+            Some("tcp://docker.example.com:2376")
+        );
+        assert_eq!(
+            parsed.docker_cert_path,
+            Some(PathBuf::from("/etc/f2/docker"))
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn can_enable_development_certificates() -> Result<()> {
+        let raw_args = vec![
+            OsString::from("--dev-tls"),
+            OsString::from("--config"),
+            OsString::from("f2.yaml"),
+        ];
+
+        let args = pico_args::Arguments::from_vec(raw_args);
+        let parsed = Args::try_from(args)?;
+
+        assert!(parsed.dev_tls);
 
         Ok(())
     }
@@ -1,9 +1,15 @@
-use std::{collections::HashMap, fmt};
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+use std::time::Duration;
 
 use color_eyre::eyre::{eyre, Result, WrapErr};
+use ring::digest::{digest, SHA256};
 use rsa::RsaPrivateKey;
 
-use crate::config::{Service, VolumeDefinition};
+use crate::config::{
+    registry_host, CpuLimit, ExternalBytes, MemorySize, NetworkAttachment, PublishedPort,
+    PullPolicy, RegistryCredentials, RestartPolicy, Service, Ulimit, VolumeDefinition,
+};
 use crate::crypto::decrypt;
 
 #[derive(Clone)]
@@ -41,11 +47,124 @@ pub struct Environment {
     pub variables: HashMap<String, String>,
 }
 
+/// The share of the host's resources a container is limited to.
+#[derive(Copy, Clone, Debug, Default)]
+pub struct ResourceLimits {
+    pub cpu_limit: Option<CpuLimit>,
+    pub memory_limit: Option<MemorySize>,
+    pub memory_reservation: Option<MemorySize>,
+}
+
+/// What a container is allowed to do, beyond Docker's defaults.
+#[derive(Clone, Debug, Default)]
+pub struct SecurityOptions {
+    pub read_only: bool,
+    pub security_opt: Vec<String>,
+    /// Resolved into a `seccomp=` security option when the container is created.
+    pub seccomp_profile: Option<ExternalBytes>,
+    pub privileged: bool,
+}
+
+/// Where a container's volumes are mounted from, once they have been resolved.
+#[derive(Clone, Debug, Default)]
+pub struct Mounts {
+    /// Host paths or named volumes, mapped to where they are mounted in the container.
+    pub binds: HashMap<String, String>,
+    /// Paths in the container backed by memory, mapped to the options to mount them with.
+    pub tmpfs: HashMap<String, String>,
+}
+
+/// How the Docker daemon runs a container, beyond what it runs.
+#[derive(Clone, Debug, Default)]
+pub struct RuntimeOptions {
+    pub resource_limits: ResourceLimits,
+    pub restart_policy: RestartPolicy,
+    pub labels: HashMap<String, String>,
+    pub published_ports: Vec<PublishedPort>,
+    pub command: Option<Vec<String>>,
+    pub entrypoint: Option<Vec<String>>,
+    pub ulimits: BTreeMap<String, Ulimit>,
+    pub sysctls: BTreeMap<String, String>,
+    pub security: SecurityOptions,
+}
+
 #[derive(Clone)]
 pub struct Container {
     pub image: String,
     pub environment: EncryptedEnvironment,
     pub volumes: HashMap<String, VolumeDefinition>,
+    pub pull_policy: PullPolicy,
+    pub registry_credentials: Option<RegistryCredentials>,
+    pub runtime: RuntimeOptions,
+    pub networks: HashMap<String, NetworkAttachment>,
+    /// Run inside the container once it is healthy, before it receives any traffic.
+    pub post_start: Option<Vec<String>>,
+    /// How long the container has to report healthy once started, if its image has a health check.
+    pub health_timeout: Duration,
+}
+
+impl Container {
+    /// Uses the credentials configured for the image's registry, unless the service has its own.
+    pub fn with_registries(mut self, registries: &HashMap<String, RegistryCredentials>) -> Self {
+        if self.registry_credentials.is_none() {
+            self.registry_credentials = registries.get(registry_host(&self.image)).cloned();
+        }
+
+        self
+    }
+
+    /// Hashes everything that goes into creating the container, so containers created from
+    /// different definitions of the same service can be told apart.
+    ///
+    /// Secrets are hashed while still encrypted, so the hash does not reveal them.
+    pub fn config_hash(&self, tag: &str) -> String {
+        let RuntimeOptions {
+            resource_limits,
+            restart_policy,
+            labels,
+            published_ports,
+            command,
+            entrypoint,
+            ulimits,
+            sysctls,
+            security,
+        } = &self.runtime;
+
+        // Sorting the maps keeps the hash the same regardless of their iteration order, and the
+        // fields are grouped since tuples only implement `Debug` for up to 12 elements
+        let definition = format!(
+            "{:?}",
+            (
+                (
+                    &self.image,
+                    tag,
+                    self.environment
+                        .variables
+                        .iter()
+                        .collect::<BTreeMap<_, _>>(),
+                    self.volumes.iter().collect::<BTreeMap<_, _>>(),
+                    self.networks.iter().collect::<BTreeMap<_, _>>(),
+                ),
+                (
+                    resource_limits,
+                    restart_policy,
+                    labels.iter().collect::<BTreeMap<_, _>>(),
+                    published_ports,
+                    command,
+                    entrypoint,
+                    ulimits,
+                    sysctls,
+                    security,
+                ),
+            )
+        );
+
+        digest(&SHA256, definition.as_bytes())
+            .as_ref()
+            .iter()
+            .map(|byte| format!("{byte:02x}"))
+            .collect()
+    }
 }
 
 impl fmt::Debug for Container {
@@ -53,6 +172,12 @@ impl fmt::Debug for Container {
         f.debug_struct("Container")
             .field("image", &self.image)
             .field("volumes", &self.volumes)
+            .field("pull_policy", &self.pull_policy)
+            .field("registry_credentials", &self.registry_credentials)
+            .field("runtime", &self.runtime)
+            .field("networks", &self.networks)
+            .field("post_start", &self.post_start)
+            .field("health_timeout", &self.health_timeout)
             .finish()
     }
 }
@@ -65,6 +190,31 @@ impl From<&Service> for Container {
                 variables: service.environment.clone(),
             },
             volumes: service.volumes.clone(),
+            pull_policy: service.pull_policy,
+            registry_credentials: service.registry_credentials.clone(),
+            runtime: RuntimeOptions {
+                resource_limits: ResourceLimits {
+                    cpu_limit: service.cpu_limit,
+                    memory_limit: service.memory_limit,
+                    memory_reservation: service.memory_reservation,
+                },
+                restart_policy: service.restart_policy,
+                labels: service.labels.clone(),
+                published_ports: service.published_ports.clone(),
+                command: service.command.clone(),
+                entrypoint: service.entrypoint.clone(),
+                ulimits: service.ulimits.clone(),
+                sysctls: service.sysctls.clone(),
+                security: SecurityOptions {
+                    read_only: service.read_only,
+                    security_opt: service.security_opt.clone(),
+                    seccomp_profile: service.seccomp_profile.clone(),
+                    privileged: service.privileged,
+                },
+            },
+            networks: service.networks.clone(),
+            post_start: service.post_start.clone(),
+            health_timeout: service.health_timeout(),
         }
     }
 }
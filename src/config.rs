@@ -1,18 +1,27 @@
-use std::collections::{HashMap, HashSet};
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fmt;
 use std::hash::{Hash, Hasher};
-use std::net::Ipv4Addr;
-use std::num::NonZeroU8;
+use std::io::ErrorKind;
+use std::net::{IpAddr, Ipv4Addr};
+use std::num::{NonZeroU32, NonZeroU8};
 use std::ops::Deref;
 use std::path::PathBuf;
+use std::time::Duration;
 
 use aws_config::BehaviorVersion;
+use aws_sdk_s3::operation::get_object::GetObjectError;
 use color_eyre::eyre::{eyre, Context, Result};
+use regex::Regex;
 use rsa::RsaPrivateKey;
 use serde::Deserialize;
 
-use crate::crypto::parse_private_key;
+use crate::crypto::{decrypt, parse_private_key};
+use crate::load_balancer::filters::BUILT_IN_FILTERS;
+use crate::load_balancer::request_id::RequestIdHeaders;
+use crate::load_balancer::rewrite::HeaderRewriters;
 
 #[derive(Clone, Debug, Eq, PartialEq)]
+#[allow(clippy::large_enum_variant)]
 pub enum Diff {
     Alteration {
         name: String,
@@ -28,10 +37,36 @@ pub enum Diff {
     },
 }
 
+impl Diff {
+    /// The name of the service the change applies to.
+    pub fn name(&self) -> &str {
+        match self {
+            Self::Alteration { name, .. }
+            | Self::Addition { name, .. }
+            | Self::Removal { name } => name,
+        }
+    }
+}
+
 #[derive(Clone, Debug, Deserialize)]
 pub struct Config {
     pub alb: AlbConfig,
     pub secrets: Option<SecretConfig>,
+    #[serde(default)]
+    pub shutdown: ShutdownConfig,
+    /// Whether to log a structured event for each change applied to a service.
+    #[serde(default)]
+    pub deploy_events: bool,
+    /// Credentials for pulling images from private registries, keyed by the registry's host.
+    #[serde(default)]
+    pub registries: HashMap<String, RegistryCredentials>,
+    /// Periodically removes stopped containers and unused images, when set.
+    pub garbage_collection: Option<GarbageCollectionConfig>,
+    /// Creates the network f2 routes to containers over on startup if it doesn't exist, when set.
+    pub network: Option<NetworkConfig>,
+    /// How requests to the Docker daemon are retried when it is briefly unavailable.
+    #[serde(default)]
+    pub docker_retries: DockerRetryConfig,
     pub services: HashMap<String, Service>,
 }
 
@@ -42,11 +77,88 @@ impl Config {
             .await
             .with_context(|| "Failed to fetch configuration")?;
 
-        let config = serde_yaml::from_slice(&bytes)?;
+        let config: Self = serde_yaml::from_slice(&bytes)?;
+        config.validate()?;
 
         Ok(config)
     }
 
+    /// Rejects configurations that parse but could not be served as written.
+    pub fn validate(&self) -> Result<()> {
+        for (name, service) in &self.services {
+            for route in &service.routes {
+                for filter in &route.filters {
+                    if !BUILT_IN_FILTERS.contains(&filter.as_str()) {
+                        return Err(eyre!("service {name} uses an unknown filter `{filter}`"));
+                    }
+                }
+
+                // Headers are otherwise only parsed once a request matches the route
+                if let Some(request_id) = &self.alb.request_id {
+                    RequestIdHeaders::resolve(request_id, route).wrap_err_with(|| {
+                        format!("service {name} uses an invalid request identifier header")
+                    })?;
+                }
+
+                if let Some(split) = route.split.as_ref().filter(|split| split.percentage > 100) {
+                    return Err(eyre!(
+                        "service {name} splits {}% of requests to {}, which is more than 100%",
+                        split.percentage,
+                        split.service,
+                    ));
+                }
+
+                if let Some(headers) = &route.headers {
+                    HeaderRewriters::resolve(headers)
+                        .wrap_err_with(|| format!("service {name} rewrites an invalid header"))?;
+                }
+
+                // Containers are otherwise verified against the route's host, which cannot be a
+                // wildcard
+                let server_name = route
+                    .upstream_tls
+                    .as_ref()
+                    .and_then(|tls| tls.server_name.as_ref());
+
+                if route.scheme == Scheme::Https
+                    && server_name.is_none()
+                    && route.host.starts_with('*')
+                {
+                    return Err(eyre!(
+                        "service {name} connects to its containers over tls for {}, which needs an explicit server name",
+                        route.host,
+                    ));
+                }
+            }
+        }
+
+        // Logs are served on every listener for any host, so they must be limited to known clients
+        if self
+            .alb
+            .container_logs
+            .as_ref()
+            .is_some_and(|logs| logs.access.allow.is_empty())
+        {
+            return Err(eyre!(
+                "container logs must only be allowed for specific networks"
+            ));
+        }
+
+        // The TLS server does not expose the address of the client, so access policies would
+        // either let every client through or refuse them all
+        if self.alb.tls.is_some() && self.alb.ports.contains_key(&Scheme::Https) {
+            for (name, service) in &self.services {
+                if service.routes.iter().any(|route| route.access.is_some()) {
+                    return Err(eyre!(
+                        "service {name} restricts client addresses, which cannot be checked for https connections yet"
+                    ));
+                }
+            }
+        }
+
+        Ok(())
+    }
+
     pub async fn get_private_key(&self) -> Result<Option<RsaPrivateKey>> {
         let private_key = match self.secrets.as_ref() {
             Some(secrets) => {
@@ -98,257 +210,1695 @@ impl Config {
     }
 }
 
-#[derive(Clone, Debug, Eq, PartialEq, Hash, Deserialize)]
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq, Hash, Deserialize)]
 #[serde(rename_all = "lowercase")]
 pub enum Scheme {
+    #[default]
     Http,
     Https,
 }
 
+impl fmt::Display for Scheme {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Http => write!(f, "http"),
+            Self::Https => write!(f, "https"),
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum HttpProtocol {
+    #[default]
+    Auto,
+    Http1,
+    Http2,
+}
+
+/// The HTTP version used when proxying requests to a service's containers.
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq, Hash, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum UpstreamProtocol {
+    #[default]
+    Http1,
+    /// HTTP/2 over cleartext (h2c), which the containers must support without negotiation.
+    Http2,
+}
+
 #[derive(Clone, Debug, Eq, PartialEq, Deserialize)]
 pub struct AlbConfig {
     pub addr: Ipv4Addr,
     pub ports: HashMap<Scheme, u16>,
+    /// Restricts the HTTP versions served by each listener, negotiating either by default.
+    #[serde(default)]
+    pub protocols: HashMap<Scheme, HttpProtocol>,
     pub reconciliation: String,
+    /// The path to expose load balancer metrics on, if any.
+    pub metrics: Option<String>,
+    /// The path to accept maintenance mode changes on, if any, which should be as hard to guess
+    /// as the reconciliation path since anyone able to reach the listeners can use it.
+    pub maintenance: Option<String>,
+    /// Serves the logs of each service's containers to clients on trusted networks, if set.
+    pub container_logs: Option<ContainerLogsConfig>,
+    pub request_id: Option<RequestIdConfig>,
+    /// Whether to drop pooled downstream connections once a deploy removes containers.
+    #[serde(default)]
+    pub evict_connections_on_deploy: bool,
+    pub path_limits: Option<PathLimits>,
+    /// How long to wait for a matched service with no running containers to gain one, such as
+    /// during a deploy, before rejecting the request.
+    pub empty_service_wait_ms: Option<u64>,
+    /// Whether to break ties between downstreams in favour of those likely to have an idle pooled
+    /// connection.
+    #[serde(default)]
+    pub prefer_warm_connections: bool,
+    /// Tunes how connections to containers are reused, which applies whenever the pool is created.
+    #[serde(default)]
+    pub connection_pool: ConnectionPool,
+    /// The most of a request body to hold in memory, such as to replay it on a retry, beyond which
+    /// the rest is streamed.
+    #[serde(default = "AlbConfig::default_max_buffered_body_size")]
+    pub max_buffered_body_size: usize,
+    /// Whether to log each proxied request, unless overridden by a service.
+    #[serde(default)]
+    pub access_log: bool,
+    /// Replaces the body of responses sent when shedding load, such as when rate limiting.
+    pub busy_response: Option<BusyResponse>,
+    /// Adds up to this many seconds to each `Retry-After` header, so clients don't all retry at
+    /// once.
+    #[serde(default)]
+    pub retry_after_jitter_secs: u64,
+    /// Pages to respond with when rejecting requests, keyed by host and then status code.
+    #[serde(default)]
+    pub error_pages: HashMap<String, HashMap<u16, ErrorPage>>,
+    /// Replaces the body of responses sent for services in maintenance mode.
+    pub maintenance_response: Option<BusyResponse>,
+    /// Routes served directly from files rather than being proxied to containers.
+    #[serde(default)]
+    pub static_routes: Vec<StaticRoute>,
+    /// Propagates trace context and exports a span for each proxied request when set.
+    pub otlp: Option<OtlpConfig>,
     pub tls: Option<TlsConfig>,
     pub mtls: Option<MtlsConfig>,
 }
 
-#[derive(Clone, Debug, Eq, PartialEq, Deserialize)]
-pub struct SecretConfig {
-    pub private_key: ExternalBytes,
-}
+impl AlbConfig {
+    fn default_max_buffered_body_size() -> usize {
+        1024 * 1024
+    }
 
-#[derive(Clone, Debug, Eq, PartialEq, Deserialize)]
-pub struct TlsConfig {
-    pub domains: HashMap<String, TlsSecrets>,
+    pub fn protocol(&self, scheme: Scheme) -> HttpProtocol {
+        self.protocols.get(&scheme).copied().unwrap_or_default()
+    }
 }
 
 #[derive(Clone, Debug, Eq, PartialEq, Deserialize)]
-pub struct TlsSecrets {
-    cert_file: ExternalBytes,
-    key_file: ExternalBytes,
+pub struct BusyResponse {
+    /// Overrides the status, which otherwise depends on why the request was rejected.
+    pub status: Option<u16>,
+    pub body: String,
+    #[serde(default = "BusyResponse::default_content_type")]
+    pub content_type: String,
 }
 
-impl TlsSecrets {
-    #[cfg(test)]
-    pub fn new(cert_file: ExternalBytes, key_file: ExternalBytes) -> Self {
-        Self {
-            cert_file,
-            key_file,
-        }
-    }
-
-    pub async fn resolve_files(&self) -> Result<(Vec<u8>, Vec<u8>)> {
-        let cert = self.cert_file.resolve().await?;
-        let key = self.key_file.resolve().await?;
-
-        Ok((cert, key))
+impl BusyResponse {
+    fn default_content_type() -> String {
+        String::from("text/plain; charset=utf-8")
     }
 }
 
 #[derive(Clone, Debug, Eq, PartialEq, Deserialize)]
-pub struct MtlsConfig {
-    /// The certificate to use as the trust anchor when validating incoming requests.
-    pub anchor: ExternalBytes,
-    /// The domains to apply mTLS to.
-    pub domains: HashSet<String>,
+pub struct StaticRoute {
+    pub host: String,
+    pub prefix: Option<String>,
+    pub source: StaticSource,
+    /// The document to serve for directory paths, such as `/` or `/docs/`.
+    #[serde(default = "StaticRoute::default_index")]
+    pub index: String,
+    /// Whether to serve the root index document for paths that don't match a file, for
+    /// client-side routed apps.
+    #[serde(default)]
+    pub spa_fallback: bool,
+    /// The `Cache-Control` header to send with each file.
+    pub cache_control: Option<String>,
+}
+
+impl StaticRoute {
+    fn default_index() -> String {
+        String::from("index.html")
+    }
 }
 
 #[derive(Clone, Debug, Eq, PartialEq, Deserialize)]
 #[serde(tag = "location", rename_all = "lowercase")]
-pub enum ExternalBytes {
+pub enum StaticSource {
     Filesystem { path: PathBuf },
-    S3 { bucket: String, key: String },
+    S3 { bucket: String, prefix: String },
 }
 
-impl ExternalBytes {
-    pub async fn resolve(&self) -> Result<Vec<u8>> {
-        let bytes = match self {
-            Self::Filesystem { path } => tokio::fs::read(path)
-                .await
-                .wrap_err_with(|| eyre!("failed to read file at {}", path.display()))?,
-            Self::S3 { bucket, key } => {
-                let config = aws_config::load_defaults(BehaviorVersion::latest()).await;
-                let client = aws_sdk_s3::Client::new(&config);
-
-                let response = client.get_object().bucket(bucket).key(key).send().await?;
-
-                response.body.collect().await?.to_vec()
-            }
-        };
-
-        tracing::debug!("Resolving a file at {self:?}, got {} bytes", bytes.len());
-
-        Ok(bytes)
-    }
+#[derive(Clone, Debug, Eq, PartialEq, Deserialize)]
+pub struct ConnectionPool {
+    /// How long a connection can sit idle in the pool before it is closed.
+    #[serde(default = "ConnectionPool::default_idle_timeout_ms")]
+    pub idle_timeout_ms: u64,
+    /// The most idle connections to keep for each container, which is unbounded if unset.
+    pub max_idle_per_host: Option<usize>,
+    /// How often to probe connections with TCP and HTTP/2 keep-alives, if at all.
+    pub keep_alive_interval_ms: Option<u64>,
 }
 
-#[derive(Copy, Clone, Debug, Eq, PartialEq, Deserialize)]
-pub struct ReplicaCount(NonZeroU8);
+impl ConnectionPool {
+    fn default_idle_timeout_ms() -> u64 {
+        90_000
+    }
 
-impl TryFrom<u8> for ReplicaCount {
-    type Error = color_eyre::Report;
+    pub fn idle_timeout(&self) -> Duration {
+        Duration::from_millis(self.idle_timeout_ms)
+    }
 
-    fn try_from(value: u8) -> Result<Self> {
-        NonZeroU8::new(value)
-            .map(Self)
-            .ok_or_else(|| eyre!("invalid value provided for replica count"))
+    pub fn keep_alive_interval(&self) -> Option<Duration> {
+        self.keep_alive_interval_ms.map(Duration::from_millis)
     }
 }
 
-impl Default for ReplicaCount {
+impl Default for ConnectionPool {
     fn default() -> Self {
-        Self(NonZeroU8::MIN)
+        Self {
+            idle_timeout_ms: Self::default_idle_timeout_ms(),
+            max_idle_per_host: None,
+            keep_alive_interval_ms: None,
+        }
     }
 }
 
-impl Deref for ReplicaCount {
-    type Target = NonZeroU8;
+#[derive(Clone, Debug, Eq, PartialEq, Deserialize)]
+pub struct OtlpConfig {
+    /// The base URL of an OTLP/HTTP collector, such as `http://localhost:4318`.
+    pub endpoint: String,
+    #[serde(default = "OtlpConfig::default_service_name")]
+    pub service_name: String,
+}
 
-    fn deref(&self) -> &Self::Target {
-        &self.0
+impl OtlpConfig {
+    fn default_service_name() -> String {
+        String::from("f2")
     }
 }
 
-#[derive(Copy, Clone, Debug, Eq, PartialEq, Deserialize)]
-#[serde(rename_all = "lowercase")]
-pub enum ShutdownMode {
-    Graceful,
-    Forceful,
+#[derive(Clone, Debug, Eq, PartialEq, Deserialize)]
+pub struct ErrorPage {
+    #[serde(flatten)]
+    pub source: ErrorPageSource,
+    #[serde(default = "ErrorPage::default_content_type")]
+    pub content_type: String,
 }
 
-impl Default for ShutdownMode {
-    fn default() -> Self {
-        Self::Forceful
+impl ErrorPage {
+    fn default_content_type() -> String {
+        String::from("text/html; charset=utf-8")
     }
 }
 
-#[derive(Clone, Debug, Default, Eq, PartialEq, Hash, Deserialize)]
-pub struct Route {
-    pub host: String,
-    pub prefix: Option<String>,
-    pub port: u16,
+#[derive(Clone, Debug, Eq, PartialEq, Deserialize)]
+#[serde(untagged)]
+pub enum ErrorPageSource {
+    Inline { body: String },
+    External { file: ExternalBytes },
 }
 
 #[derive(Clone, Debug, Default, Eq, PartialEq, Deserialize)]
-pub struct Service {
-    pub image: String,
-    pub tag: String,
-    pub replicas: ReplicaCount,
-    #[serde(default)]
-    pub routes: HashSet<Route>,
-    #[serde(default)]
-    pub environment: HashMap<String, String>,
-    #[serde(default)]
-    pub volumes: HashMap<String, VolumeDefinition>,
+pub struct PathLimits {
+    /// The longest request path to accept, in bytes.
+    pub max_length: Option<usize>,
+    /// The most path segments to accept, such as 2 for `/users/42`.
+    pub max_segments: Option<usize>,
+}
+
+#[derive(Clone, Debug, Eq, PartialEq, Deserialize)]
+pub struct ContainerLogsConfig {
+    /// The path to serve the logs under, such as `{path}/{name}/logs`.
+    pub path: String,
+    /// The clients allowed to read logs, which must name the networks to allow.
+    pub access: AccessControl,
+}
+
+#[derive(Clone, Debug, Eq, PartialEq, Deserialize)]
+pub struct RequestIdConfig {
+    /// The header carrying request identifiers, unless overridden by a route.
+    #[serde(default = "RequestIdConfig::default_header")]
+    pub header: String,
+    /// Other headers that may carry an identifier, which are normalised to the primary header.
     #[serde(default)]
-    pub shutdown_mode: ShutdownMode,
+    pub aliases: Vec<String>,
 }
 
-impl Hash for Service {
-    fn hash<H: Hasher>(&self, state: &mut H) {
-        self.image.hash(state);
-        self.tag.hash(state);
+impl RequestIdConfig {
+    fn default_header() -> String {
+        String::from("X-Request-Id")
     }
 }
 
 #[derive(Clone, Debug, Eq, PartialEq, Deserialize)]
-pub struct VolumeDefinition {
-    /// The source of the volume, which can be a filesystem path or an S3 bucket/key.
-    pub source: ExternalBytes,
-    /// The target path inside the container where the volume will be mounted.
-    pub target: String,
+pub struct ShutdownConfig {
+    /// How long to wait for in-flight requests to complete once listeners have stopped.
+    #[serde(default = "ShutdownConfig::default_grace_period_secs")]
+    pub grace_period_secs: u64,
+    /// Whether to stop the managed containers once the load balancer has drained.
+    #[serde(default)]
+    pub stop_containers: bool,
 }
 
-#[cfg(test)]
-mod tests {
-    use std::collections::HashMap;
-    use std::net::Ipv4Addr;
-
-    use crate::config::{AlbConfig, Config, Diff, Scheme, Service};
+impl ShutdownConfig {
+    fn default_grace_period_secs() -> u64 {
+        30
+    }
 
-    fn some_config() -> Config {
-        let mut services = HashMap::new();
-        services.insert(String::from("backend"), Service::default());
+    pub fn grace_period(&self) -> Duration {
+        Duration::from_secs(self.grace_period_secs)
+    }
+}
 
-        Config {
-            alb: AlbConfig {
-                addr: Ipv4Addr::LOCALHOST,
-                ports: HashMap::from([(Scheme::Http, 5000)]),
-                reconciliation: String::new(),
-                tls: None,
-                mtls: None,
-            },
-            secrets: None,
-            services,
+impl Default for ShutdownConfig {
+    fn default() -> Self {
+        Self {
+            grace_period_secs: Self::default_grace_period_secs(),
+            stop_containers: false,
         }
     }
+}
 
-    #[test]
-    fn can_diff_configurations() {
-        let left = some_config();
-
-        let mut right = left.clone();
-        right.services.get_mut("backend").unwrap().tag = String::from("2");
+#[derive(Clone, Debug, Eq, PartialEq, Deserialize)]
+pub struct GarbageCollectionConfig {
+    /// How often to look for containers and images to remove.
+    #[serde(default = "GarbageCollectionConfig::default_interval_secs")]
+    pub interval_secs: u64,
+    /// How long ago stopped containers and dangling images must have been created before they
+    /// are removed.
+    #[serde(default = "GarbageCollectionConfig::default_max_age_secs")]
+    pub max_age_secs: u64,
+}
 
-        let diff = left.diff(&right);
+impl GarbageCollectionConfig {
+    fn default_interval_secs() -> u64 {
+        60 * 60
+    }
 
-        assert_eq!(
-            diff,
-            Some(vec![Diff::Alteration {
-                name: String::from("backend"),
-                old_definition: left.services.get("backend").unwrap().clone(),
-                new_definition: right.services.get("backend").unwrap().clone()
-            }])
-        );
+    fn default_max_age_secs() -> u64 {
+        24 * 60 * 60
     }
 
-    #[test]
-    fn same_configuration_produces_an_empty_diff() {
-        let left = some_config();
-        let diff = left.diff(&left);
+    pub fn interval(&self) -> Duration {
+        Duration::from_secs(self.interval_secs)
+    }
 
-        assert_eq!(diff, None);
+    pub fn max_age(&self) -> Duration {
+        Duration::from_secs(self.max_age_secs)
     }
+}
 
-    #[test]
-    fn can_notice_non_tag_changes() {
-        let left = some_config();
+#[derive(Clone, Debug, Eq, PartialEq, Deserialize)]
+pub struct DockerRetryConfig {
+    /// How many times to send a request before giving up, including the first attempt.
+    #[serde(default = "DockerRetryConfig::default_max_attempts")]
+    pub max_attempts: u32,
+    /// How long to wait before the first retry, which doubles for each one after it.
+    #[serde(default = "DockerRetryConfig::default_initial_backoff_ms")]
+    pub initial_backoff_ms: u64,
+    /// The longest to wait between any two attempts.
+    #[serde(default = "DockerRetryConfig::default_max_backoff_ms")]
+    pub max_backoff_ms: u64,
+}
 
-        let mut right = left.clone();
+impl DockerRetryConfig {
+    fn default_max_attempts() -> u32 {
+        4
+    }
 
-        // Add an environment with some variables
-        let mut environment = HashMap::new();
-        environment.insert("NEW_PROPERTY".into(), "some-value".into());
+    fn default_initial_backoff_ms() -> u64 {
+        100
+    }
 
-        right.services.get_mut("backend").unwrap().environment = environment;
+    fn default_max_backoff_ms() -> u64 {
+        5000
+    }
 
-        let diff = left.diff(&right);
+    pub fn initial_backoff(&self) -> Duration {
+        Duration::from_millis(self.initial_backoff_ms)
+    }
 
-        assert_eq!(
-            diff,
-            Some(vec![Diff::Alteration {
-                name: String::from("backend"),
-                old_definition: left.services.get("backend").unwrap().clone(),
-                new_definition: right.services.get("backend").unwrap().clone()
-            }])
-        );
+    pub fn max_backoff(&self) -> Duration {
+        Duration::from_millis(self.max_backoff_ms)
     }
+}
 
-    #[test]
-    fn can_notice_additional_services() {
-        let left = some_config();
-        let mut right = left.clone();
+impl Default for DockerRetryConfig {
+    fn default() -> Self {
+        Self {
+            max_attempts: Self::default_max_attempts(),
+            initial_backoff_ms: Self::default_initial_backoff_ms(),
+            max_backoff_ms: Self::default_max_backoff_ms(),
+        }
+    }
+}
 
-        let service = Service::default();
-        right.services.insert("frontend".into(), service.clone());
+#[derive(Clone, Debug, Eq, PartialEq, Deserialize)]
+pub struct NetworkConfig {
+    /// The driver to create the network with.
+    #[serde(default = "NetworkConfig::default_driver")]
+    pub driver: String,
+    /// The range to assign container addresses from, such as `172.28.0.0/16`, instead of one
+    /// Docker picks.
+    pub subnet: Option<String>,
+    /// The address of the network's gateway within `subnet`.
+    pub gateway: Option<String>,
+    /// An IPv6 range to also assign container addresses from, such as `fd00:f2::/64`, which makes
+    /// the network dual-stack.
+    pub ipv6_subnet: Option<String>,
+}
 
-        let diff = left.diff(&right);
+impl NetworkConfig {
+    fn default_driver() -> String {
+        String::from("bridge")
+    }
+}
 
-        assert_eq!(
-            diff,
-            Some(vec![Diff::Addition {
+impl Default for NetworkConfig {
+    fn default() -> Self {
+        Self {
+            driver: Self::default_driver(),
+            subnet: None,
+            gateway: None,
+            ipv6_subnet: None,
+        }
+    }
+}
+
+#[derive(Clone, Debug, Eq, PartialEq, Deserialize)]
+pub struct SecretConfig {
+    pub private_key: ExternalBytes,
+}
+
+/// Credentials for pulling images from a private registry.
+#[derive(Clone, Debug, Eq, PartialEq, Deserialize)]
+pub struct RegistryCredentials {
+    pub username: String,
+    pub password: RegistryPassword,
+}
+
+/// The password or token for a registry, either given inline or resolved from elsewhere.
+#[derive(Clone, Eq, PartialEq, Deserialize)]
+#[serde(untagged)]
+pub enum RegistryPassword {
+    /// A value which can be encrypted with a `secret:` prefix, like environment variables.
+    Value(String),
+    /// A file holding the value, such as one written by a secrets manager.
+    External(ExternalBytes),
+}
+
+impl fmt::Debug for RegistryPassword {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            // Inline values may not be encrypted, so keep them out of the logs
+            Self::Value(_) => f.debug_tuple("Value").finish_non_exhaustive(),
+            Self::External(location) => f.debug_tuple("External").field(location).finish(),
+        }
+    }
+}
+
+impl RegistryCredentials {
+    pub async fn resolve_password(&self, private_key: Option<&RsaPrivateKey>) -> Result<String> {
+        let password = match &self.password {
+            RegistryPassword::Value(value) => match value.strip_prefix("secret:") {
+                Some(value) => {
+                    let private_key = private_key
+                        .ok_or_else(|| eyre!("Tried to decrypt secret without a key"))?;
+
+                    decrypt(value, private_key).wrap_err_with(|| {
+                        format!(
+                            "Failed to decrypt registry password for '{}'",
+                            self.username
+                        )
+                    })?
+                }
+                None => value.clone(),
+            },
+            RegistryPassword::External(location) => {
+                let bytes = location.resolve().await?;
+
+                // Files written by hand usually end with a newline, which isn't part of the value
+                String::from_utf8(bytes)?.trim_end().to_owned()
+            }
+        };
+
+        Ok(password)
+    }
+}
+
+/// Finds the registry an image is pulled from, which Docker only takes from the first component
+/// of the name if it looks like a host, such as `ghcr.io` in `ghcr.io/owner/image`.
+pub fn registry_host(image: &str) -> &str {
+    match image.split_once('/') {
+        Some((host, _)) if host.contains(['.', ':']) || host == "localhost" => host,
+        _ => "docker.io",
+    }
+}
+
+#[derive(Clone, Debug, Eq, PartialEq, Deserialize)]
+pub struct TlsConfig {
+    #[serde(default)]
+    pub domains: HashMap<String, TlsSecrets>,
+    /// The certificate to present to clients asking for a domain without one of its own.
+    pub default_certificate: Option<TlsSecrets>,
+    /// How to obtain certificates for domains that are managed over ACME.
+    pub acme: Option<AcmeConfig>,
+    /// How often to check certificates stored in S3 for changes, as they cannot be watched.
+    #[serde(default = "TlsConfig::default_poll_interval_secs")]
+    pub poll_interval_secs: u64,
+    /// The oldest TLS version to negotiate with clients.
+    pub min_version: Option<TlsVersion>,
+    /// The newest TLS version to negotiate with clients.
+    pub max_version: Option<TlsVersion>,
+    /// The names of the cipher suites to negotiate with clients, such as
+    /// `TLS13_AES_256_GCM_SHA384`, defaulting to all of those supported.
+    pub cipher_suites: Option<Vec<String>>,
+    /// How long before a certificate expires to start warning about it.
+    #[serde(default = "TlsConfig::default_expiry_warning_days")]
+    pub expiry_warning_days: u64,
+    /// Whether to refuse to start if any certificate has already expired.
+    #[serde(default)]
+    pub reject_expired_certificates: bool,
+    /// Serves a certificate generated at startup for each route's host instead of those in
+    /// `domains`, so HTTPS can be tested locally. Clients will not trust these certificates.
+    #[serde(default)]
+    pub self_signed: bool,
+}
+
+impl Default for TlsConfig {
+    fn default() -> Self {
+        Self {
+            domains: HashMap::new(),
+            default_certificate: None,
+            acme: None,
+            poll_interval_secs: Self::default_poll_interval_secs(),
+            min_version: None,
+            max_version: None,
+            cipher_suites: None,
+            expiry_warning_days: Self::default_expiry_warning_days(),
+            reject_expired_certificates: false,
+            self_signed: false,
+        }
+    }
+}
+
+impl TlsConfig {
+    fn default_poll_interval_secs() -> u64 {
+        300
+    }
+
+    fn default_expiry_warning_days() -> u64 {
+        14
+    }
+
+    pub fn poll_interval(&self) -> Duration {
+        Duration::from_secs(self.poll_interval_secs)
+    }
+
+    pub fn expiry_warning_window(&self) -> Duration {
+        Duration::from_secs(self.expiry_warning_days * 24 * 60 * 60)
+    }
+}
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq, PartialOrd, Ord, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum TlsVersion {
+    Tls12,
+    Tls13,
+}
+
+#[derive(Clone, Debug, Eq, PartialEq, Deserialize)]
+pub struct AcmeConfig {
+    /// The contact address for the account, which the certificate authority sends expiry notices
+    /// to.
+    pub email: String,
+    /// The directory of the certificate authority, defaulting to Let's Encrypt.
+    #[serde(default = "AcmeConfig::default_directory")]
+    pub directory: String,
+    /// The PKCS#8 encoded P-256 key for the account, which is generated if it cannot be resolved.
+    pub account_key: ExternalBytes,
+    /// How long before a certificate expires to renew it.
+    #[serde(default = "AcmeConfig::default_renew_before_days")]
+    pub renew_before_days: u64,
+}
+
+impl AcmeConfig {
+    fn default_directory() -> String {
+        String::from("https://acme-v02.api.letsencrypt.org/directory")
+    }
+
+    fn default_renew_before_days() -> u64 {
+        30
+    }
+
+    pub fn renew_before(&self) -> Duration {
+        Duration::from_secs(self.renew_before_days * 24 * 60 * 60)
+    }
+}
+
+#[derive(Clone, Debug, Eq, PartialEq, Deserialize)]
+pub struct TlsSecrets {
+    cert_file: ExternalBytes,
+    key_file: ExternalBytes,
+    /// Whether to redirect requests for this domain arriving over plain HTTP to HTTPS.
+    #[serde(default)]
+    pub redirect_http: bool,
+    /// Whether to obtain and renew the certificate over ACME, storing it in the files above.
+    #[serde(default)]
+    pub acme: bool,
+}
+
+impl TlsSecrets {
+    #[cfg(test)]
+    pub fn new(cert_file: ExternalBytes, key_file: ExternalBytes) -> Self {
+        Self {
+            cert_file,
+            key_file,
+            redirect_http: false,
+            acme: false,
+        }
+    }
+
+    pub async fn resolve_files(&self) -> Result<(Vec<u8>, Vec<u8>)> {
+        let cert = self.cert_file.resolve().await?;
+        let key = self.key_file.resolve().await?;
+
+        Ok((cert, key))
+    }
+
+    pub fn files(&self) -> [&ExternalBytes; 2] {
+        [&self.cert_file, &self.key_file]
+    }
+
+    pub async fn store_files(&self, cert: Vec<u8>, key: Vec<u8>) -> Result<()> {
+        self.key_file.store(key).await?;
+        self.cert_file.store(cert).await?;
+
+        Ok(())
+    }
+}
+
+#[derive(Clone, Debug, Eq, PartialEq, Deserialize)]
+pub struct MtlsConfig {
+    /// The certificate to use as the trust anchor when validating incoming requests.
+    pub anchor: ExternalBytes,
+    /// The domains to apply mTLS to.
+    pub domains: HashSet<String>,
+    /// The PEM encoded revocation lists to reject client certificates from, which are resolved
+    /// again whenever certificates are updated.
+    #[serde(default)]
+    pub crls: Vec<ExternalBytes>,
+    /// The SPIFFE trust domains client certificates must hold an ID from, such as `example.com`,
+    /// accepting certificates without one if empty.
+    #[serde(default)]
+    pub spiffe_trust_domains: HashSet<String>,
+}
+
+#[derive(Clone, Debug, Eq, PartialEq, Hash, Deserialize)]
+#[serde(tag = "location", rename_all = "lowercase")]
+pub enum ExternalBytes {
+    Filesystem { path: PathBuf },
+    S3 { bucket: String, key: String },
+}
+
+impl ExternalBytes {
+    pub async fn resolve(&self) -> Result<Vec<u8>> {
+        let bytes = match self {
+            Self::Filesystem { path } => tokio::fs::read(path)
+                .await
+                .wrap_err_with(|| eyre!("failed to read file at {}", path.display()))?,
+            Self::S3 { bucket, key } => {
+                let config = aws_config::load_defaults(BehaviorVersion::latest()).await;
+                let client = aws_sdk_s3::Client::new(&config);
+
+                let response = client.get_object().bucket(bucket).key(key).send().await?;
+
+                response.body.collect().await?.to_vec()
+            }
+        };
+
+        tracing::debug!("Resolving a file at {self:?}, got {} bytes", bytes.len());
+
+        Ok(bytes)
+    }
+
+    /// Resolves the bytes, or `None` if nothing has been stored at the location yet, failing for
+    /// any other reason they could not be read.
+    pub async fn resolve_if_exists(&self) -> Result<Option<Vec<u8>>> {
+        match self {
+            Self::Filesystem { path } => match tokio::fs::read(path).await {
+                Ok(bytes) => Ok(Some(bytes)),
+                Err(e) if e.kind() == ErrorKind::NotFound => Ok(None),
+                Err(e) => {
+                    Err(e).wrap_err_with(|| eyre!("failed to read file at {}", path.display()))
+                }
+            },
+            Self::S3 { bucket, key } => {
+                let config = aws_config::load_defaults(BehaviorVersion::latest()).await;
+                let client = aws_sdk_s3::Client::new(&config);
+
+                match client.get_object().bucket(bucket).key(key).send().await {
+                    Ok(response) => Ok(Some(response.body.collect().await?.to_vec())),
+                    Err(e)
+                        if e.as_service_error()
+                            .is_some_and(GetObjectError::is_no_such_key) =>
+                    {
+                        Ok(None)
+                    }
+                    Err(e) => Err(e.into()),
+                }
+            }
+        }
+    }
+
+    pub async fn store(&self, bytes: Vec<u8>) -> Result<()> {
+        tracing::debug!("Storing {} bytes in a file at {self:?}", bytes.len());
+
+        match self {
+            Self::Filesystem { path } => tokio::fs::write(path, bytes)
+                .await
+                .wrap_err_with(|| eyre!("failed to write file at {}", path.display()))?,
+            Self::S3 { bucket, key } => {
+                let config = aws_config::load_defaults(BehaviorVersion::latest()).await;
+                let client = aws_sdk_s3::Client::new(&config);
+
+                client
+                    .put_object()
+                    .bucket(bucket)
+                    .key(key)
+                    .body(bytes.into())
+                    .send()
+                    .await?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Deserialize)]
+pub struct ReplicaCount(NonZeroU8);
+
+impl TryFrom<u8> for ReplicaCount {
+    type Error = color_eyre::Report;
+
+    fn try_from(value: u8) -> Result<Self> {
+        NonZeroU8::new(value)
+            .map(Self)
+            .ok_or_else(|| eyre!("invalid value provided for replica count"))
+    }
+}
+
+impl Default for ReplicaCount {
+    fn default() -> Self {
+        Self(NonZeroU8::MIN)
+    }
+}
+
+impl Deref for ReplicaCount {
+    type Target = NonZeroU8;
+
+    fn deref(&self) -> &Self::Target {
+        &self.0
+    }
+}
+
+/// A share of the host's CPUs, held in billionths of a CPU as Docker expects.
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Deserialize)]
+#[serde(try_from = "f64")]
+pub struct CpuLimit(u64);
+
+impl CpuLimit {
+    pub fn nano_cpus(self) -> u64 {
+        self.0
+    }
+}
+
+impl TryFrom<f64> for CpuLimit {
+    type Error = color_eyre::Report;
+
+    fn try_from(value: f64) -> Result<Self> {
+        let nano_cpus = (value * 1e9).round();
+
+        if !nano_cpus.is_finite() || nano_cpus < 1.0 || nano_cpus > u64::MAX as f64 {
+            return Err(eyre!("invalid value provided for CPU limit: {value}"));
+        }
+
+        Ok(Self(nano_cpus as u64))
+    }
+}
+
+/// An amount of memory in bytes, which can also be given with a `k`, `m` or `g` suffix such as
+/// `512m`, matching the units Docker accepts.
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Deserialize)]
+#[serde(try_from = "MemorySizeValue")]
+pub struct MemorySize(u64);
+
+impl MemorySize {
+    pub fn bytes(self) -> u64 {
+        self.0
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum MemorySizeValue {
+    Bytes(u64),
+    Text(String),
+}
+
+impl TryFrom<MemorySizeValue> for MemorySize {
+    type Error = color_eyre::Report;
+
+    fn try_from(value: MemorySizeValue) -> Result<Self> {
+        let text = match value {
+            MemorySizeValue::Bytes(bytes) => return Self::try_from(bytes),
+            MemorySizeValue::Text(text) => text,
+        };
+
+        let lowercase = text.trim().to_ascii_lowercase();
+        let (amount, multiplier) = match lowercase.char_indices().last() {
+            Some((index, 'b')) => (&lowercase[..index], 1),
+            Some((index, 'k')) => (&lowercase[..index], 1 << 10),
+            Some((index, 'm')) => (&lowercase[..index], 1 << 20),
+            Some((index, 'g')) => (&lowercase[..index], 1 << 30),
+            _ => (lowercase.as_str(), 1),
+        };
+
+        let bytes = amount
+            .parse::<u64>()
+            .ok()
+            .and_then(|amount| amount.checked_mul(multiplier))
+            .ok_or_else(|| eyre!("invalid value provided for memory size: {text}"))?;
+
+        Self::try_from(bytes)
+    }
+}
+
+impl TryFrom<u64> for MemorySize {
+    type Error = color_eyre::Report;
+
+    fn try_from(value: u64) -> Result<Self> {
+        if value == 0 {
+            return Err(eyre!("memory sizes must be greater than zero"));
+        }
+
+        Ok(Self(value))
+    }
+}
+
+/// A resource limit for the processes in a container, such as `nofile`, given as a single number
+/// for both limits or as separate `soft` and `hard` limits.
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Deserialize)]
+#[serde(try_from = "UlimitValue")]
+pub struct Ulimit {
+    pub soft: u64,
+    pub hard: u64,
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum UlimitValue {
+    Both(u64),
+    Separate { soft: u64, hard: u64 },
+}
+
+impl TryFrom<UlimitValue> for Ulimit {
+    type Error = color_eyre::Report;
+
+    fn try_from(value: UlimitValue) -> Result<Self> {
+        let (soft, hard) = match value {
+            UlimitValue::Both(limit) => (limit, limit),
+            UlimitValue::Separate { soft, hard } => (soft, hard),
+        };
+
+        if soft > hard {
+            return Err(eyre!(
+                "soft limit {soft} cannot be greater than the hard limit {hard}"
+            ));
+        }
+
+        Ok(Self { soft, hard })
+    }
+}
+
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ShutdownMode {
+    Graceful,
+    #[default]
+    Forceful,
+}
+
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum PullPolicy {
+    /// Pull the image before every container start, even if it exists locally.
+    Always,
+    /// Only pull the image if it does not exist locally.
+    #[default]
+    IfNotPresent,
+    /// Never pull the image, failing if it does not exist locally.
+    Never,
+}
+
+/// Whether the Docker daemon restarts containers when they exit, written as `no`,
+/// `on-failure`, `on-failure:<max retries>` or `unless-stopped`.
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq, Deserialize)]
+#[serde(try_from = "String")]
+pub enum RestartPolicy {
+    /// Leave containers stopped once they exit.
+    #[default]
+    No,
+    /// Restart containers that exit with an error, giving up after `max_retries` if it is set.
+    OnFailure { max_retries: Option<u32> },
+    /// Restart containers whenever they exit, unless they were stopped deliberately.
+    UnlessStopped,
+}
+
+impl TryFrom<String> for RestartPolicy {
+    type Error = color_eyre::Report;
+
+    fn try_from(value: String) -> Result<Self> {
+        let policy = match value.split_once(':') {
+            None if value == "no" => Self::No,
+            None if value == "on-failure" => Self::OnFailure { max_retries: None },
+            None if value == "unless-stopped" => Self::UnlessStopped,
+            Some(("on-failure", max_retries)) => Self::OnFailure {
+                max_retries: Some(
+                    max_retries
+                        .parse()
+                        .wrap_err_with(|| eyre!("invalid retry count in {value}"))?,
+                ),
+            },
+            _ => return Err(eyre!("invalid value provided for restart policy: {value}")),
+        };
+
+        Ok(policy)
+    }
+}
+
+#[derive(Clone, Debug, Eq, PartialEq, Hash, Deserialize)]
+#[serde(tag = "type", rename_all = "kebab-case")]
+pub enum Affinity {
+    /// Uses the path segment at the given index, such as `1` for the `{id}` in `/users/{id}`.
+    PathSegment { index: usize },
+    /// Uses the value of the given request header, such as `X-Tenant-Id`.
+    Header { name: String },
+}
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Hash, Deserialize)]
+pub struct RateLimit {
+    /// How many requests are accepted each second once any burst is used up.
+    pub requests_per_second: NonZeroU32,
+    /// How many requests can be accepted at once, defaulting to a second's worth.
+    pub burst: Option<NonZeroU32>,
+}
+
+/// Changes to make to the headers of a request or response.
+#[derive(Clone, Debug, Default, Eq, PartialEq, Hash, Deserialize)]
+pub struct HeaderRewrite {
+    /// Headers to add alongside any existing values.
+    #[serde(default)]
+    pub add: BTreeMap<String, String>,
+    /// Headers to set, replacing any existing values.
+    #[serde(default)]
+    pub set: BTreeMap<String, String>,
+    /// Headers to remove entirely.
+    #[serde(default)]
+    pub remove: Vec<String>,
+}
+
+#[derive(Clone, Debug, Default, Eq, PartialEq, Hash, Deserialize)]
+pub struct HeaderRules {
+    /// Applied to requests before they are sent downstream.
+    #[serde(default)]
+    pub request: HeaderRewrite,
+    /// Applied to responses before they are returned to the client.
+    #[serde(default)]
+    pub response: HeaderRewrite,
+}
+
+#[derive(Clone, Debug, Default, Eq, PartialEq, Hash, Deserialize)]
+pub struct CorsConfig {
+    /// The origins allowed to make requests, where `*` allows any origin.
+    pub allowed_origins: Vec<String>,
+    /// The methods allowed in requests, allowing any if empty.
+    #[serde(default)]
+    pub allowed_methods: Vec<String>,
+    /// The headers allowed in requests beyond those that are always safe.
+    #[serde(default)]
+    pub allowed_headers: Vec<String>,
+    #[serde(default)]
+    pub allow_credentials: bool,
+    /// How long browsers can cache the result of a preflight for.
+    pub max_age_secs: Option<u64>,
+}
+
+/// A regular expression matched against the path of a request, such as `^/users/\d+$`.
+#[derive(Clone, Debug, Eq, PartialEq, Hash, Deserialize)]
+#[serde(try_from = "String")]
+pub struct PathPattern(String);
+
+impl PathPattern {
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+}
+
+impl TryFrom<String> for PathPattern {
+    type Error = color_eyre::Report;
+
+    fn try_from(value: String) -> Result<Self> {
+        Regex::new(&value).wrap_err_with(|| eyre!("invalid path pattern {value}"))?;
+
+        Ok(Self(value))
+    }
+}
+
+/// Sends requests meeting a condition to another service, such as to opt in to a beta.
+#[derive(Clone, Debug, Eq, PartialEq, Hash, Deserialize)]
+pub struct AlternateService {
+    pub service: String,
+    pub when: RequestCondition,
+}
+
+#[derive(Clone, Debug, Eq, PartialEq, Hash, Deserialize)]
+#[serde(tag = "type", rename_all = "kebab-case")]
+pub enum RequestCondition {
+    /// Matches requests with a header set to the given value, such as `X-Beta: true`.
+    Header { name: String, value: String },
+    /// Matches requests with a cookie set to the given value.
+    Cookie { name: String, value: String },
+}
+
+/// A header naming the service a request should be sent to, out of those its route can use.
+#[derive(Clone, Debug, Eq, PartialEq, Deserialize)]
+pub struct VariantHeader {
+    #[serde(default = "default_variant_header")]
+    pub name: String,
+    /// The networks allowed to pick a variant, allowing any client if empty.
+    #[serde(default)]
+    pub trusted: Vec<IpNetwork>,
+}
+
+fn default_variant_header() -> String {
+    String::from("X-F2-Variant")
+}
+
+/// Sends a share of a route's requests to another service, such as a canary version.
+#[derive(Clone, Debug, Eq, PartialEq, Hash, Deserialize)]
+pub struct TrafficSplit {
+    /// The service to send requests to instead, on the port of its own route for the request.
+    pub service: String,
+    /// The percentage of requests to send to the service, up to 100.
+    pub percentage: u8,
+}
+
+/// Only serves a route over HTTPS, such as for pages handling credentials.
+#[derive(Clone, Debug, Default, Eq, PartialEq, Hash, Deserialize)]
+pub struct HttpsOnly {
+    /// What to do with requests that arrive over plain HTTP.
+    #[serde(default)]
+    pub plain_http: PlainHttpAction,
+    /// Adds a `Strict-Transport-Security` header to responses sent over HTTPS when set.
+    pub hsts: Option<Hsts>,
+}
+
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq, Hash, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum PlainHttpAction {
+    /// Redirects the client to the same location over HTTPS.
+    #[default]
+    Redirect,
+    /// Rejects the request without proxying it.
+    Refuse,
+}
+
+#[derive(Clone, Debug, Eq, PartialEq, Hash, Deserialize)]
+pub struct Hsts {
+    /// How long clients should remember to only use HTTPS, in seconds.
+    pub max_age_secs: u64,
+    #[serde(default)]
+    pub include_subdomains: bool,
+    #[serde(default)]
+    pub preload: bool,
+}
+
+impl Hsts {
+    pub fn header_value(&self) -> String {
+        let mut value = format!("max-age={}", self.max_age_secs);
+
+        if self.include_subdomains {
+            value.push_str("; includeSubDomains");
+        }
+
+        if self.preload {
+            value.push_str("; preload");
+        }
+
+        value
+    }
+}
+
+/// Restricts which clients verified through mutual TLS can make requests, beyond their
+/// certificates being trusted.
+#[derive(Clone, Debug, Eq, PartialEq, Hash, Deserialize)]
+pub struct ClientCertificateRules {
+    /// The common names permitted to make requests, permitting any if empty.
+    #[serde(default)]
+    pub common_names: Vec<String>,
+}
+
+/// Copies requests to another service in the background, discarding its responses.
+#[derive(Clone, Debug, Eq, PartialEq, Hash, Deserialize)]
+pub struct Mirror {
+    /// The service to send copies of requests to.
+    pub service: String,
+    /// The port on the service's containers to send copies to.
+    pub port: u16,
+    /// The percentage of requests to copy.
+    #[serde(default = "default_mirror_percentage")]
+    pub percentage: u8,
+}
+
+fn default_mirror_percentage() -> u8 {
+    100
+}
+
+/// Restricts which clients can make requests, based on their address.
+#[derive(Clone, Debug, Default, Eq, PartialEq, Hash, Deserialize)]
+pub struct AccessControl {
+    /// The networks allowed to make requests, allowing any client if empty.
+    #[serde(default)]
+    pub allow: Vec<IpNetwork>,
+    /// The networks refused, even if they are also allowed.
+    #[serde(default)]
+    pub deny: Vec<IpNetwork>,
+}
+
+/// A range of addresses in CIDR notation, such as `10.0.0.0/8`.
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Hash, Deserialize)]
+#[serde(try_from = "String")]
+pub struct IpNetwork {
+    addr: IpAddr,
+    prefix_len: u8,
+}
+
+impl IpNetwork {
+    pub fn contains(&self, addr: IpAddr) -> bool {
+        // Dual stack listeners report IPv4 clients as mapped IPv6 addresses
+        match (self.addr, addr.to_canonical()) {
+            (IpAddr::V4(network), IpAddr::V4(addr)) => {
+                let mask = u32::MAX
+                    .checked_shl(32 - u32::from(self.prefix_len))
+                    .unwrap_or(0);
+
+                u32::from(network) & mask == u32::from(addr) & mask
+            }
+            (IpAddr::V6(network), IpAddr::V6(addr)) => {
+                let mask = u128::MAX
+                    .checked_shl(128 - u32::from(self.prefix_len))
+                    .unwrap_or(0);
+
+                u128::from(network) & mask == u128::from(addr) & mask
+            }
+            _ => false,
+        }
+    }
+}
+
+impl TryFrom<String> for IpNetwork {
+    type Error = color_eyre::Report;
+
+    fn try_from(value: String) -> Result<Self> {
+        let (addr, prefix_len) = match value.split_once('/') {
+            Some((addr, prefix_len)) => (addr, Some(prefix_len)),
+            None => (value.as_str(), None),
+        };
+
+        let addr: IpAddr = addr
+            .parse()
+            .wrap_err_with(|| eyre!("invalid address in network {value}"))?;
+
+        let max_prefix_len = match addr {
+            IpAddr::V4(_) => 32,
+            IpAddr::V6(_) => 128,
+        };
+
+        let prefix_len = match prefix_len {
+            Some(prefix_len) => prefix_len
+                .parse()
+                .ok()
+                .filter(|prefix_len| *prefix_len <= max_prefix_len)
+                .ok_or_else(|| eyre!("invalid prefix length in network {value}"))?,
+            None => max_prefix_len,
+        };
+
+        Ok(Self { addr, prefix_len })
+    }
+}
+
+/// Failures talking to a downstream that a request can be retried after.
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Hash, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum RetryCondition {
+    /// The connection could not be established, such as when it is refused.
+    ConnectFailure,
+    /// The connection was reset before a response was received.
+    ConnectionReset,
+}
+
+#[derive(Clone, Debug, Eq, PartialEq, Deserialize)]
+pub struct RetryConfig {
+    /// How many other downstreams to try after the first fails.
+    pub attempts: usize,
+    #[serde(default = "RetryConfig::default_conditions")]
+    pub on: HashSet<RetryCondition>,
+}
+
+impl RetryConfig {
+    fn default_conditions() -> HashSet<RetryCondition> {
+        HashSet::from([RetryCondition::ConnectFailure])
+    }
+}
+
+#[derive(Clone, Debug, Eq, PartialEq, Deserialize)]
+pub struct CompressionConfig {
+    /// The smallest response body worth compressing, in bytes.
+    #[serde(default = "CompressionConfig::default_min_size")]
+    pub min_size: usize,
+}
+
+impl CompressionConfig {
+    fn default_min_size() -> usize {
+        1024
+    }
+}
+
+#[derive(Clone, Debug, Eq, PartialEq, Deserialize)]
+pub struct CircuitBreakerConfig {
+    /// How many consecutive failed requests trip the breaker.
+    pub failure_threshold: u32,
+    /// How long to reject requests for before letting a single probe through.
+    pub open_ms: u64,
+}
+
+impl CircuitBreakerConfig {
+    pub fn open_duration(&self) -> Duration {
+        Duration::from_millis(self.open_ms)
+    }
+}
+
+#[derive(Clone, Debug, Eq, PartialEq, Deserialize)]
+pub struct OutlierDetectionConfig {
+    /// How many consecutive failed requests eject a container.
+    pub failure_threshold: u32,
+    /// How long to keep a container out of rotation for once ejected.
+    pub ejection_ms: u64,
+    /// The most of a service's containers that can be ejected at once, as a percentage.
+    #[serde(default = "default_max_ejection_percent")]
+    pub max_ejection_percent: u8,
+}
+
+impl OutlierDetectionConfig {
+    pub fn ejection_duration(&self) -> Duration {
+        Duration::from_millis(self.ejection_ms)
+    }
+}
+
+fn default_max_ejection_percent() -> u8 {
+    50
+}
+
+#[derive(Clone, Debug, Default, Eq, PartialEq, Hash, Deserialize)]
+pub struct Route {
+    pub host: String,
+    pub prefix: Option<String>,
+    /// Matches paths against a regular expression instead of the prefix, where only the matching
+    /// paths are sent to this route.
+    pub path_pattern: Option<PathPattern>,
+    /// The methods this route accepts, accepting any if empty.
+    #[serde(default)]
+    pub methods: Vec<String>,
+    pub port: u16,
+    /// Connects to the containers over TLS instead of plain HTTP when set to `https`.
+    #[serde(default)]
+    pub scheme: Scheme,
+    /// How to verify the containers when connecting to them over TLS.
+    pub upstream_tls: Option<UpstreamTls>,
+    /// Overrides the request identifier header for requests matching this route.
+    pub request_id_header: Option<String>,
+    /// Consistently sends requests with the same affinity key to the same downstream.
+    pub affinity: Option<Affinity>,
+    /// Limits how quickly requests matching this route are accepted.
+    pub rate_limit: Option<RateLimit>,
+    /// The largest request body to accept, in bytes.
+    pub max_body_size: Option<usize>,
+    /// Rewrites the headers of requests and responses passing through this route.
+    pub headers: Option<HeaderRules>,
+    /// Removes the matched prefix from the path before forwarding requests downstream.
+    #[serde(default)]
+    pub strip_prefix: bool,
+    /// Answers CORS preflights and adds CORS headers to responses for this route.
+    pub cors: Option<CorsConfig>,
+    /// Refuses requests from clients outside of the allowed networks.
+    pub access: Option<AccessControl>,
+    /// Refuses requests from clients whose verified certificates do not satisfy the rules.
+    pub client_certificates: Option<ClientCertificateRules>,
+    /// Copies requests to a shadow service, such as to test a new version with real traffic.
+    pub mirror: Option<Mirror>,
+    /// Sends a share of requests to another service, on the same port as this route.
+    pub split: Option<TrafficSplit>,
+    /// Sends requests meeting a condition to another service, on the same port as this route,
+    /// taking precedence over any split.
+    #[serde(default)]
+    pub alternates: Vec<AlternateService>,
+    /// Refuses or redirects requests arriving over plain HTTP when set.
+    pub https_only: Option<HttpsOnly>,
+    /// The names of filters to run against requests, in order, before they are proxied, such as
+    /// `require-authorization` to refuse requests without credentials.
+    #[serde(default)]
+    pub filters: Vec<String>,
+}
+
+/// How the containers behind a route are verified when connecting to them over TLS.
+#[derive(Clone, Debug, Default, Eq, PartialEq, Hash, Deserialize)]
+pub struct UpstreamTls {
+    /// The certificate authorities to trust instead of the public roots, such as an internal one.
+    pub ca: Option<ExternalBytes>,
+    /// The name to request a certificate for and verify it against, defaulting to the route's host.
+    pub server_name: Option<String>,
+}
+
+/// Forwards traffic arriving on a port of the load balancer to a port on a service's containers.
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Hash, Deserialize)]
+pub struct PortRoute {
+    /// The port the load balancer listens on.
+    pub listen: u16,
+    /// The port on the containers to forward traffic to.
+    pub port: u16,
+}
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Hash)]
+pub enum Transport {
+    Tcp,
+    Udp,
+}
+
+impl fmt::Display for Transport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Tcp => write!(f, "tcp"),
+            Self::Udp => write!(f, "udp"),
+        }
+    }
+}
+
+/// How a container is connected to an additional network.
+#[derive(Clone, Debug, Default, Eq, PartialEq, Deserialize)]
+pub struct NetworkAttachment {
+    /// Names other containers on the network can reach the container by.
+    #[serde(default)]
+    pub aliases: Vec<String>,
+}
+
+/// A container port published directly on the host rather than proxied, written as
+/// `[<host ip>:]<host port>:<container port>[/tcp|/udp]` like `docker run --publish`.
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Hash, Deserialize)]
+#[serde(try_from = "String")]
+pub struct PublishedPort {
+    /// The host address to listen on, defaulting to every address.
+    pub host_ip: Option<IpAddr>,
+    pub host_port: u16,
+    pub container_port: u16,
+    pub transport: Transport,
+}
+
+impl TryFrom<String> for PublishedPort {
+    type Error = color_eyre::Report;
+
+    fn try_from(value: String) -> Result<Self> {
+        let invalid = || eyre!("invalid value provided for published port: {value}");
+
+        let (ports, transport) = match value.split_once('/') {
+            None => (value.as_str(), Transport::Tcp),
+            Some((ports, "tcp")) => (ports, Transport::Tcp),
+            Some((ports, "udp")) => (ports, Transport::Udp),
+            Some(_) => return Err(invalid()),
+        };
+
+        // IPv6 addresses contain colons themselves, so the ports are split from the end
+        let (rest, container_port) = ports.rsplit_once(':').ok_or_else(invalid)?;
+        let (host_ip, host_port) = match rest.rsplit_once(':') {
+            Some((host_ip, host_port)) => {
+                let host_ip = host_ip.trim_start_matches('[').trim_end_matches(']');
+
+                (Some(host_ip.parse().map_err(|_| invalid())?), host_port)
+            }
+            None => (None, rest),
+        };
+
+        Ok(Self {
+            host_ip,
+            host_port: host_port.parse().map_err(|_| invalid())?,
+            container_port: container_port.parse().map_err(|_| invalid())?,
+            transport,
+        })
+    }
+}
+
+/// How long containers have to become healthy when a service doesn't say otherwise.
+const DEFAULT_HEALTH_TIMEOUT: Duration = Duration::from_secs(60);
+
+#[derive(Clone, Debug, Default, Eq, PartialEq, Deserialize)]
+pub struct Service {
+    pub image: String,
+    pub tag: String,
+    pub replicas: ReplicaCount,
+    #[serde(default)]
+    pub routes: HashSet<Route>,
+    /// Ports accepting plain TCP connections that are streamed to the containers.
+    #[serde(default)]
+    pub tcp_routes: HashSet<PortRoute>,
+    /// Ports accepting UDP datagrams that are forwarded to the containers.
+    #[serde(default)]
+    pub udp_routes: HashSet<PortRoute>,
+    #[serde(default)]
+    pub environment: HashMap<String, String>,
+    #[serde(default)]
+    pub volumes: HashMap<String, VolumeDefinition>,
+    #[serde(default)]
+    pub shutdown_mode: ShutdownMode,
+    #[serde(default)]
+    pub pull_policy: PullPolicy,
+    /// Whether the Docker daemon restarts containers that exit, even between deployments.
+    #[serde(default)]
+    pub restart_policy: RestartPolicy,
+    /// Starts a new container in place of any that exit without f2 stopping them, for services the
+    /// Docker daemon does not restart itself.
+    #[serde(default)]
+    pub replace_dead_containers: bool,
+    /// Replaces the image's `CMD`, such as to run it as a worker rather than a web server.
+    pub command: Option<Vec<String>>,
+    /// Replaces the image's `ENTRYPOINT`.
+    pub entrypoint: Option<Vec<String>>,
+    /// Limits for the processes in each container, keyed by name such as `nofile` or `nproc`.
+    #[serde(default)]
+    pub ulimits: BTreeMap<String, Ulimit>,
+    /// Kernel parameters to set in each container's namespaces, such as `net.core.somaxconn`.
+    #[serde(default)]
+    pub sysctls: BTreeMap<String, String>,
+    /// Mounts each container's root filesystem as read-only, leaving volumes writable.
+    #[serde(default)]
+    pub read_only: bool,
+    /// Options for the container's security modules, such as `no-new-privileges` or
+    /// `apparmor=<profile>`.
+    #[serde(default)]
+    pub security_opt: Vec<String>,
+    /// A seccomp profile to apply to each container instead of Docker's default.
+    pub seccomp_profile: Option<ExternalBytes>,
+    /// Gives each container full access to the host's devices and capabilities.
+    #[serde(default)]
+    pub privileged: bool,
+    /// A command run inside each container once it is healthy and before it is added to the
+    /// load balancer, such as to warm caches, which fails the deploy if it exits unsuccessfully.
+    pub post_start: Option<Vec<String>>,
+    /// A command run inside each container before it is taken out of the load balancer to be
+    /// stopped, such as to start draining connections.
+    pub pre_stop: Option<Vec<String>>,
+    /// Networks to connect containers to alongside the one f2 routes to them over, keyed by name.
+    #[serde(default)]
+    pub networks: HashMap<String, NetworkAttachment>,
+    /// Container ports published directly on the host, such as for SSH, which only one container
+    /// can bind at a time.
+    #[serde(default)]
+    pub published_ports: Vec<PublishedPort>,
+    /// Labels applied to each container alongside the ones f2 uses to identify them.
+    #[serde(default)]
+    pub labels: HashMap<String, String>,
+    /// Overrides the credentials used to pull the image, rather than those for its registry.
+    pub registry_credentials: Option<RegistryCredentials>,
+    /// Overrides whether requests to this service are written to the access log.
+    pub access_log: Option<bool>,
+    /// The share of traffic each replica receives, in the order they are started, with any
+    /// replicas beyond the end of the list receiving a weight of 1.
+    #[serde(default)]
+    pub weights: Vec<u32>,
+    #[serde(default)]
+    pub upstream_protocol: UpstreamProtocol,
+    /// Replaces the `Host` header sent to the containers, such as with an internal name, rather than
+    /// passing on the one the client used.
+    pub upstream_host: Option<String>,
+    /// Retries requests against other downstreams when the chosen one fails.
+    pub retry: Option<RetryConfig>,
+    /// Rejects requests for a while once the service's downstreams keep failing.
+    pub circuit_breaker: Option<CircuitBreakerConfig>,
+    /// Takes containers out of rotation for a while once requests to them keep failing.
+    pub outlier_detection: Option<OutlierDetectionConfig>,
+    /// How long containers from images with a `HEALTHCHECK` have to report healthy before the
+    /// deploy fails, defaulting to 60 seconds.
+    pub health_timeout_secs: Option<u64>,
+    /// Ramps newly added containers up to their full share of traffic over this many milliseconds.
+    pub slow_start_ms: Option<u64>,
+    /// The most requests each container handles at once, beyond which requests are shed.
+    pub max_in_flight: Option<usize>,
+    /// Compresses responses for clients that accept it, if the containers don't already.
+    pub compression: Option<CompressionConfig>,
+    /// Responds to requests with the maintenance response instead of proxying them, leaving the
+    /// containers running.
+    #[serde(default)]
+    pub maintenance: bool,
+    /// Lets clients pick which variant of a route handles their request, such as to test a canary.
+    pub variant_header: Option<VariantHeader>,
+    /// The most CPUs each container can use, such as `0.5` for half of one.
+    pub cpu_limit: Option<CpuLimit>,
+    /// The most memory each container can use before it is killed.
+    pub memory_limit: Option<MemorySize>,
+    /// The memory each container is kept down to when the host runs short.
+    pub memory_reservation: Option<MemorySize>,
+}
+
+impl Service {
+    pub fn slow_start(&self) -> Option<Duration> {
+        self.slow_start_ms.map(Duration::from_millis)
+    }
+
+    pub fn health_timeout(&self) -> Duration {
+        self.health_timeout_secs
+            .map_or(DEFAULT_HEALTH_TIMEOUT, Duration::from_secs)
+    }
+
+    pub fn port_routes(&self, transport: Transport) -> &HashSet<PortRoute> {
+        match transport {
+            Transport::Tcp => &self.tcp_routes,
+            Transport::Udp => &self.udp_routes,
+        }
+    }
+
+    /// Checks whether two definitions only differ in how requests reach them, such as their routes
+    /// or maintenance mode, which can be changed without replacing any containers.
+    pub fn only_routing_differs(&self, other: &Self) -> bool {
+        self != other
+            && *self
+                == Self {
+                    maintenance: self.maintenance,
+                    routes: self.routes.clone(),
+                    variant_header: self.variant_header.clone(),
+                    ..other.clone()
+                }
+    }
+}
+
+impl Hash for Service {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.image.hash(state);
+        self.tag.hash(state);
+    }
+}
+
+#[derive(Clone, Debug, Eq, PartialEq, Deserialize)]
+pub struct VolumeDefinition {
+    /// The source of the volume, which can be a filesystem path, an S3 bucket/key or storage
+    /// managed by Docker.
+    pub source: VolumeSource,
+    /// The target path inside the container where the volume will be mounted.
+    pub target: String,
+}
+
+#[derive(Clone, Debug, Eq, PartialEq, Deserialize)]
+#[serde(untagged)]
+pub enum VolumeSource {
+    /// Content resolved by f2 and bind mounted into the container.
+    External(ExternalBytes),
+    Managed(ManagedVolume),
+}
+
+/// Storage created and owned by the Docker daemon rather than by f2.
+#[derive(Clone, Debug, Eq, PartialEq, Deserialize)]
+#[serde(tag = "location", rename_all = "lowercase")]
+pub enum ManagedVolume {
+    /// A named volume, created by Docker if it doesn't exist, whose contents outlive the container
+    /// so they are kept across deploys.
+    Named { name: String },
+    /// Scratch space held in memory, which is discarded when the container stops.
+    Tmpfs { size: Option<MemorySize> },
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::{BTreeMap, HashMap, HashSet};
+    use std::net::{IpAddr, Ipv4Addr};
+
+    use color_eyre::eyre::Result;
+
+    use crate::config::{
+        registry_host, AccessControl, AlbConfig, Config, ConnectionPool, ContainerLogsConfig,
+        CpuLimit, Diff, DockerRetryConfig, ExternalBytes, HeaderRewrite, HeaderRules, Hsts,
+        IpNetwork, ManagedVolume, MemorySize, PublishedPort, RegistryCredentials, RegistryPassword,
+        RequestIdConfig, RestartPolicy, Route, Scheme, Service, ShutdownConfig, TlsConfig,
+        TrafficSplit, Transport, Ulimit, UpstreamTls, VolumeDefinition, VolumeSource,
+    };
+
+    fn some_config() -> Config {
+        let mut services = HashMap::new();
+        services.insert(String::from("backend"), Service::default());
+
+        Config {
+            alb: AlbConfig {
+                addr: Ipv4Addr::LOCALHOST,
+                ports: HashMap::from([(Scheme::Http, 5000)]),
+                protocols: HashMap::new(),
+                reconciliation: String::new(),
+                metrics: None,
+                maintenance: None,
+                container_logs: None,
+                request_id: None,
+                evict_connections_on_deploy: false,
+                path_limits: None,
+                empty_service_wait_ms: None,
+                prefer_warm_connections: false,
+                connection_pool: ConnectionPool::default(),
+                max_buffered_body_size: 1024 * 1024,
+                access_log: false,
+                busy_response: None,
+                retry_after_jitter_secs: 0,
+                error_pages: HashMap::new(),
+                maintenance_response: None,
+                static_routes: Vec::new(),
+                otlp: None,
+                tls: None,
+                mtls: None,
+            },
+            secrets: None,
+            shutdown: ShutdownConfig::default(),
+            deploy_events: false,
+            registries: HashMap::new(),
+            garbage_collection: None,
+            network: None,
+            docker_retries: DockerRetryConfig::default(),
+            services,
+        }
+    }
+
+    #[test]
+    fn can_diff_configurations() {
+        let left = some_config();
+
+        let mut right = left.clone();
+        right.services.get_mut("backend").unwrap().tag = String::from("2");
+
+        let diff = left.diff(&right);
+
+        assert_eq!(
+            diff,
+            Some(vec![Diff::Alteration {
+                name: String::from("backend"),
+                old_definition: left.services.get("backend").unwrap().clone(),
+                new_definition: right.services.get("backend").unwrap().clone()
+            }])
+        );
+    }
+
+    #[test]
+    fn same_configuration_produces_an_empty_diff() {
+        let left = some_config();
+        let diff = left.diff(&left);
+
+        assert_eq!(diff, None);
+    }
+
+    #[test]
+    fn can_notice_non_tag_changes() {
+        let left = some_config();
+
+        let mut right = left.clone();
+
+        // Add an environment with some variables
+        let mut environment = HashMap::new();
+        environment.insert("NEW_PROPERTY".into(), "some-value".into());
+
+        right.services.get_mut("backend").unwrap().environment = environment;
+
+        let diff = left.diff(&right);
+
+        assert_eq!(
+            diff,
+            Some(vec![Diff::Alteration {
+                name: String::from("backend"),
+                old_definition: left.services.get("backend").unwrap().clone(),
+                new_definition: right.services.get("backend").unwrap().clone()
+            }])
+        );
+    }
+
+    #[test]
+    fn resource_limit_changes_replace_containers() -> Result<()> {
+        let left = some_config();
+        let mut right = left.clone();
+
+        let backend = right.services.get_mut("backend").unwrap();
+        backend.cpu_limit = Some(CpuLimit::try_from(0.5)?);
+        backend.memory_limit = Some(MemorySize::try_from(512 * 1024 * 1024)?);
+
+        let old_definition = left.services.get("backend").unwrap();
+        let new_definition = right.services.get("backend").unwrap();
+
+        assert!(!old_definition.only_routing_differs(new_definition));
+        assert_eq!(
+            left.diff(&right),
+            Some(vec![Diff::Alteration {
+                name: String::from("backend"),
+                old_definition: old_definition.clone(),
+                new_definition: new_definition.clone(),
+            }])
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn can_notice_additional_services() {
+        let left = some_config();
+        let mut right = left.clone();
+
+        let service = Service::default();
+        right.services.insert("frontend".into(), service.clone());
+
+        let diff = left.diff(&right);
+
+        assert_eq!(
+            diff,
+            Some(vec![Diff::Addition {
                 name: "frontend".into(),
                 definition: service,
             }])
@@ -371,4 +1921,500 @@ mod tests {
             }])
         )
     }
+
+    #[test]
+    fn networks_contain_addresses_within_their_prefix() -> Result<()> {
+        let network = IpNetwork::try_from(String::from("10.1.0.0/16"))?;
+
+        assert!(network.contains("10.1.255.3".parse()?));
+        assert!(network.contains("::ffff:10.1.0.1".parse()?));
+        assert!(!network.contains("10.2.0.1".parse()?));
+        assert!(!network.contains("::1".parse()?));
+
+        let everything = IpNetwork::try_from(String::from("0.0.0.0/0"))?;
+        assert!(everything.contains("192.168.1.1".parse()?));
+
+        let single = IpNetwork::try_from(String::from("2001:db8::1"))?;
+        assert!(single.contains("2001:db8::1".parse()?));
+        assert!(!single.contains("2001:db8::2".parse()?));
+
+        Ok(())
+    }
+
+    #[test]
+    fn hsts_headers_include_the_configured_directives() {
+        let hsts = Hsts {
+            max_age_secs: 31536000,
+            include_subdomains: false,
+            preload: false,
+        };
+
+        assert_eq!(hsts.header_value(), "max-age=31536000");
+
+        let hsts = Hsts {
+            include_subdomains: true,
+            preload: true,
+            ..hsts
+        };
+
+        assert_eq!(
+            hsts.header_value(),
+            "max-age=31536000; includeSubDomains; preload"
+        );
+    }
+
+    #[test]
+    fn invalid_networks_are_rejected() {
+        assert!(IpNetwork::try_from(String::from("10.0.0.0/33")).is_err());
+        assert!(IpNetwork::try_from(String::from("10.0.0/8")).is_err());
+        assert!(IpNetwork::try_from(String::from("::/abc")).is_err());
+    }
+
+    #[test]
+    fn cpu_limits_are_converted_to_nano_cpus() -> Result<()> {
+        assert_eq!(CpuLimit::try_from(0.5)?.nano_cpus(), 500_000_000);
+        assert_eq!(CpuLimit::try_from(2.0)?.nano_cpus(), 2_000_000_000);
+
+        assert!(CpuLimit::try_from(0.0).is_err());
+        assert!(CpuLimit::try_from(-1.0).is_err());
+        assert!(CpuLimit::try_from(f64::NAN).is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn memory_sizes_can_be_given_with_units() -> Result<()> {
+        let parse = |value: &str| serde_yaml::from_str::<MemorySize>(value).map(MemorySize::bytes);
+
+        assert_eq!(parse("1048576")?, 1 << 20);
+        assert_eq!(parse("512k")?, 512 << 10);
+        assert_eq!(parse("256M")?, 256 << 20);
+        assert_eq!(parse("2g")?, 2 << 30);
+
+        assert!(parse("0").is_err());
+        assert!(parse("lots").is_err());
+        assert!(parse("99999999999g").is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn volumes_can_come_from_files_or_docker() -> Result<()> {
+        let volumes: HashMap<String, VolumeDefinition> = serde_yaml::from_str(
+            r#"
+            config:
+              source:
+                location: s3
+                bucket: configuration
+                key: backend.yaml
+              target: /etc/backend/config.yaml
+            data:
+              source:
+                location: named
+                name: backend-data
+              target: /var/lib/backend
+            scratch:
+              source:
+                location: tmpfs
+                size: 64m
+              target: /tmp
+            "#,
+        )?;
+
+        assert_eq!(
+            volumes["config"].source,
+            VolumeSource::External(ExternalBytes::S3 {
+                bucket: String::from("configuration"),
+                key: String::from("backend.yaml"),
+            })
+        );
+        assert_eq!(
+            volumes["data"].source,
+            VolumeSource::Managed(ManagedVolume::Named {
+                name: String::from("backend-data"),
+            })
+        );
+        assert_eq!(
+            volumes["scratch"].source,
+            VolumeSource::Managed(ManagedVolume::Tmpfs {
+                size: Some(MemorySize::try_from(64 << 20)?),
+            })
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn services_can_override_the_command_and_entrypoint() -> Result<()> {
+        let service: Service = serde_yaml::from_str(
+            r#"
+            image: alexanderjackson/f2
+            tag: latest
+            replicas: 1
+            command: ["worker", "--queue", "emails"]
+            entrypoint: ["/usr/bin/tini", "--"]
+            "#,
+        )?;
+
+        assert_eq!(
+            service.command,
+            Some(vec![
+                String::from("worker"),
+                String::from("--queue"),
+                String::from("emails")
+            ])
+        );
+        assert_eq!(
+            service.entrypoint,
+            Some(vec![String::from("/usr/bin/tini"), String::from("--")])
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn ulimits_can_share_or_separate_their_limits() -> Result<()> {
+        let ulimits: BTreeMap<String, Ulimit> = serde_yaml::from_str(
+            r#"
+            nofile:
+              soft: 65536
+              hard: 131072
+            nproc: 4096
+            "#,
+        )?;
+
+        assert_eq!(
+            ulimits["nofile"],
+            Ulimit {
+                soft: 65536,
+                hard: 131072
+            }
+        );
+        assert_eq!(
+            ulimits["nproc"],
+            Ulimit {
+                soft: 4096,
+                hard: 4096
+            }
+        );
+
+        assert!(serde_yaml::from_str::<Ulimit>("{ soft: 2, hard: 1 }").is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn published_ports_can_be_parsed() -> Result<()> {
+        let parse = |value: &str| PublishedPort::try_from(String::from(value));
+
+        assert_eq!(
+            parse("2222:22")?,
+            PublishedPort {
+                host_ip: None,
+                host_port: 2222,
+                container_port: 22,
+                transport: Transport::Tcp,
+            }
+        );
+        assert_eq!(
+            parse("127.0.0.1:9100:9100/udp")?,
+            PublishedPort {
+                host_ip: Some(IpAddr::V4(Ipv4Addr::LOCALHOST)),
+                host_port: 9100,
+                container_port: 9100,
+                transport: Transport::Udp,
+            }
+        );
+        assert_eq!(parse("[::1]:53:53/udp")?.host_ip, Some("::1".parse()?));
+
+        assert!(parse("22").is_err());
+        assert!(parse("2222:22/sctp").is_err());
+        assert!(parse("localhost:2222:22").is_err());
+        assert!(parse("70000:22").is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn restart_policies_can_be_parsed() -> Result<()> {
+        let parse = |value: &str| RestartPolicy::try_from(String::from(value));
+
+        assert_eq!(parse("no")?, RestartPolicy::No);
+        assert_eq!(parse("unless-stopped")?, RestartPolicy::UnlessStopped);
+        assert_eq!(
+            parse("on-failure")?,
+            RestartPolicy::OnFailure { max_retries: None }
+        );
+        assert_eq!(
+            parse("on-failure:3")?,
+            RestartPolicy::OnFailure {
+                max_retries: Some(3)
+            }
+        );
+
+        assert!(parse("always-and-forever").is_err());
+        assert!(parse("on-failure:many").is_err());
+        assert!(parse("unless-stopped:3").is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn registries_are_only_taken_from_host_like_components() {
+        assert_eq!(registry_host("ghcr.io/alexander-jackson/f2"), "ghcr.io");
+        assert_eq!(registry_host("localhost:5000/f2"), "localhost:5000");
+        assert_eq!(registry_host("localhost/f2"), "localhost");
+
+        assert_eq!(registry_host("alexanderjackson/f2"), "docker.io");
+        assert_eq!(registry_host("nginx"), "docker.io");
+    }
+
+    #[tokio::test]
+    async fn registry_passwords_can_be_read_from_files() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("registry-password");
+
+        tokio::fs::write(&path, "hunter2\n").await?;
+
+        let credentials = RegistryCredentials {
+            username: String::from("alexander-jackson"),
+            password: RegistryPassword::External(ExternalBytes::Filesystem { path }),
+        };
+
+        assert_eq!(credentials.resolve_password(None).await?, "hunter2");
+
+        // Encrypted values can't be resolved without the key
+        let credentials = RegistryCredentials {
+            password: RegistryPassword::Value(String::from("secret:aHVudGVyMg==")),
+            ..credentials
+        };
+
+        assert!(credentials.resolve_password(None).await.is_err());
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn only_missing_files_resolve_to_nothing() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("account.key");
+
+        let missing = ExternalBytes::Filesystem { path: path.clone() };
+        assert_eq!(missing.resolve_if_exists().await?, None);
+
+        std::fs::write(&path, b"key")?;
+        assert_eq!(missing.resolve_if_exists().await?, Some(b"key".to_vec()));
+
+        // Reading a directory fails for a reason other than it not existing
+        let unreadable = ExternalBytes::Filesystem {
+            path: dir.path().to_owned(),
+        };
+        assert!(unreadable.resolve_if_exists().await.is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn access_policies_are_rejected_with_https_listeners() -> Result<()> {
+        let mut config = some_config();
+
+        config.services.get_mut("backend").unwrap().routes = HashSet::from([Route {
+            host: String::from("example.com"),
+            access: Some(AccessControl {
+                allow: Vec::new(),
+                deny: vec![IpNetwork::try_from(String::from("10.0.0.0/8"))?],
+            }),
+            ..Default::default()
+        }]);
+
+        config.validate()?;
+
+        config.alb.ports.insert(Scheme::Https, 5443);
+        config.alb.tls = Some(TlsConfig::default());
+
+        assert!(config.validate().is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn routes_can_only_use_built_in_filters() -> Result<()> {
+        let mut config = some_config();
+
+        let route = |filter: &str| Route {
+            host: String::from("example.com"),
+            filters: vec![String::from(filter)],
+            ..Default::default()
+        };
+
+        let backend = config.services.get_mut("backend").unwrap();
+        backend.routes = HashSet::from([route("require-authorization")]);
+
+        config.validate()?;
+
+        let backend = config.services.get_mut("backend").unwrap();
+        backend.routes = HashSet::from([route("require-authorisation")]);
+
+        assert!(config.validate().is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn request_identifier_headers_are_parsed_on_load() -> Result<()> {
+        let yaml = r#"
+            header: X-Correlation-Id
+            aliases:
+              - Request-Id
+        "#;
+
+        let request_id: RequestIdConfig = serde_yaml::from_str(yaml)?;
+
+        assert_eq!(request_id.header, "X-Correlation-Id");
+        assert_eq!(request_id.aliases, ["Request-Id"]);
+
+        let request_id: RequestIdConfig = serde_yaml::from_str("aliases: []")?;
+        assert_eq!(request_id.header, "X-Request-Id");
+
+        let mut config = some_config();
+        config.alb.request_id = Some(request_id);
+
+        let backend = config.services.get_mut("backend").unwrap();
+        backend.routes = HashSet::from([Route {
+            host: String::from("example.com"),
+            request_id_header: Some(String::from("X-Correlation-Id")),
+            ..Default::default()
+        }]);
+
+        config.validate()?;
+
+        let backend = config.services.get_mut("backend").unwrap();
+        backend.routes = HashSet::from([Route {
+            host: String::from("example.com"),
+            request_id_header: Some(String::from("X Correlation Id")),
+            ..Default::default()
+        }]);
+
+        assert!(config.validate().is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn header_rewrites_are_parsed_on_load() -> Result<()> {
+        let mut config = some_config();
+
+        let route = |value: &str| Route {
+            host: String::from("example.com"),
+            headers: Some(HeaderRules {
+                response: HeaderRewrite {
+                    set: BTreeMap::from([(String::from("Cache-Control"), String::from(value))]),
+                    ..Default::default()
+                },
+                ..Default::default()
+            }),
+            ..Default::default()
+        };
+
+        let backend = config.services.get_mut("backend").unwrap();
+        backend.routes = HashSet::from([route("max-age=3600")]);
+
+        config.validate()?;
+
+        let backend = config.services.get_mut("backend").unwrap();
+        backend.routes = HashSet::from([route("max-age=3600\n")]);
+
+        assert!(config.validate().is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn splits_cannot_exceed_every_request() -> Result<()> {
+        let mut config = some_config();
+
+        let route = |percentage| Route {
+            host: String::from("example.com"),
+            split: Some(TrafficSplit {
+                service: String::from("canary"),
+                percentage,
+            }),
+            ..Default::default()
+        };
+
+        let backend = config.services.get_mut("backend").unwrap();
+        backend.routes = HashSet::from([route(100)]);
+
+        config.validate()?;
+
+        let backend = config.services.get_mut("backend").unwrap();
+        backend.routes = HashSet::from([route(101)]);
+
+        assert!(config.validate().is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn wildcard_routes_need_a_server_name_for_upstream_tls() -> Result<()> {
+        let mut config = some_config();
+
+        let route = |server_name: Option<&str>| Route {
+            host: String::from("*.example.com"),
+            scheme: Scheme::Https,
+            upstream_tls: Some(UpstreamTls {
+                ca: None,
+                server_name: server_name.map(String::from),
+            }),
+            ..Default::default()
+        };
+
+        let backend = config.services.get_mut("backend").unwrap();
+        backend.routes = HashSet::from([route(Some("backend.internal"))]);
+
+        config.validate()?;
+
+        let backend = config.services.get_mut("backend").unwrap();
+        backend.routes = HashSet::from([route(None)]);
+
+        assert!(config.validate().is_err());
+
+        let backend = config.services.get_mut("backend").unwrap();
+        backend.routes = HashSet::from([Route {
+            host: String::from("*.example.com"),
+            scheme: Scheme::Https,
+            ..Default::default()
+        }]);
+
+        assert!(config.validate().is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn container_logs_must_be_limited_to_specific_networks() -> Result<()> {
+        let mut config = some_config();
+
+        config.alb.container_logs = Some(ContainerLogsConfig {
+            path: String::from("/services"),
+            access: AccessControl {
+                allow: Vec::new(),
+                deny: vec![IpNetwork::try_from(String::from("10.0.0.0/8"))?],
+            },
+        });
+
+        assert!(config.validate().is_err());
+
+        config.alb.container_logs = Some(ContainerLogsConfig {
+            path: String::from("/services"),
+            access: AccessControl {
+                allow: vec![IpNetwork::try_from(String::from("10.0.0.0/8"))?],
+                deny: Vec::new(),
+            },
+        });
+
+        config.validate()?;
+
+        Ok(())
+    }
 }
@@ -1,26 +1,42 @@
 use std::collections::HashMap;
-use std::net::Ipv4Addr;
+use std::net::IpAddr;
 use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
 
 use color_eyre::eyre::{eyre, Context, Result};
 use rsa::RsaPrivateKey;
 
-use crate::common::Container;
-use crate::config::{ExternalBytes, VolumeDefinition};
+use crate::common::{Container, Mounts, RuntimeOptions, SecurityOptions};
+use crate::config::{
+    registry_host, ExternalBytes, ManagedVolume, NetworkAttachment, NetworkConfig, PullPolicy,
+    VolumeDefinition, VolumeSource,
+};
 use crate::docker::client::{DockerClient, DOCKER_NETWORK_NAME};
-use crate::docker::models::ContainerId;
+use crate::docker::models::{ContainerId, HealthStatus, RegistryAuth};
 
 use super::models::NetworkId;
 
+/// The label holding the name of the service a container was created for.
+pub const SERVICE_LABEL: &str = "f2.service";
+/// The label holding a hash of the definition a container was created from.
+pub const CONFIG_HASH_LABEL: &str = "f2.config-hash";
+/// The label holding which of the service's replicas a container is, starting from 0.
+pub const REPLICA_LABEL: &str = "f2.replica";
+
+/// How often to check whether a newly started container has become healthy.
+const HEALTH_POLL_INTERVAL: Duration = Duration::from_millis(500);
+
 #[derive(Clone, Debug, Eq, PartialEq, Hash)]
 pub struct StartedContainerDetails {
     pub id: ContainerId,
-    pub addr: Ipv4Addr,
+    pub addr: IpAddr,
 }
 
 #[tracing::instrument(skip(client, private_key))]
 pub async fn create_and_start_container<C: DockerClient>(
     client: &C,
+    service: &str,
+    replica: usize,
     container: &Container,
     tag: &str,
     private_key: Option<&RsaPrivateKey>,
@@ -29,10 +45,12 @@ pub async fn create_and_start_container<C: DockerClient>(
         image,
         environment,
         volumes,
+        runtime,
+        ..
     } = &container;
 
     // Ensure the image exists locally
-    pull_image_if_needed(client, container, tag).await?;
+    pull_image_if_needed(client, container, tag, private_key).await?;
 
     // Fetch the identifier for the Docker network
     let network_id = fetch_network_id(client).await?;
@@ -42,23 +60,47 @@ pub async fn create_and_start_container<C: DockerClient>(
 
     let hostname = generate_hostname(image);
     let environment = environment.decrypt(private_key)?;
-    let volumes = format_volumes(image, tag, volumes, private_key).await?;
+    let mounts = format_volumes(image, tag, volumes, private_key).await?;
+    let runtime = RuntimeOptions {
+        labels: container_labels(service, replica, container, tag),
+        security: resolve_seccomp_profile(&runtime.security).await?,
+        ..runtime.clone()
+    };
 
-    tracing::debug!(%name, ?volumes, "creating container with the following details");
+    tracing::debug!(%name, ?mounts, "creating container with the following details");
 
     let id = client
         .create_container(
             &name,
             &Some(environment),
-            &volumes,
+            &mounts,
+            &runtime,
             Some((&network_id, &hostname)),
         )
         .await?;
 
+    if let Err(e) = connect_networks(client, &id, &container.networks).await {
+        client.remove_container(&id).await?;
+        return Err(e);
+    }
+
     client.start_container(&id).await?;
 
     tracing::info!(%id, %name, %hostname, "created and started a container");
 
+    // Don't leave a container that never became healthy behind when failing the deploy
+    if let Err(e) = wait_until_healthy(client, &id, container.health_timeout).await {
+        client.remove_container(&id).await?;
+        return Err(e);
+    }
+
+    if let Some(command) = &container.post_start {
+        if let Err(e) = run_hook(client, &id, "post-start", command).await {
+            client.remove_container(&id).await?;
+            return Err(e);
+        }
+    }
+
     // Get the container itself and the port details
     let addr = client.get_container_ip(&id).await?;
 
@@ -75,6 +117,58 @@ pub async fn create_and_start_container<C: DockerClient>(
     Ok(StartedContainerDetails { id, addr })
 }
 
+/// Runs one of a service's lifecycle hooks inside a container, failing if the command exits
+/// unsuccessfully.
+pub async fn run_hook<C: DockerClient>(
+    client: &C,
+    id: &ContainerId,
+    hook: &str,
+    command: &[String],
+) -> Result<()> {
+    tracing::info!(%id, %hook, ?command, "running a lifecycle hook");
+
+    let exit_code = client.exec(id, command).await?;
+
+    if exit_code != 0 {
+        return Err(eyre!(
+            "The {hook} hook {command:?} exited with {exit_code} in container {id}"
+        ));
+    }
+
+    Ok(())
+}
+
+/// Waits for a container's health check to pass, returning immediately if its image doesn't define
+/// one.
+async fn wait_until_healthy<C: DockerClient>(
+    client: &C,
+    id: &ContainerId,
+    timeout: Duration,
+) -> Result<()> {
+    let deadline = Instant::now() + timeout;
+
+    loop {
+        match client.get_container_health(id).await? {
+            None | Some(HealthStatus::None | HealthStatus::Healthy) => return Ok(()),
+            Some(HealthStatus::Unhealthy) => {
+                return Err(eyre!("Container {id} failed its health check"));
+            }
+            Some(HealthStatus::Starting) => {}
+        }
+
+        if Instant::now() >= deadline {
+            return Err(eyre!(
+                "Container {id} did not become healthy within {}s",
+                timeout.as_secs()
+            ));
+        }
+
+        tracing::debug!(%id, "waiting for the container to become healthy");
+
+        tokio::time::sleep(HEALTH_POLL_INTERVAL).await;
+    }
+}
+
 /// Fetches the Docker network ID by its name, returning an error if it does not exist.
 async fn fetch_network_id<C: DockerClient>(client: &C) -> Result<NetworkId> {
     client
@@ -82,12 +176,92 @@ async fn fetch_network_id<C: DockerClient>(client: &C) -> Result<NetworkId> {
         .await?
         .ok_or_else(|| {
             eyre!(
-                "Docker network '{}' not found. Please create it before starting containers.",
+                "Docker network '{}' not found. Please create it before starting containers, or set `network` in the configuration for f2 to create it.",
                 DOCKER_NETWORK_NAME
             )
         })
 }
 
+/// Creates the network f2 routes to containers over if it doesn't exist yet and `config` allows
+/// it to, leaving an existing network untouched.
+pub async fn ensure_network<C: DockerClient>(
+    client: &C,
+    config: Option<&NetworkConfig>,
+) -> Result<()> {
+    if client
+        .get_network_by_name(DOCKER_NETWORK_NAME)
+        .await?
+        .is_some()
+    {
+        return Ok(());
+    }
+
+    match config {
+        Some(config) => {
+            tracing::info!(name = %DOCKER_NETWORK_NAME, ?config, "creating the missing network");
+
+            client.create_network(DOCKER_NETWORK_NAME, config).await?;
+        }
+        None => {
+            fetch_network_id(client).await?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Connects a container to each of the additional networks its service asks for.
+async fn connect_networks<C: DockerClient>(
+    client: &C,
+    id: &ContainerId,
+    networks: &HashMap<String, NetworkAttachment>,
+) -> Result<()> {
+    for (name, attachment) in networks {
+        let network_id = client
+            .get_network_by_name(name)
+            .await?
+            .ok_or_else(|| eyre!("Docker network '{name}' not found"))?;
+
+        client
+            .connect_network(&network_id, id, &attachment.aliases)
+            .await?;
+    }
+
+    Ok(())
+}
+
+/// Reads the service's seccomp profile into a security option, since Docker expects the profile
+/// itself rather than a path to it.
+async fn resolve_seccomp_profile(security: &SecurityOptions) -> Result<SecurityOptions> {
+    let mut resolved = security.clone();
+
+    if let Some(profile) = resolved.seccomp_profile.take() {
+        let bytes = profile.resolve().await?;
+        let profile = String::from_utf8(bytes).wrap_err("seccomp profile is not valid UTF-8")?;
+
+        resolved.security_opt.push(format!("seccomp={profile}"));
+    }
+
+    Ok(resolved)
+}
+
+/// Adds the labels f2 uses to identify containers to those configured for the service, replacing
+/// any configured labels with the same names.
+fn container_labels(
+    service: &str,
+    replica: usize,
+    container: &Container,
+    tag: &str,
+) -> HashMap<String, String> {
+    let mut labels = container.runtime.labels.clone();
+
+    labels.insert(SERVICE_LABEL.to_owned(), service.to_owned());
+    labels.insert(CONFIG_HASH_LABEL.to_owned(), container.config_hash(tag));
+    labels.insert(REPLICA_LABEL.to_owned(), replica.to_string());
+
+    labels
+}
+
 /// Generates a container name and hostname based on the image and tag.
 fn generate_hostname(image: &str) -> String {
     image
@@ -100,83 +274,142 @@ fn generate_hostname(image: &str) -> String {
         .to_string()
 }
 
-/// Formats the volumes for a container, resolving their content and writing it to a temporary file.
+/// Formats the volumes for a container, writing any content f2 resolves to a temporary file and
+/// leaving named volumes and tmpfs mounts for Docker to create.
 async fn format_volumes(
     image: &str,
     tag: &str,
     volumes: &HashMap<String, VolumeDefinition>,
     private_key: Option<&RsaPrivateKey>,
-) -> Result<HashMap<String, String>> {
-    let mut resolved_volumes = HashMap::new();
+) -> Result<Mounts> {
+    let mut mounts = Mounts::default();
 
     for (name, definition) in volumes {
         let span = tracing::info_span!("processing a volume definition", %name, ?definition);
         let _guard = span.enter();
 
-        // if the content is from S3, we need to write it to a temporary file
-        let path = match &definition.source {
-            ExternalBytes::S3 { .. } => {
-                let raw_content = definition.source.resolve().await?;
-                let content = decrypt_content(&raw_content, private_key)
-                    .wrap_err_with(|| format!("failed to decrypt content for volume '{name}'"))?;
-
-                tracing::info!(bytes = %content.len(), "decrypted content for volume");
-
-                // Ensure we're handling paths correctly regardless of trailing slashes
-                let clean_target = definition.target.trim_end_matches('/');
-                let target_filename = Path::new(clean_target)
-                    .file_name()
-                    .ok_or_else(|| eyre!("invalid target path: {}", definition.target))?;
-
-                // write the content to a temporary file
-                let directory: PathBuf = format!("/tmp/f2/{image}/{tag}/{name}").into();
-                let path = directory.join(target_filename);
+        let source = match &definition.source {
+            VolumeSource::External(source) => {
+                resolve_volume_content(image, tag, name, definition, source, private_key)
+                    .await?
+                    .to_string_lossy()
+                    .into_owned()
+            }
+            VolumeSource::Managed(ManagedVolume::Named { name: volume }) => {
+                // Docker treats anything that isn't an absolute path as the name of a volume
+                if volume.is_empty() || volume.contains('/') {
+                    return Err(eyre!("invalid name for volume '{name}': {volume}"));
+                }
 
-                // Ensure the directory exists and write the content
-                std::fs::create_dir_all(&directory)?;
-                std::fs::write(&path, content)?;
+                volume.clone()
+            }
+            VolumeSource::Managed(ManagedVolume::Tmpfs { size }) => {
+                let options = size
+                    .map(|size| format!("size={}", size.bytes()))
+                    .unwrap_or_default();
 
-                tracing::info!(?directory, ?path, "wrote volume content to temporary file");
+                mounts.tmpfs.insert(definition.target.clone(), options);
 
-                path
+                continue;
             }
-            ExternalBytes::Filesystem { path } => PathBuf::from(path),
         };
 
-        resolved_volumes.insert(
-            path.to_string_lossy().into_owned(),
-            definition.target.clone(),
-        );
+        mounts.binds.insert(source, definition.target.clone());
     }
 
-    Ok(resolved_volumes)
+    Ok(mounts)
+}
+
+/// Resolves the content of a volume to a path on the host, writing it to a temporary file if it
+/// isn't already on the filesystem.
+async fn resolve_volume_content(
+    image: &str,
+    tag: &str,
+    name: &str,
+    definition: &VolumeDefinition,
+    source: &ExternalBytes,
+    private_key: Option<&RsaPrivateKey>,
+) -> Result<PathBuf> {
+    // if the content is from S3, we need to write it to a temporary file
+    let path = match source {
+        ExternalBytes::S3 { .. } => {
+            let raw_content = source.resolve().await?;
+            let content = decrypt_content(&raw_content, private_key)
+                .wrap_err_with(|| format!("failed to decrypt content for volume '{name}'"))?;
+
+            tracing::info!(bytes = %content.len(), "decrypted content for volume");
+
+            // Ensure we're handling paths correctly regardless of trailing slashes
+            let clean_target = definition.target.trim_end_matches('/');
+            let target_filename = Path::new(clean_target)
+                .file_name()
+                .ok_or_else(|| eyre!("invalid target path: {}", definition.target))?;
+
+            // write the content to a temporary file
+            let directory: PathBuf = format!("/tmp/f2/{image}/{tag}/{name}").into();
+            let path = directory.join(target_filename);
+
+            // Ensure the directory exists and write the content
+            std::fs::create_dir_all(&directory)?;
+            std::fs::write(&path, content)?;
+
+            tracing::info!(?directory, ?path, "wrote volume content to temporary file");
+
+            path
+        }
+        ExternalBytes::Filesystem { path } => PathBuf::from(path),
+    };
+
+    Ok(path)
 }
 
-#[tracing::instrument(skip(client))]
+#[tracing::instrument(skip(client, private_key))]
 async fn pull_image_if_needed<C: DockerClient>(
     client: &C,
     container: &Container,
     tag: &str,
+    private_key: Option<&RsaPrivateKey>,
 ) -> Result<()> {
-    // Check whether we have the image locally
-    let expected_tag = format!("{}:{tag}", container.image);
+    let policy = container.pull_policy;
 
-    let local_images = client.fetch_images().await?;
+    if policy != PullPolicy::Always {
+        // Check whether we have the image locally
+        let expected_tag = format!("{}:{tag}", container.image);
 
-    // Find all the ones with matching tags
-    let exists = local_images
-        .iter()
-        .any(|image| image.repo_tags.contains(&expected_tag));
+        let local_images = client.fetch_images().await?;
 
-    if exists {
-        tracing::info!("image already exists locally");
-        return Ok(());
+        // Find all the ones with matching tags
+        let exists = local_images
+            .iter()
+            .any(|image| image.repo_tags.contains(&expected_tag));
+
+        if exists {
+            tracing::info!("image already exists locally");
+            return Ok(());
+        }
+
+        if policy == PullPolicy::Never {
+            return Err(eyre!(
+                "image {expected_tag} does not exist locally and the pull policy forbids pulling it"
+            ));
+        }
     }
 
-    tracing::info!("image does not exist locally, pulling from repository");
+    tracing::info!(?policy, "pulling image from repository");
+
+    let auth = match &container.registry_credentials {
+        Some(credentials) => Some(RegistryAuth {
+            username: credentials.username.clone(),
+            password: credentials.resolve_password(private_key).await?,
+            serveraddress: registry_host(&container.image).to_owned(),
+        }),
+        None => None,
+    };
 
     // Pull the image from the remote
-    client.pull_image(&container.image, tag).await?;
+    client
+        .pull_image(&container.image, tag, auth.as_ref())
+        .await?;
 
     tracing::info!("successfully pulled the image from the repository");
 
@@ -256,7 +489,387 @@ fn find_replaceable_segments(content: &str) -> Vec<Segment> {
 
 #[cfg(test)]
 mod tests {
-    use crate::docker::api::{find_replaceable_segments, generate_hostname, Segment};
+    use std::collections::HashMap;
+
+    use color_eyre::eyre::{eyre, Result};
+
+    use crate::common::{Container, SecurityOptions};
+    use crate::config::{
+        ExternalBytes, ManagedVolume, MemorySize, NetworkAttachment, NetworkConfig, PullPolicy,
+        RegistryCredentials, RegistryPassword, Service, VolumeDefinition, VolumeSource,
+    };
+    use crate::docker::api::{
+        create_and_start_container, ensure_network, find_replaceable_segments, format_volumes,
+        generate_hostname, pull_image_if_needed, resolve_seccomp_profile, Segment,
+        CONFIG_HASH_LABEL, REPLICA_LABEL, SERVICE_LABEL,
+    };
+    use crate::docker::client::{DockerClient, DOCKER_NETWORK_NAME};
+    use crate::docker::models::{HealthStatus, NetworkId};
+    use crate::reconciler::tests::FakeDockerClient;
+
+    const IMAGE: &str = "alexanderjackson/f2";
+    const TAG: &str = "latest";
+
+    fn container_with_policy(pull_policy: PullPolicy) -> Container {
+        Container::from(&Service {
+            image: IMAGE.to_owned(),
+            tag: TAG.to_owned(),
+            pull_policy,
+            ..Default::default()
+        })
+    }
+
+    async fn count_pulls(policy: PullPolicy, exists_locally: bool) -> Result<usize> {
+        let docker_client = FakeDockerClient::default();
+
+        if exists_locally {
+            docker_client.add_image(&format!("{IMAGE}:{TAG}")).await;
+        }
+
+        pull_image_if_needed(&docker_client, &container_with_policy(policy), TAG, None).await?;
+
+        Ok(docker_client.pull_count().await)
+    }
+
+    #[tokio::test]
+    async fn always_pull_policy_pulls_existing_images() -> Result<()> {
+        assert_eq!(count_pulls(PullPolicy::Always, true).await?, 1);
+        assert_eq!(count_pulls(PullPolicy::Always, false).await?, 1);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn if_not_present_pull_policy_only_pulls_missing_images() -> Result<()> {
+        assert_eq!(count_pulls(PullPolicy::IfNotPresent, true).await?, 0);
+        assert_eq!(count_pulls(PullPolicy::IfNotPresent, false).await?, 1);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn never_pull_policy_fails_for_missing_images() -> Result<()> {
+        assert_eq!(count_pulls(PullPolicy::Never, true).await?, 0);
+        assert!(count_pulls(PullPolicy::Never, false).await.is_err());
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn registry_credentials_are_sent_when_pulling() -> Result<()> {
+        let docker_client = FakeDockerClient::default();
+
+        let credentials = RegistryCredentials {
+            username: String::from("alexander-jackson"),
+            password: RegistryPassword::Value(String::from("hunter2")),
+        };
+
+        let registries = HashMap::from([(String::from("ghcr.io"), credentials)]);
+        let container = Container::from(&Service {
+            image: String::from("ghcr.io/alexander-jackson/f2"),
+            tag: TAG.to_owned(),
+            ..Default::default()
+        })
+        .with_registries(&registries);
+
+        pull_image_if_needed(&docker_client, &container, TAG, None).await?;
+
+        let auth = docker_client
+            .last_pull_auth()
+            .await
+            .ok_or_else(|| eyre!("expected the pull to be authenticated"))?;
+
+        assert_eq!(auth.username, "alexander-jackson");
+        assert_eq!(auth.password, "hunter2");
+        assert_eq!(auth.serveraddress, "ghcr.io");
+
+        // Images from other registries don't pick up the credentials
+        let container = container_with_policy(PullPolicy::Always).with_registries(&registries);
+        pull_image_if_needed(&docker_client, &container, TAG, None).await?;
+
+        assert_eq!(docker_client.last_pull_auth().await, None);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn containers_are_labelled_for_identification() -> Result<()> {
+        let docker_client = FakeDockerClient::default();
+        docker_client.add_image(&format!("{IMAGE}:{TAG}")).await;
+
+        let service = Service {
+            image: IMAGE.to_owned(),
+            tag: TAG.to_owned(),
+            labels: HashMap::from([
+                (String::from("team"), String::from("platform")),
+                (SERVICE_LABEL.to_owned(), String::from("impostor")),
+            ]),
+            ..Default::default()
+        };
+        let container = Container::from(&service);
+
+        let details =
+            create_and_start_container(&docker_client, "backend", 1, &container, TAG, None).await?;
+        let labels = docker_client.labels(&details.id).await;
+
+        assert_eq!(labels["team"], "platform");
+        assert_eq!(labels[SERVICE_LABEL], "backend");
+        assert_eq!(labels[REPLICA_LABEL], "1");
+        assert_eq!(labels[CONFIG_HASH_LABEL], container.config_hash(TAG));
+
+        Ok(())
+    }
+
+    async fn start_with_health(
+        statuses: Vec<HealthStatus>,
+        health_timeout_secs: Option<u64>,
+    ) -> (Result<()>, usize) {
+        let docker_client = FakeDockerClient::default();
+        docker_client.add_image(&format!("{IMAGE}:{TAG}")).await;
+        docker_client.set_health(statuses).await;
+
+        let container = Container::from(&Service {
+            image: IMAGE.to_owned(),
+            tag: TAG.to_owned(),
+            health_timeout_secs,
+            ..Default::default()
+        });
+
+        let result =
+            create_and_start_container(&docker_client, "backend", 0, &container, TAG, None)
+                .await
+                .map(|_| ());
+
+        (result, docker_client.container_count().await)
+    }
+
+    #[tokio::test]
+    async fn containers_are_only_started_once_healthy() {
+        let (result, count) =
+            start_with_health(vec![HealthStatus::Starting, HealthStatus::Healthy], None).await;
+
+        assert!(result.is_ok());
+        assert_eq!(count, 1);
+    }
+
+    #[tokio::test]
+    async fn unhealthy_containers_fail_the_deploy_and_are_removed() {
+        let (result, count) =
+            start_with_health(vec![HealthStatus::Starting, HealthStatus::Unhealthy], None).await;
+
+        assert!(result.is_err());
+        assert_eq!(count, 0);
+    }
+
+    #[tokio::test]
+    async fn containers_that_stay_starting_time_out() {
+        let statuses = vec![HealthStatus::Starting; 3];
+        let (result, count) = start_with_health(statuses, Some(0)).await;
+
+        assert!(result.is_err());
+        assert_eq!(count, 0);
+    }
+
+    #[tokio::test]
+    async fn containers_are_connected_to_additional_networks() -> Result<()> {
+        let docker_client = FakeDockerClient::default();
+        docker_client.add_image(&format!("{IMAGE}:{TAG}")).await;
+
+        let attachment = NetworkAttachment {
+            aliases: vec![String::from("backend")],
+        };
+
+        let container = Container::from(&Service {
+            image: IMAGE.to_owned(),
+            tag: TAG.to_owned(),
+            networks: HashMap::from([(String::from("monitoring"), attachment)]),
+            ..Default::default()
+        });
+
+        let details =
+            create_and_start_container(&docker_client, "backend", 0, &container, TAG, None).await?;
+
+        assert_eq!(
+            docker_client.connections(&details.id).await,
+            vec![(
+                NetworkId(String::from("mesh")),
+                vec![String::from("backend")]
+            )]
+        );
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn docker_volumes_are_left_for_docker_to_create() -> Result<()> {
+        let volume = |source, target: &str| VolumeDefinition {
+            source: VolumeSource::Managed(source),
+            target: target.to_owned(),
+        };
+
+        let volumes = HashMap::from([
+            (
+                String::from("data"),
+                volume(
+                    ManagedVolume::Named {
+                        name: String::from("backend-data"),
+                    },
+                    "/var/lib/backend",
+                ),
+            ),
+            (
+                String::from("scratch"),
+                volume(
+                    ManagedVolume::Tmpfs {
+                        size: Some(MemorySize::try_from(1 << 20)?),
+                    },
+                    "/tmp",
+                ),
+            ),
+            (
+                String::from("cache"),
+                volume(ManagedVolume::Tmpfs { size: None }, "/cache"),
+            ),
+        ]);
+
+        let mounts = format_volumes(IMAGE, TAG, &volumes, None).await?;
+
+        assert_eq!(
+            mounts.binds,
+            HashMap::from([(
+                String::from("backend-data"),
+                String::from("/var/lib/backend")
+            )])
+        );
+        assert_eq!(
+            mounts.tmpfs,
+            HashMap::from([
+                (String::from("/tmp"), String::from("size=1048576")),
+                (String::from("/cache"), String::new()),
+            ])
+        );
+
+        let invalid = HashMap::from([(
+            String::from("data"),
+            volume(
+                ManagedVolume::Named {
+                    name: String::from("/var/lib/backend"),
+                },
+                "/data",
+            ),
+        )]);
+
+        assert!(format_volumes(IMAGE, TAG, &invalid, None).await.is_err());
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn missing_networks_are_only_created_if_configured() -> Result<()> {
+        let docker_client = FakeDockerClient::default();
+
+        ensure_network(&docker_client, Some(&NetworkConfig::default())).await?;
+        assert!(docker_client.created_networks().await.is_empty());
+
+        docker_client.remove_network(DOCKER_NETWORK_NAME).await;
+        assert!(ensure_network(&docker_client, None).await.is_err());
+
+        let config = NetworkConfig {
+            subnet: Some(String::from("172.28.0.0/16")),
+            ..Default::default()
+        };
+
+        ensure_network(&docker_client, Some(&config)).await?;
+
+        assert_eq!(
+            docker_client.created_networks().await,
+            vec![(DOCKER_NETWORK_NAME.to_owned(), config)]
+        );
+        assert!(docker_client
+            .get_network_by_name(DOCKER_NETWORK_NAME)
+            .await?
+            .is_some());
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn failing_post_start_hooks_fail_the_deploy() -> Result<()> {
+        let docker_client = FakeDockerClient::default();
+        docker_client.add_image(&format!("{IMAGE}:{TAG}")).await;
+
+        let container = Container::from(&Service {
+            image: IMAGE.to_owned(),
+            tag: TAG.to_owned(),
+            post_start: Some(vec![String::from("/bin/warm-cache")]),
+            ..Default::default()
+        });
+
+        let details =
+            create_and_start_container(&docker_client, "backend", 0, &container, TAG, None).await?;
+
+        assert_eq!(
+            docker_client.execs().await,
+            vec![(details.id, vec![String::from("/bin/warm-cache")])]
+        );
+
+        docker_client.set_exec_exit_code(1).await;
+
+        let result =
+            create_and_start_container(&docker_client, "backend", 1, &container, TAG, None).await;
+
+        assert!(result.is_err());
+        assert_eq!(docker_client.container_count().await, 1);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn seccomp_profiles_are_passed_as_security_options() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("seccomp.json");
+
+        std::fs::write(&path, r#"{"defaultAction":"SCMP_ACT_ERRNO"}"#)?;
+
+        let security = SecurityOptions {
+            security_opt: vec![String::from("no-new-privileges")],
+            seccomp_profile: Some(ExternalBytes::Filesystem { path }),
+            ..Default::default()
+        };
+
+        let resolved = resolve_seccomp_profile(&security).await?;
+
+        assert_eq!(
+            resolved.security_opt,
+            vec![
+                String::from("no-new-privileges"),
+                String::from(r#"seccomp={"defaultAction":"SCMP_ACT_ERRNO"}"#),
+            ]
+        );
+        assert!(resolved.seccomp_profile.is_none());
+
+        Ok(())
+    }
+
+    #[test]
+    fn config_hashes_change_with_the_definition() {
+        let container = container_with_policy(PullPolicy::IfNotPresent);
+        let hash = container.config_hash(TAG);
+
+        let mut relabelled = container.clone();
+        relabelled
+            .runtime
+            .labels
+            .insert(String::from("team"), String::from("platform"));
+
+        let mut worker = container.clone();
+        worker.runtime.command = Some(vec![String::from("worker")]);
+
+        assert_eq!(hash, container.clone().config_hash(TAG));
+        assert_ne!(hash, container.config_hash("v2"));
+        assert_ne!(hash, relabelled.config_hash(TAG));
+        assert_ne!(hash, worker.config_hash(TAG));
+    }
 
     #[test]
     fn can_find_replaceable_content_correctly() {
@@ -1,98 +1,407 @@
 use std::collections::HashMap;
-use std::net::Ipv4Addr;
+use std::net::IpAddr;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
 
+use base64::engine::general_purpose::URL_SAFE;
+use base64::Engine;
 use color_eyre::eyre::{self, eyre, Context, Result};
 use color_eyre::Section;
+use futures::StreamExt;
 use http::Response;
 use http_body_util::{BodyExt, Full};
 use hyper::body::{Bytes, Incoming};
 use hyper::{Method, Request, Uri};
-use hyper_util::client::legacy::Client as HyperClient;
+use hyper_rustls::{HttpsConnector, HttpsConnectorBuilder};
+use hyper_util::client::legacy::connect::HttpConnector;
+use hyper_util::client::legacy::{Client as HyperClient, ResponseFuture};
+use hyper_util::rt::TokioExecutor;
 use hyperlocal::{UnixClientExt, UnixConnector};
+use rand::rngs::SmallRng;
+use rand::SeedableRng;
+use rustls::crypto::ring::default_provider;
+use rustls::{ClientConfig, RootCertStore};
 use serde::de::DeserializeOwned;
 
-use crate::common::Environment;
+use crate::common::{Environment, Mounts, RuntimeOptions};
+use crate::config::{
+    CpuLimit, DockerRetryConfig, MemorySize, NetworkConfig, PublishedPort, RestartPolicy,
+};
+use crate::docker::events::{decode_events, events_query, EventStream};
+use crate::docker::logs::{demultiplex, LogStream};
 use crate::docker::models::{
-    CreateContainerOptions, CreateContainerResponse, EndpointConfig, HostConfig, ImageSummary,
-    InspectContainerResponse, Network, NetworkId, NetworkingConfig,
+    ConnectNetworkOptions, CreateContainerOptions, CreateContainerResponse, CreateExecOptions,
+    CreateExecResponse, CreateNetworkOptions, CreateNetworkResponse, EndpointConfig, HealthStatus,
+    HostConfig, ImageSummary, InspectContainerResponse, InspectExecResponse, Ipam, IpamConfig,
+    Network, NetworkId, NetworkingConfig, PortBinding, PruneContainersResponse,
+    PruneImagesResponse, PruneReport, RegistryAuth, RestartPolicyOptions, UlimitOptions,
 };
+use crate::docker::retry::{backoff, is_transient_status};
+use crate::docker::tls;
+use crate::docker::version::{self, ApiVersion, VersionResponse, PRUNE_UNTIL_API_VERSION};
 
 use super::models::ContainerId;
 
 pub const DOCKER_NETWORK_NAME: &str = "internal";
 
+/// The environment variable Docker's own tools read the daemon's address from.
+pub const DOCKER_HOST: &str = "DOCKER_HOST";
+
+const X_REGISTRY_AUTH: &str = "X-Registry-Auth";
+
+const DEFAULT_SOCKET_PATH: &str = "/var/run/docker.sock";
+
 #[async_trait::async_trait]
 pub trait DockerClient {
     async fn fetch_images(&self) -> Result<Vec<ImageSummary>>;
-    async fn pull_image(&self, image: &str, tag: &str) -> Result<()>;
+    async fn pull_image(&self, image: &str, tag: &str, auth: Option<&RegistryAuth>) -> Result<()>;
 
     async fn get_network_by_name(&self, name: &str) -> Result<Option<NetworkId>>;
 
+    /// Creates a network called `name` with the given driver and addressing.
+    async fn create_network(&self, name: &str, config: &NetworkConfig) -> Result<NetworkId>;
+
     async fn create_container(
         &self,
         image: &str,
         environment: &Option<Environment>,
-        docker_volumes: &HashMap<String, String>,
+        mounts: &Mounts,
+        runtime: &RuntimeOptions,
         network: Option<(&NetworkId, &str)>,
     ) -> Result<ContainerId>;
 
+    /// Connects a container to another network, where it can also be reached by `aliases`.
+    async fn connect_network(
+        &self,
+        network: &NetworkId,
+        id: &ContainerId,
+        aliases: &[String],
+    ) -> Result<()>;
+
     async fn start_container(&self, id: &ContainerId) -> Result<()>;
 
-    async fn get_container_ip(&self, id: &ContainerId) -> Result<Ipv4Addr>;
+    async fn get_container_ip(&self, id: &ContainerId) -> Result<IpAddr>;
+
+    /// Fetches the status of the container's health check, if its image defines one.
+    async fn get_container_health(&self, id: &ContainerId) -> Result<Option<HealthStatus>>;
+
+    /// Fetches how many times the daemon has restarted the container under its restart policy.
+    async fn get_restart_count(&self, id: &ContainerId) -> Result<u32>;
 
     async fn stop_container(&self, id: &ContainerId) -> Result<()>;
 
     async fn remove_container(&self, id: &ContainerId) -> Result<()>;
+
+    /// Streams what the container has written to stdout and stderr, continuing with anything it
+    /// writes afterwards if `follow` is set.
+    async fn stream_logs(&self, id: &ContainerId, follow: bool) -> Result<LogStream>;
+
+    /// Runs a command inside a running container, waiting for it to finish and returning its exit
+    /// code.
+    async fn exec(&self, id: &ContainerId, command: &[String]) -> Result<i64>;
+
+    /// Removes stopped containers carrying `label` that were created more than `older_than` ago.
+    async fn prune_containers(&self, label: &str, older_than: Duration) -> Result<PruneReport>;
+
+    /// Removes untagged images that were created more than `older_than` ago.
+    async fn prune_images(&self, older_than: Duration) -> Result<PruneReport>;
+
+    /// Streams the containers f2 manages dying, running out of memory or being started, from now
+    /// until the connection to the daemon is lost.
+    async fn stream_events(&self) -> Result<EventStream>;
 }
 
+/// How requests reach the daemon, which is usually over a local socket.
+#[derive(Clone)]
+enum Transport {
+    Unix {
+        client: HyperClient<UnixConnector, Full<Bytes>>,
+        socket: String,
+    },
+    Tcp {
+        client: HyperClient<HttpsConnector<HttpConnector>, Full<Bytes>>,
+        base: String,
+    },
+}
+
+#[derive(Clone)]
 pub struct Client {
-    client: HyperClient<UnixConnector, Full<Bytes>>,
-    base: String,
+    transport: Transport,
+    /// The version of the API requests are made against, once agreed with the daemon.
+    api_version: Option<ApiVersion>,
+    retries: DockerRetryConfig,
+    /// Jitters the backoff between retries, shared between clones of the client.
+    rng: Arc<Mutex<SmallRng>>,
 }
 
 impl Default for Client {
     fn default() -> Self {
-        let base = String::from("/var/run/docker.sock");
+        Self::new(DEFAULT_SOCKET_PATH)
+    }
+}
 
-        tracing::debug!(%base, "created a new Docker client");
+impl Client {
+    /// Connects to the daemon listening on the Unix socket at `path`.
+    pub fn new(path: &str) -> Self {
+        let socket = String::from(path);
+
+        tracing::debug!(%socket, "created a new Docker client");
 
         Self {
-            client: HyperClient::unix(),
-            base,
+            transport: Transport::Unix {
+                client: HyperClient::unix(),
+                socket,
+            },
+            api_version: None,
+            retries: DockerRetryConfig::default(),
+            rng: Arc::new(Mutex::new(SmallRng::from_entropy())),
         }
     }
-}
 
-impl Client {
-    fn build_uri(&self, endpoint: &str) -> Uri {
-        hyperlocal::Uri::new(&self.base, endpoint).into()
+    /// Connects to the daemon at `host`, given in the same form as `DOCKER_HOST` such as
+    /// `unix:///run/user/1000/docker.sock` or `tcp://docker.example.com:2376`, or as a plain path
+    /// to the socket.
+    ///
+    /// Daemons reached over TCP are connected to with TLS if `cert_path` is given, presenting the
+    /// client certificate found there.
+    pub fn from_host(host: &str, cert_path: Option<&Path>) -> Result<Self> {
+        let addr = match host.split_once("://") {
+            Some(("unix", path)) => return Ok(Self::new(path)),
+            Some(("tcp", addr)) => addr,
+            Some((scheme, _)) => {
+                return Err(eyre!("unsupported scheme {scheme} for Docker host {host}"))
+            }
+            None => return Ok(Self::new(host)),
+        };
+
+        let (config, scheme) = match cert_path {
+            Some(cert_path) => (tls::client_config(cert_path)?, "https"),
+            None => {
+                let roots = RootCertStore {
+                    roots: webpki_roots::TLS_SERVER_ROOTS.to_vec(),
+                };
+
+                let config = ClientConfig::builder_with_provider(Arc::new(default_provider()))
+                    .with_safe_default_protocol_versions()?
+                    .with_root_certificates(roots)
+                    .with_no_client_auth();
+
+                (config, "http")
+            }
+        };
+
+        let connector = HttpsConnectorBuilder::new()
+            .with_tls_config(config)
+            .https_or_http()
+            .enable_http1()
+            .build();
+
+        let base = format!("{scheme}://{addr}");
+
+        tracing::debug!(%base, "created a new Docker client");
+
+        Ok(Self {
+            transport: Transport::Tcp {
+                client: HyperClient::builder(TokioExecutor::new()).build(connector),
+                base,
+            },
+            api_version: None,
+            retries: DockerRetryConfig::default(),
+            rng: Arc::new(Mutex::new(SmallRng::from_entropy())),
+        })
+    }
+
+    /// Retries requests that fail because the daemon is briefly unavailable according to `retries`.
+    pub fn with_retries(mut self, retries: DockerRetryConfig) -> Self {
+        self.retries = retries;
+        self
+    }
+
+    /// Asks the daemon which versions of the API it supports and pins all further requests to the
+    /// newest one f2 also understands, failing if there isn't one.
+    pub async fn negotiate_version(&mut self) -> Result<ApiVersion> {
+        // Always ask without a version prefix, since we don't know which ones are valid yet
+        self.api_version = None;
+
+        let uri = self.build_uri("/version")?;
+        let response = self.get(uri).await?;
+        let daemon: VersionResponse = deserialize_body(response)
+            .await
+            .wrap_err("failed to fetch the version of the Docker daemon")?;
+
+        let api_version = version::negotiate(&daemon)?;
+
+        tracing::info!(version = %daemon.version, %api_version, "negotiated the Docker API version");
+
+        self.api_version = Some(api_version);
+
+        Ok(api_version)
+    }
+
+    /// Fails with an explanation if the daemon is too old to support `feature`, which was added in
+    /// the `minimum` version of the API.
+    fn require_api_version(&self, feature: &str, minimum: ApiVersion) -> Result<()> {
+        match self.api_version {
+            Some(api_version) if api_version < minimum => Err(eyre!(
+                "{feature} requires Docker API version {minimum}, but the daemon only supports {api_version}"
+            )
+            .suggestion("upgrade Docker or disable the feature in the configuration")),
+            _ => Ok(()),
+        }
+    }
+
+    fn build_uri(&self, endpoint: &str) -> Result<Uri> {
+        let endpoint = match self.api_version {
+            Some(api_version) => format!("/v{api_version}{endpoint}"),
+            None => endpoint.to_owned(),
+        };
+
+        let uri: Uri = match &self.transport {
+            Transport::Unix { socket, .. } => hyperlocal::Uri::new(socket, &endpoint).into(),
+            Transport::Tcp { base, .. } => format!("{base}{endpoint}").parse()?,
+        };
+
+        Ok(uri)
+    }
+
+    fn send(&self, request: Request<Full<Bytes>>) -> ResponseFuture {
+        match &self.transport {
+            Transport::Unix { client, .. } => client.request(request),
+            Transport::Tcp { client, .. } => client.request(request),
+        }
+    }
+
+    /// Sends a request to the daemon, sending it again after a backoff if the daemon couldn't be
+    /// reached, up to the configured number of attempts.
+    ///
+    /// Requests that only read from the daemon are also sent again if it responded with a transient
+    /// error, since others may have taken effect before it failed.
+    async fn request(&self, request: Request<Full<Bytes>>) -> Result<Response<Incoming>> {
+        let (parts, body) = request.into_parts();
+        let idempotent = matches!(parts.method, Method::GET | Method::HEAD);
+        let mut attempt = 0;
+
+        loop {
+            let mut request = Request::new(body.clone());
+            *request.method_mut() = parts.method.clone();
+            *request.uri_mut() = parts.uri.clone();
+            *request.headers_mut() = parts.headers.clone();
+
+            let result = self.send(request).await;
+            attempt += 1;
+
+            let exhausted = attempt >= self.retries.max_attempts;
+
+            match result {
+                Ok(response)
+                    if exhausted || !idempotent || !is_transient_status(response.status()) =>
+                {
+                    return Ok(response)
+                }
+                Err(e) if exhausted || !e.is_connect() => return Err(e.into()),
+                Ok(response) => {
+                    tracing::warn!(
+                        uri = %parts.uri,
+                        status = %response.status(),
+                        %attempt,
+                        "the Docker daemon responded with a transient error"
+                    );
+                }
+                Err(e) => {
+                    tracing::warn!(
+                        uri = %parts.uri,
+                        error = ?e,
+                        %attempt,
+                        "failed to connect to the Docker daemon"
+                    );
+                }
+            }
+
+            let delay = backoff(&self.retries, attempt - 1, &mut *self.rng.lock().unwrap());
+            tokio::time::sleep(delay).await;
+        }
+    }
+
+    async fn get(&self, uri: Uri) -> Result<Response<Incoming>> {
+        let request = Request::builder()
+            .uri(uri)
+            .method(Method::GET)
+            .body(Full::default())?;
+
+        self.request(request).await
+    }
+
+    async fn prune<T: DeserializeOwned>(&self, path: &str) -> Result<T> {
+        self.require_api_version("Garbage collection", PRUNE_UNTIL_API_VERSION)?;
+
+        let uri = self.build_uri(path)?;
+
+        tracing::info!(%uri, "pruning unused resources");
+
+        let request = Request::builder()
+            .uri(uri)
+            .method(Method::POST)
+            .body(Full::default())?;
+
+        let response = self.request(request).await?;
+
+        eyre::ensure!(
+            response.status().is_success(),
+            "Failed to prune using {path}, got {}",
+            response.status(),
+        );
+
+        deserialize_body(response).await
+    }
+
+    async fn inspect_container(&self, id: &ContainerId) -> Result<InspectContainerResponse> {
+        let path = format!("/containers/{id}/json");
+        let uri = self.build_uri(&path)?;
+
+        let request = Request::builder()
+            .uri(uri)
+            .method(Method::GET)
+            .body(Full::default())?;
+
+        let response = self.request(request).await?;
+        let payload = deserialize_body(response)
+            .await
+            .wrap_err_with(|| format!("failed to inspect container {id}"))
+            .suggestion("Does the container exist?")?;
+
+        Ok(payload)
     }
 }
 
 #[async_trait::async_trait]
 impl DockerClient for Client {
     async fn fetch_images(&self) -> Result<Vec<ImageSummary>> {
-        let uri = self.build_uri("/images/json");
+        let uri = self.build_uri("/images/json")?;
 
         tracing::info!(%uri, "Fetching images from the Docker server");
 
-        let response = self.client.get(uri).await?;
+        let response = self.get(uri).await?;
 
         Ok(deserialize_body(response).await?)
     }
 
-    async fn pull_image(&self, image: &str, tag: &str) -> Result<()> {
+    async fn pull_image(&self, image: &str, tag: &str, auth: Option<&RegistryAuth>) -> Result<()> {
         let path_and_query = format!("/images/create?fromImage={image}:{tag}");
-        let uri = self.build_uri(&path_and_query);
+        let uri = self.build_uri(&path_and_query)?;
 
-        tracing::info!(%image, %tag, "Pulling an image from the Docker registry");
+        tracing::info!(%image, %tag, ?auth, "Pulling an image from the Docker registry");
 
-        let request = Request::builder()
-            .uri(uri)
-            .method(Method::POST)
-            .body(Full::default())?;
+        let mut request = Request::builder().uri(uri).method(Method::POST);
 
-        let response = self.client.request(request).await?;
+        if let Some(auth) = auth {
+            let encoded = URL_SAFE.encode(serde_json::to_vec(auth)?);
+            request = request.header(X_REGISTRY_AUTH, encoded);
+        }
+
+        let request = request.body(Full::default())?;
+
+        let response = self.request(request).await?;
 
         // Check the image actually exists on the remote
         eyre::ensure!(
@@ -108,11 +417,11 @@ impl DockerClient for Client {
 
     #[tracing::instrument(skip(self))]
     async fn get_network_by_name(&self, name: &str) -> Result<Option<NetworkId>> {
-        let uri = self.build_uri("/networks");
+        let uri = self.build_uri("/networks")?;
 
         tracing::info!(%name, "Searching for network by name");
 
-        let response = self.client.get(uri).await?;
+        let response = self.get(uri).await?;
         let networks: Vec<Network> = deserialize_body(response).await?;
 
         let network = networks.iter().find(|n| n.name == name);
@@ -120,23 +429,84 @@ impl DockerClient for Client {
         Ok(network.map(|n| NetworkId(n.id.clone())))
     }
 
+    async fn create_network(&self, name: &str, config: &NetworkConfig) -> Result<NetworkId> {
+        let uri = self.build_uri("/networks/create")?;
+
+        let ipv4 = config.subnet.as_deref().map(|subnet| IpamConfig {
+            subnet,
+            gateway: config.gateway.as_deref(),
+        });
+
+        let ipv6 = config.ipv6_subnet.as_deref().map(|subnet| IpamConfig {
+            subnet,
+            gateway: None,
+        });
+
+        let options = CreateNetworkOptions {
+            name,
+            driver: &config.driver,
+            check_duplicate: true,
+            enable_ipv6: ipv6.is_some(),
+            ipam: Ipam {
+                config: ipv4.into_iter().chain(ipv6).collect(),
+            },
+        };
+
+        let request = Request::builder()
+            .uri(uri)
+            .method(Method::POST)
+            .header(hyper::http::header::CONTENT_TYPE, "application/json")
+            .body(Full::new(Bytes::from(serde_json::to_vec(&options)?)))?;
+
+        let response = self.request(request).await?;
+        let network: CreateNetworkResponse = deserialize_body(response)
+            .await
+            .wrap_err_with(|| format!("failed to create the {name} network"))?;
+
+        tracing::info!(%name, id = %network.id, driver = %config.driver, "created a network");
+
+        Ok(network.id)
+    }
+
     #[tracing::instrument(skip(self, environment))]
     async fn create_container(
         &self,
         image: &str,
         environment: &Option<Environment>,
-        docker_volumes: &HashMap<String, String>,
+        mounts: &Mounts,
+        runtime: &RuntimeOptions,
         network: Option<(&NetworkId, &str)>,
     ) -> Result<ContainerId> {
-        let uri = self.build_uri("/containers/create");
+        let uri = self.build_uri("/containers/create")?;
 
         let env = format_environment_variables(environment);
+        let resource_limits = &runtime.resource_limits;
 
         let host_config = HostConfig {
-            binds: docker_volumes
+            binds: mounts
+                .binds
+                .iter()
+                .map(|(source, container_path)| format!("{source}:{container_path}"))
+                .collect(),
+            tmpfs: mounts.tmpfs.clone(),
+            port_bindings: port_bindings(&runtime.published_ports),
+            ulimits: runtime
+                .ulimits
                 .iter()
-                .map(|(host_path, container_path)| format!("{host_path}:{container_path}"))
+                .map(|(name, limit)| UlimitOptions {
+                    name: name.clone(),
+                    soft: limit.soft,
+                    hard: limit.hard,
+                })
                 .collect(),
+            sysctls: &runtime.sysctls,
+            readonly_rootfs: runtime.security.read_only,
+            security_opt: &runtime.security.security_opt,
+            privileged: runtime.security.privileged,
+            nano_cpus: resource_limits.cpu_limit.map(CpuLimit::nano_cpus),
+            memory: resource_limits.memory_limit.map(MemorySize::bytes),
+            memory_reservation: resource_limits.memory_reservation.map(MemorySize::bytes),
+            restart_policy: restart_policy_options(runtime.restart_policy),
         };
 
         tracing::info!(?host_config, "creating a container");
@@ -159,7 +529,15 @@ impl DockerClient for Client {
         let options = CreateContainerOptions {
             image: String::from(image),
             env,
+            cmd: runtime.command.as_deref(),
+            entrypoint: runtime.entrypoint.as_deref(),
+            labels: &runtime.labels,
             volumes: &HashMap::new(),
+            exposed_ports: runtime
+                .published_ports
+                .iter()
+                .map(|port| (container_port_key(port), HashMap::new()))
+                .collect(),
             host_config,
             networking_config,
         };
@@ -172,7 +550,7 @@ impl DockerClient for Client {
             .header(hyper::http::header::CONTENT_TYPE, "application/json")
             .body(Full::new(Bytes::from(body)))?;
 
-        let response = self.client.request(request).await?;
+        let response = self.request(request).await?;
         let body: CreateContainerResponse = deserialize_body(response)
             .await
             .wrap_err_with(|| format!("failed to create container with image {image}"))?;
@@ -182,9 +560,43 @@ impl DockerClient for Client {
         Ok(body.id)
     }
 
+    async fn connect_network(
+        &self,
+        network: &NetworkId,
+        id: &ContainerId,
+        aliases: &[String],
+    ) -> Result<()> {
+        let path = format!("/networks/{network}/connect");
+        let uri = self.build_uri(&path)?;
+
+        tracing::info!(%id, %network, ?aliases, "connecting a container to a network");
+
+        let options = ConnectNetworkOptions {
+            container: id,
+            endpoint_config: EndpointConfig {
+                aliases: (!aliases.is_empty()).then(|| aliases.to_vec()),
+            },
+        };
+
+        let request = Request::builder()
+            .uri(uri)
+            .method(Method::POST)
+            .header(hyper::http::header::CONTENT_TYPE, "application/json")
+            .body(Full::new(Bytes::from(serde_json::to_vec(&options)?)))?;
+
+        let response = self.request(request).await?;
+
+        eyre::ensure!(
+            response.status().is_success(),
+            "Failed to connect container {id} to network {network}",
+        );
+
+        Ok(())
+    }
+
     async fn start_container(&self, id: &ContainerId) -> Result<()> {
         let path = format!("/containers/{id}/start");
-        let uri = self.build_uri(&path);
+        let uri = self.build_uri(&path)?;
 
         tracing::info!(?id, "starting a container");
 
@@ -193,43 +605,44 @@ impl DockerClient for Client {
             .method(Method::POST)
             .body(Full::default())?;
 
-        self.client.request(request).await?;
+        self.request(request).await?;
 
         Ok(())
     }
 
-    async fn get_container_ip(&self, id: &ContainerId) -> Result<Ipv4Addr> {
-        let path = format!("/containers/{id}/json");
-        let uri = self.build_uri(&path);
-
+    async fn get_container_ip(&self, id: &ContainerId) -> Result<IpAddr> {
         tracing::info!(?id, "fetching exposed ports for a container");
 
-        let request = Request::builder()
-            .uri(uri)
-            .method(Method::GET)
-            .body(Full::default())?;
+        let payload = self.inspect_container(id).await?;
 
-        let response = self.client.request(request).await?;
-        let payload: InspectContainerResponse = deserialize_body(response)
-            .await
-            .wrap_err_with(|| format!("failed to inspect container {id}"))
-            .suggestion("Does the container exist?")?;
-
-        let ip_address = payload
+        let network = payload
             .network_settings
             .networks
             .get(DOCKER_NETWORK_NAME)
-            .map(|network| network.ip_address)
             .ok_or_else(|| {
                 eyre!("Container {id} is not connected to the {DOCKER_NETWORK_NAME} network")
             })?;
 
-        Ok(ip_address)
+        network.addr().ok_or_else(|| {
+            eyre!("Container {id} has no address on the {DOCKER_NETWORK_NAME} network")
+        })
+    }
+
+    async fn get_container_health(&self, id: &ContainerId) -> Result<Option<HealthStatus>> {
+        let payload = self.inspect_container(id).await?;
+
+        Ok(payload.state.health.map(|health| health.status))
+    }
+
+    async fn get_restart_count(&self, id: &ContainerId) -> Result<u32> {
+        let payload = self.inspect_container(id).await?;
+
+        Ok(payload.restart_count)
     }
 
     async fn stop_container(&self, id: &ContainerId) -> Result<()> {
         let path = format!("/containers/{id}/stop?signal=SIGTERM&t=15");
-        let uri = self.build_uri(&path);
+        let uri = self.build_uri(&path)?;
 
         tracing::info!(%id, "stopping a container");
 
@@ -238,14 +651,14 @@ impl DockerClient for Client {
             .method(Method::POST)
             .body(Full::default())?;
 
-        self.client.request(request).await?;
+        self.request(request).await?;
 
         Ok(())
     }
 
     async fn remove_container(&self, id: &ContainerId) -> Result<()> {
         let path = format!("/containers/{id}?force=true");
-        let uri = self.build_uri(&path);
+        let uri = self.build_uri(&path)?;
 
         tracing::info!(%id, "removing a container forcefully");
 
@@ -254,10 +667,179 @@ impl DockerClient for Client {
             .method(Method::DELETE)
             .body(Full::default())?;
 
-        self.client.request(request).await?;
+        self.request(request).await?;
 
         Ok(())
     }
+
+    async fn stream_logs(&self, id: &ContainerId, follow: bool) -> Result<LogStream> {
+        let path = format!("/containers/{id}/logs?stdout=true&stderr=true&follow={follow}");
+        let uri = self.build_uri(&path)?;
+
+        tracing::info!(%id, %follow, "streaming logs for a container");
+
+        let request = Request::builder()
+            .uri(uri)
+            .method(Method::GET)
+            .body(Full::default())?;
+
+        let response = self.request(request).await?;
+
+        eyre::ensure!(
+            response.status().is_success(),
+            "Failed to fetch logs for container {id}, it may not exist",
+        );
+
+        Ok(demultiplex(response.into_body().into_data_stream()))
+    }
+
+    async fn exec(&self, id: &ContainerId, command: &[String]) -> Result<i64> {
+        let uri = self.build_uri(&format!("/containers/{id}/exec"))?;
+
+        let options = CreateExecOptions {
+            cmd: command,
+            attach_stdout: true,
+            attach_stderr: true,
+        };
+
+        let request = Request::builder()
+            .uri(uri)
+            .method(Method::POST)
+            .header(hyper::http::header::CONTENT_TYPE, "application/json")
+            .body(Full::new(Bytes::from(serde_json::to_vec(&options)?)))?;
+
+        let response = self.request(request).await?;
+        let exec: CreateExecResponse = deserialize_body(response)
+            .await
+            .wrap_err_with(|| format!("failed to create exec in container {id}"))?;
+
+        // Starting the exec without detaching streams its output until the command exits
+        let uri = self.build_uri(&format!("/exec/{}/start", exec.id))?;
+
+        let request = Request::builder()
+            .uri(uri)
+            .method(Method::POST)
+            .header(hyper::http::header::CONTENT_TYPE, "application/json")
+            .body(Full::new(Bytes::from_static(br#"{"Detach":false}"#)))?;
+
+        let response = self.request(request).await?;
+        let mut output = demultiplex(response.into_body().into_data_stream());
+
+        while let Some(frame) = output.next().await {
+            let frame = frame?;
+            let line = String::from_utf8_lossy(&frame.data);
+
+            tracing::info!(%id, stream = %frame.stream, output = %line.trim_end(), "exec output");
+        }
+
+        let uri = self.build_uri(&format!("/exec/{}/json", exec.id))?;
+        let response = self.get(uri).await?;
+        let inspected: InspectExecResponse = deserialize_body(response).await?;
+
+        match inspected.exit_code {
+            Some(exit_code) if !inspected.running => Ok(exit_code),
+            _ => Err(eyre!(
+                "command {command:?} in container {id} did not finish"
+            )),
+        }
+    }
+
+    async fn prune_containers(&self, label: &str, older_than: Duration) -> Result<PruneReport> {
+        let filters = serde_json::json!({
+            "label": [label],
+            "until": [format!("{}s", older_than.as_secs())],
+        });
+
+        let path = format!("/containers/prune?{}", filters_query(&filters));
+        let response: PruneContainersResponse = self.prune(&path).await?;
+
+        Ok(PruneReport {
+            deleted: response.containers_deleted.unwrap_or_default().len(),
+            space_reclaimed: response.space_reclaimed,
+        })
+    }
+
+    async fn prune_images(&self, older_than: Duration) -> Result<PruneReport> {
+        let filters = serde_json::json!({
+            "dangling": ["true"],
+            "until": [format!("{}s", older_than.as_secs())],
+        });
+
+        let path = format!("/images/prune?{}", filters_query(&filters));
+        let response: PruneImagesResponse = self.prune(&path).await?;
+
+        Ok(PruneReport {
+            deleted: response.images_deleted.unwrap_or_default().len(),
+            space_reclaimed: response.space_reclaimed,
+        })
+    }
+
+    async fn stream_events(&self) -> Result<EventStream> {
+        let path = format!("/events?{}", events_query());
+        let uri = self.build_uri(&path)?;
+
+        tracing::info!("subscribing to container events");
+
+        let response = self.get(uri).await?;
+
+        eyre::ensure!(
+            response.status().is_success(),
+            "Failed to subscribe to container events, got {}",
+            response.status(),
+        );
+
+        Ok(decode_events(response.into_body().into_data_stream()))
+    }
+}
+
+fn restart_policy_options(restart_policy: RestartPolicy) -> RestartPolicyOptions {
+    let (name, maximum_retry_count) = match restart_policy {
+        RestartPolicy::No => ("no", None),
+        RestartPolicy::OnFailure { max_retries } => ("on-failure", max_retries),
+        RestartPolicy::UnlessStopped => ("unless-stopped", None),
+    };
+
+    RestartPolicyOptions {
+        name,
+        maximum_retry_count,
+    }
+}
+
+/// Encodes filters for the query string of endpoints that accept them, such as `/events`.
+pub fn filters_query(filters: &serde_json::Value) -> String {
+    let encoded: String = filters
+        .to_string()
+        .bytes()
+        .map(|byte| match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' => {
+                char::from(byte).to_string()
+            }
+            _ => format!("%{byte:02X}"),
+        })
+        .collect();
+
+    format!("filters={encoded}")
+}
+
+/// Formats a published port the way Docker keys container ports, such as `22/tcp`.
+fn container_port_key(port: &PublishedPort) -> String {
+    format!("{}/{}", port.container_port, port.transport)
+}
+
+fn port_bindings(published_ports: &[PublishedPort]) -> HashMap<String, Vec<PortBinding>> {
+    let mut bindings: HashMap<_, Vec<_>> = HashMap::new();
+
+    for port in published_ports {
+        bindings
+            .entry(container_port_key(port))
+            .or_default()
+            .push(PortBinding {
+                host_ip: port.host_ip.map(|ip| ip.to_string()),
+                host_port: port.host_port.to_string(),
+            });
+    }
+
+    bindings
 }
 
 fn format_environment_variables(environment: &Option<Environment>) -> Vec<String> {
@@ -294,3 +876,198 @@ where
 
     Ok(json)
 }
+
+#[cfg(test)]
+mod tests {
+    use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+
+    use color_eyre::eyre::Result;
+    use http_body_util::Full;
+    use hyper::body::Bytes;
+    use hyper::server::conn::http1;
+    use hyper::service::service_fn;
+    use hyper::{Method, Request, Response, StatusCode};
+    use hyper_util::rt::TokioIo;
+    use tokio::net::TcpListener;
+
+    use crate::config::{DockerRetryConfig, PublishedPort};
+    use crate::docker::client::{port_bindings, Client, Transport};
+    use crate::docker::models::{NetworkInfo, PortBinding};
+    use crate::docker::version::{ApiVersion, PRUNE_UNTIL_API_VERSION};
+
+    fn base(client: &Client) -> &str {
+        match &client.transport {
+            Transport::Unix { socket, .. } => socket,
+            Transport::Tcp { base, .. } => base,
+        }
+    }
+
+    #[test]
+    fn clients_can_be_created_from_hosts() -> Result<()> {
+        let unix = Client::from_host("unix:///run/user/1000/docker.sock", None)?;
+        let path = Client::from_host("/run/podman/podman.sock", None)?;
+        let tcp = Client::from_host("tcp://docker.example.com:2375", None)?;
+
+        assert_eq!(base(&unix), "/run/user/1000/docker.sock");
+        assert_eq!(base(&path), "/run/podman/podman.sock");
+        assert_eq!(base(&tcp), "http://docker.example.com:2375");
+
+        assert!(Client::from_host("ssh://docker.example.com", None).is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn requests_are_pinned_to_the_negotiated_version() -> Result<()> {
+        let mut client = Client::from_host("tcp://docker.example.com:2375", None)?;
+
+        assert_eq!(
+            client.build_uri("/containers/json")?,
+            "http://docker.example.com:2375/containers/json"
+        );
+
+        client.api_version = Some(ApiVersion::new(1, 27));
+
+        assert_eq!(
+            client.build_uri("/containers/json")?,
+            "http://docker.example.com:2375/v1.27/containers/json"
+        );
+
+        let error = client
+            .require_api_version("Garbage collection", PRUNE_UNTIL_API_VERSION)
+            .unwrap_err();
+
+        assert!(error.to_string().contains("1.28"));
+
+        client.api_version = Some(PRUNE_UNTIL_API_VERSION);
+
+        assert!(client
+            .require_api_version("Garbage collection", PRUNE_UNTIL_API_VERSION)
+            .is_ok());
+
+        Ok(())
+    }
+
+    #[test]
+    fn containers_can_be_reached_over_either_address_family() -> Result<()> {
+        let addr = |body: &str| -> Result<_> {
+            let network: NetworkInfo = serde_json::from_str(body)?;
+
+            Ok(network.addr())
+        };
+
+        let v4: IpAddr = "172.18.0.2".parse()?;
+        let v6: IpAddr = "fd00:dead:beef::2".parse()?;
+
+        assert_eq!(
+            addr(r#"{"IPAddress":"172.18.0.2","GlobalIPv6Address":"fd00:dead:beef::2"}"#)?,
+            Some(v4)
+        );
+        assert_eq!(
+            addr(r#"{"IPAddress":"","GlobalIPv6Address":"fd00:dead:beef::2"}"#)?,
+            Some(v6)
+        );
+        assert_eq!(addr(r#"{"IPAddress":"","GlobalIPv6Address":""}"#)?, None);
+
+        Ok(())
+    }
+
+    #[test]
+    fn published_ports_are_bound_by_container_port() -> Result<()> {
+        let ports = ["2222:22", "127.0.0.1:2223:22", "5353:53/udp"]
+            .map(|port| PublishedPort::try_from(String::from(port)))
+            .into_iter()
+            .collect::<Result<Vec<_>>>()?;
+
+        let bindings = port_bindings(&ports);
+
+        assert_eq!(
+            bindings["22/tcp"],
+            vec![
+                PortBinding {
+                    host_ip: None,
+                    host_port: String::from("2222"),
+                },
+                PortBinding {
+                    host_ip: Some(String::from("127.0.0.1")),
+                    host_port: String::from("2223"),
+                },
+            ]
+        );
+        assert_eq!(bindings["53/udp"].len(), 1);
+        assert_eq!(bindings.len(), 2);
+
+        Ok(())
+    }
+
+    /// Spawns a daemon that is always unavailable, counting the requests it receives.
+    async fn spawn_unavailable_daemon() -> Result<(SocketAddr, Arc<AtomicUsize>)> {
+        let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, 0)).await?;
+        let addr = listener.local_addr()?;
+        let requests = Arc::new(AtomicUsize::new(0));
+
+        tokio::spawn({
+            let requests = Arc::clone(&requests);
+
+            async move {
+                loop {
+                    let (stream, _) = listener.accept().await.unwrap();
+                    let requests = Arc::clone(&requests);
+
+                    let service = service_fn(move |_| {
+                        requests.fetch_add(1, Ordering::SeqCst);
+
+                        async {
+                            Response::builder()
+                                .status(StatusCode::SERVICE_UNAVAILABLE)
+                                .body(Full::<Bytes>::default())
+                        }
+                    });
+
+                    tokio::spawn(async move {
+                        let _ = http1::Builder::new()
+                            .serve_connection(TokioIo::new(stream), service)
+                            .await;
+                    });
+                }
+            }
+        });
+
+        Ok((addr, requests))
+    }
+
+    #[tokio::test]
+    async fn only_reads_are_retried_after_transient_errors() -> Result<()> {
+        let (addr, requests) = spawn_unavailable_daemon().await?;
+
+        let client =
+            Client::from_host(&format!("tcp://{addr}"), None)?.with_retries(DockerRetryConfig {
+                max_attempts: 3,
+                initial_backoff_ms: 1,
+                max_backoff_ms: 1,
+            });
+
+        let send = |method: Method| -> Result<_> {
+            let request = Request::builder()
+                .uri(client.build_uri("/containers/json")?)
+                .method(method)
+                .body(Full::default())?;
+
+            Ok(client.request(request))
+        };
+
+        let response = send(Method::POST)?.await?;
+
+        assert_eq!(response.status(), StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(requests.load(Ordering::SeqCst), 1);
+
+        let response = send(Method::GET)?.await?;
+
+        assert_eq!(response.status(), StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(requests.load(Ordering::SeqCst), 4);
+
+        Ok(())
+    }
+}
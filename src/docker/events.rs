@@ -0,0 +1,227 @@
+//! Watching the Docker daemon for containers that stop without f2 asking them to, which it reports
+//! as a stream of newline delimited JSON messages.
+
+use std::collections::HashMap;
+
+use color_eyre::eyre::{Report, Result};
+use futures::stream::{self, BoxStream};
+use futures::{future, Stream, StreamExt};
+use hyper::body::Bytes;
+use serde::Deserialize;
+
+use crate::docker::api::{REPLICA_LABEL, SERVICE_LABEL};
+use crate::docker::client::filters_query;
+use crate::docker::models::ContainerId;
+
+/// What happened to a container managed by f2.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum ContainerAction {
+    /// The container's main process exited, with the code Docker reported if there was one.
+    Died { exit_code: Option<i64> },
+    /// A process in the container was killed for using more than its memory limit.
+    OutOfMemory,
+    /// The container was started, such as by the daemon applying its restart policy.
+    Started,
+}
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct ContainerEvent {
+    pub id: ContainerId,
+    pub action: ContainerAction,
+    /// Which of its service's replicas the container was started as.
+    pub replica: Option<usize>,
+}
+
+pub type EventStream = BoxStream<'static, Result<ContainerEvent>>;
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct EventMessage {
+    action: String,
+    actor: EventActor,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct EventActor {
+    #[serde(rename = "ID")]
+    id: ContainerId,
+    #[serde(default)]
+    attributes: HashMap<String, String>,
+}
+
+impl EventMessage {
+    fn into_event(self) -> Option<ContainerEvent> {
+        let action = match self.action.as_str() {
+            "die" => ContainerAction::Died {
+                exit_code: self
+                    .actor
+                    .attributes
+                    .get("exitCode")
+                    .and_then(|code| code.parse().ok()),
+            },
+            "oom" => ContainerAction::OutOfMemory,
+            "start" => ContainerAction::Started,
+            _ => return None,
+        };
+
+        let replica = self
+            .actor
+            .attributes
+            .get(REPLICA_LABEL)
+            .and_then(|replica| replica.parse().ok());
+
+        Some(ContainerEvent {
+            id: self.actor.id,
+            action,
+            replica,
+        })
+    }
+}
+
+/// Builds the query for the events endpoint, so the daemon only reports the actions f2 acts on for
+/// the containers it manages.
+pub fn events_query() -> String {
+    filters_query(&serde_json::json!({
+        "type": ["container"],
+        "event": ["die", "oom", "start"],
+        "label": [SERVICE_LABEL],
+    }))
+}
+
+/// Splits the body of an events request into messages, regardless of how it was chunked.
+#[derive(Debug, Default)]
+pub struct EventDecoder {
+    buffer: Vec<u8>,
+}
+
+impl EventDecoder {
+    /// Adds the next chunk of the body, returning the events for any messages it completes.
+    pub fn push(&mut self, chunk: &[u8]) -> Result<Vec<ContainerEvent>> {
+        self.buffer.extend_from_slice(chunk);
+
+        let Some(end) = self.buffer.iter().rposition(|byte| *byte == b'\n') else {
+            return Ok(Vec::new());
+        };
+
+        let complete: Vec<u8> = self.buffer.drain(..=end).collect();
+        let mut events = Vec::new();
+
+        for line in complete.split(|byte| *byte == b'\n') {
+            if line.iter().all(u8::is_ascii_whitespace) {
+                continue;
+            }
+
+            let message: EventMessage = serde_json::from_slice(line)?;
+            events.extend(message.into_event());
+        }
+
+        Ok(events)
+    }
+}
+
+/// Splits the chunks of an events response body into the events f2 acts on.
+pub fn decode_events<S, E>(body: S) -> EventStream
+where
+    S: Stream<Item = Result<Bytes, E>> + Send + 'static,
+    E: std::error::Error + Send + Sync + 'static,
+{
+    body.scan(EventDecoder::default(), |decoder, chunk| {
+        let events = match chunk
+            .map_err(Report::from)
+            .and_then(|chunk| decoder.push(&chunk))
+        {
+            Ok(events) => events.into_iter().map(Ok).collect(),
+            Err(e) => vec![Err(e)],
+        };
+
+        future::ready(Some(stream::iter(events)))
+    })
+    .flatten()
+    .boxed()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::convert::Infallible;
+
+    use color_eyre::eyre::Result;
+    use futures::TryStreamExt;
+    use hyper::body::Bytes;
+
+    use crate::docker::events::{
+        decode_events, events_query, ContainerAction, ContainerEvent, EventDecoder,
+    };
+    use crate::docker::models::ContainerId;
+
+    fn message(action: &str, id: &str, exit_code: &str) -> String {
+        format!(
+            r#"{{"Type":"container","Action":"{action}","Actor":{{"ID":"{id}","Attributes":{{"exitCode":"{exit_code}","f2.replica":"1","f2.service":"backend"}}}}}}"#
+        ) + "\n"
+    }
+
+    #[tokio::test]
+    async fn messages_can_span_multiple_chunks() -> Result<()> {
+        let body = [
+            message("oom", "6cd915f16ab3", "0"),
+            message("die", "6cd915f16ab3", "137"),
+            message("start", "0e27a8f3b2c4", "0"),
+        ]
+        .concat();
+
+        let chunks = [&body[..20], &body[20..150], &body[150..]]
+            .map(|chunk| Ok::<_, Infallible>(Bytes::copy_from_slice(chunk.as_bytes())));
+
+        let events: Vec<_> = decode_events(futures::stream::iter(chunks))
+            .try_collect()
+            .await?;
+
+        let first = ContainerId(String::from("6cd915f16ab3"));
+        let second = ContainerId(String::from("0e27a8f3b2c4"));
+
+        assert_eq!(
+            events,
+            vec![
+                ContainerEvent {
+                    id: first.clone(),
+                    action: ContainerAction::OutOfMemory,
+                    replica: Some(1),
+                },
+                ContainerEvent {
+                    id: first,
+                    action: ContainerAction::Died {
+                        exit_code: Some(137)
+                    },
+                    replica: Some(1),
+                },
+                ContainerEvent {
+                    id: second,
+                    action: ContainerAction::Started,
+                    replica: Some(1),
+                },
+            ]
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn other_actions_are_ignored() -> Result<()> {
+        let mut decoder = EventDecoder::default();
+        let events = decoder.push(message("pause", "6cd915f16ab3", "0").as_bytes())?;
+
+        assert!(events.is_empty());
+        assert!(decoder.push(b"not json\n").is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn queries_only_ask_for_managed_containers() {
+        let query = events_query();
+
+        assert!(query.starts_with("filters=%7B"));
+        assert!(query.contains("f2.service"));
+        assert!(!query.contains('"'));
+    }
+}
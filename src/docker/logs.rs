@@ -0,0 +1,157 @@
+//! Reading the output of containers, which Docker multiplexes into a single stream of frames for
+//! containers without a TTY.
+
+use std::fmt;
+
+use color_eyre::eyre::{eyre, Report, Result};
+use futures::stream::{self, BoxStream};
+use futures::{future, Stream, StreamExt};
+use hyper::body::Bytes;
+
+/// The length of the header Docker writes before each frame, holding the stream it was written to
+/// and the length of its data.
+const HEADER_LENGTH: usize = 8;
+
+/// Which of a container's outputs a frame was written to.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum OutputStream {
+    Stdout,
+    Stderr,
+}
+
+impl fmt::Display for OutputStream {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Stdout => write!(f, "stdout"),
+            Self::Stderr => write!(f, "stderr"),
+        }
+    }
+}
+
+/// A single write to a container's output, which is usually a complete line.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct LogFrame {
+    pub stream: OutputStream,
+    pub data: Bytes,
+}
+
+pub type LogStream = BoxStream<'static, Result<LogFrame>>;
+
+/// Splits the body of a logs request into frames, regardless of how it was chunked.
+#[derive(Debug, Default)]
+pub struct Demultiplexer {
+    buffer: Vec<u8>,
+}
+
+impl Demultiplexer {
+    /// Adds the next chunk of the body, returning any frames it completes.
+    pub fn push(&mut self, chunk: &[u8]) -> Result<Vec<LogFrame>> {
+        self.buffer.extend_from_slice(chunk);
+
+        let mut frames = Vec::new();
+        let mut offset = 0;
+
+        while let Some(header) = self.buffer.get(offset..offset + HEADER_LENGTH) {
+            let stream = match header[0] {
+                1 => OutputStream::Stdout,
+                2 => OutputStream::Stderr,
+                other => return Err(eyre!("unexpected stream {other} in container logs")),
+            };
+
+            let length = u32::from_be_bytes([header[4], header[5], header[6], header[7]]) as usize;
+            let start = offset + HEADER_LENGTH;
+
+            let Some(data) = self.buffer.get(start..start + length) else {
+                break;
+            };
+
+            frames.push(LogFrame {
+                stream,
+                data: Bytes::copy_from_slice(data),
+            });
+
+            offset = start + length;
+        }
+
+        self.buffer.drain(..offset);
+
+        Ok(frames)
+    }
+}
+
+/// Splits the chunks of a logs response body into the frames the container wrote.
+pub fn demultiplex<S, E>(body: S) -> LogStream
+where
+    S: Stream<Item = Result<Bytes, E>> + Send + 'static,
+    E: std::error::Error + Send + Sync + 'static,
+{
+    body.scan(Demultiplexer::default(), |demultiplexer, chunk| {
+        let frames = match chunk
+            .map_err(Report::from)
+            .and_then(|chunk| demultiplexer.push(&chunk))
+        {
+            Ok(frames) => frames.into_iter().map(Ok).collect(),
+            Err(e) => vec![Err(e)],
+        };
+
+        future::ready(Some(stream::iter(frames)))
+    })
+    .flatten()
+    .boxed()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::convert::Infallible;
+
+    use color_eyre::eyre::Result;
+    use futures::TryStreamExt;
+    use hyper::body::Bytes;
+
+    use crate::docker::logs::{demultiplex, Demultiplexer, LogFrame, OutputStream};
+
+    fn frame(stream: u8, data: &str) -> Vec<u8> {
+        let mut frame = vec![stream, 0, 0, 0];
+        frame.extend_from_slice(&(data.len() as u32).to_be_bytes());
+        frame.extend_from_slice(data.as_bytes());
+
+        frame
+    }
+
+    fn log_frame(stream: OutputStream, data: &'static str) -> LogFrame {
+        LogFrame {
+            stream,
+            data: Bytes::from_static(data.as_bytes()),
+        }
+    }
+
+    #[tokio::test]
+    async fn frames_can_span_multiple_chunks() -> Result<()> {
+        let body = [frame(1, "starting up\n"), frame(2, "failed to connect\n")].concat();
+
+        // Split the body mid-header and mid-data to check partial frames are held back
+        let chunks = [&body[..3], &body[3..10], &body[10..25], &body[25..]]
+            .map(|chunk| Ok::<_, Infallible>(Bytes::copy_from_slice(chunk)));
+
+        let frames: Vec<_> = demultiplex(futures::stream::iter(chunks))
+            .try_collect()
+            .await?;
+
+        assert_eq!(
+            frames,
+            vec![
+                log_frame(OutputStream::Stdout, "starting up\n"),
+                log_frame(OutputStream::Stderr, "failed to connect\n"),
+            ]
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn unknown_streams_are_rejected() {
+        let mut demultiplexer = Demultiplexer::default();
+
+        assert!(demultiplexer.push(&frame(3, "unexpected\n")).is_err());
+    }
+}
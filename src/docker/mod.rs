@@ -1,3 +1,8 @@
 pub mod api;
 pub mod client;
+pub mod events;
+pub mod logs;
 pub mod models;
+pub mod retry;
+pub mod tls;
+pub mod version;
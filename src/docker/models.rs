@@ -1,10 +1,10 @@
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
 use std::fmt;
-use std::net::Ipv4Addr;
+use std::net::IpAddr;
 
 use serde::{Deserialize, Serialize};
 
-#[derive(Clone, Debug, Eq, PartialEq, Hash, Deserialize)]
+#[derive(Clone, Debug, Eq, PartialEq, Hash, Serialize, Deserialize)]
 pub struct ContainerId(pub String);
 
 impl fmt::Display for ContainerId {
@@ -41,21 +41,88 @@ pub struct ImageSummary {
     pub repo_tags: Vec<String>,
 }
 
+/// Credentials for a registry, sent to the daemon as JSON in the `X-Registry-Auth` header.
+#[derive(Clone, Eq, PartialEq, Serialize)]
+pub struct RegistryAuth {
+    pub username: String,
+    pub password: String,
+    pub serveraddress: String,
+}
+
+impl fmt::Debug for RegistryAuth {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("RegistryAuth")
+            .field("username", &self.username)
+            .field("serveraddress", &self.serveraddress)
+            .finish_non_exhaustive()
+    }
+}
+
 #[derive(Debug, Serialize)]
 #[serde(rename_all = "PascalCase")]
 pub struct CreateContainerOptions<'a> {
     pub image: String,
     pub env: Vec<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub cmd: Option<&'a [String]>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub entrypoint: Option<&'a [String]>,
+    pub labels: &'a HashMap<String, String>,
     pub volumes: &'a HashMap<String, HashMap<String, String>>,
-    pub host_config: HostConfig,
+    #[serde(skip_serializing_if = "HashMap::is_empty")]
+    pub exposed_ports: HashMap<String, HashMap<String, String>>,
+    pub host_config: HostConfig<'a>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub networking_config: Option<NetworkingConfig>,
 }
 
 #[derive(Debug, Serialize)]
 #[serde(rename_all = "PascalCase")]
-pub struct HostConfig {
+pub struct HostConfig<'a> {
     pub binds: Vec<String>,
+    #[serde(skip_serializing_if = "HashMap::is_empty")]
+    pub tmpfs: HashMap<String, String>,
+    #[serde(skip_serializing_if = "HashMap::is_empty")]
+    pub port_bindings: HashMap<String, Vec<PortBinding>>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub ulimits: Vec<UlimitOptions>,
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub sysctls: &'a BTreeMap<String, String>,
+    pub readonly_rootfs: bool,
+    #[serde(skip_serializing_if = "<[_]>::is_empty")]
+    pub security_opt: &'a [String],
+    pub privileged: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub nano_cpus: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub memory: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub memory_reservation: Option<u64>,
+    pub restart_policy: RestartPolicyOptions,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct UlimitOptions {
+    pub name: String,
+    pub soft: u64,
+    pub hard: u64,
+}
+
+#[derive(Debug, Eq, PartialEq, Serialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct PortBinding {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub host_ip: Option<String>,
+    pub host_port: String,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct RestartPolicyOptions {
+    pub name: &'static str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub maximum_retry_count: Option<u32>,
 }
 
 #[derive(Debug, Serialize)]
@@ -64,6 +131,13 @@ pub struct NetworkingConfig {
     pub endpoints_config: HashMap<String, EndpointConfig>,
 }
 
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct ConnectNetworkOptions<'a> {
+    pub container: &'a ContainerId,
+    pub endpoint_config: EndpointConfig,
+}
+
 #[derive(Debug, Serialize)]
 #[serde(rename_all = "PascalCase")]
 pub struct EndpointConfig {
@@ -81,6 +155,34 @@ pub struct CreateContainerResponse {
 #[serde(rename_all = "PascalCase")]
 pub struct InspectContainerResponse {
     pub network_settings: NetworkSettings,
+    pub state: ContainerState,
+    /// How many times the daemon has restarted the container under its restart policy.
+    #[serde(default)]
+    pub restart_count: u32,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct ContainerState {
+    /// Only present for containers whose image or configuration defines a health check.
+    pub health: Option<Health>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct Health {
+    pub status: HealthStatus,
+}
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum HealthStatus {
+    /// The container is within its start period or has not yet been checked.
+    Starting,
+    Healthy,
+    Unhealthy,
+    /// The health check was disabled when creating the container.
+    None,
 }
 
 #[derive(Debug, Deserialize)]
@@ -92,8 +194,21 @@ pub struct NetworkSettings {
 #[derive(Debug, Deserialize)]
 #[serde(rename_all = "PascalCase")]
 pub struct NetworkInfo {
-    #[serde(rename = "IPAddress")]
-    pub ip_address: Ipv4Addr,
+    /// The container's IPv4 address, which is empty on networks without IPv4 addressing.
+    #[serde(rename = "IPAddress", default)]
+    pub ip_address: String,
+    /// The container's IPv6 address, which is empty unless the network has IPv6 enabled.
+    #[serde(rename = "GlobalIPv6Address", default)]
+    pub global_ipv6_address: String,
+}
+
+impl NetworkInfo {
+    /// The address to reach the container at, preferring IPv4 on dual-stack networks.
+    pub fn addr(&self) -> Option<IpAddr> {
+        [&self.ip_address, &self.global_ipv6_address]
+            .into_iter()
+            .find_map(|addr| addr.parse().ok())
+    }
 }
 
 #[derive(Debug, Deserialize)]
@@ -102,3 +217,79 @@ pub struct Network {
     pub id: String,
     pub name: String,
 }
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct CreateNetworkOptions<'a> {
+    pub name: &'a str,
+    pub driver: &'a str,
+    pub check_duplicate: bool,
+    #[serde(rename = "EnableIPv6")]
+    pub enable_ipv6: bool,
+    #[serde(rename = "IPAM")]
+    pub ipam: Ipam<'a>,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct Ipam<'a> {
+    pub config: Vec<IpamConfig<'a>>,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct IpamConfig<'a> {
+    pub subnet: &'a str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub gateway: Option<&'a str>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct CreateNetworkResponse {
+    pub id: NetworkId,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct CreateExecOptions<'a> {
+    pub cmd: &'a [String],
+    pub attach_stdout: bool,
+    pub attach_stderr: bool,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct CreateExecResponse {
+    pub id: String,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct InspectExecResponse {
+    pub running: bool,
+    pub exit_code: Option<i64>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct PruneContainersResponse {
+    /// Docker sends `null` rather than an empty list when nothing was removed.
+    pub containers_deleted: Option<Vec<ContainerId>>,
+    pub space_reclaimed: u64,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct PruneImagesResponse {
+    /// Docker sends `null` rather than an empty list when nothing was removed.
+    pub images_deleted: Option<Vec<HashMap<String, String>>>,
+    pub space_reclaimed: u64,
+}
+
+/// What was removed when pruning containers or images.
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq)]
+pub struct PruneReport {
+    pub deleted: usize,
+    pub space_reclaimed: u64,
+}
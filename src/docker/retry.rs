@@ -0,0 +1,77 @@
+//! Retrying requests to the Docker daemon that failed because it was briefly unavailable, such as
+//! while it is restarting or too busy to respond, rather than failing a whole reconciliation.
+
+use std::time::Duration;
+
+use hyper::StatusCode;
+use rand::Rng;
+
+use crate::config::DockerRetryConfig;
+
+/// Checks whether a response suggests the same request might succeed if sent again.
+pub fn is_transient_status(status: StatusCode) -> bool {
+    matches!(
+        status,
+        StatusCode::BAD_GATEWAY | StatusCode::SERVICE_UNAVAILABLE | StatusCode::GATEWAY_TIMEOUT
+    )
+}
+
+/// Picks how long to wait before the retry following `attempt`, counting from 0, which doubles
+/// with each attempt up to the configured limit and is jittered so clients don't retry in step.
+pub fn backoff<R: Rng>(config: &DockerRetryConfig, attempt: u32, rng: &mut R) -> Duration {
+    let ceiling = config
+        .initial_backoff()
+        .saturating_mul(2_u32.saturating_pow(attempt))
+        .min(config.max_backoff());
+
+    rng.gen_range(Duration::ZERO..=ceiling)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use hyper::StatusCode;
+    use rand::rngs::SmallRng;
+    use rand::SeedableRng;
+
+    use crate::config::DockerRetryConfig;
+    use crate::docker::retry::{backoff, is_transient_status};
+
+    #[test]
+    fn backoff_grows_exponentially_up_to_the_limit() {
+        let config = DockerRetryConfig {
+            max_attempts: 10,
+            initial_backoff_ms: 100,
+            max_backoff_ms: 1000,
+        };
+
+        let mut rng = SmallRng::seed_from_u64(0);
+
+        for (attempt, ceiling) in [(0, 100), (1, 200), (3, 800), (4, 1000), (20, 1000)] {
+            let delays: Vec<_> = (0..50)
+                .map(|_| backoff(&config, attempt, &mut rng))
+                .collect();
+
+            assert!(delays
+                .iter()
+                .all(|delay| *delay <= Duration::from_millis(ceiling)));
+
+            // Jitter should spread the delays out rather than always waiting the longest time
+            assert!(delays.iter().any(|delay| *delay != delays[0]));
+        }
+    }
+
+    #[test]
+    fn only_unavailable_daemons_are_retried() {
+        assert!(is_transient_status(StatusCode::SERVICE_UNAVAILABLE));
+        assert!(is_transient_status(StatusCode::GATEWAY_TIMEOUT));
+
+        // The daemon reports most failures as internal errors, which won't go away by themselves
+        assert!(!is_transient_status(StatusCode::INTERNAL_SERVER_ERROR));
+
+        assert!(!is_transient_status(StatusCode::NOT_FOUND));
+        assert!(!is_transient_status(StatusCode::CONFLICT));
+        assert!(!is_transient_status(StatusCode::NOT_IMPLEMENTED));
+    }
+}
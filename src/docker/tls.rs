@@ -0,0 +1,84 @@
+//! Connecting to a remote Docker daemon over TLS, using the same certificate files as Docker's own
+//! tools.
+
+use std::fs::File;
+use std::io::BufReader;
+use std::path::Path;
+use std::sync::Arc;
+
+use color_eyre::eyre::{eyre, Context, Result};
+use itertools::Itertools;
+use rustls::crypto::ring::default_provider;
+use rustls::pki_types::CertificateDer;
+use rustls::{ClientConfig, RootCertStore};
+
+/// The environment variable Docker's own tools read the directory of certificates from.
+pub const DOCKER_CERT_PATH: &str = "DOCKER_CERT_PATH";
+
+fn open(path: &Path) -> Result<BufReader<File>> {
+    let file = File::open(path).wrap_err_with(|| eyre!("failed to open {}", path.display()))?;
+
+    Ok(BufReader::new(file))
+}
+
+fn certificates(path: &Path) -> Result<Vec<CertificateDer<'static>>> {
+    let certificates: Vec<_> = rustls_pemfile::certs(&mut open(path)?).try_collect()?;
+
+    if certificates.is_empty() {
+        return Err(eyre!("no certificates found in {}", path.display()));
+    }
+
+    Ok(certificates)
+}
+
+/// Builds the configuration for a daemon protected by mutual TLS, which trusts the authority in
+/// `ca.pem` and presents the certificate in `cert.pem` with the key in `key.pem`, all found under
+/// `cert_path`.
+pub fn client_config(cert_path: &Path) -> Result<ClientConfig> {
+    let mut roots = RootCertStore::empty();
+    roots.add_parsable_certificates(certificates(&cert_path.join("ca.pem"))?);
+
+    let chain = certificates(&cert_path.join("cert.pem"))?;
+
+    let key_path = cert_path.join("key.pem");
+    let key = rustls_pemfile::private_key(&mut open(&key_path)?)?
+        .ok_or_else(|| eyre!("no private key found in {}", key_path.display()))?;
+
+    let config = ClientConfig::builder_with_provider(Arc::new(default_provider()))
+        .with_safe_default_protocol_versions()?
+        .with_root_certificates(roots)
+        .with_client_auth_cert(chain, key)?;
+
+    Ok(config)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use color_eyre::eyre::Result;
+
+    use crate::docker::tls::client_config;
+
+    #[test]
+    fn configurations_require_every_file() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+
+        let certificate = std::fs::read("resources/certificates/stapled.crt")?;
+        let key = std::fs::read("resources/certificates/stapled.key")?;
+
+        std::fs::write(dir.path().join("ca.pem"), &certificate)?;
+        std::fs::write(dir.path().join("cert.pem"), &certificate)?;
+
+        assert!(client_config(dir.path()).is_err());
+
+        std::fs::write(dir.path().join("key.pem"), &key)?;
+
+        let config = client_config(dir.path())?;
+
+        assert!(config.client_auth_cert_resolver.has_certs());
+        assert!(client_config(Path::new("resources/missing")).is_err());
+
+        Ok(())
+    }
+}
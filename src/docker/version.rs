@@ -0,0 +1,138 @@
+//! Agreeing on a version of the Docker Engine API with the daemon, so requests keep the same
+//! meaning as the daemon is upgraded.
+
+use std::fmt;
+use std::str::FromStr;
+
+use color_eyre::eyre::{eyre, Report, Result};
+use serde::Deserialize;
+
+/// A version of the Docker Engine API, such as `1.43`.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub struct ApiVersion {
+    pub major: u32,
+    pub minor: u32,
+}
+
+impl ApiVersion {
+    pub const fn new(major: u32, minor: u32) -> Self {
+        Self { major, minor }
+    }
+}
+
+impl fmt::Display for ApiVersion {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}.{}", self.major, self.minor)
+    }
+}
+
+impl FromStr for ApiVersion {
+    type Err = Report;
+
+    fn from_str(value: &str) -> Result<Self> {
+        let (major, minor) = value
+            .split_once('.')
+            .ok_or_else(|| eyre!("invalid Docker API version {value}"))?;
+
+        Ok(Self::new(major.parse()?, minor.parse()?))
+    }
+}
+
+impl<'de> Deserialize<'de> for ApiVersion {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let value = String::deserialize(deserializer)?;
+
+        value.parse().map_err(serde::de::Error::custom)
+    }
+}
+
+/// The oldest version f2 can work with, which is the first to report container health.
+pub const MINIMUM_API_VERSION: ApiVersion = ApiVersion::new(1, 24);
+
+/// The newest version f2 knows about, which newer daemons will still serve.
+pub const MAXIMUM_API_VERSION: ApiVersion = ApiVersion::new(1, 43);
+
+/// The version that added the `until` filter when pruning containers and images.
+pub const PRUNE_UNTIL_API_VERSION: ApiVersion = ApiVersion::new(1, 28);
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct VersionResponse {
+    pub version: String,
+    pub api_version: ApiVersion,
+    #[serde(rename = "MinAPIVersion")]
+    pub min_api_version: Option<ApiVersion>,
+}
+
+/// Picks the newest version that both f2 and the daemon understand.
+pub fn negotiate(daemon: &VersionResponse) -> Result<ApiVersion> {
+    let version = daemon.api_version.min(MAXIMUM_API_VERSION);
+
+    if version < MINIMUM_API_VERSION {
+        return Err(eyre!(
+            "Docker {} only supports API version {}, but f2 requires at least {MINIMUM_API_VERSION}",
+            daemon.version,
+            daemon.api_version,
+        ));
+    }
+
+    if let Some(min_api_version) = daemon.min_api_version {
+        if version < min_api_version {
+            return Err(eyre!(
+                "Docker {} requires at least API version {min_api_version}, but f2 only supports up to {MAXIMUM_API_VERSION}",
+                daemon.version,
+            ));
+        }
+    }
+
+    Ok(version)
+}
+
+#[cfg(test)]
+mod tests {
+    use color_eyre::eyre::Result;
+
+    use crate::docker::version::{
+        negotiate, ApiVersion, VersionResponse, MAXIMUM_API_VERSION, MINIMUM_API_VERSION,
+    };
+
+    fn daemon(api_version: &str, min_api_version: &str) -> Result<VersionResponse> {
+        let body = format!(
+            r#"{{"Version":"24.0.7","ApiVersion":"{api_version}","MinAPIVersion":"{min_api_version}"}}"#
+        );
+
+        Ok(serde_json::from_str(&body)?)
+    }
+
+    #[test]
+    fn versions_are_compared_numerically() -> Result<()> {
+        let older: ApiVersion = "1.9".parse()?;
+        let newer: ApiVersion = "1.43".parse()?;
+
+        assert!(older < newer);
+        assert_eq!(newer.to_string(), "1.43");
+        assert!("43".parse::<ApiVersion>().is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn the_newest_shared_version_is_used() -> Result<()> {
+        assert_eq!(negotiate(&daemon("1.30", "1.12")?)?, ApiVersion::new(1, 30));
+        assert_eq!(negotiate(&daemon("1.45", "1.24")?)?, MAXIMUM_API_VERSION);
+        assert_eq!(
+            negotiate(&daemon(&MINIMUM_API_VERSION.to_string(), "1.12")?)?,
+            MINIMUM_API_VERSION
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn daemons_outside_the_supported_range_are_rejected() -> Result<()> {
+        assert!(negotiate(&daemon("1.23", "1.12")?).is_err());
+        assert!(negotiate(&daemon("1.50", "1.44")?).is_err());
+
+        Ok(())
+    }
+}
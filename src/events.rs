@@ -0,0 +1,124 @@
+use std::sync::Arc;
+
+use chrono::Utc;
+use serde::Serialize;
+use uuid::Uuid;
+
+use crate::config::{Diff, Service};
+use crate::ipc::MessageBus;
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum DeployEventKind {
+    Addition,
+    Alteration,
+    Removal,
+}
+
+/// What caused a set of changes to be deployed.
+#[derive(Clone, Debug, Eq, PartialEq, Serialize)]
+pub struct DeployTrigger {
+    pub reconciliation_id: String,
+    pub source: String,
+}
+
+impl DeployTrigger {
+    pub fn new(reconciliation_id: Uuid, source: &str) -> Self {
+        Self {
+            reconciliation_id: reconciliation_id.to_string(),
+            source: source.to_owned(),
+        }
+    }
+}
+
+/// A record of a single change applied to a service, for use in deployment history.
+#[derive(Clone, Debug, Eq, PartialEq, Serialize)]
+pub struct DeployEvent {
+    pub service: String,
+    pub kind: DeployEventKind,
+    pub old_image: Option<String>,
+    pub new_image: Option<String>,
+    pub old_replicas: Option<u8>,
+    pub new_replicas: Option<u8>,
+    pub trigger: DeployTrigger,
+    pub timestamp: String,
+}
+
+impl DeployEvent {
+    /// Describes a diff, using `previous` as the old definition for removals since they do not
+    /// carry one.
+    pub fn new(diff: &Diff, previous: Option<&Service>, trigger: DeployTrigger) -> Self {
+        let (service, kind, old, new) = match diff {
+            Diff::Alteration {
+                name,
+                old_definition,
+                new_definition,
+            } => (
+                name,
+                DeployEventKind::Alteration,
+                Some(old_definition),
+                Some(new_definition),
+            ),
+            Diff::Addition { name, definition } => {
+                (name, DeployEventKind::Addition, None, Some(definition))
+            }
+            Diff::Removal { name } => (name, DeployEventKind::Removal, previous, None),
+        };
+
+        let image = |service: &Service| format!("{}:{}", service.image, service.tag);
+        let replicas = |service: &Service| service.replicas.get();
+
+        Self {
+            service: service.clone(),
+            kind,
+            old_image: old.map(image),
+            new_image: new.map(image),
+            old_replicas: old.map(replicas),
+            new_replicas: new.map(replicas),
+            trigger,
+            timestamp: Utc::now().to_rfc3339(),
+        }
+    }
+}
+
+/// Writes each deploy event to the logs as a single line of JSON.
+pub async fn log_deploy_events(message_bus: Arc<MessageBus>) {
+    while let Ok(message) = message_bus.receive_deploy_event().await {
+        match serde_json::to_string(&message.into_content()) {
+            Ok(event) => tracing::info!(target: "f2::deploy", %event, "applied a deploy event"),
+            Err(e) => tracing::warn!(%e, "failed to serialize deploy event"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use uuid::Uuid;
+
+    use crate::config::{Diff, ReplicaCount, Service};
+    use crate::events::{DeployEvent, DeployEventKind, DeployTrigger};
+
+    #[test]
+    fn removals_use_the_previous_definition() -> color_eyre::Result<()> {
+        let previous = Service {
+            image: String::from("alexanderjackson/f2"),
+            tag: String::from("1"),
+            replicas: ReplicaCount::try_from(2)?,
+            ..Default::default()
+        };
+
+        let diff = Diff::Removal {
+            name: String::from("backend"),
+        };
+
+        let trigger = DeployTrigger::new(Uuid::new_v4(), "test");
+        let event = DeployEvent::new(&diff, Some(&previous), trigger);
+
+        assert_eq!(event.kind, DeployEventKind::Removal);
+        assert_eq!(event.old_image.as_deref(), Some("alexanderjackson/f2:1"));
+        assert_eq!(event.old_replicas, Some(2));
+        assert_eq!(event.new_image, None);
+
+        Ok(())
+    }
+}
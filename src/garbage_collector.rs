@@ -0,0 +1,79 @@
+//! Removing the stopped containers and unused images that deploys leave behind, so hosts don't
+//! fill their disks over time.
+
+use std::sync::Arc;
+use std::time::Duration;
+
+use arc_swap::ArcSwap;
+use color_eyre::eyre::Result;
+
+use crate::config::Config;
+use crate::docker::api::SERVICE_LABEL;
+use crate::docker::client::DockerClient;
+
+/// How often to check whether garbage collection has been enabled by a reconciliation.
+const DISABLED_POLL_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Collects garbage on the configured interval for as long as f2 runs, following any changes to
+/// the configuration.
+pub async fn collect_garbage<C: DockerClient>(client: C, config: Arc<ArcSwap<Config>>) {
+    loop {
+        let garbage_collection = config.load().garbage_collection.clone();
+
+        let interval = match garbage_collection {
+            Some(garbage_collection) => {
+                if let Err(error) = prune(&client, garbage_collection.max_age()).await {
+                    tracing::warn!(?error, "failed to collect garbage, trying again later");
+                }
+
+                garbage_collection.interval()
+            }
+            None => DISABLED_POLL_INTERVAL,
+        };
+
+        tokio::time::sleep(interval).await;
+    }
+}
+
+/// Removes stopped containers that f2 created and dangling images, if they were created more than
+/// `max_age` ago.
+async fn prune<C: DockerClient>(client: &C, max_age: Duration) -> Result<()> {
+    let containers = client.prune_containers(SERVICE_LABEL, max_age).await?;
+    let images = client.prune_images(max_age).await?;
+
+    tracing::info!(
+        containers = %containers.deleted,
+        images = %images.deleted,
+        bytes = %(containers.space_reclaimed + images.space_reclaimed),
+        "collected garbage"
+    );
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use color_eyre::eyre::Result;
+
+    use crate::docker::api::SERVICE_LABEL;
+    use crate::garbage_collector::prune;
+    use crate::reconciler::tests::FakeDockerClient;
+
+    #[tokio::test]
+    async fn only_containers_created_by_f2_are_pruned() -> Result<()> {
+        let docker_client = FakeDockerClient::default();
+        let max_age = Duration::from_secs(60 * 60);
+
+        prune(&docker_client, max_age).await?;
+
+        assert_eq!(
+            docker_client.container_prunes().await,
+            vec![(SERVICE_LABEL.to_owned(), max_age)]
+        );
+        assert_eq!(docker_client.image_prunes().await, vec![max_age]);
+
+        Ok(())
+    }
+}
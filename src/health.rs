@@ -9,6 +9,7 @@ use hyper::Uri;
 use hyper_util::client::legacy::connect::HttpConnector;
 use hyper_util::client::legacy::Client;
 use hyper_util::rt::TokioExecutor;
+use rand::Rng;
 
 #[derive(Copy, Clone, Debug, Eq, PartialEq)]
 pub enum HealthCheckResult {
@@ -20,6 +21,7 @@ pub struct HealthCheckConfiguration {
     period: Duration,
     success_threshold: u32,
     failure_threshold: u32,
+    jitter: Duration,
 }
 
 impl HealthCheckConfiguration {
@@ -28,8 +30,15 @@ impl HealthCheckConfiguration {
             period,
             success_threshold,
             failure_threshold,
+            jitter: Duration::ZERO,
         }
     }
+
+    /// Adds up to `jitter` to each period, so checks for different containers drift apart.
+    pub fn with_jitter(mut self, jitter: Duration) -> Self {
+        self.jitter = jitter;
+        self
+    }
 }
 
 pub struct HealthCheck {
@@ -53,7 +62,8 @@ impl HealthCheck {
         let mut failures = 0;
 
         loop {
-            tokio::time::sleep(self.configuration.period).await;
+            let jitter = rand::thread_rng().gen_range(Duration::ZERO..=self.configuration.jitter);
+            tokio::time::sleep(self.configuration.period + jitter).await;
 
             match client.get(self.target.clone()).await {
                 Ok(res) if res.status().is_success() => successes += 1,
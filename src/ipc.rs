@@ -4,16 +4,32 @@ use color_eyre::eyre::{eyre, Result};
 use flume::{Receiver, Sender};
 use uuid::Uuid;
 
+use crate::events::DeployEvent;
+
 pub struct Message<T> {
     identifier: Uuid,
-    #[allow(dead_code)]
     content: T,
 }
 
+impl<T> Message<T> {
+    pub fn identifier(&self) -> Uuid {
+        self.identifier
+    }
+
+    pub fn into_content(self) -> T {
+        self.content
+    }
+}
+
 #[derive(Debug)]
-pub struct CertificateUpdateRequest;
+pub struct CertificateUpdateRequest {
+    /// The domain to refresh the certificate for, or all of them if unset.
+    pub domain: Option<String>,
+}
 #[derive(Debug)]
 pub struct ReconciliationRequest;
+#[derive(Debug)]
+pub struct PoolEvictionRequest;
 
 #[derive(Debug)]
 pub struct ChannelPair<T> {
@@ -33,16 +49,22 @@ impl<T> ChannelPair<T> {
 pub struct MessageBus {
     reconciliation: ChannelPair<ReconciliationRequest>,
     resolver: ChannelPair<CertificateUpdateRequest>,
+    pool_eviction: ChannelPair<PoolEvictionRequest>,
+    deploy_events: ChannelPair<DeployEvent>,
 }
 
 impl MessageBus {
     pub fn new() -> Arc<Self> {
         let reconciliation_pair = ChannelPair::<ReconciliationRequest>::new();
         let resolver_pair = ChannelPair::<CertificateUpdateRequest>::new();
+        let pool_eviction_pair = ChannelPair::<PoolEvictionRequest>::new();
+        let deploy_events_pair = ChannelPair::<DeployEvent>::new();
 
         let message_bus = MessageBus {
             reconciliation: reconciliation_pair,
             resolver: resolver_pair,
+            pool_eviction: pool_eviction_pair,
+            deploy_events: deploy_events_pair,
         };
 
         Arc::new(message_bus)
@@ -65,11 +87,11 @@ impl MessageBus {
         Ok(identifier)
     }
 
-    pub fn send_certificate_update_request(&self) -> Result<Uuid> {
+    pub fn send_certificate_update_request(&self, domain: Option<String>) -> Result<Uuid> {
         let identifier = Uuid::new_v4();
         let message = Message {
             identifier,
-            content: CertificateUpdateRequest,
+            content: CertificateUpdateRequest { domain },
         };
 
         tracing::debug!(%identifier, "sending certificate update request");
@@ -82,6 +104,40 @@ impl MessageBus {
         Ok(identifier)
     }
 
+    pub fn send_pool_eviction_request(&self) -> Result<Uuid> {
+        let identifier = Uuid::new_v4();
+        let message = Message {
+            identifier,
+            content: PoolEvictionRequest,
+        };
+
+        tracing::debug!(%identifier, "sending pool eviction request");
+
+        self.pool_eviction
+            .sender
+            .send(message)
+            .map_err(|_| eyre!("Failed to send pool eviction request"))?;
+
+        Ok(identifier)
+    }
+
+    pub fn send_deploy_event(&self, event: DeployEvent) -> Result<Uuid> {
+        let identifier = Uuid::new_v4();
+        let message = Message {
+            identifier,
+            content: event,
+        };
+
+        tracing::debug!(%identifier, "sending deploy event");
+
+        self.deploy_events
+            .sender
+            .send(message)
+            .map_err(|_| eyre!("Failed to send deploy event"))?;
+
+        Ok(identifier)
+    }
+
     pub async fn receive_reconciliation_request(
         &self,
     ) -> Result<Message<ReconciliationRequest>, flume::RecvError> {
@@ -101,6 +157,24 @@ impl MessageBus {
 
         Ok(received)
     }
+
+    pub async fn receive_pool_eviction_request(
+        &self,
+    ) -> Result<Message<PoolEvictionRequest>, flume::RecvError> {
+        let received = self.pool_eviction.receiver.recv_async().await?;
+
+        tracing::debug!(%received.identifier, "received pool eviction request");
+
+        Ok(received)
+    }
+
+    pub async fn receive_deploy_event(&self) -> Result<Message<DeployEvent>, flume::RecvError> {
+        let received = self.deploy_events.receiver.recv_async().await?;
+
+        tracing::debug!(%received.identifier, "received deploy event");
+
+        Ok(received)
+    }
 }
 
 #[cfg(test)]
@@ -125,11 +199,23 @@ mod tests {
     async fn can_send_and_receive_certificate_update_requests() -> Result<()> {
         let message_bus = MessageBus::new();
 
-        let sent = message_bus.send_certificate_update_request()?;
+        let sent = message_bus.send_certificate_update_request(None)?;
         let received = message_bus.receive_certificate_update_request().await?;
 
         assert_eq!(sent, received.identifier);
 
         Ok(())
     }
+
+    #[tokio::test]
+    async fn can_send_and_receive_pool_eviction_requests() -> Result<()> {
+        let message_bus = MessageBus::new();
+
+        let sent = message_bus.send_pool_eviction_request()?;
+        let received = message_bus.receive_pool_eviction_request().await?;
+
+        assert_eq!(sent, received.identifier);
+
+        Ok(())
+    }
 }
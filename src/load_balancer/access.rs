@@ -0,0 +1,66 @@
+use std::net::IpAddr;
+
+use crate::config::AccessControl;
+
+/// Checks whether a client can make requests under the policy.
+///
+/// Clients with an unknown address are only refused if the policy has an allowlist, as they cannot
+/// be shown to belong to it.
+pub fn is_allowed(policy: &AccessControl, addr: Option<IpAddr>) -> bool {
+    let Some(addr) = addr else {
+        return policy.allow.is_empty();
+    };
+
+    let denied = policy.deny.iter().any(|network| network.contains(addr));
+    let allowed =
+        policy.allow.is_empty() || policy.allow.iter().any(|network| network.contains(addr));
+
+    allowed && !denied
+}
+
+#[cfg(test)]
+mod tests {
+    use color_eyre::eyre::Result;
+
+    use crate::config::{AccessControl, IpNetwork};
+    use crate::load_balancer::access::is_allowed;
+
+    fn networks(values: &[&str]) -> Result<Vec<IpNetwork>> {
+        values
+            .iter()
+            .map(|value| IpNetwork::try_from(value.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn denied_networks_take_precedence_over_allowed_ones() -> Result<()> {
+        let policy = AccessControl {
+            allow: networks(&["10.0.0.0/8"])?,
+            deny: networks(&["10.0.1.0/24"])?,
+        };
+
+        assert!(is_allowed(&policy, Some("10.0.0.1".parse()?)));
+        assert!(!is_allowed(&policy, Some("10.0.1.1".parse()?)));
+        assert!(!is_allowed(&policy, Some("192.168.0.1".parse()?)));
+
+        Ok(())
+    }
+
+    #[test]
+    fn unknown_clients_are_only_refused_by_allowlists() -> Result<()> {
+        let denylist = AccessControl {
+            allow: Vec::new(),
+            deny: networks(&["10.0.0.0/8"])?,
+        };
+
+        let allowlist = AccessControl {
+            allow: networks(&["10.0.0.0/8"])?,
+            deny: Vec::new(),
+        };
+
+        assert!(is_allowed(&denylist, None));
+        assert!(!is_allowed(&allowlist, None));
+
+        Ok(())
+    }
+}
@@ -0,0 +1,366 @@
+//! A client for the parts of RFC 8555 needed to issue certificates with HTTP-01 challenges.
+
+use std::time::Duration;
+
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
+use color_eyre::eyre::{eyre, Result};
+use http::header::{CONTENT_TYPE, LOCATION, RETRY_AFTER};
+use http::{Method, Request, Response};
+use http_body_util::{BodyExt, Full};
+use hyper::body::Bytes;
+use hyper_rustls::{HttpsConnector, HttpsConnectorBuilder};
+use hyper_util::client::legacy::connect::HttpConnector;
+use hyper_util::client::legacy::Client;
+use hyper_util::rt::TokioExecutor;
+use ring::digest::{digest, SHA256};
+use ring::rand::SystemRandom;
+use ring::signature::{
+    EcdsaKeyPair, KeyPair, ECDSA_P256_SHA256_ASN1_SIGNING, ECDSA_P256_SHA256_FIXED_SIGNING,
+};
+use rustls::crypto::ring::default_provider;
+use serde::de::DeserializeOwned;
+use serde::Deserialize;
+use serde_json::{json, Value};
+
+use crate::load_balancer::acme::{pem, Challenges};
+use crate::load_balancer::der::{certification_request, certification_request_info};
+
+/// How long to wait for the certificate authority to respond to a single request.
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// How many times to check on a pending authorization or order before giving up.
+const POLL_ATTEMPTS: usize = 10;
+
+/// How long to wait between checks if the certificate authority does not say.
+const POLL_INTERVAL: Duration = Duration::from_secs(2);
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Directory {
+    new_nonce: String,
+    new_account: String,
+    new_order: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct Order {
+    status: String,
+    authorizations: Vec<String>,
+    finalize: String,
+    certificate: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct Authorization {
+    status: String,
+    challenges: Vec<Challenge>,
+}
+
+#[derive(Debug, Deserialize)]
+struct Challenge {
+    #[serde(rename = "type")]
+    kind: String,
+    url: String,
+    token: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct Problem {
+    #[serde(rename = "type")]
+    kind: String,
+    detail: Option<String>,
+}
+
+/// A certificate chain and its private key, both PEM encoded.
+#[derive(Debug)]
+pub struct IssuedCertificate {
+    pub chain: Vec<u8>,
+    pub key: Vec<u8>,
+}
+
+pub struct AcmeClient {
+    http: Client<HttpsConnector<HttpConnector>, Full<Bytes>>,
+    rng: SystemRandom,
+    key: EcdsaKeyPair,
+    directory: Directory,
+    account: String,
+    nonce: Option<String>,
+}
+
+impl AcmeClient {
+    /// Finds the endpoints of the certificate authority and registers the account with it, which
+    /// returns the existing account if the key has been used before.
+    pub async fn connect(directory: &str, email: &str, account_key: &[u8]) -> Result<Self> {
+        let rng = SystemRandom::new();
+        let key = EcdsaKeyPair::from_pkcs8(&ECDSA_P256_SHA256_FIXED_SIGNING, account_key, &rng)
+            .map_err(|e| eyre!("failed to parse the acme account key: {e}"))?;
+
+        let connector = HttpsConnectorBuilder::new()
+            .with_provider_and_webpki_roots(default_provider())?
+            .https_or_http()
+            .enable_http1()
+            .build();
+
+        let http = Client::builder(TokioExecutor::new()).build(connector);
+
+        let request = Request::get(directory).body(Full::default())?;
+        let response = send(&http, request).await?;
+        let directory = parse(response.body())?;
+
+        let mut client = Self {
+            http,
+            rng,
+            key,
+            directory,
+            account: String::new(),
+            nonce: None,
+        };
+
+        let payload = json!({
+            "termsOfServiceAgreed": true,
+            "contact": [format!("mailto:{email}")],
+        });
+
+        let url = client.directory.new_account.clone();
+        let response = client.post(&url, Some(&payload)).await?;
+
+        client.account = location(&response)?;
+
+        tracing::debug!(account = %client.account, "registered with the acme directory");
+
+        Ok(client)
+    }
+
+    /// Orders a certificate for the domain, answering its challenges through `challenges`.
+    pub async fn issue(
+        &mut self,
+        domain: &str,
+        challenges: &Challenges,
+    ) -> Result<IssuedCertificate> {
+        let payload = json!({ "identifiers": [{ "type": "dns", "value": domain }] });
+
+        let url = self.directory.new_order.clone();
+        let response = self.post(&url, Some(&payload)).await?;
+        let order_url = location(&response)?;
+        let order: Order = parse(response.body())?;
+
+        for authorization in &order.authorizations {
+            self.authorize(authorization, challenges).await?;
+        }
+
+        let rng = SystemRandom::new();
+        let pkcs8 = EcdsaKeyPair::generate_pkcs8(&ECDSA_P256_SHA256_ASN1_SIGNING, &rng)?;
+        let key = EcdsaKeyPair::from_pkcs8(&ECDSA_P256_SHA256_ASN1_SIGNING, pkcs8.as_ref(), &rng)?;
+
+        let info = certification_request_info(domain, key.public_key().as_ref());
+        let signature = key.sign(&rng, &info)?;
+        let csr = certification_request(&info, signature.as_ref());
+
+        let payload = json!({ "csr": URL_SAFE_NO_PAD.encode(csr) });
+        self.post(&order.finalize, Some(&payload)).await?;
+
+        let order: Order = self.poll(&order_url, |order: &Order| &order.status).await?;
+
+        let certificate = match (order.status.as_str(), order.certificate) {
+            ("valid", Some(certificate)) => certificate,
+            (status, _) => return Err(eyre!("order for {domain} ended up {status}")),
+        };
+
+        let response = self.post(&certificate, None).await?;
+
+        Ok(IssuedCertificate {
+            chain: response.into_body().to_vec(),
+            key: pem("PRIVATE KEY", pkcs8.as_ref()).into_bytes(),
+        })
+    }
+
+    async fn authorize(&mut self, url: &str, challenges: &Challenges) -> Result<()> {
+        let response = self.post(url, None).await?;
+        let authorization: Authorization = parse(response.body())?;
+
+        if authorization.status == "valid" {
+            return Ok(());
+        }
+
+        let challenge = authorization
+            .challenges
+            .into_iter()
+            .find(|challenge| challenge.kind == "http-01")
+            .ok_or_else(|| eyre!("authorization at {url} has no http-01 challenge"))?;
+
+        challenges.insert(&challenge.token, self.key_authorization(&challenge.token));
+
+        let result = async {
+            self.post(&challenge.url, Some(&json!({}))).await?;
+
+            self.poll(url, |authorization: &Authorization| &authorization.status)
+                .await
+        }
+        .await;
+
+        challenges.remove(&challenge.token);
+
+        match result?.status.as_str() {
+            "valid" => Ok(()),
+            status => Err(eyre!("authorization at {url} ended up {status}")),
+        }
+    }
+
+    /// Fetches a resource until it is no longer pending or processing.
+    async fn poll<T, F>(&mut self, url: &str, status: F) -> Result<T>
+    where
+        T: DeserializeOwned,
+        F: Fn(&T) -> &String,
+    {
+        for _ in 0..POLL_ATTEMPTS {
+            let response = self.post(url, None).await?;
+            let resource: T = parse(response.body())?;
+
+            if !matches!(status(&resource).as_str(), "pending" | "processing") {
+                return Ok(resource);
+            }
+
+            let delay = response
+                .headers()
+                .get(RETRY_AFTER)
+                .and_then(|value| value.to_str().ok()?.parse().ok())
+                .map_or(POLL_INTERVAL, Duration::from_secs);
+
+            tokio::time::sleep(delay).await;
+        }
+
+        Err(eyre!("gave up waiting for {url} to be processed"))
+    }
+
+    /// Sends a signed request, or a request to fetch a resource if there is no payload, retrying
+    /// once if the certificate authority rejects the nonce.
+    async fn post(&mut self, url: &str, payload: Option<&Value>) -> Result<Response<Bytes>> {
+        let mut retried = false;
+
+        loop {
+            let body = self.sign(url, payload).await?;
+
+            let request = Request::post(url)
+                .header(CONTENT_TYPE, "application/jose+json")
+                .body(Full::new(Bytes::from(body.to_string())))?;
+
+            let response = send(&self.http, request).await?;
+            self.remember_nonce(&response);
+
+            if response.status().is_success() {
+                return Ok(response);
+            }
+
+            let problem: Problem = parse(response.body())?;
+
+            if problem.kind == "urn:ietf:params:acme:error:badNonce" && !retried {
+                retried = true;
+                continue;
+            }
+
+            return Err(eyre!(
+                "acme request to {url} failed with {}: {}",
+                problem.kind,
+                problem.detail.unwrap_or_default()
+            ));
+        }
+    }
+
+    async fn sign(&mut self, url: &str, payload: Option<&Value>) -> Result<Value> {
+        let nonce = match self.nonce.take() {
+            Some(nonce) => nonce,
+            None => self.fetch_nonce().await?,
+        };
+
+        // The account is identified by its key until it has been registered
+        let mut protected = json!({ "alg": "ES256", "nonce": nonce, "url": url });
+
+        match self.account.is_empty() {
+            true => protected["jwk"] = serde_json::from_str(&self.jwk())?,
+            false => protected["kid"] = Value::from(self.account.as_str()),
+        }
+
+        let protected = URL_SAFE_NO_PAD.encode(protected.to_string());
+        let payload = payload.map_or_else(String::new, |payload| {
+            URL_SAFE_NO_PAD.encode(payload.to_string())
+        });
+
+        let signature = self
+            .key
+            .sign(&self.rng, format!("{protected}.{payload}").as_bytes())?;
+
+        Ok(json!({
+            "protected": protected,
+            "payload": payload,
+            "signature": URL_SAFE_NO_PAD.encode(signature),
+        }))
+    }
+
+    async fn fetch_nonce(&mut self) -> Result<String> {
+        let request = Request::builder()
+            .method(Method::HEAD)
+            .uri(&self.directory.new_nonce)
+            .body(Full::default())?;
+
+        let response = send(&self.http, request).await?;
+        self.remember_nonce(&response);
+
+        self.nonce
+            .take()
+            .ok_or_else(|| eyre!("acme directory did not provide a nonce"))
+    }
+
+    fn remember_nonce(&mut self, response: &Response<Bytes>) {
+        if let Some(nonce) = response
+            .headers()
+            .get("replay-nonce")
+            .and_then(|value| value.to_str().ok())
+        {
+            self.nonce = Some(nonce.to_owned());
+        }
+    }
+
+    /// The public half of the account key, with its members in the order needed for thumbprints.
+    fn jwk(&self) -> String {
+        // Uncompressed points are a marker byte followed by the x and y coordinates
+        let point = self.key.public_key().as_ref();
+        let x = URL_SAFE_NO_PAD.encode(&point[1..33]);
+        let y = URL_SAFE_NO_PAD.encode(&point[33..]);
+
+        format!(r#"{{"crv":"P-256","kty":"EC","x":"{x}","y":"{y}"}}"#)
+    }
+
+    /// The response expected for a challenge token, which proves control of the account key.
+    fn key_authorization(&self, token: &str) -> String {
+        let thumbprint = digest(&SHA256, self.jwk().as_bytes());
+
+        format!("{token}.{}", URL_SAFE_NO_PAD.encode(thumbprint))
+    }
+}
+
+async fn send(
+    http: &Client<HttpsConnector<HttpConnector>, Full<Bytes>>,
+    request: Request<Full<Bytes>>,
+) -> Result<Response<Bytes>> {
+    let response = tokio::time::timeout(REQUEST_TIMEOUT, http.request(request)).await??;
+    let (parts, body) = response.into_parts();
+    let body = tokio::time::timeout(REQUEST_TIMEOUT, body.collect())
+        .await??
+        .to_bytes();
+
+    Ok(Response::from_parts(parts, body))
+}
+
+fn parse<T: DeserializeOwned>(body: &Bytes) -> Result<T> {
+    Ok(serde_json::from_slice(body)?)
+}
+
+fn location(response: &Response<Bytes>) -> Result<String> {
+    let location = response
+        .headers()
+        .get(LOCATION)
+        .ok_or_else(|| eyre!("acme response did not include a location"))?;
+
+    Ok(location.to_str()?.to_owned())
+}
@@ -0,0 +1,473 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use chrono::Utc;
+use color_eyre::eyre::Result;
+use hyper::body::Incoming;
+use ring::rand::SystemRandom;
+use ring::signature::{EcdsaKeyPair, ECDSA_P256_SHA256_FIXED_SIGNING};
+use rustls::pki_types::pem::PemObject;
+use rustls::pki_types::{CertificateDer, PrivatePkcs8KeyDer};
+
+use crate::config::{AcmeConfig, TlsConfig, TlsSecrets};
+use crate::load_balancer::acme::client::AcmeClient;
+use crate::load_balancer::der;
+use crate::load_balancer::proxy::Context;
+
+mod client;
+
+/// Where certificate authorities fetch HTTP-01 challenge responses from.
+pub const CHALLENGE_PATH_PREFIX: &str = "/.well-known/acme-challenge/";
+
+/// How often to check whether managed certificates need renewing.
+const RENEWAL_CHECK_INTERVAL: Duration = Duration::from_secs(12 * 60 * 60);
+
+/// The responses to HTTP-01 challenges currently being validated, keyed by their token.
+#[derive(Debug, Default)]
+pub struct Challenges {
+    tokens: Mutex<HashMap<String, String>>,
+}
+
+impl Challenges {
+    pub fn insert(&self, token: &str, key_authorization: String) {
+        self.tokens
+            .lock()
+            .unwrap()
+            .insert(token.to_owned(), key_authorization);
+    }
+
+    pub fn remove(&self, token: &str) {
+        self.tokens.lock().unwrap().remove(token);
+    }
+
+    /// Finds the response to a challenge from the path it was requested at.
+    pub fn respond(&self, path: &str) -> Option<String> {
+        let token = path.strip_prefix(CHALLENGE_PATH_PREFIX)?;
+
+        self.tokens.lock().unwrap().get(token).cloned()
+    }
+}
+
+/// Encodes DER in the textual format certificates and keys are stored in.
+fn pem(label: &str, der: &[u8]) -> String {
+    let encoded = STANDARD.encode(der);
+    let mut pem = format!("-----BEGIN {label}-----\n");
+
+    for line in encoded.as_bytes().chunks(64) {
+        pem.push_str(std::str::from_utf8(line).unwrap());
+        pem.push('\n');
+    }
+
+    pem.push_str(&format!("-----END {label}-----\n"));
+    pem
+}
+
+/// Resolves the account key, generating and storing one if it does not exist yet.
+///
+/// Any other failure to read the key is returned, since replacing it would lose the account.
+async fn account_key(config: &AcmeConfig) -> Result<Vec<u8>> {
+    if let Some(bytes) = config.account_key.resolve_if_exists().await? {
+        let key = PrivatePkcs8KeyDer::from_pem_slice(&bytes)?;
+
+        return Ok(key.secret_pkcs8_der().to_vec());
+    }
+
+    tracing::info!(account_key = ?config.account_key, "generating a new acme account key");
+
+    let pkcs8 =
+        EcdsaKeyPair::generate_pkcs8(&ECDSA_P256_SHA256_FIXED_SIGNING, &SystemRandom::new())?;
+
+    config
+        .account_key
+        .store(pem("PRIVATE KEY", pkcs8.as_ref()).into_bytes())
+        .await?;
+
+    Ok(pkcs8.as_ref().to_vec())
+}
+
+/// Checks whether a domain has no certificate yet or one expiring within the renewal window.
+async fn needs_renewal(config: &AcmeConfig, secrets: &TlsSecrets) -> bool {
+    let Ok((chain, _)) = secrets.resolve_files().await else {
+        return true;
+    };
+
+    let Ok(certificate) = CertificateDer::from_pem_slice(&chain) else {
+        return true;
+    };
+
+    match der::not_after(&certificate) {
+        Ok(not_after) => {
+            not_after - Utc::now()
+                < chrono::Duration::from_std(config.renew_before()).unwrap_or_default()
+        }
+        Err(_) => true,
+    }
+}
+
+/// Issues certificates for any managed domains that need them, returning the domains that were
+/// updated.
+async fn renew_due_certificates(
+    config: &AcmeConfig,
+    domains: &[(&String, &TlsSecrets)],
+    challenges: &Challenges,
+) -> Result<Vec<String>> {
+    let mut due = Vec::new();
+
+    for (domain, secrets) in domains {
+        if needs_renewal(config, secrets).await {
+            due.push((*domain, *secrets));
+        }
+    }
+
+    if due.is_empty() {
+        return Ok(Vec::new());
+    }
+
+    let account_key = account_key(config).await?;
+    let mut client = AcmeClient::connect(&config.directory, &config.email, &account_key).await?;
+    let mut renewed = Vec::new();
+
+    for (domain, secrets) in due {
+        tracing::info!(%domain, "requesting a certificate over acme");
+
+        match client.issue(domain, challenges).await {
+            Ok(certificate) => {
+                secrets
+                    .store_files(certificate.chain, certificate.key)
+                    .await?;
+
+                renewed.push(domain.clone());
+            }
+            Err(error) => {
+                tracing::error!(%domain, %error, "failed to obtain a certificate over acme");
+            }
+        }
+    }
+
+    Ok(renewed)
+}
+
+/// Keeps the certificates for domains managed over ACME issued and renewed, asking the resolver
+/// to pick up any changes.
+pub async fn renew_certificates(context: Arc<Context<Incoming>>, tls: TlsConfig) {
+    let domains: Vec<_> = tls
+        .domains
+        .iter()
+        .filter(|(_, secrets)| secrets.acme)
+        .collect();
+
+    if domains.is_empty() {
+        return;
+    }
+
+    let Some(config) = tls.acme.as_ref() else {
+        tracing::warn!("domains are managed over acme, but no acme account is configured");
+        return;
+    };
+
+    loop {
+        match renew_due_certificates(config, &domains, &context.acme_challenges).await {
+            Ok(renewed) => {
+                for domain in renewed {
+                    if let Err(error) = context
+                        .message_bus
+                        .send_certificate_update_request(Some(domain))
+                    {
+                        tracing::error!(%error, "failed to request a certificate update");
+                    }
+                }
+            }
+            Err(error) => {
+                tracing::error!(%error, "failed to renew certificates over acme");
+            }
+        }
+
+        tokio::time::sleep(RENEWAL_CHECK_INTERVAL).await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::{Ipv4Addr, SocketAddrV4};
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::sync::{Arc, Mutex};
+
+    use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+    use base64::Engine;
+    use color_eyre::eyre::Result;
+    use http::{Method, Request, Response, StatusCode};
+    use http_body_util::{BodyExt, Full};
+    use hyper::body::{Bytes, Incoming};
+    use hyper::service::service_fn;
+    use hyper_util::rt::{TokioExecutor, TokioIo};
+    use hyper_util::server::conn::auto::Builder;
+    use ring::signature::{UnparsedPublicKey, ECDSA_P256_SHA256_FIXED};
+    use rustls::pki_types::pem::PemObject;
+    use rustls::pki_types::PrivatePkcs8KeyDer;
+    use serde_json::{json, Value};
+    use tokio::net::TcpListener;
+
+    use crate::config::{AcmeConfig, ExternalBytes, TlsSecrets};
+    use crate::load_balancer::acme::{renew_due_certificates, Challenges, CHALLENGE_PATH_PREFIX};
+
+    const DOMAIN: &str = "opentracker.app";
+    const TOKEN: &str = "challenge-token";
+    const CERTIFICATE: &[u8] = include_bytes!("../../../resources/certificates/new.crt");
+
+    /// A certificate authority that checks requests are signed by the account and that the
+    /// challenge is being answered before issuing a fixed certificate.
+    struct MockAuthority {
+        base: String,
+        challenges: Arc<Challenges>,
+        account_key: Mutex<Option<Vec<u8>>>,
+        rejected_nonce: AtomicBool,
+        validated: AtomicBool,
+    }
+
+    impl MockAuthority {
+        fn verify(&self, path: &str, body: &[u8]) -> Result<Value> {
+            let body: Value = serde_json::from_slice(body)?;
+            let field = |name: &str| body[name].as_str().unwrap_or_default().to_owned();
+
+            let protected: Value =
+                serde_json::from_slice(&URL_SAFE_NO_PAD.decode(field("protected"))?)?;
+
+            assert_eq!(protected["alg"], "ES256");
+            assert_eq!(protected["url"], format!("{}{path}", self.base));
+            assert!(protected["nonce"].is_string());
+
+            let mut account_key = self.account_key.lock().unwrap();
+
+            match protected["kid"].as_str() {
+                Some(kid) => assert_eq!(kid, format!("{}/account/1", self.base)),
+                None => {
+                    let coordinate = |name: &str| {
+                        URL_SAFE_NO_PAD.decode(protected["jwk"][name].as_str().unwrap())
+                    };
+
+                    *account_key = Some([vec![4], coordinate("x")?, coordinate("y")?].concat());
+                }
+            }
+
+            let public_key =
+                UnparsedPublicKey::new(&ECDSA_P256_SHA256_FIXED, account_key.clone().unwrap());
+            let message = format!("{}.{}", field("protected"), field("payload"));
+
+            assert!(public_key
+                .verify(
+                    message.as_bytes(),
+                    &URL_SAFE_NO_PAD.decode(field("signature"))?
+                )
+                .is_ok());
+
+            match field("payload").as_str() {
+                "" => Ok(Value::Null),
+                payload => Ok(serde_json::from_slice(&URL_SAFE_NO_PAD.decode(payload)?)?),
+            }
+        }
+
+        fn order(&self, status: &str) -> Value {
+            json!({
+                "status": status,
+                "authorizations": [format!("{}/authz/1", self.base)],
+                "finalize": format!("{}/finalize", self.base),
+                "certificate": format!("{}/certificate", self.base),
+            })
+        }
+
+        async fn handle(&self, req: Request<Incoming>) -> Result<Response<Full<Bytes>>> {
+            let path = req.uri().path().to_owned();
+            let method = req.method().clone();
+            let body = req.into_body().collect().await?.to_bytes();
+
+            let response = Response::builder().header("replay-nonce", "nonce");
+
+            let (status, location, body) = match (method, path.as_str()) {
+                (Method::GET, "/directory") => (
+                    StatusCode::OK,
+                    None,
+                    json!({
+                        "newNonce": format!("{}/nonce", self.base),
+                        "newAccount": format!("{}/account", self.base),
+                        "newOrder": format!("{}/order", self.base),
+                    }),
+                ),
+                (Method::HEAD, "/nonce") => (StatusCode::OK, None, Value::Null),
+                (Method::POST, "/order") if !self.rejected_nonce.swap(true, Ordering::SeqCst) => (
+                    StatusCode::BAD_REQUEST,
+                    None,
+                    json!({ "type": "urn:ietf:params:acme:error:badNonce" }),
+                ),
+                (Method::POST, path) => {
+                    let payload = self.verify(path, &body)?;
+
+                    match path {
+                        "/account" => (
+                            StatusCode::CREATED,
+                            Some("/account/1"),
+                            json!({ "status": "valid" }),
+                        ),
+                        "/order" => {
+                            assert_eq!(payload["identifiers"][0]["value"], DOMAIN);
+
+                            (StatusCode::CREATED, Some("/order/1"), self.order("pending"))
+                        }
+                        "/authz/1" => {
+                            let status = match self.validated.load(Ordering::SeqCst) {
+                                true => "valid",
+                                false => "pending",
+                            };
+
+                            let challenges = json!([{
+                                "type": "http-01",
+                                "url": format!("{}/challenge/1", self.base),
+                                "token": TOKEN,
+                            }]);
+
+                            (
+                                StatusCode::OK,
+                                None,
+                                json!({ "status": status, "challenges": challenges }),
+                            )
+                        }
+                        "/challenge/1" => {
+                            let path = format!("{CHALLENGE_PATH_PREFIX}{TOKEN}");
+                            let response = self.challenges.respond(&path).unwrap();
+
+                            assert!(response.starts_with(&format!("{TOKEN}.")));
+                            self.validated.store(true, Ordering::SeqCst);
+
+                            (StatusCode::OK, None, json!({}))
+                        }
+                        "/finalize" => {
+                            assert!(payload["csr"].is_string());
+
+                            (StatusCode::OK, None, self.order("processing"))
+                        }
+                        "/order/1" => (StatusCode::OK, None, self.order("valid")),
+                        "/certificate" => {
+                            let response = response.status(StatusCode::OK);
+
+                            return Ok(response.body(Full::new(Bytes::from_static(CERTIFICATE)))?);
+                        }
+                        path => panic!("unexpected request to {path}"),
+                    }
+                }
+                (method, path) => panic!("unexpected {method} request to {path}"),
+            };
+
+            let response = match location {
+                Some(location) => response.header("location", format!("{}{location}", self.base)),
+                None => response,
+            };
+
+            let body = match body {
+                Value::Null => Bytes::new(),
+                body => Bytes::from(body.to_string()),
+            };
+
+            Ok(response.status(status).body(Full::new(body))?)
+        }
+    }
+
+    async fn spawn_mock_authority(challenges: Arc<Challenges>) -> Result<String> {
+        let addr = SocketAddrV4::new(Ipv4Addr::LOCALHOST, 0);
+        let listener = TcpListener::bind(&addr).await?;
+
+        let authority = Arc::new(MockAuthority {
+            base: format!("http://{}", listener.local_addr()?),
+            challenges,
+            account_key: Mutex::new(None),
+            rejected_nonce: AtomicBool::new(false),
+            validated: AtomicBool::new(false),
+        });
+
+        let directory = format!("{}/directory", authority.base);
+
+        tokio::spawn(async move {
+            loop {
+                let (stream, _) = listener.accept().await.unwrap();
+                let io = TokioIo::new(stream);
+                let authority = Arc::clone(&authority);
+
+                tokio::spawn(async move {
+                    Builder::new(TokioExecutor::new())
+                        .serve_connection(
+                            io,
+                            service_fn(move |req| {
+                                let authority = Arc::clone(&authority);
+
+                                async move { authority.handle(req).await }
+                            }),
+                        )
+                        .await
+                        .unwrap();
+                });
+            }
+        });
+
+        Ok(directory)
+    }
+
+    #[test]
+    fn challenges_are_answered_by_token() {
+        let challenges = Challenges::default();
+        challenges.insert(TOKEN, String::from("key-authorization"));
+
+        assert_eq!(
+            challenges.respond(&format!("{CHALLENGE_PATH_PREFIX}{TOKEN}")),
+            Some(String::from("key-authorization"))
+        );
+        assert_eq!(challenges.respond(&format!("/{TOKEN}")), None);
+
+        challenges.remove(TOKEN);
+
+        assert_eq!(
+            challenges.respond(&format!("{CHALLENGE_PATH_PREFIX}{TOKEN}")),
+            None
+        );
+    }
+
+    #[tokio::test]
+    async fn missing_certificates_are_issued_and_stored() -> Result<()> {
+        let challenges = Arc::new(Challenges::default());
+        let directory = spawn_mock_authority(Arc::clone(&challenges)).await?;
+
+        let dir = tempfile::tempdir()?;
+        let file = |name: &str| ExternalBytes::Filesystem {
+            path: dir.path().join(name),
+        };
+
+        let config = AcmeConfig {
+            email: String::from("admin@opentracker.app"),
+            directory,
+            account_key: file("account.pem"),
+            renew_before_days: 30,
+        };
+
+        let mut secrets = TlsSecrets::new(file("cert.pem"), file("key.pem"));
+        secrets.acme = true;
+
+        let domain = String::from(DOMAIN);
+        let renewed = renew_due_certificates(&config, &[(&domain, &secrets)], &challenges).await?;
+
+        assert_eq!(renewed, vec![domain]);
+
+        let (cert, key) = secrets.resolve_files().await?;
+
+        assert_eq!(cert, CERTIFICATE);
+        assert!(PrivatePkcs8KeyDer::from_pem_slice(&key).is_ok());
+        assert!(PrivatePkcs8KeyDer::from_pem_slice(&config.account_key.resolve().await?).is_ok());
+
+        // Challenges are only answered while they are being validated
+        assert_eq!(
+            challenges.respond(&format!("{CHALLENGE_PATH_PREFIX}{TOKEN}")),
+            None
+        );
+
+        Ok(())
+    }
+}
@@ -0,0 +1,175 @@
+use std::hash::{DefaultHasher, Hash, Hasher};
+
+use http::HeaderMap;
+use indexmap::IndexSet;
+
+use crate::config::Affinity;
+use crate::docker::api::StartedContainerDetails;
+
+/// Extracts the key used to pick a downstream for a request, if the request contains one.
+pub fn extract_key<'a>(
+    affinity: &Affinity,
+    path: &'a str,
+    headers: &'a HeaderMap,
+) -> Option<&'a str> {
+    match affinity {
+        Affinity::PathSegment { index } => path
+            .split('/')
+            .filter(|segment| !segment.is_empty())
+            .nth(*index),
+        Affinity::Header { name } => headers
+            .get(name.as_str())
+            .and_then(|value| value.to_str().ok())
+            .filter(|value| !value.is_empty()),
+    }
+}
+
+/// Picks the downstream with the highest score for the key, so that each key is consistently sent
+/// to the same downstream and only keys for removed downstreams are moved elsewhere.
+///
+/// Downstreams are scored by their `replica` where it is known, so a container replacing another
+/// in the same slot keeps its keys.
+pub fn select<'a, F>(
+    downstreams: &'a IndexSet<StartedContainerDetails>,
+    key: &str,
+    replica: F,
+) -> Option<&'a StartedContainerDetails>
+where
+    F: Fn(&StartedContainerDetails) -> Option<usize>,
+{
+    downstreams.iter().max_by_key(|downstream| {
+        let mut hasher = DefaultHasher::new();
+
+        key.hash(&mut hasher);
+
+        match replica(downstream) {
+            Some(replica) => replica.hash(&mut hasher),
+            None => downstream.id.hash(&mut hasher),
+        }
+
+        hasher.finish()
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashSet;
+    use std::net::Ipv4Addr;
+
+    use http::{HeaderMap, HeaderValue};
+    use indexmap::IndexSet;
+
+    use crate::config::Affinity;
+    use crate::docker::api::StartedContainerDetails;
+    use crate::docker::models::ContainerId;
+    use crate::load_balancer::affinity::{extract_key, select};
+
+    fn downstreams(count: u8) -> IndexSet<StartedContainerDetails> {
+        (0..count)
+            .map(|i| StartedContainerDetails {
+                id: ContainerId(format!("container-{i}")),
+                addr: Ipv4Addr::new(172, 17, 0, i).into(),
+            })
+            .collect()
+    }
+
+    /// Which replica a downstream is, from the end of its identifier.
+    fn replica(downstream: &StartedContainerDetails) -> Option<usize> {
+        downstream.id.0.rsplit_once('-')?.1.parse().ok()
+    }
+
+    #[test]
+    fn can_extract_path_segments() {
+        let affinity = Affinity::PathSegment { index: 1 };
+
+        let headers = HeaderMap::new();
+
+        assert_eq!(
+            extract_key(&affinity, "/users/42/posts", &headers),
+            Some("42")
+        );
+        assert_eq!(extract_key(&affinity, "/users/42", &headers), Some("42"));
+        assert_eq!(extract_key(&affinity, "/users", &headers), None);
+    }
+
+    #[test]
+    fn can_extract_header_values() {
+        let affinity = Affinity::Header {
+            name: "X-Tenant-Id".to_owned(),
+        };
+
+        let mut headers = HeaderMap::new();
+
+        assert_eq!(extract_key(&affinity, "/", &headers), None);
+
+        headers.insert("x-tenant-id", HeaderValue::from_static("acme"));
+
+        assert_eq!(extract_key(&affinity, "/", &headers), Some("acme"));
+    }
+
+    #[test]
+    fn requests_for_the_same_key_are_routed_consistently() {
+        let downstreams = downstreams(5);
+
+        let first = select(&downstreams, "42", replica);
+
+        for _ in 0..10 {
+            assert_eq!(select(&downstreams, "42", replica), first);
+        }
+    }
+
+    #[test]
+    fn requests_for_different_keys_are_distributed() {
+        let downstreams = downstreams(5);
+
+        let selected: HashSet<_> = (0..100)
+            .filter_map(|id| select(&downstreams, &id.to_string(), replica))
+            .map(|downstream| &downstream.id)
+            .collect();
+
+        assert!(selected.len() > 1);
+    }
+
+    #[test]
+    fn only_keys_for_removed_downstreams_are_moved() {
+        let original = downstreams(5);
+
+        let mut reduced = original.clone();
+        let removed = reduced.shift_remove_index(0).unwrap();
+
+        for id in 0..100 {
+            let key = id.to_string();
+            let before = select(&original, &key, replica).unwrap();
+
+            if *before != removed {
+                assert_eq!(select(&reduced, &key, replica), Some(before));
+            }
+        }
+    }
+
+    #[test]
+    fn keys_stay_with_the_replica_when_its_container_is_replaced() {
+        let original = downstreams(5);
+
+        // replica 2 is redeployed as a new container at a new address
+        let mut replaced = original.clone();
+        replaced.shift_remove_index(2);
+        replaced.insert(StartedContainerDetails {
+            id: ContainerId(String::from("replacement-2")),
+            addr: Ipv4Addr::new(172, 17, 0, 12).into(),
+        });
+
+        for id in 0..100 {
+            let key = id.to_string();
+            let before = select(&original, &key, replica).unwrap();
+            let after = select(&replaced, &key, replica).unwrap();
+
+            assert_eq!(replica(after), replica(before));
+        }
+    }
+
+    #[test]
+    fn no_downstream_is_selected_from_an_empty_set() {
+        assert_eq!(select(&IndexSet::new(), "42", replica), None);
+    }
+}
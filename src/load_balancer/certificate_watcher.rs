@@ -0,0 +1,232 @@
+//! Refreshes certificates automatically when their key material changes, rather than waiting for
+//! an update to be requested.
+
+use std::collections::{HashMap, HashSet};
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Duration;
+
+use color_eyre::eyre::{eyre, Result};
+use hyper::body::Incoming;
+use notify::{EventKind, RecommendedWatcher, RecursiveMode, Watcher};
+
+use crate::config::{ExternalBytes, TlsConfig, TlsSecrets};
+use crate::ipc::MessageBus;
+use crate::load_balancer::proxy::Context;
+use crate::load_balancer::tls::certificate_secrets;
+
+fn request_update(message_bus: &MessageBus, domain: &str) {
+    tracing::info!(%domain, "certificate changed, requesting an update");
+
+    if let Err(error) = message_bus.send_certificate_update_request(Some(domain.to_owned())) {
+        tracing::error!(%error, "failed to request a certificate update");
+    }
+}
+
+/// Watches the directories holding certificates and keys on the filesystem, requesting an update
+/// for a domain whenever one of its files is written.
+///
+/// Directories are watched rather than the files themselves, as files replaced by renaming would
+/// otherwise stop being watched.
+fn watch_files(
+    message_bus: Arc<MessageBus>,
+    domains: &HashMap<String, TlsSecrets>,
+) -> Result<Option<RecommendedWatcher>> {
+    let mut watched: HashMap<PathBuf, Vec<String>> = HashMap::new();
+
+    for (domain, secrets) in domains {
+        for file in secrets.files() {
+            let ExternalBytes::Filesystem { path } = file else {
+                continue;
+            };
+
+            let name = path
+                .file_name()
+                .ok_or_else(|| eyre!("{} is not a file", path.display()))?;
+
+            // Paths are compared against those in events, which are relative to the watched
+            // directory as it was given
+            let directory = match path.parent() {
+                Some(parent) if !parent.as_os_str().is_empty() => parent.canonicalize()?,
+                _ => std::env::current_dir()?,
+            };
+
+            watched
+                .entry(directory.join(name))
+                .or_default()
+                .push(domain.clone());
+        }
+    }
+
+    if watched.is_empty() {
+        return Ok(None);
+    }
+
+    let directories: HashSet<_> = watched
+        .keys()
+        .filter_map(|path| path.parent())
+        .map(PathBuf::from)
+        .collect();
+
+    let mut watcher = notify::recommended_watcher(move |event: notify::Result<notify::Event>| {
+        let event = match event {
+            Ok(event) => event,
+            Err(error) => {
+                tracing::warn!(%error, "failed to watch certificate files");
+                return;
+            }
+        };
+
+        if !matches!(event.kind, EventKind::Create(_) | EventKind::Modify(_)) {
+            return;
+        }
+
+        for domain in event
+            .paths
+            .iter()
+            .filter_map(|path| watched.get(path))
+            .flatten()
+        {
+            request_update(&message_bus, domain);
+        }
+    })?;
+
+    for directory in directories {
+        tracing::info!(directory = %directory.display(), "watching for certificate changes");
+
+        watcher.watch(&directory, RecursiveMode::NonRecursive)?;
+    }
+
+    Ok(Some(watcher))
+}
+
+/// Periodically resolves the certificates and keys stored in S3, requesting an update for a domain
+/// whenever they differ from the last time they were resolved.
+async fn poll_objects(
+    message_bus: Arc<MessageBus>,
+    certificates: &HashMap<String, TlsSecrets>,
+    poll_interval: Duration,
+) {
+    let domains: Vec<_> = certificates
+        .iter()
+        .filter(|(_, secrets)| {
+            secrets
+                .files()
+                .iter()
+                .any(|file| matches!(file, ExternalBytes::S3 { .. }))
+        })
+        .collect();
+
+    if domains.is_empty() {
+        return std::future::pending().await;
+    }
+
+    let mut previous = HashMap::new();
+
+    loop {
+        for (domain, secrets) in &domains {
+            let files = match secrets.resolve_files().await {
+                Ok(files) => files,
+                Err(error) => {
+                    tracing::warn!(%domain, %error, "failed to check certificate for changes");
+                    continue;
+                }
+            };
+
+            if previous.get(domain).is_some_and(|last| *last != files) {
+                request_update(&message_bus, domain);
+            }
+
+            previous.insert(*domain, files);
+        }
+
+        tokio::time::sleep(poll_interval).await;
+    }
+}
+
+/// Requests certificate updates whenever the files or objects they are stored in change.
+pub async fn watch_certificates(context: Arc<Context<Incoming>>, tls: TlsConfig) {
+    let message_bus = Arc::clone(&context.message_bus);
+    let certificates = certificate_secrets(&tls);
+
+    // The watcher stops once dropped, so it is held for as long as objects are being polled
+    let _watcher = match watch_files(Arc::clone(&message_bus), &certificates) {
+        Ok(watcher) => watcher,
+        Err(error) => {
+            tracing::error!(%error, "failed to watch certificate files for changes");
+            None
+        }
+    };
+
+    poll_objects(message_bus, &certificates, tls.poll_interval()).await;
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    use color_eyre::eyre::Result;
+
+    use crate::config::{ExternalBytes, TlsSecrets};
+    use crate::ipc::MessageBus;
+    use crate::load_balancer::certificate_watcher::watch_files;
+
+    const DOMAIN: &str = "new.example.com";
+
+    #[tokio::test]
+    async fn writing_certificates_requests_an_update() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let cert_path = dir.path().join("new.crt");
+        let key_path = dir.path().join("new.key");
+
+        tokio::fs::copy("resources/certificates/new.crt", &cert_path).await?;
+        tokio::fs::copy("resources/certificates/new.key", &key_path).await?;
+
+        let secrets = TlsSecrets::new(
+            ExternalBytes::Filesystem { path: cert_path },
+            ExternalBytes::Filesystem {
+                path: key_path.clone(),
+            },
+        );
+
+        let message_bus = MessageBus::new();
+        let domains = HashMap::from([(String::from(DOMAIN), secrets)]);
+        let _watcher = watch_files(Arc::clone(&message_bus), &domains)?;
+
+        // Files that do not hold key material are ignored
+        tokio::fs::write(dir.path().join("unrelated"), "").await?;
+        tokio::fs::copy("resources/certificates/old.key", &key_path).await?;
+
+        let request = tokio::time::timeout(
+            Duration::from_secs(5),
+            message_bus.receive_certificate_update_request(),
+        )
+        .await??;
+
+        assert_eq!(request.into_content().domain.as_deref(), Some(DOMAIN));
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn objects_are_not_watched() -> Result<()> {
+        let secrets = TlsSecrets::new(
+            ExternalBytes::S3 {
+                bucket: String::from("certificates"),
+                key: String::from("new.crt"),
+            },
+            ExternalBytes::S3 {
+                bucket: String::from("certificates"),
+                key: String::from("new.key"),
+            },
+        );
+
+        let domains = HashMap::from([(String::from(DOMAIN), secrets)]);
+
+        assert!(watch_files(MessageBus::new(), &domains)?.is_none());
+
+        Ok(())
+    }
+}
@@ -0,0 +1,176 @@
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::Instant;
+
+use crate::config::CircuitBreakerConfig;
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+enum Circuit {
+    Closed {
+        failures: u32,
+    },
+    Open {
+        since: Instant,
+    },
+    /// A single probe request has been let through to check whether the service has recovered.
+    HalfOpen {
+        since: Instant,
+    },
+}
+
+/// Tracks failing requests for each service, rejecting requests to those that keep failing
+/// rather than continuing to send them traffic.
+#[derive(Debug, Default)]
+pub struct CircuitBreakers {
+    circuits: Mutex<HashMap<String, Circuit>>,
+}
+
+impl CircuitBreakers {
+    /// Checks whether a request to the service should be sent downstream.
+    pub fn allow(&self, service: &str, config: &CircuitBreakerConfig) -> bool {
+        let mut circuits = self.circuits.lock().unwrap();
+
+        let Some(circuit) = circuits.get_mut(service) else {
+            return true;
+        };
+
+        match *circuit {
+            Circuit::Closed { .. } => true,
+            // probes that never report back should not hold the circuit half-open forever
+            Circuit::Open { since } | Circuit::HalfOpen { since }
+                if since.elapsed() >= config.open_duration() =>
+            {
+                *circuit = Circuit::HalfOpen {
+                    since: Instant::now(),
+                };
+
+                true
+            }
+            Circuit::Open { .. } | Circuit::HalfOpen { .. } => false,
+        }
+    }
+
+    /// Records the outcome of a request sent downstream for the service.
+    pub fn record(&self, service: &str, config: &CircuitBreakerConfig, success: bool) {
+        let mut circuits = self.circuits.lock().unwrap();
+
+        if success {
+            if let Some(Circuit::HalfOpen { .. }) = circuits.remove(service) {
+                tracing::info!(%service, "circuit closed after a successful probe");
+            }
+
+            return;
+        }
+
+        let circuit = circuits
+            .entry(service.to_owned())
+            .or_insert(Circuit::Closed { failures: 0 });
+
+        let tripped = match circuit {
+            Circuit::Closed { failures } => {
+                *failures += 1;
+                *failures >= config.failure_threshold
+            }
+            Circuit::HalfOpen { .. } => true,
+            Circuit::Open { .. } => false,
+        };
+
+        if tripped {
+            tracing::warn!(%service, "circuit opened after failed requests");
+
+            *circuit = Circuit::Open {
+                since: Instant::now(),
+            };
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use crate::config::CircuitBreakerConfig;
+    use crate::load_balancer::circuit_breaker::CircuitBreakers;
+
+    const SERVICE: &str = "backend";
+
+    fn config(open_ms: u64) -> CircuitBreakerConfig {
+        CircuitBreakerConfig {
+            failure_threshold: 3,
+            open_ms,
+        }
+    }
+
+    #[test]
+    fn circuits_open_after_consecutive_failures() {
+        let breakers = CircuitBreakers::default();
+        let config = config(60_000);
+
+        for _ in 0..2 {
+            breakers.record(SERVICE, &config, false);
+            assert!(breakers.allow(SERVICE, &config));
+        }
+
+        breakers.record(SERVICE, &config, false);
+
+        assert!(!breakers.allow(SERVICE, &config));
+        assert!(breakers.allow("other", &config));
+    }
+
+    #[test]
+    fn successes_reset_the_failure_count() {
+        let breakers = CircuitBreakers::default();
+        let config = config(60_000);
+
+        for _ in 0..2 {
+            breakers.record(SERVICE, &config, false);
+        }
+
+        breakers.record(SERVICE, &config, true);
+        breakers.record(SERVICE, &config, false);
+
+        assert!(breakers.allow(SERVICE, &config));
+    }
+
+    #[test]
+    fn a_single_probe_is_allowed_once_the_circuit_has_been_open_long_enough() {
+        let breakers = CircuitBreakers::default();
+        let config = config(10);
+
+        for _ in 0..3 {
+            breakers.record(SERVICE, &config, false);
+        }
+
+        assert!(!breakers.allow(SERVICE, &config));
+
+        std::thread::sleep(Duration::from_millis(20));
+
+        assert!(breakers.allow(SERVICE, &config));
+        assert!(!breakers.allow(SERVICE, &config));
+    }
+
+    #[test]
+    fn probes_close_or_reopen_the_circuit() {
+        let breakers = CircuitBreakers::default();
+        let config = config(10);
+
+        for _ in 0..3 {
+            breakers.record(SERVICE, &config, false);
+        }
+
+        std::thread::sleep(Duration::from_millis(20));
+
+        // a failed probe opens the circuit again straight away
+        assert!(breakers.allow(SERVICE, &config));
+        breakers.record(SERVICE, &config, false);
+        assert!(!breakers.allow(SERVICE, &config));
+
+        std::thread::sleep(Duration::from_millis(20));
+
+        assert!(breakers.allow(SERVICE, &config));
+        breakers.record(SERVICE, &config, true);
+
+        assert!(breakers.allow(SERVICE, &config));
+        assert!(breakers.allow(SERVICE, &config));
+    }
+}
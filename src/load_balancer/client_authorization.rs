@@ -0,0 +1,53 @@
+use crate::config::ClientCertificateRules;
+use crate::load_balancer::forwarded::ClientIdentity;
+
+/// Checks whether a client verified through mutual TLS can make requests under the rules.
+///
+/// Clients without a verified certificate are always refused, as they cannot be shown to satisfy
+/// any of the rules.
+pub fn is_authorized(rules: &ClientCertificateRules, identity: Option<&ClientIdentity>) -> bool {
+    let Some(identity) = identity else {
+        return false;
+    };
+
+    rules.common_names.is_empty()
+        || rules
+            .common_names
+            .iter()
+            .any(|common_name| **common_name == *identity.common_name)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use crate::config::ClientCertificateRules;
+    use crate::load_balancer::client_authorization::is_authorized;
+    use crate::load_balancer::forwarded::ClientIdentity;
+
+    fn identity(common_name: &str) -> ClientIdentity {
+        ClientIdentity {
+            common_name: Arc::from(common_name),
+        }
+    }
+
+    #[test]
+    fn only_permitted_common_names_are_authorized() {
+        let rules = ClientCertificateRules {
+            common_names: vec![String::from("billing-service")],
+        };
+
+        assert!(is_authorized(&rules, Some(&identity("billing-service"))));
+        assert!(!is_authorized(&rules, Some(&identity("reporting-service"))));
+    }
+
+    #[test]
+    fn clients_without_certificates_are_refused() {
+        let rules = ClientCertificateRules {
+            common_names: Vec::new(),
+        };
+
+        assert!(is_authorized(&rules, Some(&identity("billing-service"))));
+        assert!(!is_authorized(&rules, None));
+    }
+}
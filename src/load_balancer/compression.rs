@@ -0,0 +1,211 @@
+use http::header::{ACCEPT_ENCODING, CONTENT_ENCODING, CONTENT_LENGTH, CONTENT_TYPE, VARY};
+use http::{HeaderMap, HeaderValue};
+
+/// The largest response body to buffer for compression, beyond which it is streamed as is.
+pub const MAX_COMPRESSIBLE_SIZE: usize = 8 * 1024 * 1024;
+
+const COMPRESSION_LEVEL: u8 = 6;
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum Encoding {
+    Gzip,
+    Deflate,
+}
+
+impl Encoding {
+    fn as_str(self) -> &'static str {
+        match self {
+            Self::Gzip => "gzip",
+            Self::Deflate => "deflate",
+        }
+    }
+
+    /// Picks the encoding to use from a request's `Accept-Encoding` header, preferring gzip.
+    pub fn negotiate(headers: &HeaderMap) -> Option<Self> {
+        let accepted: Vec<_> = headers
+            .get_all(ACCEPT_ENCODING)
+            .iter()
+            .filter_map(|value| value.to_str().ok())
+            .flat_map(|value| value.split(','))
+            .filter_map(|entry| {
+                let mut parameters = entry.split(';').map(str::trim);
+                let coding = parameters.next()?;
+
+                // codings with a quality of zero are explicitly refused
+                let refused = parameters
+                    .filter_map(|parameter| parameter.strip_prefix("q="))
+                    .any(|quality| quality.parse::<f32>().is_ok_and(|q| q == 0.0));
+
+                (!refused).then_some(coding)
+            })
+            .collect();
+
+        [Self::Gzip, Self::Deflate].into_iter().find(|encoding| {
+            accepted
+                .iter()
+                .any(|coding| coding.eq_ignore_ascii_case(encoding.as_str()))
+        })
+    }
+
+    pub fn compress(self, data: &[u8]) -> Vec<u8> {
+        match self {
+            Self::Gzip => gzip(data),
+            Self::Deflate => miniz_oxide::deflate::compress_to_vec_zlib(data, COMPRESSION_LEVEL),
+        }
+    }
+}
+
+/// Checks whether a response is worth compressing, returning its length if so.
+pub fn compressible_length(headers: &HeaderMap, min_size: usize) -> Option<usize> {
+    if headers.contains_key(CONTENT_ENCODING) {
+        return None;
+    }
+
+    let content_type = headers.get(CONTENT_TYPE)?.to_str().ok()?;
+
+    if !is_compressible_type(content_type) {
+        return None;
+    }
+
+    // only bodies of a known length are buffered, so streamed responses are left alone
+    let length = headers
+        .get(CONTENT_LENGTH)?
+        .to_str()
+        .ok()?
+        .parse::<usize>()
+        .ok()?;
+
+    (min_size..=MAX_COMPRESSIBLE_SIZE)
+        .contains(&length)
+        .then_some(length)
+}
+
+/// Updates the response headers to describe a body compressed with the encoding.
+pub fn apply_headers(headers: &mut HeaderMap, encoding: Encoding, length: usize) {
+    headers.insert(
+        CONTENT_ENCODING,
+        HeaderValue::from_static(encoding.as_str()),
+    );
+    headers.insert(CONTENT_LENGTH, HeaderValue::from(length));
+    headers.append(VARY, HeaderValue::from_static("accept-encoding"));
+}
+
+fn is_compressible_type(content_type: &str) -> bool {
+    let essence = content_type
+        .split(';')
+        .next()
+        .unwrap_or_default()
+        .trim()
+        .to_ascii_lowercase();
+
+    essence.starts_with("text/")
+        || essence.ends_with("+json")
+        || essence.ends_with("+xml")
+        || matches!(
+            essence.as_str(),
+            "application/json" | "application/javascript" | "application/xml" | "image/svg+xml"
+        )
+}
+
+fn gzip(data: &[u8]) -> Vec<u8> {
+    // magic, deflate, no flags, no modification time, no extra flags, unknown operating system
+    let mut output = vec![0x1f, 0x8b, 8, 0, 0, 0, 0, 0, 0, 0xff];
+
+    output.extend(miniz_oxide::deflate::compress_to_vec(
+        data,
+        COMPRESSION_LEVEL,
+    ));
+    output.extend(crc32fast::hash(data).to_le_bytes());
+    output.extend((data.len() as u32).to_le_bytes());
+
+    output
+}
+
+#[cfg(test)]
+mod tests {
+    use http::header::{ACCEPT_ENCODING, CONTENT_ENCODING, CONTENT_LENGTH, CONTENT_TYPE};
+    use http::{HeaderMap, HeaderValue};
+
+    use crate::load_balancer::compression::{compressible_length, Encoding};
+
+    fn accepting(value: &'static str) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        headers.insert(ACCEPT_ENCODING, HeaderValue::from_static(value));
+
+        headers
+    }
+
+    fn response(content_type: &'static str, length: usize) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        headers.insert(CONTENT_TYPE, HeaderValue::from_static(content_type));
+        headers.insert(CONTENT_LENGTH, HeaderValue::from(length));
+
+        headers
+    }
+
+    #[test]
+    fn encodings_are_negotiated_from_accept_encoding() {
+        assert_eq!(
+            Encoding::negotiate(&accepting("br, gzip, deflate")),
+            Some(Encoding::Gzip)
+        );
+        assert_eq!(
+            Encoding::negotiate(&accepting("gzip;q=0, deflate")),
+            Some(Encoding::Deflate)
+        );
+        assert_eq!(Encoding::negotiate(&accepting("identity")), None);
+        assert_eq!(Encoding::negotiate(&HeaderMap::new()), None);
+    }
+
+    #[test]
+    fn only_textual_responses_of_a_known_size_are_compressed() {
+        assert_eq!(
+            compressible_length(&response("text/html; charset=utf-8", 2048), 1024),
+            Some(2048)
+        );
+        assert_eq!(
+            compressible_length(&response("application/json", 2048), 1024),
+            Some(2048)
+        );
+        assert_eq!(
+            compressible_length(&response("image/png", 2048), 1024),
+            None
+        );
+        assert_eq!(
+            compressible_length(&response("text/plain", 512), 1024),
+            None
+        );
+
+        let mut encoded = response("text/plain", 2048);
+        encoded.insert(CONTENT_ENCODING, HeaderValue::from_static("gzip"));
+
+        assert_eq!(compressible_length(&encoded, 1024), None);
+    }
+
+    #[test]
+    fn gzip_output_can_be_decompressed() {
+        let data = b"hello hello hello hello hello".repeat(10);
+        let compressed = Encoding::Gzip.compress(&data);
+
+        assert_eq!(&compressed[..2], &[0x1f, 0x8b]);
+
+        let trailer = &compressed[compressed.len() - 8..];
+        let deflated = &compressed[10..compressed.len() - 8];
+
+        let decompressed = miniz_oxide::inflate::decompress_to_vec(deflated).unwrap();
+
+        assert_eq!(decompressed, data);
+        assert_eq!(trailer[..4], crc32fast::hash(&data).to_le_bytes());
+        assert_eq!(trailer[4..], (data.len() as u32).to_le_bytes());
+    }
+
+    #[test]
+    fn deflate_output_can_be_decompressed() {
+        let data = b"hello hello hello hello hello".repeat(10);
+        let compressed = Encoding::Deflate.compress(&data);
+
+        let decompressed = miniz_oxide::inflate::decompress_to_vec_zlib(&compressed).unwrap();
+
+        assert_eq!(decompressed, data);
+    }
+}
@@ -0,0 +1,190 @@
+//! Serving the logs of a service's containers, so deploys can be debugged without access to the
+//! host.
+
+use std::convert::Infallible;
+use std::fmt;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+
+use color_eyre::eyre::Result;
+use futures::stream::{self, StreamExt};
+use hyper::body::{Body, Bytes, Frame};
+use tokio::sync::mpsc;
+
+use crate::docker::client::DockerClient;
+use crate::docker::models::ContainerId;
+
+/// How many lines are buffered for a slow client before reading from the containers pauses.
+const BUFFERED_LINES: usize = 64;
+
+/// How many characters of a container's identifier to prefix its lines with, as `docker ps` shows.
+const SHORT_ID_LENGTH: usize = 12;
+
+/// Finds the service whose logs are being requested from a path such as `{prefix}/backend/logs`.
+pub fn service_name<'a>(prefix: &str, path: &'a str) -> Option<&'a str> {
+    path.strip_prefix(prefix)?
+        .strip_prefix('/')?
+        .strip_suffix("/logs")
+        .filter(|name| !name.is_empty() && !name.contains('/'))
+}
+
+/// Reads the logs of containers through the Docker daemon.
+pub struct ContainerLogs {
+    client: Arc<dyn DockerClient + Send + Sync>,
+}
+
+impl fmt::Debug for ContainerLogs {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ContainerLogs").finish_non_exhaustive()
+    }
+}
+
+impl ContainerLogs {
+    pub fn new(client: Arc<dyn DockerClient + Send + Sync>) -> Self {
+        Self { client }
+    }
+
+    /// Streams the logs of every container at once, prefixing each line with the container and
+    /// stream it was written to.
+    ///
+    /// Every container's logs are requested before returning, so a container that cannot be read
+    /// from fails the whole request rather than silently being left out.
+    pub async fn stream(&self, containers: Vec<ContainerId>, follow: bool) -> Result<LogBody> {
+        let mut streams = Vec::new();
+
+        for id in containers {
+            let logs = self.client.stream_logs(&id, follow).await?;
+            let prefix = id.0.get(..SHORT_ID_LENGTH).unwrap_or(&id.0).to_owned();
+
+            streams.push(logs.map(move |frame| {
+                frame.map(|frame| {
+                    let mut line = format!("{prefix} {} ", frame.stream).into_bytes();
+                    line.extend_from_slice(&frame.data);
+
+                    Bytes::from(line)
+                })
+            }));
+        }
+
+        let (sender, receiver) = mpsc::channel(BUFFERED_LINES);
+
+        tokio::spawn(async move {
+            let mut lines = stream::select_all(streams);
+
+            while let Some(line) = lines.next().await {
+                let line = match line {
+                    Ok(line) => line,
+                    Err(e) => {
+                        tracing::warn!(?e, "failed to read container logs, ending the stream");
+                        break;
+                    }
+                };
+
+                // The client has gone away, so stop reading from the containers
+                if sender.send(line).await.is_err() {
+                    break;
+                }
+            }
+        });
+
+        Ok(LogBody { receiver })
+    }
+}
+
+/// The body of a logs response, which ends once every container's logs have been read.
+#[derive(Debug)]
+pub struct LogBody {
+    receiver: mpsc::Receiver<Bytes>,
+}
+
+impl Body for LogBody {
+    type Data = Bytes;
+    type Error = Infallible;
+
+    fn poll_frame(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<Frame<Self::Data>, Self::Error>>> {
+        self.receiver
+            .poll_recv(cx)
+            .map(|line| line.map(|line| Ok(Frame::data(line))))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use color_eyre::eyre::Result;
+    use http_body_util::BodyExt;
+    use hyper::body::Bytes;
+
+    use crate::docker::logs::{LogFrame, OutputStream};
+    use crate::docker::models::ContainerId;
+    use crate::load_balancer::container_logs::{service_name, ContainerLogs};
+    use crate::reconciler::tests::FakeDockerClient;
+
+    fn log_frame(stream: OutputStream, data: &'static str) -> LogFrame {
+        LogFrame {
+            stream,
+            data: Bytes::from_static(data.as_bytes()),
+        }
+    }
+
+    #[test]
+    fn service_names_are_found_in_logs_paths() {
+        let prefix = "/services";
+
+        assert_eq!(
+            service_name(prefix, "/services/backend/logs"),
+            Some("backend")
+        );
+
+        assert_eq!(service_name(prefix, "/services//logs"), None);
+        assert_eq!(service_name(prefix, "/services/backend/other/logs"), None);
+        assert_eq!(service_name(prefix, "/services/backend"), None);
+        assert_eq!(service_name(prefix, "/servicesbackend/logs"), None);
+        assert_eq!(service_name(prefix, "/other/backend/logs"), None);
+    }
+
+    #[tokio::test]
+    async fn logs_are_prefixed_with_their_container_and_stream() -> Result<()> {
+        let docker_client = FakeDockerClient::default();
+
+        let first = ContainerId(String::from("6cd915f16ab3e5a1"));
+        let second = ContainerId(String::from("0e27a8f3b2c4"));
+
+        docker_client
+            .add_logs(&first, vec![log_frame(OutputStream::Stdout, "listening\n")])
+            .await;
+        docker_client
+            .add_logs(&second, vec![log_frame(OutputStream::Stderr, "panicked\n")])
+            .await;
+
+        let logs = ContainerLogs::new(Arc::new(docker_client));
+        let body = logs.stream(vec![first, second], false).await?;
+        let body = body.collect().await?.to_bytes();
+
+        let mut lines: Vec<_> = std::str::from_utf8(&body)?.lines().collect();
+        lines.sort_unstable();
+
+        assert_eq!(
+            lines,
+            vec![
+                "0e27a8f3b2c4 stderr panicked",
+                "6cd915f16ab3 stdout listening"
+            ]
+        );
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn unreadable_containers_fail_the_request() {
+        let logs = ContainerLogs::new(Arc::new(FakeDockerClient::default()));
+        let missing = ContainerId(String::from("6cd915f16ab3"));
+
+        assert!(logs.stream(vec![missing], false).await.is_err());
+    }
+}
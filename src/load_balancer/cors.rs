@@ -0,0 +1,180 @@
+use http::header::{
+    ACCESS_CONTROL_ALLOW_CREDENTIALS, ACCESS_CONTROL_ALLOW_HEADERS, ACCESS_CONTROL_ALLOW_METHODS,
+    ACCESS_CONTROL_ALLOW_ORIGIN, ACCESS_CONTROL_MAX_AGE, ACCESS_CONTROL_REQUEST_METHOD, ORIGIN,
+    VARY,
+};
+use http::{HeaderMap, HeaderValue, Method};
+
+use crate::config::CorsConfig;
+
+/// Checks whether a request is a CORS preflight, which the load balancer answers itself.
+pub fn is_preflight(method: &Method, headers: &HeaderMap) -> bool {
+    method == Method::OPTIONS
+        && headers.contains_key(ORIGIN)
+        && headers.contains_key(ACCESS_CONTROL_REQUEST_METHOD)
+}
+
+/// Returns the request's origin if the policy allows it.
+pub fn allowed_origin(config: &CorsConfig, headers: &HeaderMap) -> Option<HeaderValue> {
+    let origin = headers.get(ORIGIN)?;
+    let value = origin.to_str().ok()?;
+
+    config
+        .allowed_origins
+        .iter()
+        .any(|allowed| allowed == "*" || allowed.eq_ignore_ascii_case(value))
+        .then(|| origin.clone())
+}
+
+/// Builds the headers to answer a preflight with, or `None` if the requested method is not
+/// allowed.
+pub fn preflight_headers(
+    config: &CorsConfig,
+    origin: HeaderValue,
+    headers: &HeaderMap,
+) -> Option<HeaderMap> {
+    let method = headers.get(ACCESS_CONTROL_REQUEST_METHOD)?.to_str().ok()?;
+
+    let method_allowed = config.allowed_methods.is_empty()
+        || config
+            .allowed_methods
+            .iter()
+            .any(|allowed| allowed.eq_ignore_ascii_case(method));
+
+    if !method_allowed {
+        return None;
+    }
+
+    let mut response = HeaderMap::new();
+    apply(config, origin, &mut response);
+
+    let methods = match config.allowed_methods.is_empty() {
+        true => method.to_owned(),
+        false => config.allowed_methods.join(", "),
+    };
+
+    response.insert(
+        ACCESS_CONTROL_ALLOW_METHODS,
+        HeaderValue::from_str(&methods).ok()?,
+    );
+
+    if !config.allowed_headers.is_empty() {
+        let allowed = config.allowed_headers.join(", ");
+        response.insert(
+            ACCESS_CONTROL_ALLOW_HEADERS,
+            HeaderValue::from_str(&allowed).ok()?,
+        );
+    }
+
+    if let Some(max_age) = config.max_age_secs {
+        response.insert(ACCESS_CONTROL_MAX_AGE, HeaderValue::from(max_age));
+    }
+
+    Some(response)
+}
+
+/// Adds the headers allowing the origin to read a response.
+pub fn apply(config: &CorsConfig, origin: HeaderValue, headers: &mut HeaderMap) {
+    // Credentialed requests cannot use the wildcard, so the origin is always echoed
+    headers.insert(ACCESS_CONTROL_ALLOW_ORIGIN, origin);
+    headers.append(VARY, HeaderValue::from_static("Origin"));
+
+    if config.allow_credentials {
+        headers.insert(
+            ACCESS_CONTROL_ALLOW_CREDENTIALS,
+            HeaderValue::from_static("true"),
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use http::header::{
+        ACCESS_CONTROL_ALLOW_CREDENTIALS, ACCESS_CONTROL_ALLOW_HEADERS,
+        ACCESS_CONTROL_ALLOW_METHODS, ACCESS_CONTROL_ALLOW_ORIGIN, ACCESS_CONTROL_MAX_AGE,
+        ACCESS_CONTROL_REQUEST_METHOD, ORIGIN,
+    };
+    use http::{HeaderMap, HeaderValue, Method};
+
+    use crate::config::CorsConfig;
+    use crate::load_balancer::cors::{allowed_origin, is_preflight, preflight_headers};
+
+    fn build_config() -> CorsConfig {
+        CorsConfig {
+            allowed_origins: vec![String::from("https://opentracker.app")],
+            allowed_methods: vec![String::from("GET"), String::from("POST")],
+            allowed_headers: vec![String::from("Content-Type")],
+            allow_credentials: true,
+            max_age_secs: Some(600),
+        }
+    }
+
+    fn build_headers(origin: &'static str, method: &'static str) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        headers.insert(ORIGIN, HeaderValue::from_static(origin));
+        headers.insert(
+            ACCESS_CONTROL_REQUEST_METHOD,
+            HeaderValue::from_static(method),
+        );
+
+        headers
+    }
+
+    #[test]
+    fn only_configured_origins_are_allowed() {
+        let config = build_config();
+
+        let allowed = build_headers("https://opentracker.app", "GET");
+        let disallowed = build_headers("https://example.com", "GET");
+
+        assert!(allowed_origin(&config, &allowed).is_some());
+        assert!(allowed_origin(&config, &disallowed).is_none());
+        assert!(allowed_origin(&config, &HeaderMap::new()).is_none());
+    }
+
+    #[test]
+    fn wildcard_origins_allow_anything() {
+        let config = CorsConfig {
+            allowed_origins: vec![String::from("*")],
+            ..build_config()
+        };
+
+        let headers = build_headers("https://example.com", "GET");
+
+        assert_eq!(
+            allowed_origin(&config, &headers),
+            Some(HeaderValue::from_static("https://example.com"))
+        );
+    }
+
+    #[test]
+    fn preflights_are_answered_for_allowed_methods() {
+        let config = build_config();
+        let headers = build_headers("https://opentracker.app", "POST");
+
+        assert!(is_preflight(&Method::OPTIONS, &headers));
+        assert!(!is_preflight(&Method::GET, &headers));
+
+        let origin = allowed_origin(&config, &headers).unwrap();
+        let response = preflight_headers(&config, origin, &headers).unwrap();
+
+        assert_eq!(
+            response[ACCESS_CONTROL_ALLOW_ORIGIN],
+            "https://opentracker.app"
+        );
+        assert_eq!(response[ACCESS_CONTROL_ALLOW_METHODS], "GET, POST");
+        assert_eq!(response[ACCESS_CONTROL_ALLOW_HEADERS], "Content-Type");
+        assert_eq!(response[ACCESS_CONTROL_ALLOW_CREDENTIALS], "true");
+        assert_eq!(response[ACCESS_CONTROL_MAX_AGE], "600");
+    }
+
+    #[test]
+    fn preflights_for_other_methods_are_refused() {
+        let config = build_config();
+        let headers = build_headers("https://opentracker.app", "DELETE");
+
+        let origin = allowed_origin(&config, &headers).unwrap();
+
+        assert!(preflight_headers(&config, origin, &headers).is_none());
+    }
+}
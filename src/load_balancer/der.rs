@@ -0,0 +1,500 @@
+//! Just enough DER to request or generate certificates and check their expiry and revocation
+//! status.
+
+use chrono::{DateTime, Datelike, NaiveDateTime, Utc};
+use color_eyre::eyre::{eyre, Result};
+use ring::digest::{digest, SHA1_FOR_LEGACY_USE_ONLY};
+
+const INTEGER: u8 = 0x02;
+const BIT_STRING: u8 = 0x03;
+const OCTET_STRING: u8 = 0x04;
+const NULL: u8 = 0x05;
+const ENUMERATED: u8 = 0x0a;
+const UTF8_STRING: u8 = 0x0c;
+const UTC_TIME: u8 = 0x17;
+const GENERALIZED_TIME: u8 = 0x18;
+const SEQUENCE: u8 = 0x30;
+const SET: u8 = 0x31;
+const CONTEXT_ATTRIBUTES: u8 = 0xa0;
+const CONTEXT_VERSION: u8 = 0xa0;
+const CONTEXT_EXTENSIONS: u8 = 0xa3;
+const CONTEXT_DNS_NAME: u8 = 0x82;
+const CONTEXT_URI: u8 = 0x86;
+
+const OID_EC_PUBLIC_KEY: &[u8] = &[0x06, 0x07, 0x2a, 0x86, 0x48, 0xce, 0x3d, 0x02, 0x01];
+const OID_PRIME256V1: &[u8] = &[0x06, 0x08, 0x2a, 0x86, 0x48, 0xce, 0x3d, 0x03, 0x01, 0x07];
+const OID_ECDSA_WITH_SHA256: &[u8] = &[0x06, 0x08, 0x2a, 0x86, 0x48, 0xce, 0x3d, 0x04, 0x03, 0x02];
+const OID_COMMON_NAME: &[u8] = &[0x06, 0x03, 0x55, 0x04, 0x03];
+const OID_EXTENSION_REQUEST: &[u8] = &[
+    0x06, 0x09, 0x2a, 0x86, 0x48, 0x86, 0xf7, 0x0d, 0x01, 0x09, 0x0e,
+];
+const OID_SUBJECT_ALT_NAME: &[u8] = &[0x06, 0x03, 0x55, 0x1d, 0x11];
+const OID_SHA1: &[u8] = &[0x06, 0x05, 0x2b, 0x0e, 0x03, 0x02, 0x1a];
+const OID_AUTHORITY_INFO_ACCESS: &[u8] =
+    &[0x06, 0x08, 0x2b, 0x06, 0x01, 0x05, 0x05, 0x07, 0x01, 0x01];
+const OID_OCSP: &[u8] = &[0x06, 0x08, 0x2b, 0x06, 0x01, 0x05, 0x05, 0x07, 0x30, 0x01];
+
+fn encode(tag: u8, contents: &[u8]) -> Vec<u8> {
+    let mut encoded = vec![tag];
+    let length = contents.len();
+
+    if length < 0x80 {
+        encoded.push(length as u8);
+    } else {
+        let bytes: Vec<u8> = length
+            .to_be_bytes()
+            .into_iter()
+            .skip_while(|byte| *byte == 0)
+            .collect();
+
+        encoded.push(0x80 | bytes.len() as u8);
+        encoded.extend(bytes);
+    }
+
+    encoded.extend_from_slice(contents);
+    encoded
+}
+
+fn sequence(parts: &[&[u8]]) -> Vec<u8> {
+    encode(SEQUENCE, &parts.concat())
+}
+
+fn bit_string(contents: &[u8]) -> Vec<u8> {
+    encode(BIT_STRING, &[&[0], contents].concat())
+}
+
+/// Encodes a name holding only `domain` as its common name.
+fn name(domain: &str) -> Vec<u8> {
+    let common_name = sequence(&[OID_COMMON_NAME, &encode(UTF8_STRING, domain.as_bytes())]);
+
+    sequence(&[&encode(SET, &common_name)])
+}
+
+/// Encodes an uncompressed P-256 point as a subject public key info.
+fn subject_public_key_info(public_key: &[u8]) -> Vec<u8> {
+    let algorithm = sequence(&[OID_EC_PUBLIC_KEY, OID_PRIME256V1]);
+
+    sequence(&[&algorithm, &bit_string(public_key)])
+}
+
+/// Encodes the extensions naming `domain` as the only subject alternative name.
+fn extensions(domain: &str) -> Vec<u8> {
+    let names = sequence(&[&encode(CONTEXT_DNS_NAME, domain.as_bytes())]);
+    let subject_alt_name = sequence(&[OID_SUBJECT_ALT_NAME, &encode(OCTET_STRING, &names)]);
+
+    sequence(&[&subject_alt_name])
+}
+
+/// Encodes a time as certificates expect, using two digit years until 2050.
+fn time(at: DateTime<Utc>) -> Vec<u8> {
+    match at.year() {
+        1950..2050 => encode(UTC_TIME, at.format("%y%m%d%H%M%SZ").to_string().as_bytes()),
+        _ => encode(
+            GENERALIZED_TIME,
+            at.format("%Y%m%d%H%M%SZ").to_string().as_bytes(),
+        ),
+    }
+}
+
+/// Completes a signing request or certificate from the part that was signed and an ASN.1 encoded
+/// ECDSA signature over it.
+fn signed(to_be_signed: &[u8], signature: &[u8]) -> Vec<u8> {
+    let algorithm = sequence(&[OID_ECDSA_WITH_SHA256]);
+
+    sequence(&[to_be_signed, &algorithm, &bit_string(signature)])
+}
+
+/// Builds the part of a certificate signing request that gets signed, naming `domain` as both the
+/// subject and the only subject alternative name.
+///
+/// `public_key` is an uncompressed P-256 point.
+pub fn certification_request_info(domain: &str, public_key: &[u8]) -> Vec<u8> {
+    let extension_request = sequence(&[OID_EXTENSION_REQUEST, &encode(SET, &extensions(domain))]);
+    let attributes = encode(CONTEXT_ATTRIBUTES, &extension_request);
+
+    sequence(&[
+        &encode(INTEGER, &[0]),
+        &name(domain),
+        &subject_public_key_info(public_key),
+        &attributes,
+    ])
+}
+
+/// Completes a certificate signing request from its info and an ASN.1 encoded ECDSA signature
+/// over it.
+pub fn certification_request(info: &[u8], signature: &[u8]) -> Vec<u8> {
+    signed(info, signature)
+}
+
+/// Builds the part of a self-signed certificate that gets signed, naming `domain` as the subject,
+/// the issuer and the only subject alternative name.
+///
+/// `public_key` is an uncompressed P-256 point and `serial_number` must not start with a set bit,
+/// as it would otherwise be negative.
+pub fn self_signed_certificate_info(
+    domain: &str,
+    public_key: &[u8],
+    serial_number: &[u8],
+    not_before: DateTime<Utc>,
+    not_after: DateTime<Utc>,
+) -> Vec<u8> {
+    let version = encode(CONTEXT_VERSION, &encode(INTEGER, &[2]));
+    let algorithm = sequence(&[OID_ECDSA_WITH_SHA256]);
+    let validity = sequence(&[&time(not_before), &time(not_after)]);
+
+    sequence(&[
+        &version,
+        &encode(INTEGER, serial_number),
+        &algorithm,
+        &name(domain),
+        &validity,
+        &name(domain),
+        &subject_public_key_info(public_key),
+        &encode(CONTEXT_EXTENSIONS, &extensions(domain)),
+    ])
+}
+
+/// Completes a certificate from its info and an ASN.1 encoded ECDSA signature over it.
+pub fn certificate(info: &[u8], signature: &[u8]) -> Vec<u8> {
+    signed(info, signature)
+}
+
+/// Splits the first element off the input, returning its tag, contents and whatever follows it.
+fn read(input: &[u8]) -> Result<(u8, &[u8], &[u8])> {
+    let truncated = || eyre!("truncated DER element");
+
+    let (&tag, input) = input.split_first().ok_or_else(truncated)?;
+    let (&first, input) = input.split_first().ok_or_else(truncated)?;
+
+    let (length, input) = match first {
+        length if length < 0x80 => (length as usize, input),
+        prefix => {
+            let count = (prefix & 0x7f) as usize;
+
+            if count > size_of::<usize>() || input.len() < count {
+                return Err(truncated());
+            }
+
+            let (bytes, input) = input.split_at(count);
+            let length = bytes
+                .iter()
+                .fold(0, |length, byte| (length << 8) | *byte as usize);
+
+            (length, input)
+        }
+    };
+
+    if input.len() < length {
+        return Err(truncated());
+    }
+
+    let (contents, rest) = input.split_at(length);
+
+    Ok((tag, contents, rest))
+}
+
+/// An element of some DER, along with its encoding.
+struct Element<'a> {
+    tag: u8,
+    contents: &'a [u8],
+    encoded: &'a [u8],
+}
+
+/// Splits the input into each of the elements it contains.
+fn elements(mut input: &[u8]) -> Result<Vec<Element<'_>>> {
+    let mut elements = Vec::new();
+
+    while !input.is_empty() {
+        let (tag, contents, rest) = read(input)?;
+        let encoded = &input[..input.len() - rest.len()];
+
+        elements.push(Element {
+            tag,
+            contents,
+            encoded,
+        });
+
+        input = rest;
+    }
+
+    Ok(elements)
+}
+
+/// Splits the signed part of a DER encoded certificate into its fields, leaving out the version so
+/// that the serial number always comes first and the subject public key info sixth.
+fn to_be_signed(certificate: &[u8]) -> Result<Vec<Element<'_>>> {
+    let (_, certificate, _) = read(certificate)?;
+    let (_, tbs, _) = read(certificate)?;
+
+    let mut fields = elements(tbs)?;
+
+    // The version is optional, so the serial number may have been the first field
+    if fields
+        .first()
+        .is_some_and(|field| field.tag == CONTEXT_VERSION)
+    {
+        fields.remove(0);
+    }
+
+    if fields.len() < 6 {
+        return Err(eyre!("certificate only has {} fields", fields.len()));
+    }
+
+    Ok(fields)
+}
+
+/// Reads when a DER encoded certificate stops being valid.
+pub fn not_after(certificate: &[u8]) -> Result<DateTime<Utc>> {
+    let fields = to_be_signed(certificate)?;
+    let validity = elements(fields[3].contents)?;
+
+    let Some(not_after) = validity.get(1) else {
+        return Err(eyre!("certificate validity has no end"));
+    };
+
+    let time = std::str::from_utf8(not_after.contents)?;
+
+    // Two digit years are in the range 1950 to 2049
+    let time = match not_after.tag {
+        UTC_TIME if time.as_bytes().first().is_some_and(|digit| *digit >= b'5') => {
+            format!("19{time}")
+        }
+        UTC_TIME => format!("20{time}"),
+        GENERALIZED_TIME => time.to_owned(),
+        tag => return Err(eyre!("unexpected tag {tag:#x} for certificate expiry")),
+    };
+
+    let time = NaiveDateTime::parse_from_str(&time, "%Y%m%d%H%M%SZ")?;
+
+    Ok(time.and_utc())
+}
+
+/// Finds the value of an extension among the signed fields of a certificate, if it has one.
+fn extension<'a>(fields: &[Element<'a>], id: &[u8]) -> Result<Option<&'a [u8]>> {
+    let Some(extensions) = fields.iter().find(|field| field.tag == CONTEXT_EXTENSIONS) else {
+        return Ok(None);
+    };
+
+    let (_, extensions, _) = read(extensions.contents)?;
+
+    for extension in elements(extensions)? {
+        let parts = elements(extension.contents)?;
+
+        // Extensions may be marked as critical, so the value is always last rather than second
+        if let (Some(oid), Some(value)) = (parts.first(), parts.last()) {
+            if oid.encoded == id {
+                return Ok(Some(value.contents));
+            }
+        }
+    }
+
+    Ok(None)
+}
+
+/// Finds where the issuer of a DER encoded certificate answers whether it has been revoked, if it
+/// says.
+pub fn ocsp_responder(certificate: &[u8]) -> Result<Option<String>> {
+    let fields = to_be_signed(certificate)?;
+
+    let Some(value) = extension(&fields, OID_AUTHORITY_INFO_ACCESS)? else {
+        return Ok(None);
+    };
+
+    let (_, descriptions, _) = read(value)?;
+
+    for description in elements(descriptions)? {
+        if let [method, location] = elements(description.contents)?.as_slice() {
+            if method.encoded == OID_OCSP && location.tag == CONTEXT_URI {
+                return Ok(Some(std::str::from_utf8(location.contents)?.to_owned()));
+            }
+        }
+    }
+
+    Ok(None)
+}
+
+/// Reads the URI subject alternative names of a DER encoded certificate, which is where a SPIFFE
+/// ID is held.
+pub fn uri_names(certificate: &[u8]) -> Result<Vec<String>> {
+    let fields = to_be_signed(certificate)?;
+
+    let Some(value) = extension(&fields, OID_SUBJECT_ALT_NAME)? else {
+        return Ok(Vec::new());
+    };
+
+    let (_, names, _) = read(value)?;
+
+    elements(names)?
+        .iter()
+        .filter(|name| name.tag == CONTEXT_URI)
+        .map(|name| Ok(std::str::from_utf8(name.contents)?.to_owned()))
+        .collect()
+}
+
+/// Builds an OCSP request asking whether a DER encoded certificate, issued by the certificate
+/// `issuer`, has been revoked.
+pub fn ocsp_request(certificate: &[u8], issuer: &[u8]) -> Result<Vec<u8>> {
+    let fields = to_be_signed(certificate)?;
+    let (serial_number, issuer_name) = (&fields[0], &fields[2]);
+
+    let issuer_fields = to_be_signed(issuer)?;
+    let (_, _, public_key) = read(issuer_fields[5].contents)?;
+    let (_, public_key, _) = read(public_key)?;
+
+    // Bit strings start with the number of unused bits, which is not part of the key
+    let public_key = public_key
+        .get(1..)
+        .ok_or_else(|| eyre!("issuer public key is empty"))?;
+
+    let hash = |data: &[u8]| {
+        let digest = digest(&SHA1_FOR_LEGACY_USE_ONLY, data);
+        encode(OCTET_STRING, digest.as_ref())
+    };
+
+    let algorithm = sequence(&[OID_SHA1, &encode(NULL, &[])]);
+    let certificate_id = sequence(&[
+        &algorithm,
+        &hash(issuer_name.encoded),
+        &hash(public_key),
+        serial_number.encoded,
+    ]);
+
+    let request = sequence(&[&certificate_id]);
+    let request_list = sequence(&[&request]);
+    let tbs_request = sequence(&[&request_list]);
+
+    Ok(sequence(&[&tbs_request]))
+}
+
+/// Checks whether a DER encoded OCSP response holds the status of a certificate, rather than
+/// saying why the responder could not provide one.
+pub fn ocsp_response_successful(response: &[u8]) -> Result<bool> {
+    let (_, response, _) = read(response)?;
+    let (tag, status, _) = read(response)?;
+
+    Ok(tag == ENUMERATED && status == [0])
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::{TimeZone, Utc};
+    use color_eyre::eyre::Result;
+    use itertools::Itertools;
+    use ring::rand::SystemRandom;
+    use ring::signature::{
+        EcdsaKeyPair, KeyPair, UnparsedPublicKey, ECDSA_P256_SHA256_ASN1,
+        ECDSA_P256_SHA256_ASN1_SIGNING,
+    };
+    use rustls::pki_types::pem::PemObject;
+    use rustls::pki_types::CertificateDer;
+
+    use crate::load_balancer::der::{
+        certification_request, certification_request_info, encode, not_after, ocsp_request,
+        ocsp_responder, ocsp_response_successful, read, uri_names, ENUMERATED, SEQUENCE,
+    };
+
+    #[test]
+    fn long_lengths_are_encoded_in_multiple_bytes() -> Result<()> {
+        let contents = vec![0; 300];
+        let encoded = encode(SEQUENCE, &contents);
+
+        assert_eq!(encoded[..4], [SEQUENCE, 0x82, 0x01, 0x2c]);
+
+        let (tag, decoded, rest) = read(&encoded)?;
+
+        assert_eq!(tag, SEQUENCE);
+        assert_eq!(decoded, contents);
+        assert!(rest.is_empty());
+
+        Ok(())
+    }
+
+    #[test]
+    fn certificate_expiry_can_be_read() -> Result<()> {
+        let certificate = CertificateDer::from_pem_file("resources/certificates/new.crt")?;
+
+        assert_eq!(
+            not_after(&certificate)?,
+            Utc.with_ymd_and_hms(2026, 6, 25, 15, 32, 33).unwrap()
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn signing_requests_are_signed_by_their_key() -> Result<()> {
+        let rng = SystemRandom::new();
+        let pkcs8 = EcdsaKeyPair::generate_pkcs8(&ECDSA_P256_SHA256_ASN1_SIGNING, &rng)?;
+        let key = EcdsaKeyPair::from_pkcs8(&ECDSA_P256_SHA256_ASN1_SIGNING, pkcs8.as_ref(), &rng)?;
+
+        let info = certification_request_info("opentracker.app", key.public_key().as_ref());
+        let signature = key.sign(&rng, &info)?;
+        let request = certification_request(&info, signature.as_ref());
+
+        let (_, request, _) = read(&request)?;
+        let (_, _, signed) = read(request)?;
+        let (_, _, signed) = read(signed)?;
+        let (_, signature, _) = read(signed)?;
+
+        // bit strings start with the number of unused bits
+        let public_key = UnparsedPublicKey::new(&ECDSA_P256_SHA256_ASN1, key.public_key());
+        assert!(public_key.verify(&info, &signature[1..]).is_ok());
+
+        Ok(())
+    }
+
+    #[test]
+    fn ocsp_responders_are_read_from_certificates() -> Result<()> {
+        let certificate = CertificateDer::from_pem_file("resources/certificates/stapled.crt")?;
+
+        assert_eq!(
+            ocsp_responder(&certificate)?,
+            Some(String::from("http://ocsp.example.com"))
+        );
+
+        let certificate = CertificateDer::from_pem_file("resources/certificates/new.crt")?;
+
+        assert_eq!(ocsp_responder(&certificate)?, None);
+
+        Ok(())
+    }
+
+    #[test]
+    fn uri_names_are_read_from_certificates() -> Result<()> {
+        let certificate =
+            CertificateDer::from_pem_file("resources/certificates/workload-client.crt")?;
+
+        assert_eq!(uri_names(&certificate)?, ["spiffe://example.com/billing"]);
+
+        let certificate =
+            CertificateDer::from_pem_file("resources/certificates/trusted-client.crt")?;
+
+        assert!(uri_names(&certificate)?.is_empty());
+
+        Ok(())
+    }
+
+    #[test]
+    fn ocsp_requests_identify_the_certificate_and_its_issuer() -> Result<()> {
+        let chain: Vec<_> =
+            CertificateDer::pem_file_iter("resources/certificates/stapled.crt")?.try_collect()?;
+
+        // Generated by `openssl ocsp` alongside the chain
+        let expected = std::fs::read("resources/certificates/stapled.ocsp-request.der")?;
+
+        assert_eq!(ocsp_request(&chain[0], &chain[1])?, expected);
+
+        Ok(())
+    }
+
+    #[test]
+    fn unsuccessful_ocsp_responses_are_recognised() -> Result<()> {
+        let successful = encode(SEQUENCE, &encode(ENUMERATED, &[0]));
+        let unauthorized = encode(SEQUENCE, &encode(ENUMERATED, &[6]));
+
+        assert!(ocsp_response_successful(&successful)?);
+        assert!(!ocsp_response_successful(&unauthorized)?);
+
+        Ok(())
+    }
+}
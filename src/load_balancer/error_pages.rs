@@ -0,0 +1,76 @@
+use std::collections::HashMap;
+use std::sync::Mutex;
+
+use color_eyre::eyre::Result;
+use hyper::body::Bytes;
+
+use crate::config::{ErrorPage, ErrorPageSource, ExternalBytes};
+
+/// Caches the bodies of error pages stored externally, so they are not fetched for every rejected
+/// request.
+#[derive(Debug, Default)]
+pub struct ErrorPages {
+    external: Mutex<HashMap<ExternalBytes, Bytes>>,
+}
+
+impl ErrorPages {
+    pub async fn body(&self, page: &ErrorPage) -> Result<Bytes> {
+        let file = match &page.source {
+            ErrorPageSource::Inline { body } => return Ok(Bytes::from(body.clone())),
+            ErrorPageSource::External { file } => file,
+        };
+
+        if let Some(body) = self.external.lock().unwrap().get(file) {
+            return Ok(body.clone());
+        }
+
+        let body = Bytes::from(file.resolve().await?);
+
+        self.external
+            .lock()
+            .unwrap()
+            .insert(file.clone(), body.clone());
+
+        Ok(body)
+    }
+
+    /// Forgets any cached pages, such as when the configuration may have changed.
+    pub fn clear(&self) {
+        self.external.lock().unwrap().clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use color_eyre::eyre::Result;
+
+    use crate::config::{ErrorPage, ErrorPageSource, ExternalBytes};
+    use crate::load_balancer::error_pages::ErrorPages;
+
+    #[tokio::test]
+    async fn external_pages_are_cached_until_cleared() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("404.html");
+
+        tokio::fs::write(&path, "<h1>Not Found</h1>").await?;
+
+        let page = ErrorPage {
+            source: ErrorPageSource::External {
+                file: ExternalBytes::Filesystem { path: path.clone() },
+            },
+            content_type: String::from("text/html"),
+        };
+
+        let pages = ErrorPages::default();
+
+        assert_eq!(pages.body(&page).await?, "<h1>Not Found</h1>");
+
+        tokio::fs::write(&path, "<h1>Gone</h1>").await?;
+        assert_eq!(pages.body(&page).await?, "<h1>Not Found</h1>");
+
+        pages.clear();
+        assert_eq!(pages.body(&page).await?, "<h1>Gone</h1>");
+
+        Ok(())
+    }
+}
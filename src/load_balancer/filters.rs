@@ -0,0 +1,212 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::{Arc, RwLock};
+
+use color_eyre::eyre::{eyre, Result};
+use http::header::AUTHORIZATION;
+use http::request::Parts;
+use http::{Response, StatusCode};
+use hyper::body::Bytes;
+
+/// The filters every load balancer starts with, which routes can name without any setup.
+pub const BUILT_IN_FILTERS: &[&str] = &["require-authorization"];
+
+/// What a filter decided to do with a request.
+#[derive(Debug)]
+pub enum FilterAction {
+    /// Carries on proxying the request, including any changes the filter made to it.
+    Continue,
+    /// Responds to the client directly, without the request reaching a container.
+    Respond(Response<Bytes>),
+}
+
+/// Custom logic that routes opt into by name, which can inspect and modify requests before they
+/// are proxied or respond to them itself.
+pub trait RequestFilter: Send + Sync {
+    fn filter(&self, parts: &mut Parts) -> FilterAction;
+}
+
+/// Answers requests without an `Authorization` header itself, leaving checking the credentials
+/// to the container.
+struct RequireAuthorization;
+
+impl RequestFilter for RequireAuthorization {
+    fn filter(&self, parts: &mut Parts) -> FilterAction {
+        if parts.headers.contains_key(AUTHORIZATION) {
+            return FilterAction::Continue;
+        }
+
+        let response = Response::builder()
+            .status(StatusCode::UNAUTHORIZED)
+            .body(Bytes::from("missing credentials"))
+            .expect("response should be valid");
+
+        FilterAction::Respond(response)
+    }
+}
+
+/// The filters available to routes, keyed by the name they are configured with.
+pub struct Filters {
+    filters: RwLock<HashMap<String, Arc<dyn RequestFilter>>>,
+}
+
+impl Default for Filters {
+    fn default() -> Self {
+        let built_in: [(&str, Arc<dyn RequestFilter>); 1] =
+            [("require-authorization", Arc::new(RequireAuthorization))];
+
+        let filters = built_in
+            .into_iter()
+            .map(|(name, filter)| (name.to_owned(), filter))
+            .collect();
+
+        Self {
+            filters: RwLock::new(filters),
+        }
+    }
+}
+
+impl fmt::Debug for Filters {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let filters = self.filters.read().unwrap();
+
+        f.debug_set().entries(filters.keys()).finish()
+    }
+}
+
+impl Filters {
+    pub fn register(&self, name: &str, filter: Arc<dyn RequestFilter>) {
+        self.filters
+            .write()
+            .unwrap()
+            .insert(name.to_owned(), filter);
+    }
+
+    /// Runs the named filters over a request in order, stopping at the first to respond.
+    ///
+    /// Routes naming a filter that isn't registered fail rather than skip it, as it may be
+    /// responsible for rejecting requests.
+    pub fn apply(&self, names: &[String], parts: &mut Parts) -> Result<FilterAction> {
+        if names.is_empty() {
+            return Ok(FilterAction::Continue);
+        }
+
+        let filters = self.filters.read().unwrap();
+
+        for name in names {
+            let filter = filters
+                .get(name)
+                .ok_or_else(|| eyre!("no filter named {name} is registered"))?;
+
+            if let FilterAction::Respond(response) = filter.filter(parts) {
+                return Ok(FilterAction::Respond(response));
+            }
+        }
+
+        Ok(FilterAction::Continue)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use color_eyre::eyre::Result;
+    use http::header::AUTHORIZATION;
+    use http::request::Parts;
+    use http::{HeaderValue, Request, Response, StatusCode};
+    use hyper::body::Bytes;
+
+    use crate::load_balancer::filters::{FilterAction, Filters, RequestFilter, BUILT_IN_FILTERS};
+
+    struct TagRequests;
+
+    impl RequestFilter for TagRequests {
+        fn filter(&self, parts: &mut Parts) -> FilterAction {
+            parts
+                .headers
+                .insert("x-tagged", HeaderValue::from_static("true"));
+
+            FilterAction::Continue
+        }
+    }
+
+    struct RejectUntagged;
+
+    impl RequestFilter for RejectUntagged {
+        fn filter(&self, parts: &mut Parts) -> FilterAction {
+            match parts.headers.contains_key("x-tagged") {
+                true => FilterAction::Continue,
+                false => FilterAction::Respond(
+                    Response::builder()
+                        .status(StatusCode::FORBIDDEN)
+                        .body(Bytes::new())
+                        .unwrap(),
+                ),
+            }
+        }
+    }
+
+    fn names(values: &[&str]) -> Vec<String> {
+        values.iter().map(ToString::to_string).collect()
+    }
+
+    #[test]
+    fn filters_run_in_the_configured_order() -> Result<()> {
+        let filters = Filters::default();
+        filters.register("tag", Arc::new(TagRequests));
+        filters.register("reject-untagged", Arc::new(RejectUntagged));
+
+        let (mut parts, ()) = Request::new(()).into_parts();
+        let action = filters.apply(&names(&["tag", "reject-untagged"]), &mut parts)?;
+
+        assert!(matches!(action, FilterAction::Continue));
+        assert_eq!(parts.headers["x-tagged"], "true");
+
+        let (mut parts, ()) = Request::new(()).into_parts();
+        let action = filters.apply(&names(&["reject-untagged", "tag"]), &mut parts)?;
+
+        assert!(
+            matches!(action, FilterAction::Respond(response) if response.status() == StatusCode::FORBIDDEN)
+        );
+        assert!(!parts.headers.contains_key("x-tagged"));
+
+        Ok(())
+    }
+
+    #[test]
+    fn unregistered_filters_fail_requests() {
+        let filters = Filters::default();
+        let (mut parts, ()) = Request::new(()).into_parts();
+
+        assert!(filters.apply(&names(&["missing"]), &mut parts).is_err());
+        assert!(filters.apply(&[], &mut parts).is_ok());
+    }
+
+    #[test]
+    fn built_in_filters_are_registered_by_default() -> Result<()> {
+        let filters = Filters::default();
+
+        for name in BUILT_IN_FILTERS {
+            let (mut parts, ()) = Request::new(()).into_parts();
+            filters.apply(&names(&[name]), &mut parts)?;
+        }
+
+        let (mut parts, ()) = Request::new(()).into_parts();
+        let action = filters.apply(&names(&["require-authorization"]), &mut parts)?;
+
+        assert!(
+            matches!(action, FilterAction::Respond(response) if response.status() == StatusCode::UNAUTHORIZED)
+        );
+
+        let (mut parts, ()) = Request::builder()
+            .header(AUTHORIZATION, "Bearer token")
+            .body(())?
+            .into_parts();
+        let action = filters.apply(&names(&["require-authorization"]), &mut parts)?;
+
+        assert!(matches!(action, FilterAction::Continue));
+
+        Ok(())
+    }
+}
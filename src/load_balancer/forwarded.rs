@@ -0,0 +1,170 @@
+use std::net::SocketAddr;
+use std::sync::Arc;
+
+use http::{HeaderName, HeaderValue, Request};
+
+use crate::config::Scheme;
+
+pub const X_FORWARDED_FOR: HeaderName = HeaderName::from_static("x-forwarded-for");
+pub const X_FORWARDED_PROTO: HeaderName = HeaderName::from_static("x-forwarded-proto");
+pub const X_FORWARDED_HOST: HeaderName = HeaderName::from_static("x-forwarded-host");
+pub const X_CLIENT_CERT_CN: HeaderName = HeaderName::from_static("x-client-cert-cn");
+
+/// Details of the client connection a request arrived on, attached to each request by the
+/// listener that accepted it.
+#[derive(Copy, Clone, Debug)]
+pub struct ClientConnection {
+    pub scheme: Scheme,
+    /// The address of the client, if the listener knows it.
+    pub addr: Option<SocketAddr>,
+}
+
+/// The identity of a client verified through mutual TLS, attached to each request on the
+/// connection.
+#[derive(Clone, Debug)]
+pub struct ClientIdentity {
+    pub common_name: Arc<str>,
+}
+
+/// Replaces any `X-Forwarded-*` and client certificate headers sent by the client with values
+/// describing the connection the request actually arrived on, since the load balancer is the edge
+/// and clients could otherwise spoof them.
+pub fn apply<B>(req: &mut Request<B>, host: &str) {
+    let connection = req.extensions().get::<ClientConnection>().copied();
+    let identity = req.extensions().get::<ClientIdentity>().cloned();
+    let headers = req.headers_mut();
+
+    for name in [
+        X_FORWARDED_FOR,
+        X_FORWARDED_PROTO,
+        X_FORWARDED_HOST,
+        X_CLIENT_CERT_CN,
+    ] {
+        headers.remove(name);
+    }
+
+    if let Some(identity) = identity {
+        let common_name = &identity.common_name;
+
+        match HeaderValue::from_str(common_name) {
+            Ok(value) => {
+                headers.insert(X_CLIENT_CERT_CN, value);
+            }
+            Err(_) => {
+                tracing::warn!(%common_name, "client common name is not a valid header value");
+            }
+        }
+    }
+
+    if let Ok(value) = HeaderValue::from_str(host) {
+        headers.insert(X_FORWARDED_HOST, value);
+    }
+
+    let Some(connection) = connection else {
+        return;
+    };
+
+    headers.insert(
+        X_FORWARDED_PROTO,
+        HeaderValue::from_static(match connection.scheme {
+            Scheme::Http => "http",
+            Scheme::Https => "https",
+        }),
+    );
+
+    if let Some(addr) = connection.addr {
+        let value = HeaderValue::from_str(&addr.ip().to_string())
+            .expect("ip addresses are always valid header values");
+        headers.insert(X_FORWARDED_FOR, value);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::SocketAddr;
+    use std::sync::Arc;
+
+    use http::Request;
+
+    use crate::config::Scheme;
+    use crate::load_balancer::forwarded::{
+        apply, ClientConnection, ClientIdentity, X_CLIENT_CERT_CN, X_FORWARDED_FOR,
+        X_FORWARDED_HOST, X_FORWARDED_PROTO,
+    };
+
+    fn build_request(connection: Option<ClientConnection>) -> Request<()> {
+        let mut req = Request::builder()
+            .uri("/")
+            .header(X_FORWARDED_FOR, "10.0.0.1")
+            .header(X_FORWARDED_PROTO, "https")
+            .header(X_FORWARDED_HOST, "spoofed.com")
+            .header(X_CLIENT_CERT_CN, "admin")
+            .body(())
+            .unwrap();
+
+        if let Some(connection) = connection {
+            req.extensions_mut().insert(connection);
+        }
+
+        req
+    }
+
+    #[test]
+    fn spoofed_headers_are_replaced_with_connection_details() {
+        let addr: SocketAddr = "192.168.1.20:53124".parse().unwrap();
+        let mut req = build_request(Some(ClientConnection {
+            scheme: Scheme::Http,
+            addr: Some(addr),
+        }));
+
+        apply(&mut req, "example.com");
+
+        let headers = req.headers();
+
+        assert_eq!(headers.get_all(X_FORWARDED_FOR).iter().count(), 1);
+        assert_eq!(headers[X_FORWARDED_FOR], "192.168.1.20");
+        assert_eq!(headers[X_FORWARDED_PROTO], "http");
+        assert_eq!(headers[X_FORWARDED_HOST], "example.com");
+    }
+
+    #[test]
+    fn spoofed_client_addresses_are_removed_when_the_peer_is_unknown() {
+        let mut req = build_request(Some(ClientConnection {
+            scheme: Scheme::Https,
+            addr: None,
+        }));
+
+        apply(&mut req, "example.com");
+
+        let headers = req.headers();
+
+        assert!(headers.get(X_FORWARDED_FOR).is_none());
+        assert_eq!(headers[X_FORWARDED_PROTO], "https");
+        assert_eq!(headers[X_FORWARDED_HOST], "example.com");
+    }
+
+    #[test]
+    fn verified_client_identities_are_forwarded() {
+        let mut req = build_request(Some(ClientConnection {
+            scheme: Scheme::Https,
+            addr: None,
+        }));
+
+        req.extensions_mut().insert(ClientIdentity {
+            common_name: Arc::from("billing-service"),
+        });
+
+        apply(&mut req, "example.com");
+
+        assert_eq!(req.headers()[X_CLIENT_CERT_CN], "billing-service");
+    }
+
+    #[test]
+    fn spoofed_client_identities_are_removed() {
+        let mut req = build_request(None);
+
+        apply(&mut req, "example.com");
+
+        assert!(req.headers().get(X_CLIENT_CERT_CN).is_none());
+    }
+}
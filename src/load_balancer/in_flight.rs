@@ -0,0 +1,160 @@
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll};
+
+use hyper::body::{Body, Frame, SizeHint};
+use indexmap::IndexSet;
+
+use crate::docker::api::StartedContainerDetails;
+
+type Counts = Arc<Mutex<HashMap<IpAddr, usize>>>;
+
+/// Tracks how many requests each container is handling, so that saturated containers stop being
+/// sent more.
+#[derive(Debug, Default)]
+pub struct InFlightLimiter {
+    counts: Counts,
+}
+
+impl InFlightLimiter {
+    /// Filters out any containers already handling the most requests they are allowed.
+    pub fn available<'a>(
+        &self,
+        downstreams: Cow<'a, IndexSet<StartedContainerDetails>>,
+        max: usize,
+    ) -> Cow<'a, IndexSet<StartedContainerDetails>> {
+        let counts = self.counts.lock().unwrap();
+        let saturated = |downstream: &StartedContainerDetails| {
+            counts
+                .get(&downstream.addr)
+                .is_some_and(|count| *count >= max)
+        };
+
+        if !downstreams.iter().any(saturated) {
+            return downstreams;
+        }
+
+        Cow::Owned(
+            downstreams
+                .iter()
+                .filter(|downstream| !saturated(downstream))
+                .cloned()
+                .collect(),
+        )
+    }
+
+    /// Counts a request against a container until the returned permit is dropped.
+    pub fn acquire(&self, addr: IpAddr) -> InFlightPermit {
+        *self.counts.lock().unwrap().entry(addr).or_default() += 1;
+
+        InFlightPermit {
+            counts: Arc::clone(&self.counts),
+            addr,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct InFlightPermit {
+    counts: Counts,
+    addr: IpAddr,
+}
+
+impl Drop for InFlightPermit {
+    fn drop(&mut self) {
+        let mut counts = self.counts.lock().unwrap();
+
+        if let Some(count) = counts.get_mut(&self.addr) {
+            *count -= 1;
+
+            if *count == 0 {
+                counts.remove(&self.addr);
+            }
+        }
+    }
+}
+
+/// A response body that holds on to a permit until it has been sent, so requests count as in
+/// flight while their responses are still streaming.
+#[derive(Debug)]
+pub struct PermitBody<B> {
+    inner: B,
+    _permit: Option<InFlightPermit>,
+}
+
+impl<B> PermitBody<B> {
+    pub fn new(inner: B, permit: Option<InFlightPermit>) -> Self {
+        Self {
+            inner,
+            _permit: permit,
+        }
+    }
+}
+
+impl<B> Body for PermitBody<B>
+where
+    B: Body + Unpin,
+{
+    type Data = B::Data;
+    type Error = B::Error;
+
+    fn poll_frame(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<Frame<Self::Data>, Self::Error>>> {
+        Pin::new(&mut self.inner).poll_frame(cx)
+    }
+
+    fn is_end_stream(&self) -> bool {
+        self.inner.is_end_stream()
+    }
+
+    fn size_hint(&self) -> SizeHint {
+        self.inner.size_hint()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::borrow::Cow;
+    use std::net::Ipv4Addr;
+
+    use indexmap::IndexSet;
+
+    use crate::docker::api::StartedContainerDetails;
+    use crate::docker::models::ContainerId;
+    use crate::load_balancer::in_flight::InFlightLimiter;
+
+    fn downstreams() -> IndexSet<StartedContainerDetails> {
+        (1..=2)
+            .map(|i| StartedContainerDetails {
+                id: ContainerId::random(),
+                addr: Ipv4Addr::new(10, 0, 0, i).into(),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn saturated_containers_are_unavailable_until_permits_are_dropped() {
+        let limiter = InFlightLimiter::default();
+        let downstreams = downstreams();
+        let busy = downstreams[0].addr;
+
+        let first = limiter.acquire(busy);
+        assert_eq!(limiter.available(Cow::Borrowed(&downstreams), 2).len(), 2);
+
+        let second = limiter.acquire(busy);
+        let available = limiter.available(Cow::Borrowed(&downstreams), 2);
+
+        assert_eq!(available.len(), 1);
+        assert!(available.iter().all(|downstream| downstream.addr != busy));
+
+        drop(first);
+        drop(second);
+
+        assert_eq!(limiter.available(Cow::Borrowed(&downstreams), 2).len(), 2);
+    }
+}
@@ -0,0 +1,49 @@
+use std::collections::HashMap;
+use std::sync::Mutex;
+
+/// Maintenance modes set through the admin endpoint, which take precedence over the configuration
+/// until the load balancer restarts.
+#[derive(Debug, Default)]
+pub struct MaintenanceOverrides {
+    services: Mutex<HashMap<String, bool>>,
+}
+
+impl MaintenanceOverrides {
+    pub fn set(&self, service: &str, enabled: bool) {
+        self.services
+            .lock()
+            .unwrap()
+            .insert(service.to_owned(), enabled);
+    }
+
+    /// Checks whether a service is in maintenance mode, falling back to its configured value.
+    pub fn is_enabled(&self, service: &str, configured: bool) -> bool {
+        self.services
+            .lock()
+            .unwrap()
+            .get(service)
+            .copied()
+            .unwrap_or(configured)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::load_balancer::maintenance::MaintenanceOverrides;
+
+    #[test]
+    fn overrides_take_precedence_over_the_configuration() {
+        let overrides = MaintenanceOverrides::default();
+
+        assert!(!overrides.is_enabled("backend", false));
+        assert!(overrides.is_enabled("backend", true));
+
+        overrides.set("backend", true);
+        assert!(overrides.is_enabled("backend", false));
+
+        overrides.set("backend", false);
+        assert!(!overrides.is_enabled("backend", true));
+
+        assert!(overrides.is_enabled("frontend", true));
+    }
+}
@@ -1,6 +1,8 @@
-use std::collections::HashMap;
+use std::collections::{BTreeSet, HashMap};
 use std::error::Error;
-use std::io::Cursor;
+use std::future::Future;
+use std::net::SocketAddrV4;
+use std::pin::Pin;
 use std::sync::Arc;
 
 use arc_swap::ArcSwap;
@@ -8,36 +10,79 @@ use color_eyre::eyre::Result;
 use http::{Request, Response};
 use http_body_util::combinators::BoxBody;
 use hyper::body::{Bytes, Incoming};
+use hyper::server::conn::http1;
 use hyper::service::{service_fn, Service};
-use hyper_util::client::legacy::connect::HttpConnector;
-use hyper_util::client::legacy::Client;
 use hyper_util::rt::{TokioExecutor, TokioIo};
 use hyper_util::server::conn::auto::Builder;
 use mutual_tls::{ConnectionContext, Server, ServerConfiguration};
 use rand::prelude::{SeedableRng, SmallRng};
 use rustls::server::danger::ClientCertVerifier;
-use rustls::server::{NoClientAuth, WebPkiClientVerifier};
-use rustls::RootCertStore;
+use rustls::server::NoClientAuth;
 use tls::DynamicAuthenticationLevelResolver;
-use tokio::net::TcpListener;
-use tokio::sync::{Mutex, RwLock};
+use tokio::net::{TcpListener, UdpSocket};
+use tokio::sync::{watch, Mutex, RwLock};
 use tokio::task::JoinSet;
 
-use crate::config::{Config, MtlsConfig, Scheme, TlsConfig};
+use crate::config::{Config, HttpProtocol, MtlsConfig, Scheme, TlsConfig, Transport};
+use crate::docker::client::DockerClient;
 use crate::ipc::MessageBus;
-use crate::load_balancer::tls::CertificateResolver;
+use crate::load_balancer::acme::Challenges;
+use crate::load_balancer::circuit_breaker::CircuitBreakers;
+use crate::load_balancer::container_logs::ContainerLogs;
+use crate::load_balancer::error_pages::ErrorPages;
+use crate::load_balancer::filters::{Filters, RequestFilter};
+use crate::load_balancer::forwarded::{ClientConnection, ClientIdentity};
+use crate::load_balancer::in_flight::InFlightLimiter;
+use crate::load_balancer::maintenance::MaintenanceOverrides;
+use crate::load_balancer::outlier::OutlierDetector;
+use crate::load_balancer::proxy::{Context, DownstreamClients};
+use crate::load_balancer::rate_limit::RateLimiters;
+use crate::load_balancer::round_robin::WeightedRoundRobin;
+use crate::load_balancer::tls::{
+    CertificateResolver, ExpiryMonitor, LoggingClientCertVerifier,
+    RevocationCheckingClientCertVerifier,
+};
+use crate::load_balancer::trace::SpanBuffer;
+use crate::load_balancer::warm::WarmDownstreams;
+use crate::metrics::Metrics;
 use crate::service_registry::ServiceRegistry;
 
+mod access;
+mod acme;
+mod affinity;
+mod certificate_watcher;
+mod circuit_breaker;
+mod client_authorization;
+mod compression;
+mod container_logs;
+mod cors;
+mod der;
+mod error_pages;
+pub(crate) mod filters;
+mod forwarded;
+mod in_flight;
+mod maintenance;
+mod ocsp;
+mod outlier;
+mod passthrough;
 mod proxy;
+mod rate_limit;
+pub(crate) mod request_id;
+mod retry;
+pub(crate) mod rewrite;
+mod round_robin;
+mod self_signed;
+mod static_files;
 mod tls;
+mod trace;
+mod upstream_error;
+mod upstream_tls;
+mod variants;
+mod warm;
 
 #[derive(Debug)]
 pub struct LoadBalancer {
-    service_registry: Arc<RwLock<ServiceRegistry>>,
-    client: Client<HttpConnector, Incoming>,
-    rng: Arc<Mutex<SmallRng>>,
-    config: Arc<ArcSwap<Config>>,
-    message_bus: Arc<MessageBus>,
+    context: Arc<Context<Incoming>>,
 }
 
 impl LoadBalancer {
@@ -45,91 +90,205 @@ impl LoadBalancer {
         service_registry: Arc<RwLock<ServiceRegistry>>,
         config: Arc<ArcSwap<Config>>,
         message_bus: Arc<MessageBus>,
+        metrics: Arc<Metrics>,
+        docker_client: Arc<dyn DockerClient + Send + Sync>,
     ) -> Self {
-        let client = Client::builder(TokioExecutor::new()).build_http();
-        let rng = Arc::new(Mutex::new(SmallRng::from_entropy()));
+        let clients = DownstreamClients::new(&config.load().alb.connection_pool);
+        let clients = ArcSwap::from_pointee(clients);
+        let rng = Mutex::new(SmallRng::from_entropy());
 
-        Self {
+        let context = Context {
             service_registry,
-            client,
             rng,
+            clients,
             config,
             message_bus,
+            metrics,
+            warm_downstreams: WarmDownstreams::default(),
+            round_robin: WeightedRoundRobin::default(),
+            circuit_breakers: CircuitBreakers::default(),
+            outliers: OutlierDetector::default(),
+            rate_limiters: RateLimiters::default(),
+            in_flight: InFlightLimiter::default(),
+            error_pages: ErrorPages::default(),
+            spans: SpanBuffer::default(),
+            maintenance: MaintenanceOverrides::default(),
+            filters: Filters::default(),
+            acme_challenges: Challenges::default(),
+            container_logs: ContainerLogs::new(docker_client),
+        };
+
+        Self {
+            context: Arc::new(context),
         }
     }
 
-    pub async fn run(
+    /// Makes a filter available to routes under the given name.
+    #[cfg_attr(not(test), allow(dead_code))]
+    pub fn register_filter(&self, name: &str, filter: Arc<dyn RequestFilter>) {
+        self.context.filters.register(name, filter);
+    }
+
+    /// Runs the load balancer until `shutdown` completes, after which listeners stop accepting
+    /// connections and in-flight requests are given the configured grace period to complete.
+    pub async fn run<F: Future<Output = ()>>(
         self,
         mut listeners: HashMap<Scheme, TcpListener>,
         tls: Option<TlsConfig>,
         mtls: Option<MtlsConfig>,
+        shutdown: F,
     ) -> Result<()> {
-        let reconciliation_path = Arc::from(self.config.load().alb.reconciliation.as_str());
-        let message_bus = Arc::clone(&self.message_bus);
-
-        let service_factory = move |_| {
-            let service_registry = Arc::clone(&self.service_registry);
-            let rng = Arc::clone(&self.rng);
-            let client = self.client.clone();
-            let reconciliation_path = Arc::clone(&reconciliation_path);
-            let message_bus = Arc::clone(&message_bus);
-
-            service_fn(move |req| {
-                let service_registry = Arc::clone(&service_registry);
-                let rng = Arc::clone(&rng);
-                let client = client.clone();
-                let reconciliation_path = Arc::clone(&reconciliation_path);
-                let message_bus = Arc::clone(&message_bus);
-
-                async move {
-                    proxy::handle_request(
-                        service_registry,
-                        rng,
-                        client,
-                        reconciliation_path,
-                        message_bus,
-                        req,
-                    )
-                    .await
+        let context = Arc::clone(&self.context);
+
+        let service_factory = move |connection_context: ConnectionContext| {
+            let context = Arc::clone(&context);
+            let identity = connection_context
+                .common_name
+                .map(|common_name| ClientIdentity {
+                    common_name: Arc::from(common_name),
+                });
+
+            service_fn(move |mut req: Request<Incoming>| {
+                if let Some(identity) = &identity {
+                    req.extensions_mut().insert(identity.clone());
                 }
+
+                proxy::handle_request(Arc::clone(&context), req)
             })
         };
 
         let mut tasks = JoinSet::new();
+        let mut draining = JoinSet::new();
+        let (drain_sender, drain_receiver) = watch::channel(());
+
+        tasks.spawn(evict_connection_pools(Arc::clone(&self.context)));
+        tasks.spawn(trace::export_spans(Arc::clone(&self.context)));
+
+        // Passthrough services are only bound at startup, as their ports cannot change once running
+        let (tcp_ports, udp_ports) = {
+            let registry = self.context.service_registry.read().await;
+
+            (
+                registry.listen_ports(Transport::Tcp),
+                registry.listen_ports(Transport::Udp),
+            )
+        };
+        let addr = self.context.config.load().alb.addr;
+
+        for port in tcp_ports {
+            let listener = TcpListener::bind(SocketAddrV4::new(addr, port)).await?;
+
+            tracing::info!("starting tcp listener on {}", listener.local_addr()?);
+
+            tasks.spawn(passthrough::run_tcp_listener(
+                Arc::clone(&self.context),
+                listener,
+            ));
+        }
+
+        for port in udp_ports {
+            let socket = UdpSocket::bind(SocketAddrV4::new(addr, port)).await?;
+
+            tracing::info!("starting udp listener on {}", socket.local_addr()?);
+
+            tasks.spawn(passthrough::run_udp_listener(
+                Arc::clone(&self.context),
+                socket,
+            ));
+        }
 
         if let Some(listener) = listeners.remove(&Scheme::Http) {
-            let server = HttpServer::new(service_factory.clone());
+            let metrics = Arc::clone(&self.context.metrics);
+            let protocol = self.context.config.load().alb.protocol(Scheme::Http);
+            let server = HttpServer::new(service_factory.clone(), metrics, protocol);
 
             tracing::info!("starting http server on {}", listener.local_addr()?);
 
-            tasks.spawn(server.run(listener));
+            draining.spawn(server.run(listener, drain_receiver));
         }
 
         if let Some(listener) = listeners.remove(&Scheme::Https) {
             if let Some(tls) = tls {
-                let client_cert_verifier: Arc<dyn ClientCertVerifier> = match &mtls {
-                    Some(config) => {
-                        let bytes = config.anchor.resolve().await?;
-                        let mut cursor = Cursor::new(bytes);
+                tls::install_crypto_provider(&tls)?;
 
-                        let mut store = RootCertStore::empty();
-                        let certs = rustls_pemfile::certs(&mut cursor).filter_map(Result::ok);
-                        let (added, ignored) = store.add_parsable_certificates(certs);
-
-                        tracing::info!(%added, %ignored, "set up the trust store");
-
-                        WebPkiClientVerifier::builder(Arc::new(store)).build()?
-                    }
-                    None => Arc::new(NoClientAuth),
+                let revocation_checking_verifier = match &mtls {
+                    Some(config) => Some(RevocationCheckingClientCertVerifier::new(config).await?),
+                    None => None,
                 };
 
-                let config = Arc::new(tls.domains);
-                let message_bus = Arc::clone(&self.message_bus);
-
-                let certificate_resolver =
-                    Arc::new(CertificateResolver::new(config, message_bus).await?);
+                let client_cert_verifier: Arc<dyn ClientCertVerifier> =
+                    match &revocation_checking_verifier {
+                        Some(verifier) => {
+                            let inner: Arc<dyn ClientCertVerifier> = verifier.clone();
+                            LoggingClientCertVerifier::new(inner)
+                        }
+                        None => Arc::new(NoClientAuth),
+                    };
+
+                let certificate_resolver = if tls.self_signed {
+                    let config = self.context.config.load();
+                    let hosts: BTreeSet<_> = config
+                        .services
+                        .values()
+                        .flat_map(|service| service.routes.iter().map(|route| route.host.as_str()))
+                        .chain(tls.domains.keys().map(String::as_str))
+                        .collect();
+
+                    tracing::warn!("serving self-signed certificates that clients will not trust");
+
+                    CertificateResolver::self_signed(hosts, Arc::clone(&self.context.metrics))?
+                } else {
+                    tasks.spawn(acme::renew_certificates(
+                        Arc::clone(&self.context),
+                        tls.clone(),
+                    ));
+
+                    tasks.spawn(certificate_watcher::watch_certificates(
+                        Arc::clone(&self.context),
+                        tls.clone(),
+                    ));
+
+                    let expiry_monitor =
+                        ExpiryMonitor::new(Arc::clone(&self.context.metrics), &tls);
+                    let config = Arc::new(tls::certificate_secrets(&tls));
+                    let message_bus = Arc::clone(&self.context.message_bus);
+
+                    CertificateResolver::new(
+                        config,
+                        message_bus,
+                        revocation_checking_verifier,
+                        expiry_monitor,
+                    )
+                    .await?
+                };
+                let certificate_resolver = Arc::new(certificate_resolver);
                 let authentication_level_resolver =
-                    DynamicAuthenticationLevelResolver::new(Arc::clone(&self.config));
+                    DynamicAuthenticationLevelResolver::new(Arc::clone(&self.context.config));
+
+                // The TLS server accepts connections itself, so track them as services are
+                // created for each one instead
+                let metrics = Arc::clone(&self.context.metrics);
+                let service_factory = move |connection_context| {
+                    let connection = metrics.track_connection(Scheme::Https);
+                    let service = service_factory(connection_context);
+
+                    // The TLS server does not expose the address of the client
+                    let client = ClientConnection {
+                        scheme: Scheme::Https,
+                        addr: None,
+                    };
+
+                    service_fn(move |mut req: Request<Incoming>| {
+                        let _connection = &connection;
+                        req.extensions_mut().insert(client);
+                        service.call(req)
+                    })
+                };
+
+                // The TLS server negotiates protocols itself, so restrictions cannot be applied yet
+                if self.context.config.load().alb.protocol(Scheme::Https) != HttpProtocol::Auto {
+                    tracing::warn!("protocol restrictions are not supported for https listeners");
+                }
 
                 let server_configuration = ServerConfiguration::default();
                 let server = Server::new(
@@ -146,9 +305,22 @@ impl LoadBalancer {
             }
         }
 
-        tracing::info!("waiting for all servers to complete");
+        shutdown.await;
+
+        tracing::info!("shutdown requested, no longer accepting connections");
+
+        // The TLS server cannot be drained, so it stops accepting connections immediately
+        tasks.abort_all();
+        drain_sender.send_replace(());
 
-        tasks.join_all().await;
+        let grace_period = self.context.config.load().shutdown.grace_period();
+
+        if tokio::time::timeout(grace_period, draining.join_all())
+            .await
+            .is_err()
+        {
+            tracing::warn!(?grace_period, "in-flight requests did not complete in time");
+        }
 
         tracing::info!("all servers have completed");
 
@@ -156,8 +328,37 @@ impl LoadBalancer {
     }
 }
 
+/// Replaces the downstream clients whenever containers are removed, if configured to, so that
+/// pooled connections to containers that no longer exist are not reused.
+async fn evict_connection_pools(context: Arc<Context<Incoming>>) {
+    while context
+        .message_bus
+        .receive_pool_eviction_request()
+        .await
+        .is_ok()
+    {
+        // The containers being rotated through have changed, so start from the beginning again
+        context.round_robin.clear();
+
+        let config = context.config.load();
+
+        if !config.alb.evict_connections_on_deploy {
+            continue;
+        }
+
+        tracing::info!("evicting pooled connections to downstream containers");
+
+        // Requests already in flight keep hold of the old client until they complete
+        let clients = DownstreamClients::new(&config.alb.connection_pool);
+        context.clients.store(Arc::new(clients));
+        context.warm_downstreams.clear();
+    }
+}
+
 pub struct HttpServer<F> {
     service_factory: Arc<F>,
+    metrics: Arc<Metrics>,
+    protocol: HttpProtocol,
 }
 
 impl<F, S> HttpServer<F>
@@ -170,43 +371,129 @@ where
     <S as Service<Request<Incoming>>>::Future: Send,
     <S as Service<Request<Incoming>>>::Error: Into<Box<dyn Error + Send + Sync>>,
 {
-    pub fn new(service_factory: F) -> Self {
+    pub fn new(service_factory: F, metrics: Arc<Metrics>, protocol: HttpProtocol) -> Self {
         Self {
             service_factory: Arc::new(service_factory),
+            metrics,
+            protocol,
         }
     }
 
-    async fn run(self, mut listener: TcpListener) {
+    async fn run(self, mut listener: TcpListener, mut shutdown: watch::Receiver<()>) {
+        let mut connections = JoinSet::new();
+        let connection_shutdown = shutdown.clone();
+
         loop {
-            if let Err(e) = self.try_handle_connection(&mut listener).await {
-                tracing::warn!(%e, "failed to handle connection");
-            } else {
-                tracing::trace!("handled a connection from a client");
+            tokio::select! {
+                result = self.try_handle_connection(&mut listener, &mut connections, &connection_shutdown) => {
+                    if let Err(e) = result {
+                        self.metrics.record_connection_error(Scheme::Http);
+                        tracing::warn!(%e, "failed to handle connection");
+                    } else {
+                        tracing::trace!("handled a connection from a client");
+                    }
+                }
+                _ = shutdown.changed() => break,
             }
+
+            // Reap any connections that have already closed
+            while connections.try_join_next().is_some() {}
         }
+
+        drop(listener);
+
+        tracing::info!(
+            connections = %connections.len(),
+            "waiting for http connections to drain"
+        );
+
+        connections.join_all().await;
     }
 
     pub async fn try_handle_connection(
         &self,
         listener: &mut TcpListener,
+        connections: &mut JoinSet<()>,
+        shutdown: &watch::Receiver<()>,
     ) -> Result<(), Box<dyn Error + Send + Sync>> {
-        let (stream, _) = listener.accept().await?;
+        let (stream, addr) = listener.accept().await?;
         let io = TokioIo::new(stream);
 
         let service = (self.service_factory)(ConnectionContext { common_name: None });
+        let client = ClientConnection {
+            scheme: Scheme::Http,
+            addr: Some(addr),
+        };
 
-        tokio::spawn(async move {
-            if let Err(e) = Builder::new(TokioExecutor::new())
-                .serve_connection(io, service)
-                .await
-            {
+        let service = service_fn(move |mut req: Request<Incoming>| {
+            req.extensions_mut().insert(client);
+            service.call(req)
+        });
+
+        let metrics = Arc::clone(&self.metrics);
+        let connection = metrics.track_connection(Scheme::Http);
+        let shutdown = shutdown.clone();
+        let protocol = self.protocol;
+
+        connections.spawn(async move {
+            let builder = Builder::new(TokioExecutor::new());
+
+            // Upgrades are only possible over HTTP/1, and the auto builder ignores any version
+            // restriction when serving them, so HTTP/1-only listeners use hyper directly
+            let result = match protocol {
+                HttpProtocol::Auto => {
+                    let conn = builder.serve_connection_with_upgrades(io, service);
+                    serve_until_shutdown(conn, shutdown, |conn| conn.graceful_shutdown()).await
+                }
+                HttpProtocol::Http1 => {
+                    let conn = http1::Builder::new()
+                        .serve_connection(io, service)
+                        .with_upgrades();
+
+                    serve_until_shutdown(conn, shutdown, |conn| conn.graceful_shutdown())
+                        .await
+                        .map_err(Into::into)
+                }
+                HttpProtocol::Http2 => {
+                    let builder = builder.http2_only();
+                    let conn = builder.serve_connection(io, service);
+                    serve_until_shutdown(conn, shutdown, |conn| conn.graceful_shutdown()).await
+                }
+            };
+
+            if let Err(e) = result {
+                metrics.record_connection_error(Scheme::Http);
                 tracing::warn!(%e, "error handling connection");
             }
+
+            drop(connection);
         });
 
         Ok(())
     }
 }
 
+/// Drives a connection to completion, letting any in-flight request complete before closing it
+/// once shutdown is signalled.
+async fn serve_until_shutdown<C, E, G>(
+    conn: C,
+    mut shutdown: watch::Receiver<()>,
+    graceful_shutdown: G,
+) -> Result<(), E>
+where
+    C: Future<Output = Result<(), E>>,
+    G: FnOnce(Pin<&mut C>),
+{
+    tokio::pin!(conn);
+
+    tokio::select! {
+        result = conn.as_mut() => result,
+        _ = shutdown.changed() => {
+            graceful_shutdown(conn.as_mut());
+            conn.await
+        }
+    }
+}
+
 #[cfg(test)]
 mod tests;
@@ -0,0 +1,182 @@
+//! Fetching OCSP responses to staple to certificates, so clients can check they have not been
+//! revoked without asking the issuer themselves.
+
+use std::time::Duration;
+
+use color_eyre::eyre::{eyre, Result};
+use http::header::CONTENT_TYPE;
+use http::Request;
+use http_body_util::{BodyExt, Full};
+use hyper::body::Bytes;
+use hyper_rustls::{HttpsConnector, HttpsConnectorBuilder};
+use hyper_util::client::legacy::connect::HttpConnector;
+use hyper_util::client::legacy::Client;
+use hyper_util::rt::TokioExecutor;
+use rustls::crypto::ring::default_provider;
+use rustls::pki_types::CertificateDer;
+use rustls::sign::CertifiedKey;
+
+use crate::load_balancer::der;
+
+/// How often to replace stapled responses, well within how long responders say they are valid.
+pub const REFRESH_INTERVAL: Duration = Duration::from_secs(6 * 60 * 60);
+
+/// How long to wait for a responder before leaving the certificate unstapled.
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+
+pub type HttpClient = Client<HttpsConnector<HttpConnector>, Full<Bytes>>;
+
+/// Builds a client for reaching responders, choosing the crypto provider explicitly since more
+/// than one may be enabled.
+pub fn http_client() -> Result<HttpClient> {
+    let connector = HttpsConnectorBuilder::new()
+        .with_provider_and_webpki_roots(default_provider())?
+        .https_or_http()
+        .enable_http1()
+        .build();
+
+    Ok(Client::builder(TokioExecutor::new()).build(connector))
+}
+
+/// Asks a responder for the status of a certificate, returning the response if it holds one.
+async fn request_status(http: &HttpClient, responder: &str, request: Vec<u8>) -> Result<Vec<u8>> {
+    let request = Request::post(responder)
+        .header(CONTENT_TYPE, "application/ocsp-request")
+        .body(Full::new(Bytes::from(request)))?;
+
+    let response = tokio::time::timeout(REQUEST_TIMEOUT, http.request(request)).await??;
+
+    if !response.status().is_success() {
+        return Err(eyre!(
+            "ocsp responder at {responder} returned {}",
+            response.status()
+        ));
+    }
+
+    let body = tokio::time::timeout(REQUEST_TIMEOUT, response.into_body().collect())
+        .await??
+        .to_bytes();
+
+    if !der::ocsp_response_successful(&body)? {
+        return Err(eyre!(
+            "ocsp responder at {responder} did not provide a status"
+        ));
+    }
+
+    Ok(body.to_vec())
+}
+
+/// Fetches the OCSP response for the first certificate in a chain, if it has an issuer to ask.
+async fn fetch_response(
+    http: &HttpClient,
+    chain: &[CertificateDer<'_>],
+) -> Result<Option<Vec<u8>>> {
+    let [certificate, issuer, ..] = chain else {
+        return Ok(None);
+    };
+
+    let Some(responder) = der::ocsp_responder(certificate)? else {
+        return Ok(None);
+    };
+
+    let request = der::ocsp_request(certificate, issuer)?;
+    let response = request_status(http, &responder, request).await?;
+
+    Ok(Some(response))
+}
+
+/// Staples a fresh OCSP response to the key, returning whether one was fetched.
+///
+/// Any previously stapled response is kept if a fresh one cannot be fetched, as it is likely to
+/// still be valid.
+pub async fn staple(http: &HttpClient, domain: &str, certified_key: &mut CertifiedKey) -> bool {
+    match fetch_response(http, &certified_key.cert).await {
+        Ok(Some(response)) => {
+            tracing::debug!(%domain, bytes = response.len(), "stapled an ocsp response");
+
+            certified_key.ocsp = Some(response);
+            true
+        }
+        Ok(None) => false,
+        Err(error) => {
+            tracing::warn!(%domain, %error, "failed to fetch an ocsp response");
+            false
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::{Ipv4Addr, SocketAddrV4};
+
+    use color_eyre::eyre::Result;
+    use http::header::CONTENT_TYPE;
+    use http::{Method, Request, Response};
+    use http_body_util::{BodyExt, Full};
+    use hyper::body::Incoming;
+    use hyper::service::service_fn;
+    use hyper_util::rt::{TokioExecutor, TokioIo};
+    use hyper_util::server::conn::auto::Builder;
+    use tokio::net::TcpListener;
+
+    use crate::load_balancer::ocsp::{http_client, request_status};
+
+    /// The shortest response that holds a status, as only the response status is inspected.
+    const SUCCESSFUL: &[u8] = &[0x30, 0x03, 0x0a, 0x01, 0x00];
+
+    /// A response saying the responder is unable to answer for the certificate.
+    const UNAUTHORIZED: &[u8] = &[0x30, 0x03, 0x0a, 0x01, 0x06];
+
+    /// Spawns a responder that echoes back the request as its response, checking it was sent as
+    /// an OCSP request.
+    async fn spawn_echoing_responder() -> Result<String> {
+        let addr = SocketAddrV4::new(Ipv4Addr::LOCALHOST, 0);
+        let listener = TcpListener::bind(&addr).await?;
+        let responder = format!("http://{}", listener.local_addr()?);
+
+        tokio::spawn(async move {
+            loop {
+                let (stream, _) = listener.accept().await.unwrap();
+                let io = TokioIo::new(stream);
+
+                let service = service_fn(|req: Request<Incoming>| async move {
+                    assert_eq!(req.method(), Method::POST);
+                    assert_eq!(req.headers()[CONTENT_TYPE], "application/ocsp-request");
+
+                    let body = req.into_body().collect().await?.to_bytes();
+
+                    Ok::<_, hyper::Error>(Response::new(Full::new(body)))
+                });
+
+                tokio::spawn(async move {
+                    Builder::new(TokioExecutor::new())
+                        .serve_connection(io, service)
+                        .await
+                        .unwrap();
+                });
+            }
+        });
+
+        Ok(responder)
+    }
+
+    #[tokio::test]
+    async fn successful_responses_are_returned() -> Result<()> {
+        let responder = spawn_echoing_responder().await?;
+        let response = request_status(&http_client()?, &responder, SUCCESSFUL.to_vec()).await?;
+
+        assert_eq!(response, SUCCESSFUL);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn responses_without_a_status_are_rejected() -> Result<()> {
+        let responder = spawn_echoing_responder().await?;
+        let response = request_status(&http_client()?, &responder, UNAUTHORIZED.to_vec()).await;
+
+        assert!(response.is_err());
+
+        Ok(())
+    }
+}
@@ -0,0 +1,192 @@
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::sync::Mutex;
+use std::time::Instant;
+
+use indexmap::IndexSet;
+
+use crate::config::OutlierDetectionConfig;
+use crate::docker::api::StartedContainerDetails;
+
+#[derive(Copy, Clone, Debug, Default)]
+struct Health {
+    failures: u32,
+    ejected_until: Option<Instant>,
+}
+
+/// Tracks failing requests for each container, taking those that keep failing out of rotation for
+/// a while without waiting for a health check to notice.
+#[derive(Debug, Default)]
+pub struct OutlierDetector {
+    containers: Mutex<HashMap<IpAddr, Health>>,
+}
+
+impl OutlierDetector {
+    /// Filters out any ejected containers, unless doing so would eject more of them than allowed.
+    pub fn available<'a>(
+        &self,
+        downstreams: &'a IndexSet<StartedContainerDetails>,
+        config: &OutlierDetectionConfig,
+    ) -> Cow<'a, IndexSet<StartedContainerDetails>> {
+        let mut containers = self.containers.lock().unwrap();
+        let now = Instant::now();
+
+        let ejected: Vec<_> = downstreams
+            .iter()
+            .filter(|downstream| {
+                let Some(health) = containers.get_mut(&downstream.addr) else {
+                    return false;
+                };
+
+                match health.ejected_until {
+                    Some(until) if until <= now => {
+                        tracing::info!(addr = %downstream.addr, "readmitting ejected container");
+
+                        health.ejected_until = None;
+                        false
+                    }
+                    Some(_) => true,
+                    None => false,
+                }
+            })
+            .collect();
+
+        if ejected.is_empty() {
+            return Cow::Borrowed(downstreams);
+        }
+
+        if ejected.len() * 100 > downstreams.len() * usize::from(config.max_ejection_percent) {
+            tracing::debug!(
+                ejected = %ejected.len(),
+                total = %downstreams.len(),
+                "too many containers are ejected, ignoring outlier detection"
+            );
+
+            return Cow::Borrowed(downstreams);
+        }
+
+        Cow::Owned(
+            downstreams
+                .iter()
+                .filter(|downstream| !ejected.contains(downstream))
+                .cloned()
+                .collect(),
+        )
+    }
+
+    /// Records the outcome of a request sent to a container.
+    pub fn record(&self, addr: IpAddr, config: &OutlierDetectionConfig, success: bool) {
+        let mut containers = self.containers.lock().unwrap();
+
+        if success {
+            if let Some(health) = containers.get_mut(&addr) {
+                health.failures = 0;
+            }
+
+            return;
+        }
+
+        let health = containers.entry(addr).or_default();
+        health.failures += 1;
+
+        if health.failures >= config.failure_threshold && health.ejected_until.is_none() {
+            tracing::warn!(%addr, failures = %health.failures, "ejecting failing container");
+
+            health.failures = 0;
+            health.ejected_until = Some(Instant::now() + config.ejection_duration());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::Ipv4Addr;
+    use std::time::Duration;
+
+    use indexmap::IndexSet;
+
+    use crate::config::OutlierDetectionConfig;
+    use crate::docker::api::StartedContainerDetails;
+    use crate::docker::models::ContainerId;
+    use crate::load_balancer::outlier::OutlierDetector;
+
+    fn config(ejection_ms: u64) -> OutlierDetectionConfig {
+        OutlierDetectionConfig {
+            failure_threshold: 2,
+            ejection_ms,
+            max_ejection_percent: 50,
+        }
+    }
+
+    fn downstreams(count: u8) -> IndexSet<StartedContainerDetails> {
+        (1..=count)
+            .map(|i| StartedContainerDetails {
+                id: ContainerId::random(),
+                addr: Ipv4Addr::new(10, 0, 0, i).into(),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn failing_containers_are_ejected() {
+        let detector = OutlierDetector::default();
+        let config = config(60_000);
+        let downstreams = downstreams(2);
+        let failing = downstreams[0].addr;
+
+        detector.record(failing, &config, false);
+        assert_eq!(detector.available(&downstreams, &config).len(), 2);
+
+        detector.record(failing, &config, false);
+        let available = detector.available(&downstreams, &config);
+
+        assert_eq!(available.len(), 1);
+        assert!(available
+            .iter()
+            .all(|downstream| downstream.addr != failing));
+    }
+
+    #[test]
+    fn successes_reset_the_failure_count() {
+        let detector = OutlierDetector::default();
+        let config = config(60_000);
+        let downstreams = downstreams(2);
+        let addr = downstreams[0].addr;
+
+        detector.record(addr, &config, false);
+        detector.record(addr, &config, true);
+        detector.record(addr, &config, false);
+
+        assert_eq!(detector.available(&downstreams, &config).len(), 2);
+    }
+
+    #[test]
+    fn no_more_than_the_maximum_share_are_ejected() {
+        let detector = OutlierDetector::default();
+        let config = config(60_000);
+        let downstreams = downstreams(2);
+
+        for downstream in &downstreams {
+            detector.record(downstream.addr, &config, false);
+            detector.record(downstream.addr, &config, false);
+        }
+
+        assert_eq!(detector.available(&downstreams, &config).len(), 2);
+    }
+
+    #[test]
+    fn ejected_containers_are_readmitted_after_the_cool_down() {
+        let detector = OutlierDetector::default();
+        let config = config(10);
+        let downstreams = downstreams(2);
+
+        detector.record(downstreams[0].addr, &config, false);
+        detector.record(downstreams[0].addr, &config, false);
+        assert_eq!(detector.available(&downstreams, &config).len(), 1);
+
+        std::thread::sleep(Duration::from_millis(20));
+
+        assert_eq!(detector.available(&downstreams, &config).len(), 2);
+    }
+}
@@ -0,0 +1,177 @@
+use std::collections::HashMap;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::sync::Arc;
+use std::time::Duration;
+
+use color_eyre::eyre::{eyre, Result};
+use hyper::body::Incoming;
+use tokio::net::{TcpListener, TcpStream, UdpSocket};
+use tokio::sync::Mutex;
+
+use crate::config::Transport;
+use crate::load_balancer::proxy::Context;
+
+/// The largest datagram that can be carried over UDP.
+const MAX_DATAGRAM_SIZE: usize = 65535;
+
+/// How long a UDP session can go without replies before it is forgotten.
+const UDP_SESSION_IDLE_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// Accepts connections on a port routed to a TCP service, streaming each one to a container.
+pub async fn run_tcp_listener(context: Arc<Context<Incoming>>, listener: TcpListener) {
+    loop {
+        let (stream, peer) = match listener.accept().await {
+            Ok(accepted) => accepted,
+            Err(e) => {
+                tracing::warn!(%e, "failed to accept tcp connection");
+                continue;
+            }
+        };
+
+        let context = Arc::clone(&context);
+
+        tokio::spawn(async move {
+            if let Err(e) = forward_tcp(&context, stream).await {
+                tracing::warn!(%e, %peer, "error forwarding tcp connection");
+            }
+        });
+    }
+}
+
+async fn forward_tcp(context: &Context<Incoming>, mut client: TcpStream) -> Result<()> {
+    let listen = client.local_addr()?.port();
+    let addr = choose_downstream(context, Transport::Tcp, listen).await?;
+
+    let mut downstream = TcpStream::connect(addr).await?;
+
+    tracing::debug!(%listen, %addr, "forwarding tcp connection");
+
+    tokio::io::copy_bidirectional(&mut client, &mut downstream).await?;
+
+    Ok(())
+}
+
+/// Receives datagrams on a port routed to a UDP service, forwarding them to a container.
+///
+/// Each client is pinned to a single container for as long as its session stays active, so replies
+/// can be relayed back to it.
+pub async fn run_udp_listener(context: Arc<Context<Incoming>>, socket: UdpSocket) {
+    let socket = Arc::new(socket);
+    let sessions: Arc<Mutex<HashMap<SocketAddr, Arc<UdpSocket>>>> = Arc::default();
+
+    let mut buffer = vec![0; MAX_DATAGRAM_SIZE];
+
+    loop {
+        let (len, peer) = match socket.recv_from(&mut buffer).await {
+            Ok(received) => received,
+            Err(e) => {
+                tracing::warn!(%e, "failed to receive udp datagram");
+                continue;
+            }
+        };
+
+        let session = match find_or_create_session(&context, &socket, &sessions, peer).await {
+            Ok(session) => session,
+            Err(e) => {
+                tracing::warn!(%e, %peer, "failed to create udp session");
+                continue;
+            }
+        };
+
+        if let Err(e) = session.send(&buffer[..len]).await {
+            tracing::warn!(%e, %peer, "error forwarding udp datagram");
+        }
+    }
+}
+
+async fn find_or_create_session(
+    context: &Context<Incoming>,
+    socket: &Arc<UdpSocket>,
+    sessions: &Arc<Mutex<HashMap<SocketAddr, Arc<UdpSocket>>>>,
+    peer: SocketAddr,
+) -> Result<Arc<UdpSocket>> {
+    let mut lock = sessions.lock().await;
+
+    if let Some(session) = lock.get(&peer) {
+        return Ok(Arc::clone(session));
+    }
+
+    let listen = socket.local_addr()?.port();
+    let addr = choose_downstream(context, Transport::Udp, listen).await?;
+
+    // Sockets can only send to addresses of the family they were bound with
+    let unspecified = match addr {
+        SocketAddr::V4(_) => IpAddr::V4(Ipv4Addr::UNSPECIFIED),
+        SocketAddr::V6(_) => IpAddr::V6(Ipv6Addr::UNSPECIFIED),
+    };
+
+    let upstream = UdpSocket::bind(SocketAddr::new(unspecified, 0)).await?;
+    upstream.connect(addr).await?;
+
+    let upstream = Arc::new(upstream);
+    lock.insert(peer, Arc::clone(&upstream));
+
+    tracing::debug!(%listen, %addr, %peer, "created udp session");
+
+    tokio::spawn(relay_replies(
+        Arc::clone(socket),
+        Arc::clone(&upstream),
+        Arc::clone(sessions),
+        peer,
+    ));
+
+    Ok(upstream)
+}
+
+/// Sends replies from a container back to the client until the session goes idle.
+async fn relay_replies(
+    socket: Arc<UdpSocket>,
+    upstream: Arc<UdpSocket>,
+    sessions: Arc<Mutex<HashMap<SocketAddr, Arc<UdpSocket>>>>,
+    peer: SocketAddr,
+) {
+    let mut buffer = vec![0; MAX_DATAGRAM_SIZE];
+
+    loop {
+        let len = match tokio::time::timeout(UDP_SESSION_IDLE_TIMEOUT, upstream.recv(&mut buffer))
+            .await
+        {
+            Ok(Ok(len)) => len,
+            Ok(Err(e)) => {
+                tracing::warn!(%e, %peer, "error receiving udp reply");
+                break;
+            }
+            Err(_) => break,
+        };
+
+        if let Err(e) = socket.send_to(&buffer[..len], peer).await {
+            tracing::warn!(%e, %peer, "error relaying udp reply");
+        }
+    }
+
+    sessions.lock().await.remove(&peer);
+
+    tracing::debug!(%peer, "closed udp session");
+}
+
+/// Picks the container to forward traffic arriving on a port to.
+async fn choose_downstream(
+    context: &Context<Incoming>,
+    transport: Transport,
+    listen: u16,
+) -> Result<SocketAddr> {
+    let read_lock = context.service_registry.read().await;
+
+    let (route, downstreams) = read_lock
+        .find_port_downstreams(transport, listen)
+        .ok_or_else(|| eyre!("no service accepts {transport:?} traffic on port {listen}"))?;
+
+    let downstream = context
+        .round_robin
+        .choose(downstreams, |downstream| {
+            read_lock.get_weight(&downstream.id)
+        })
+        .ok_or_else(|| eyre!("no downstreams found for {transport:?} port {listen}"))?;
+
+    Ok(SocketAddr::new(downstream.addr, route.port))
+}
@@ -1,30 +1,232 @@
-use std::net::SocketAddrV4;
+use std::borrow::Cow;
+use std::collections::{HashMap, VecDeque};
+use std::net::SocketAddr;
 use std::sync::Arc;
+use std::time::Duration;
 
+use arc_swap::ArcSwap;
 use color_eyre::eyre::{eyre, Result};
-use http::header::HOST;
-use http::{Method, Version};
+use http::header::{
+    CONNECTION, CONTENT_LENGTH, CONTENT_TYPE, HOST, LOCATION, RETRY_AFTER,
+    STRICT_TRANSPORT_SECURITY, UPGRADE,
+};
+use http::{HeaderMap, HeaderValue, Method, StatusCode, Uri, Version};
 use http_body_util::combinators::BoxBody;
-use http_body_util::{BodyExt, Empty};
+use http_body_util::{BodyExt, Empty, Full, LengthLimitError, Limited};
 use hyper::body::{Body, Bytes};
+use hyper::http::request::Parts;
 use hyper::http::uri::PathAndQuery;
+use hyper::upgrade::OnUpgrade;
 use hyper::{Request, Response};
+use hyper_rustls::HttpsConnector;
 use hyper_util::client::legacy::connect::HttpConnector;
-use hyper_util::client::legacy::Client;
+use hyper_util::client::legacy::{Builder, Client, ResponseFuture};
+use hyper_util::rt::{TokioExecutor, TokioIo, TokioTimer};
 use rand::prelude::SmallRng;
-use rand::RngCore;
+use rand::Rng;
 use tokio::sync::{Mutex, RwLock};
+use tokio::time::Instant;
 
+use crate::config::{
+    AlbConfig, Config, ConnectionPool, ExternalBytes, PathLimits, PlainHttpAction, Scheme,
+    UpstreamProtocol,
+};
 use crate::ipc::MessageBus;
-use crate::service_registry::ServiceRegistry;
+use crate::load_balancer::access;
+use crate::load_balancer::acme::Challenges;
+use crate::load_balancer::affinity;
+use crate::load_balancer::circuit_breaker::CircuitBreakers;
+use crate::load_balancer::client_authorization;
+use crate::load_balancer::compression::{self, Encoding};
+use crate::load_balancer::container_logs::{self, ContainerLogs};
+use crate::load_balancer::cors;
+use crate::load_balancer::error_pages::ErrorPages;
+use crate::load_balancer::filters::{FilterAction, Filters};
+use crate::load_balancer::forwarded::{self, ClientConnection, ClientIdentity};
+use crate::load_balancer::in_flight::{InFlightLimiter, PermitBody};
+use crate::load_balancer::maintenance::MaintenanceOverrides;
+use crate::load_balancer::outlier::OutlierDetector;
+use crate::load_balancer::rate_limit::RateLimiters;
+use crate::load_balancer::request_id::RequestIdHeaders;
+use crate::load_balancer::retry::{is_retryable, BodyTooLarge, ReplayBody};
+use crate::load_balancer::rewrite::HeaderRewriters;
+use crate::load_balancer::round_robin::WeightedRoundRobin;
+use crate::load_balancer::static_files;
+use crate::load_balancer::trace::{ProxySpan, SpanBuffer, SpanDetails};
+use crate::load_balancer::upstream_error::UpstreamError;
+use crate::load_balancer::upstream_tls;
+use crate::load_balancer::variants;
+use crate::load_balancer::warm::WarmDownstreams;
+use crate::metrics::Metrics;
+use crate::service_registry::{MatchedService, ServiceRegistry};
+
+/// The largest body accepted by a reserved endpoint, none of which expect one.
+const MAX_RESERVED_BODY_SIZE: usize = 64 * 1024;
+
+/// How often to check whether a service without containers has gained one.
+const EMPTY_SERVICE_POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+/// How long clients are asked to wait before retrying when every downstream is saturated.
+const SATURATED_RETRY_AFTER: Duration = Duration::from_secs(1);
+
+/// Endpoints handled by the load balancer itself rather than being proxied downstream.
+#[derive(Clone, Debug)]
+enum ReservedEndpoint {
+    Reconciliation,
+    /// Refreshes the certificate for a single domain if one is given, or all of them otherwise.
+    Certificates {
+        domain: Option<String>,
+    },
+    /// Turns maintenance mode on or off for a service, overriding its configuration.
+    Maintenance {
+        service: Option<String>,
+        enabled: Option<bool>,
+    },
+}
+
+type TlsClient<B> = Client<HttpsConnector<HttpConnector>, B>;
+
+/// The settings that routes connecting to containers over TLS can differ by.
+type TlsClientKey = (UpstreamProtocol, String, Option<ExternalBytes>);
+
+/// Pooled clients for each HTTP version spoken to downstream containers.
+#[derive(Debug)]
+pub struct DownstreamClients<B> {
+    http1: Client<HttpConnector, B>,
+    http2: Client<HttpConnector, B>,
+    connector: HttpConnector,
+    builder: Builder,
+    /// Clients for containers served over TLS, built the first time a route needs each of them.
+    tls: std::sync::Mutex<HashMap<TlsClientKey, TlsClient<B>>>,
+}
+
+impl<B> DownstreamClients<B>
+where
+    B: Body + Send + 'static,
+    <B as Body>::Data: Send,
+{
+    pub fn new(pool: &ConnectionPool) -> Self {
+        let mut connector = HttpConnector::new();
+        connector.set_keepalive(pool.keep_alive_interval());
+
+        let mut builder = Client::builder(TokioExecutor::new());
+
+        builder
+            .timer(TokioTimer::new())
+            .pool_timer(TokioTimer::new())
+            .pool_idle_timeout(pool.idle_timeout())
+            .http2_keep_alive_interval(pool.keep_alive_interval());
+
+        if let Some(max_idle) = pool.max_idle_per_host {
+            builder.pool_max_idle_per_host(max_idle);
+        }
+
+        let build = |http2_only| {
+            builder
+                .clone()
+                .http2_only(http2_only)
+                .build(connector.clone())
+        };
+
+        Self {
+            http1: build(false),
+            http2: build(true),
+            connector,
+            builder,
+            tls: std::sync::Mutex::default(),
+        }
+    }
+
+    fn get(&self, protocol: UpstreamProtocol) -> &Client<HttpConnector, B> {
+        match protocol {
+            UpstreamProtocol::Http1 => &self.http1,
+            UpstreamProtocol::Http2 => &self.http2,
+        }
+    }
+
+    /// Gets the client for containers served over TLS that present a certificate for
+    /// `server_name`, issued by `ca` if given.
+    async fn get_tls(
+        &self,
+        protocol: UpstreamProtocol,
+        server_name: &str,
+        ca: Option<&ExternalBytes>,
+    ) -> Result<TlsClient<B>> {
+        let key = (protocol, server_name.to_owned(), ca.cloned());
+
+        if let Some(client) = self.tls.lock().unwrap().get(&key) {
+            return Ok(client.clone());
+        }
+
+        let connector =
+            upstream_tls::connector(self.connector.clone(), protocol, server_name, ca).await?;
+
+        let client = self
+            .builder
+            .clone()
+            .http2_only(protocol == UpstreamProtocol::Http2)
+            .build(connector);
+
+        self.tls.lock().unwrap().insert(key, client.clone());
+
+        Ok(client)
+    }
+}
+
+/// The client a request is proxied with, depending on whether the route connects to its
+/// containers over TLS.
+enum DownstreamClient<B> {
+    Plain(Client<HttpConnector, B>),
+    Tls(TlsClient<B>),
+}
+
+impl<B> DownstreamClient<B>
+where
+    B: Body + Send + Unpin + 'static,
+    <B as Body>::Data: Send,
+    <B as Body>::Error: Into<Box<dyn std::error::Error + Send + Sync>>,
+{
+    fn scheme(&self) -> Scheme {
+        match self {
+            Self::Plain(_) => Scheme::Http,
+            Self::Tls(_) => Scheme::Https,
+        }
+    }
+
+    fn request(&self, request: Request<B>) -> ResponseFuture {
+        match self {
+            Self::Plain(client) => client.request(request),
+            Self::Tls(client) => client.request(request),
+        }
+    }
+}
+
+/// State shared by every request passing through the proxy.
+#[derive(Debug)]
+pub struct Context<B> {
+    pub service_registry: Arc<RwLock<ServiceRegistry>>,
+    pub rng: Mutex<SmallRng>,
+    pub clients: ArcSwap<DownstreamClients<ReplayBody<B>>>,
+    pub config: Arc<ArcSwap<Config>>,
+    pub message_bus: Arc<MessageBus>,
+    pub metrics: Arc<Metrics>,
+    pub warm_downstreams: WarmDownstreams,
+    pub round_robin: WeightedRoundRobin,
+    pub circuit_breakers: CircuitBreakers,
+    pub outliers: OutlierDetector,
+    pub rate_limiters: RateLimiters,
+    pub in_flight: InFlightLimiter,
+    pub error_pages: ErrorPages,
+    pub spans: SpanBuffer,
+    pub maintenance: MaintenanceOverrides,
+    pub filters: Filters,
+    pub acme_challenges: Challenges,
+    pub container_logs: ContainerLogs,
+}
 
 pub async fn handle_request<B>(
-    service_registry: Arc<RwLock<ServiceRegistry>>,
-    rng: Arc<Mutex<SmallRng>>,
-    client: Client<HttpConnector, B>,
-    reconciliation_path: Arc<str>,
-    message_bus: Arc<MessageBus>,
-    req: Request<B>,
+    context: Arc<Context<B>>,
+    mut req: Request<B>,
 ) -> Result<Response<BoxBody<Bytes, hyper::Error>>>
 where
     B: Body + Send + Unpin + 'static,
@@ -32,63 +234,902 @@ where
     <B as Body>::Error: std::error::Error + Send + Sync + 'static,
 {
     let uri = req.uri();
+    let config = context.config.load_full();
+    let reconciliation_path = config.alb.reconciliation.as_str();
+
+    let reserved_endpoint = match uri.path_and_query() {
+        _ if req.method() != Method::PUT => None,
+        Some(suffix) if suffix.path() == reconciliation_path => {
+            Some(ReservedEndpoint::Reconciliation)
+        }
+        Some(suffix) if suffix.path() == "/certificates" => Some(ReservedEndpoint::Certificates {
+            domain: find_query_parameter(suffix.query(), "domain"),
+        }),
+        Some(suffix) if config.alb.maintenance.as_deref() == Some(suffix.path()) => {
+            Some(ReservedEndpoint::Maintenance {
+                service: find_query_parameter(suffix.query(), "service"),
+                enabled: find_query_parameter(suffix.query(), "enabled")
+                    .and_then(|enabled| enabled.parse().ok()),
+            })
+        }
+        _ => None,
+    };
+
+    if let Some(endpoint) = reserved_endpoint {
+        // Read the body before responding so the connection can be reused afterwards
+        if !drain_body(req.into_body()).await? {
+            tracing::warn!(
+                ?endpoint,
+                "rejecting oversized body sent to a reserved endpoint"
+            );
 
-    if req.method() == Method::PUT {
-        match uri.path_and_query() {
-            Some(suffix) if suffix.path() == &*reconciliation_path => {
+            return Ok(Response::builder().status(413).body(empty())?);
+        }
+
+        match endpoint {
+            ReservedEndpoint::Reconciliation => {
                 tracing::info!(
                     %reconciliation_path,
                     "informing the reconciler that a PUT request was received",
                 );
 
-                message_bus.send_reconciliation_request()?;
-                return Ok(Response::builder().status(200).body(empty())?);
+                // Error pages may have changed along with the rest of the configuration
+                context.error_pages.clear();
+                context.message_bus.send_reconciliation_request()?;
             }
-            Some(suffix) if suffix.path() == "/certificates" => {
+            ReservedEndpoint::Certificates { domain } => {
                 tracing::info!(
+                    ?domain,
                     "informing the certificate resolver that a PUT request was received"
                 );
 
-                message_bus.send_certificate_update_request()?;
-                return Ok(Response::builder().status(200).body(empty())?);
+                context
+                    .message_bus
+                    .send_certificate_update_request(domain)?;
             }
-            _ => {}
+            ReservedEndpoint::Maintenance { service, enabled } => {
+                let (Some(service), Some(enabled)) = (service, enabled) else {
+                    return Ok(Response::builder().status(400).body(empty())?);
+                };
+
+                tracing::info!(%service, %enabled, "setting maintenance mode for service");
+
+                context.maintenance.set(&service, enabled);
+            }
+        }
+
+        return Ok(Response::builder().status(200).body(empty())?);
+    }
+
+    if let Some(status) = config
+        .alb
+        .path_limits
+        .as_ref()
+        .and_then(|limits| check_path_limits(limits, uri.path()))
+    {
+        tracing::debug!(%uri, %status, "rejecting request exceeding path limits");
+
+        return Ok(Response::builder().status(status).body(empty())?);
+    }
+
+    if req.method() == Method::GET && config.alb.metrics.as_deref() == Some(uri.path()) {
+        let response = Response::builder()
+            .status(200)
+            .header(CONTENT_TYPE, "text/plain; version=0.0.4")
+            .body(full(context.metrics.render()))?;
+
+        return Ok(response);
+    }
+
+    if let Some((logs, service)) = config
+        .alb
+        .container_logs
+        .as_ref()
+        .filter(|_| req.method() == Method::GET)
+        .and_then(|logs| Some((logs, container_logs::service_name(&logs.path, uri.path())?)))
+    {
+        let client_addr = req
+            .extensions()
+            .get::<ClientConnection>()
+            .and_then(|connection| connection.addr)
+            .map(|addr| addr.ip());
+
+        if !access::is_allowed(&logs.access, client_addr) {
+            tracing::debug!(%uri, ?client_addr, "rejecting disallowed client for container logs");
+
+            return Ok(Response::builder()
+                .status(StatusCode::FORBIDDEN)
+                .body(empty())?);
         }
+
+        let follow =
+            find_query_parameter(uri.query(), "follow").is_some_and(|follow| follow == "true");
+
+        return container_logs_response(&context, service, follow).await;
+    }
+
+    // Challenges are answered before redirects, as certificate authorities validate over plain HTTP
+    if let Some(key_authorization) = (req.method() == Method::GET)
+        .then(|| context.acme_challenges.respond(uri.path()))
+        .flatten()
+    {
+        tracing::info!(path = %uri.path(), "answering acme challenge");
+
+        let response = Response::builder()
+            .status(200)
+            .header(CONTENT_TYPE, "application/octet-stream")
+            .body(full(key_authorization))?;
+
+        return Ok(response);
     }
 
     let host = extract_host(&req)?;
 
+    if let Some(location) = https_redirect(&config.alb, &req, host) {
+        tracing::debug!(%host, %uri, "redirecting plain http request to https");
+
+        return Ok(Response::builder()
+            .status(StatusCode::MOVED_PERMANENTLY)
+            .header(LOCATION, location)
+            .body(empty())?);
+    }
+
+    if let Some(route) = static_files::find_route(&config.alb.static_routes, host, uri.path()) {
+        let response = static_files::serve(route, req.method(), uri.path(), req.headers()).await?;
+
+        return Ok(response.map(full));
+    }
+
+    let deadline = config
+        .alb
+        .empty_service_wait_ms
+        .map(|wait| Instant::now() + Duration::from_millis(wait));
+
     // Filter based on the host, then do path matching for longest length
-    let read_lock = service_registry.read().await;
+    let read_lock = loop {
+        let read_lock = context.service_registry.read().await;
+
+        let Some((name, service, _)) = read_lock.find_route(host, uri.path(), req.method()) else {
+            tracing::debug!(%host, %uri, "no downstreams found for request");
 
-    let Some((downstreams, port)) = read_lock.find_downstreams(host, uri.path()) else {
+            drop(read_lock);
+
+            return error_response(&context, &config, host, StatusCode::NOT_FOUND).await;
+        };
+
+        if context.maintenance.is_enabled(name, service.maintenance) {
+            tracing::debug!(service = %name, "service is in maintenance mode, rejecting request");
+
+            return maintenance_response(&config);
+        }
+
+        if read_lock
+            .get_running_containers(name)
+            .is_some_and(|containers| !containers.is_empty())
+        {
+            break read_lock;
+        }
+
+        drop(read_lock);
+
+        match deadline {
+            Some(deadline) if Instant::now() < deadline => {
+                tokio::time::sleep(EMPTY_SERVICE_POLL_INTERVAL).await;
+            }
+            _ => {
+                tracing::warn!(%host, %uri, "matched service has no running containers");
+
+                return error_response(&context, &config, host, StatusCode::SERVICE_UNAVAILABLE)
+                    .await;
+            }
+        }
+    };
+
+    let Some(matched) = read_lock.find_downstreams(host, uri.path(), req.method()) else {
         tracing::debug!(%host, %uri, "no downstreams found for request");
 
-        return Ok(Response::builder().status(404).body(empty())?);
+        drop(read_lock);
+
+        return error_response(&context, &config, host, StatusCode::NOT_FOUND).await;
+    };
+
+    let connection = req.extensions().get::<ClientConnection>().copied();
+    let client_addr = connection
+        .and_then(|connection| connection.addr)
+        .map(|addr| addr.ip());
+
+    let requested = matched
+        .service
+        .variant_header
+        .as_ref()
+        .and_then(|header| variants::requested(header, req.headers(), client_addr));
+
+    let variant = match requested {
+        // Asking for the route's own service skips any alternates or split
+        Some(requested) if requested == matched.name => None,
+        Some(requested) if variants::is_known(matched.route, requested) => Some(requested),
+        _ => {
+            let roll = match matched.route.split {
+                Some(_) => context.rng.lock().await.gen_range(0..100),
+                None => 0,
+            };
+
+            variants::choose(matched.route, req.headers(), roll)
+        }
+    };
+
+    let MatchedService {
+        name,
+        service,
+        route,
+        downstreams,
+    } = match variant {
+        Some(variant) => read_lock
+            .find_variant(uri.path(), req.method(), variant)
+            .unwrap_or_else(|| {
+                tracing::warn!(
+                    %variant,
+                    "variant has no route for the request or no running containers, ignoring it"
+                );
+
+                matched
+            }),
+        None => matched,
+    };
+
+    if let Some(policy) = &route.access {
+        if !access::is_allowed(policy, client_addr) {
+            tracing::debug!(%host, %uri, ?client_addr, "rejecting disallowed client");
+
+            drop(read_lock);
+
+            return error_response(&context, &config, host, StatusCode::FORBIDDEN).await;
+        }
+    }
+
+    if let Some(rules) = &route.client_certificates {
+        let identity = req.extensions().get::<ClientIdentity>();
+
+        if !client_authorization::is_authorized(rules, identity) {
+            tracing::debug!(%host, %uri, ?identity, "rejecting unauthorized client certificate");
+
+            drop(read_lock);
+
+            return error_response(&context, &config, host, StatusCode::FORBIDDEN).await;
+        }
+    }
+
+    let scheme = connection.map(|connection| connection.scheme);
+
+    if let (Some(https_only), Some(Scheme::Http)) = (&route.https_only, scheme) {
+        let action = https_only.plain_http;
+
+        drop(read_lock);
+
+        return match action {
+            PlainHttpAction::Redirect => {
+                tracing::debug!(%host, %uri, "redirecting plain http request to an https-only route");
+
+                Ok(Response::builder()
+                    .status(StatusCode::MOVED_PERMANENTLY)
+                    .header(LOCATION, https_location(&config.alb, &req, host))
+                    .body(empty())?)
+            }
+            PlainHttpAction::Refuse => {
+                tracing::debug!(%host, %uri, "refusing plain http request to an https-only route");
+
+                error_response(&context, &config, host, StatusCode::FORBIDDEN).await
+            }
+        };
+    }
+
+    let hsts = route
+        .https_only
+        .as_ref()
+        .and_then(|https_only| https_only.hsts.as_ref())
+        .filter(|_| scheme == Some(Scheme::Https))
+        .map(|hsts| HeaderValue::from_str(&hsts.header_value()))
+        .transpose()?;
+
+    let cors_policy = route.cors.clone().and_then(|policy| {
+        cors::allowed_origin(&policy, req.headers()).map(|origin| (policy, origin))
+    });
+
+    if route.cors.is_some() && cors::is_preflight(req.method(), req.headers()) {
+        drop(read_lock);
+
+        let headers = cors_policy
+            .and_then(|(policy, origin)| cors::preflight_headers(&policy, origin, req.headers()));
+
+        let Some(headers) = headers else {
+            tracing::debug!(%host, %uri, "rejecting disallowed cors preflight");
+
+            return Ok(Response::builder().status(403).body(empty())?);
+        };
+
+        let mut response = Response::builder().status(204).body(empty())?;
+        *response.headers_mut() = headers;
+
+        return Ok(response);
+    }
+
+    if let Some(limit) = &route.rate_limit {
+        if let Err(retry_after) = context.rate_limiters.acquire(route, limit) {
+            tracing::debug!(%host, %uri, "rate limit exceeded, rejecting request");
+
+            drop(read_lock);
+
+            return load_shed_response(
+                &context,
+                &config,
+                StatusCode::TOO_MANY_REQUESTS,
+                retry_after,
+            )
+            .await;
+        }
+    }
+
+    let max_body_size = route.max_body_size;
+    let content_length = req
+        .headers()
+        .get(CONTENT_LENGTH)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.parse::<usize>().ok());
+
+    if max_body_size
+        .zip(content_length)
+        .is_some_and(|(max, length)| length > max)
+    {
+        tracing::debug!(%host, %uri, "request body exceeds the route's limit");
+
+        return Ok(Response::builder().status(413).body(empty())?);
+    }
+
+    let compression = service
+        .compression
+        .as_ref()
+        .filter(|_| req.method() != Method::HEAD)
+        .and_then(|compression| {
+            Encoding::negotiate(req.headers()).map(|encoding| (encoding, compression.min_size))
+        });
+
+    let circuit_breaker = service.circuit_breaker.clone();
+
+    if let Some(breaker) = &circuit_breaker {
+        if !context.circuit_breakers.allow(name, breaker) {
+            tracing::debug!(service = %name, "circuit is open, rejecting request");
+
+            drop(read_lock);
+
+            return error_response(&context, &config, host, StatusCode::SERVICE_UNAVAILABLE).await;
+        }
+    }
+
+    let service_name = name.to_owned();
+    let port = route.port;
+    let strip_prefix = route.prefix.clone().filter(|_| route.strip_prefix);
+    let upstream_protocol = match (
+        is_upgrade_request(req.headers()),
+        is_grpc_request(req.headers()),
+    ) {
+        // Upgrades only exist in HTTP/1, whereas gRPC relies on HTTP/2 for framing and trailers
+        (true, _) => UpstreamProtocol::Http1,
+        (false, true) => UpstreamProtocol::Http2,
+        (false, false) => service.upstream_protocol,
+    };
+    let access_log = service.access_log.unwrap_or(config.alb.access_log);
+    let filters = route.filters.clone();
+    let upstream_tls = (route.scheme == Scheme::Https).then(|| {
+        let tls = route.upstream_tls.clone().unwrap_or_default();
+        let server_name = tls.server_name.unwrap_or_else(|| route.host.clone());
+
+        (server_name, tls.ca)
+    });
+    let upstream_host = service
+        .upstream_host
+        .as_deref()
+        .map(HeaderValue::from_str)
+        .transpose()?;
+    let request_id_headers = config
+        .alb
+        .request_id
+        .as_ref()
+        .map(|request_id| RequestIdHeaders::resolve(request_id, route))
+        .transpose()?;
+    let header_rewriters = route
+        .headers
+        .as_ref()
+        .map(HeaderRewriters::resolve)
+        .transpose()?;
+
+    // Upgraded connections cannot be copied, so are never mirrored
+    let mirror = match route.mirror.as_ref() {
+        Some(mirror) if !is_upgrade_request(req.headers()) => {
+            let sampled = context.rng.lock().await.gen_range(0..100) < mirror.percentage;
+
+            read_lock
+                .get_running_containers(&mirror.service)
+                .filter(|_| sampled)
+                .and_then(|containers| {
+                    context.round_robin.choose(containers, |downstream| {
+                        read_lock.get_weight(&downstream.id)
+                    })
+                })
+                .map(|downstream| SocketAddr::new(downstream.addr, mirror.port))
+        }
+        _ => None,
     };
 
-    let downstream = {
-        let mut rng = rng.lock().await;
-        let next = rng.next_u32() as usize;
-        let normalised = next % downstreams.len();
+    let outlier_detection = service.outlier_detection.clone();
+    let downstreams = match &outlier_detection {
+        Some(config) => context.outliers.available(downstreams, config),
+        None => Cow::Borrowed(downstreams),
+    };
 
-        downstreams
-            .get_index(normalised)
-            .ok_or_else(|| eyre!("no downstreams found for request to {uri} with host {host}"))?
-            .addr
+    let max_in_flight = service.max_in_flight;
+    let downstreams = match max_in_flight {
+        Some(max) => context.in_flight.available(downstreams, max),
+        None => downstreams,
     };
 
+    if downstreams.is_empty() {
+        tracing::debug!(service = %name, "every downstream is saturated, shedding request");
+
+        drop(read_lock);
+
+        return load_shed_response(
+            &context,
+            &config,
+            StatusCode::SERVICE_UNAVAILABLE,
+            SATURATED_RETRY_AFTER,
+        )
+        .await;
+    }
+
+    let affinity_key = route
+        .affinity
+        .as_ref()
+        .and_then(|affinity| affinity::extract_key(affinity, uri.path(), req.headers()));
+
+    let downstream = match affinity_key {
+        Some(key) => affinity::select(&downstreams, key, |downstream| {
+            read_lock.get_replica(&downstream.id)
+        }),
+        None => {
+            let idle_timeout = config.alb.connection_pool.idle_timeout();
+
+            context.round_robin.choose_preferring(
+                &downstreams,
+                |downstream| read_lock.get_ramped_weight(&downstream.id, service.slow_start()),
+                |downstream| {
+                    config.alb.prefer_warm_connections
+                        && context
+                            .warm_downstreams
+                            .is_warm(SocketAddr::new(downstream.addr, port), idle_timeout)
+                },
+            )
+        }
+    }
+    .ok_or_else(|| eyre!("no downstreams found for request to {uri} with host {host}"))?
+    .clone();
+
+    // Upgraded connections cannot be replayed, so are never retried
+    let retry = service
+        .retry
+        .clone()
+        .filter(|_| !is_upgrade_request(req.headers()));
+    let mut retry_downstreams: VecDeque<_> = retry
+        .as_ref()
+        .map(|retry| {
+            downstreams
+                .iter()
+                .filter(|details| details.addr != downstream.addr)
+                .take(retry.attempts)
+                .cloned()
+                .collect()
+        })
+        .unwrap_or_default();
+
     drop(read_lock);
 
-    let addr = SocketAddrV4::new(downstream, port);
+    let mut permit = max_in_flight.map(|_| context.in_flight.acquire(downstream.addr));
+    let mut addr = SocketAddr::new(downstream.addr, port);
+    let mut container = downstream.id;
     let path_and_query = uri.path_and_query().map_or("/", PathAndQuery::as_str);
+    let path_and_query = match strip_prefix {
+        Some(prefix) => strip_path_prefix(path_and_query, &prefix),
+        None => path_and_query.to_owned(),
+    };
+
+    let method = req.method().clone();
+    let host = host.to_owned();
+    let path = uri.path().to_owned();
+    let start = Instant::now();
+
+    let upgrade = is_upgrade_request(req.headers()).then(|| hyper::upgrade::on(&mut req));
+
+    forwarded::apply(&mut req, &host);
+
+    if let Some(upstream_host) = upstream_host {
+        req.headers_mut().insert(HOST, upstream_host);
+    }
+
+    let (mut parts, body) = req.into_parts();
+
+    if let FilterAction::Respond(response) = context.filters.apply(&filters, &mut parts)? {
+        tracing::debug!(%host, %path, "request was answered by a filter");
+
+        return Ok(response.map(full));
+    }
+
+    let req = Request::from_parts(parts, body);
+
+    let span = match config.alb.otlp {
+        Some(_) => Some(ProxySpan::start(
+            req.headers(),
+            &mut *context.rng.lock().await,
+        )),
+        None => None,
+    };
+
+    let mut mapped = map_request(req, upstream_protocol)?;
+
+    if let Some(span) = &span {
+        span.inject(mapped.headers_mut());
+    }
+
+    let request_id = request_id_headers
+        .as_ref()
+        .map(|headers| headers.assign(&mut mapped));
+
+    if let Some(rewriters) = &header_rewriters {
+        rewriters.request.apply(mapped.headers_mut());
+    }
+
+    let (parts, body) = mapped.into_parts();
+
+    // Without a declared length the body has to be counted as it is read to enforce the limit
+    let inspect_limit = max_body_size.filter(|_| content_length.is_none());
+    let buffer_size = config.alb.max_buffered_body_size;
 
-    let target_uri = format!("http://{addr}{path_and_query}").parse()?;
+    let body = match inspect_limit {
+        Some(max) => ReplayBody::buffer(body, max.min(buffer_size)).await?,
+        None if retry.is_some() || mirror.is_some() => {
+            ReplayBody::buffer(body, buffer_size).await?
+        }
+        None => ReplayBody::streaming(body),
+    };
+
+    let mut body = match inspect_limit {
+        Some(max) => body.limit(max),
+        None => body,
+    };
+
+    if body.exceeds_limit() {
+        tracing::debug!(%host, %path, "request body exceeds the route's limit");
+
+        return Ok(Response::builder().status(413).body(empty())?);
+    }
+
+    let clients = context.clients.load_full();
+    let client = match &upstream_tls {
+        Some((server_name, ca)) => DownstreamClient::Tls(
+            clients
+                .get_tls(upstream_protocol, server_name, ca.as_ref())
+                .await?,
+        ),
+        None => DownstreamClient::Plain(clients.get(upstream_protocol).clone()),
+    };
+
+    if let Some(mirror) = mirror {
+        match body.try_clone() {
+            Some(copy) => {
+                let target_uri = format!("http://{mirror}{path_and_query}").parse()?;
+                let request = rebuild_request(&parts, target_uri, copy)?;
+
+                tokio::spawn(send_mirrored_request(
+                    clients.get(upstream_protocol).clone(),
+                    request,
+                ));
+            }
+            None => {
+                tracing::debug!(%host, %path, "request body is too large to mirror");
+            }
+        }
+    }
+
+    let span_details = |status: StatusCode, addr: SocketAddr| SpanDetails {
+        method: method.clone(),
+        host: host.clone(),
+        path: path.clone(),
+        status: status.as_u16(),
+        downstream: addr.to_string(),
+    };
+
+    let mut response = loop {
+        let replay = body.try_clone();
+        let target_uri = format!("{}://{addr}{path_and_query}", client.scheme()).parse()?;
+
+        let error = match client
+            .request(rebuild_request(&parts, target_uri, body)?)
+            .await
+        {
+            Ok(response) => break response,
+            Err(error) => error,
+        };
+
+        // Nothing was wrong with the downstream, so this is not counted against it
+        if BodyTooLarge::caused(&error) {
+            tracing::debug!(%host, %path, "request body exceeds the route's limit");
+
+            return Ok(Response::builder().status(413).body(empty())?);
+        }
+
+        if let Some(config) = &outlier_detection {
+            context.outliers.record(addr.ip(), config, false);
+        }
 
-    let mut mapped = map_request(req)?;
-    *mapped.uri_mut() = target_uri;
+        let retryable = retry
+            .as_ref()
+            .is_some_and(|retry| is_retryable(retry, &error));
 
-    Ok(client.request(mapped).await?.map(BoxBody::new))
+        match (replay, retry_downstreams.pop_front()) {
+            (Some(replay), Some(next)) if retryable => {
+                tracing::warn!(
+                    %error,
+                    failed = %addr,
+                    next = %next.addr,
+                    "retrying request against another downstream"
+                );
+
+                addr = SocketAddr::new(next.addr, port);
+                container = next.id;
+                body = replay;
+
+                if permit.is_some() {
+                    permit = Some(context.in_flight.acquire(addr.ip()));
+                }
+            }
+            _ => {
+                if let Some(breaker) = &circuit_breaker {
+                    context
+                        .circuit_breakers
+                        .record(&service_name, breaker, false);
+                }
+
+                let failure = UpstreamError::classify(&error);
+
+                tracing::warn!(
+                    %error,
+                    code = failure.code(),
+                    downstream = %addr,
+                    %container,
+                    "failed to proxy request"
+                );
+
+                if let Some(span) =
+                    span.and_then(|span| span.finish(span_details(failure.status(), addr)))
+                {
+                    context.spans.record(span);
+                }
+
+                return upstream_error_response(&context, &config, &host, &parts.headers, failure)
+                    .await;
+            }
+        }
+    };
+
+    if let Some(breaker) = &circuit_breaker {
+        let success = !response.status().is_server_error();
+        context
+            .circuit_breakers
+            .record(&service_name, breaker, success);
+    }
+
+    if let Some(config) = &outlier_detection {
+        let success = !response.status().is_server_error();
+        context.outliers.record(addr.ip(), config, success);
+    }
+
+    context
+        .warm_downstreams
+        .mark(addr, config.alb.connection_pool.idle_timeout());
+
+    if let Some(span) = span.and_then(|span| span.finish(span_details(response.status(), addr))) {
+        context.spans.record(span);
+    }
+
+    if let Some(client_upgrade) = upgrade {
+        if response.status() == StatusCode::SWITCHING_PROTOCOLS {
+            let downstream_upgrade = hyper::upgrade::on(&mut response);
+            tokio::spawn(tunnel(client_upgrade, downstream_upgrade));
+        }
+    }
+
+    if access_log {
+        tracing::info!(
+            target: "f2::access",
+            %method,
+            %host,
+            %path,
+            status = %response.status().as_u16(),
+            downstream = %addr,
+            elapsed_ms = %start.elapsed().as_millis(),
+            "proxied request",
+        );
+    }
+
+    if let (Some(headers), Some(request_id)) = (request_id_headers, request_id) {
+        response
+            .headers_mut()
+            .insert(headers.header().clone(), request_id);
+    }
+
+    if let Some((policy, origin)) = cors_policy {
+        cors::apply(&policy, origin, response.headers_mut());
+    }
+
+    if let Some(hsts) = hsts {
+        response
+            .headers_mut()
+            .insert(STRICT_TRANSPORT_SECURITY, hsts);
+    }
+
+    if let Some(rewriters) = &header_rewriters {
+        rewriters.response.apply(response.headers_mut());
+    }
+
+    if let Some((encoding, min_size)) = compression {
+        if compression::compressible_length(response.headers(), min_size).is_some() {
+            let (mut parts, body) = response.into_parts();
+
+            let bytes = body.collect().await?.to_bytes();
+            let compressed = encoding.compress(&bytes);
+
+            compression::apply_headers(&mut parts.headers, encoding, compressed.len());
+
+            return Ok(Response::from_parts(parts, full(compressed)));
+        }
+    }
+
+    Ok(response.map(|body| BoxBody::new(PermitBody::new(body, permit))))
+}
+
+/// Streams the logs of every container running for a service, continuing with anything they
+/// write afterwards if `follow` is set.
+async fn container_logs_response<B>(
+    context: &Context<B>,
+    service: &str,
+    follow: bool,
+) -> Result<Response<BoxBody<Bytes, hyper::Error>>> {
+    let containers = context
+        .service_registry
+        .read()
+        .await
+        .get_running_containers(service)
+        .map(|containers| {
+            containers
+                .iter()
+                .map(|details| details.id.clone())
+                .collect::<Vec<_>>()
+        });
+
+    let Some(containers) = containers else {
+        return Ok(Response::builder().status(404).body(empty())?);
+    };
+
+    tracing::info!(%service, %follow, "streaming container logs");
+
+    let body = match context.container_logs.stream(containers, follow).await {
+        Ok(body) => body,
+        Err(e) => {
+            tracing::warn!(%service, ?e, "failed to stream container logs");
+
+            return Ok(Response::builder().status(502).body(empty())?);
+        }
+    };
+
+    let response = Response::builder()
+        .status(200)
+        .header(CONTENT_TYPE, "text/plain; charset=utf-8")
+        .body(body.map_err(|never| match never {}).boxed())?;
+
+    Ok(response)
+}
+
+/// Builds the response for a request to a service in maintenance mode, using the configured
+/// maintenance response if there is one.
+fn maintenance_response(config: &Config) -> Result<Response<BoxBody<Bytes, hyper::Error>>> {
+    let response = match &config.alb.maintenance_response {
+        Some(maintenance) => Response::builder()
+            .status(maintenance.status.unwrap_or(503))
+            .header(CONTENT_TYPE, &maintenance.content_type)
+            .body(full(maintenance.body.clone()))?,
+        None => Response::builder().status(503).body(empty())?,
+    };
+
+    Ok(response)
+}
+
+/// Builds the response for a request rejected to shed load, using the configured busy response if
+/// there is one.
+async fn load_shed_response<B>(
+    context: &Context<B>,
+    config: &Config,
+    status: StatusCode,
+    retry_after: Duration,
+) -> Result<Response<BoxBody<Bytes, hyper::Error>>> {
+    let jitter = match config.alb.retry_after_jitter_secs {
+        0 => 0,
+        max => context.rng.lock().await.gen_range(0..=max),
+    };
+
+    // Retry-After is in whole seconds, so round up to avoid clients retrying too early
+    let retry_after = (retry_after.as_secs_f64().ceil() as u64).max(1) + jitter;
+    let builder = Response::builder().header(RETRY_AFTER, retry_after);
+
+    let response = match &config.alb.busy_response {
+        Some(busy) => builder
+            .status(busy.status.unwrap_or(status.as_u16()))
+            .header(CONTENT_TYPE, &busy.content_type)
+            .body(full(busy.body.clone()))?,
+        None => builder.status(status).body(empty())?,
+    };
+
+    Ok(response)
+}
+
+/// Reads a request body to completion, returning `false` if it exceeds the reserved endpoint limit.
+async fn drain_body<B>(body: B) -> Result<bool>
+where
+    B: Body,
+    <B as Body>::Error: std::error::Error + Send + Sync + 'static,
+{
+    match Limited::new(body, MAX_RESERVED_BODY_SIZE).collect().await {
+        Ok(_) => Ok(true),
+        Err(e) if e.is::<LengthLimitError>() => Ok(false),
+        Err(e) => Err(eyre!(e)),
+    }
+}
+
+/// Checks a request path against the configured limits, returning the status to reject it with if
+/// it exceeds them.
+fn check_path_limits(limits: &PathLimits, path: &str) -> Option<StatusCode> {
+    if limits.max_length.is_some_and(|max| path.len() > max) {
+        return Some(StatusCode::URI_TOO_LONG);
+    }
+
+    let segments = path
+        .split('/')
+        .filter(|segment| !segment.is_empty())
+        .count();
+
+    if limits.max_segments.is_some_and(|max| segments > max) {
+        return Some(StatusCode::BAD_REQUEST);
+    }
+
+    None
+}
+
+/// Removes a route's prefix from the start of a path, keeping the query and ensuring what remains
+/// is still an absolute path.
+fn strip_path_prefix(path_and_query: &str, prefix: &str) -> String {
+    let stripped = path_and_query
+        .strip_prefix(prefix)
+        .unwrap_or(path_and_query);
+
+    match stripped.chars().next() {
+        Some('/') => stripped.to_owned(),
+        _ => format!("/{stripped}"),
+    }
+}
+
+fn find_query_parameter(query: Option<&str>, name: &str) -> Option<String> {
+    query?
+        .split('&')
+        .filter_map(|pair| pair.split_once('='))
+        .find(|(key, _)| *key == name)
+        .map(|(_, value)| value.to_owned())
 }
 
 fn extract_host<B>(req: &Request<B>) -> Result<&str> {
@@ -104,16 +1145,197 @@ fn extract_host<B>(req: &Request<B>) -> Result<&str> {
     Ok(host)
 }
 
-fn map_request<B>(original: Request<B>) -> Result<Request<B>> {
+/// Builds the response for a request the load balancer rejects itself, using the host's error
+/// page for the status if one is configured.
+async fn error_response<B>(
+    context: &Context<B>,
+    config: &Config,
+    host: &str,
+    status: StatusCode,
+) -> Result<Response<BoxBody<Bytes, hyper::Error>>> {
+    let page = config
+        .alb
+        .error_pages
+        .get(strip_port(host))
+        .and_then(|pages| pages.get(&status.as_u16()));
+
+    let Some(page) = page else {
+        return Ok(Response::builder().status(status).body(empty())?);
+    };
+
+    let response = match context.error_pages.body(page).await {
+        Ok(body) => Response::builder()
+            .status(status)
+            .header(CONTENT_TYPE, &page.content_type)
+            .body(full(body))?,
+        Err(e) => {
+            tracing::warn!(%host, %status, %e, "failed to load error page");
+
+            Response::builder().status(status).body(empty())?
+        }
+    };
+
+    Ok(response)
+}
+
+/// Builds the response for a request that could not be proxied to a container, describing the
+/// failure unless the host has its own page for the status.
+async fn upstream_error_response<B>(
+    context: &Context<B>,
+    config: &Config,
+    host: &str,
+    headers: &HeaderMap,
+    failure: UpstreamError,
+) -> Result<Response<BoxBody<Bytes, hyper::Error>>> {
+    let status = failure.status();
+
+    let has_page = config
+        .alb
+        .error_pages
+        .get(strip_port(host))
+        .is_some_and(|pages| pages.contains_key(&status.as_u16()));
+
+    if has_page {
+        return error_response(context, config, host, status).await;
+    }
+
+    let (content_type, body) = failure.render(headers);
+
+    Ok(Response::builder()
+        .status(status)
+        .header(CONTENT_TYPE, content_type)
+        .body(full(body))?)
+}
+
+/// Builds the location to redirect a request to if it arrived over plain HTTP for a domain that
+/// should only be served over HTTPS.
+fn https_redirect<B>(config: &AlbConfig, req: &Request<B>, host: &str) -> Option<String> {
+    let connection = req.extensions().get::<ClientConnection>()?;
+
+    if connection.scheme != Scheme::Http {
+        return None;
+    }
+
+    let domain = strip_port(host);
+    let secrets = config.tls.as_ref()?.domains.get(domain)?;
+
+    if !secrets.redirect_http {
+        return None;
+    }
+
+    Some(https_location(config, req, host))
+}
+
+/// Builds the location of a request over HTTPS, on the listener's port.
+fn https_location<B>(config: &AlbConfig, req: &Request<B>, host: &str) -> String {
+    let domain = strip_port(host);
+
+    let port = match config.ports.get(&Scheme::Https) {
+        None | Some(443) => String::new(),
+        Some(port) => format!(":{port}"),
+    };
+
+    let path_and_query = req.uri().path_and_query().map_or("/", PathAndQuery::as_str);
+
+    format!("https://{domain}{port}{path_and_query}")
+}
+
+fn strip_port(host: &str) -> &str {
+    host.split_once(':').map_or(host, |(domain, _)| domain)
+}
+
+/// Checks whether a request is asking to switch protocols, such as to open a WebSocket.
+fn is_upgrade_request(headers: &HeaderMap) -> bool {
+    let connection_upgrade = headers
+        .get_all(CONNECTION)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .any(|token| token.trim().eq_ignore_ascii_case("upgrade"));
+
+    connection_upgrade && headers.contains_key(UPGRADE)
+}
+
+/// Copies bytes in both directions between the client and downstream once both sides of an
+/// upgraded connection are ready.
+async fn tunnel(client: OnUpgrade, downstream: OnUpgrade) {
+    let result = async {
+        let (client, downstream) = tokio::try_join!(client, downstream)?;
+
+        let mut client = TokioIo::new(client);
+        let mut downstream = TokioIo::new(downstream);
+
+        tokio::io::copy_bidirectional(&mut client, &mut downstream).await?;
+
+        Ok::<_, color_eyre::Report>(())
+    };
+
+    if let Err(error) = result.await {
+        tracing::warn!(%error, "upgraded connection closed with an error");
+    }
+}
+
+/// Sends a copy of a request to a shadow service, discarding whatever it responds with.
+async fn send_mirrored_request<B>(client: Client<HttpConnector, B>, request: Request<B>)
+where
+    B: Body + Send + Unpin + 'static,
+    <B as Body>::Data: Send,
+    <B as Body>::Error: Into<Box<dyn std::error::Error + Send + Sync>>,
+{
+    let uri = request.uri().clone();
+
+    match client.request(request).await {
+        Ok(response) => {
+            // Read the body so the connection can be reused
+            if let Err(e) = response.into_body().collect().await {
+                tracing::debug!(%e, %uri, "failed to read mirrored response");
+            }
+        }
+        Err(e) => tracing::debug!(%e, %uri, "failed to send mirrored request"),
+    }
+}
+
+/// Creates a request from the mapped parts aimed at a specific downstream, so that it can be sent
+/// more than once.
+fn rebuild_request<B>(parts: &Parts, uri: Uri, body: B) -> Result<Request<B>> {
+    let mut request = Request::builder()
+        .method(parts.method.clone())
+        .version(parts.version)
+        .uri(uri)
+        .body(body)?;
+
+    *request.headers_mut() = parts.headers.clone();
+
+    Ok(request)
+}
+
+/// Checks whether a request is a gRPC call, which must reach the downstream over HTTP/2.
+fn is_grpc_request(headers: &HeaderMap) -> bool {
+    headers
+        .get(CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .is_some_and(|value| value.starts_with("application/grpc"))
+}
+
+/// Builds the downstream request for the given protocol, keeping the `Connection` header only for
+/// HTTP/1 upgrades since it otherwise only applies to the client's connection.
+fn map_request<B>(original: Request<B>, protocol: UpstreamProtocol) -> Result<Request<B>> {
     let uri = original.uri();
 
+    let version = match protocol {
+        UpstreamProtocol::Http1 => Version::HTTP_11,
+        UpstreamProtocol::Http2 => Version::HTTP_2,
+    };
+
+    let upgrade = version == Version::HTTP_11 && is_upgrade_request(original.headers());
+
     let mut request = Request::builder()
         .method(original.method())
         .uri(uri)
-        .version(Version::HTTP_11);
+        .version(version);
 
     for (name, value) in original.headers() {
-        if !name.as_str().starts_with(':') && name != "connection" {
+        if !name.as_str().starts_with(':') && (upgrade || name != CONNECTION) {
             request = request.header(name, value);
         }
     }
@@ -129,75 +1351,143 @@ fn empty() -> BoxBody<Bytes, hyper::Error> {
         .boxed()
 }
 
+fn full<T: Into<Bytes>>(chunk: T) -> BoxBody<Bytes, hyper::Error> {
+    Full::new(chunk.into())
+        .map_err(|never| match never {})
+        .boxed()
+}
+
 #[cfg(test)]
 mod tests {
+    use std::collections::HashMap;
+    use std::net::Ipv4Addr;
     use std::sync::Arc;
     use std::time::Duration;
 
+    use arc_swap::ArcSwap;
     use color_eyre::eyre::Result;
-    use http::header::ACCEPT;
-    use http::{HeaderValue, Method, Request, Uri, Version};
-    use http_body_util::Empty;
+    use http::header::{ACCEPT, CONNECTION, CONTENT_TYPE, TE, UPGRADE};
+    use http::{HeaderMap, HeaderValue, Method, Request, Uri, Version};
+    use http_body_util::{BodyExt, Empty};
     use hyper::body::Bytes;
-    use hyper_util::client::legacy::connect::HttpConnector;
-    use hyper_util::client::legacy::Client;
-    use hyper_util::rt::TokioExecutor;
     use rand::rngs::SmallRng;
     use rand::SeedableRng;
     use tokio::sync::{Mutex, RwLock};
 
+    use crate::config::{
+        AlbConfig, Config, ConnectionPool, DockerRetryConfig, PathLimits, Scheme, ShutdownConfig,
+        UpstreamProtocol,
+    };
     use crate::ipc::MessageBus;
-    use crate::load_balancer::proxy::{extract_host, handle_request, map_request};
+    use crate::load_balancer::acme::Challenges;
+    use crate::load_balancer::circuit_breaker::CircuitBreakers;
+    use crate::load_balancer::container_logs::ContainerLogs;
+    use crate::load_balancer::error_pages::ErrorPages;
+    use crate::load_balancer::filters::Filters;
+    use crate::load_balancer::in_flight::InFlightLimiter;
+    use crate::load_balancer::maintenance::MaintenanceOverrides;
+    use crate::load_balancer::outlier::OutlierDetector;
+    use crate::load_balancer::proxy::{
+        extract_host, handle_request, is_grpc_request, is_upgrade_request, map_request,
+        strip_path_prefix, Context, DownstreamClients,
+    };
+    use crate::load_balancer::rate_limit::RateLimiters;
+    use crate::load_balancer::round_robin::WeightedRoundRobin;
+    use crate::load_balancer::trace::SpanBuffer;
+    use crate::load_balancer::warm::WarmDownstreams;
+    use crate::metrics::Metrics;
+    use crate::reconciler::tests::FakeDockerClient;
     use crate::service_registry::ServiceRegistry;
 
+    const RECONCILIATION_PATH: &str = "/reconciliation";
+    const METRICS_PATH: &str = "/metrics";
+
     /// Gets all the dependencies required for calling `handle_request`.
-    fn get_dependencies() -> (
-        Arc<RwLock<ServiceRegistry>>,
-        Arc<Mutex<SmallRng>>,
-        Client<HttpConnector, Empty<Bytes>>,
-        Arc<str>,
-        Arc<MessageBus>,
-    ) {
-        let service_registry = Arc::new(RwLock::new(ServiceRegistry::default()));
-        let rng = Arc::new(Mutex::new(SmallRng::from_entropy()));
-        let client = Client::builder(TokioExecutor::new()).build_http();
-        let reconciliation_path = Arc::from("/reconciliation");
-        let message_bus = MessageBus::new();
-
-        (
-            service_registry,
-            rng,
-            client,
-            reconciliation_path,
-            Arc::clone(&message_bus),
-        )
+    fn get_context() -> Arc<Context<Empty<Bytes>>> {
+        get_configured_context(|_| {})
+    }
+
+    /// Gets the dependencies for calling `handle_request`, adjusting the configuration first.
+    fn get_configured_context<F: FnOnce(&mut AlbConfig)>(
+        configure: F,
+    ) -> Arc<Context<Empty<Bytes>>> {
+        let mut config = Config {
+            alb: AlbConfig {
+                addr: Ipv4Addr::LOCALHOST,
+                ports: HashMap::from([(Scheme::Http, 5000)]),
+                protocols: HashMap::new(),
+                reconciliation: String::from(RECONCILIATION_PATH),
+                metrics: Some(String::from(METRICS_PATH)),
+                maintenance: None,
+                container_logs: None,
+                request_id: None,
+                evict_connections_on_deploy: false,
+                path_limits: None,
+                empty_service_wait_ms: None,
+                prefer_warm_connections: false,
+                connection_pool: ConnectionPool::default(),
+                max_buffered_body_size: 1024 * 1024,
+                access_log: false,
+                busy_response: None,
+                retry_after_jitter_secs: 0,
+                error_pages: HashMap::new(),
+                maintenance_response: None,
+                static_routes: Vec::new(),
+                otlp: None,
+                tls: None,
+                mtls: None,
+            },
+            secrets: None,
+            shutdown: ShutdownConfig::default(),
+            deploy_events: false,
+            registries: HashMap::new(),
+            garbage_collection: None,
+            network: None,
+            docker_retries: DockerRetryConfig::default(),
+            services: HashMap::new(),
+        };
+
+        configure(&mut config.alb);
+
+        Arc::new(Context {
+            service_registry: Arc::new(RwLock::new(ServiceRegistry::default())),
+            rng: Mutex::new(SmallRng::from_entropy()),
+            clients: ArcSwap::from_pointee(DownstreamClients::new(&config.alb.connection_pool)),
+            config: Arc::new(ArcSwap::from_pointee(config)),
+            message_bus: MessageBus::new(),
+            metrics: Metrics::new(),
+            warm_downstreams: WarmDownstreams::default(),
+            round_robin: WeightedRoundRobin::default(),
+            circuit_breakers: CircuitBreakers::default(),
+            outliers: OutlierDetector::default(),
+            rate_limiters: RateLimiters::default(),
+            in_flight: InFlightLimiter::default(),
+            error_pages: ErrorPages::default(),
+            spans: SpanBuffer::default(),
+            maintenance: MaintenanceOverrides::default(),
+            filters: Filters::default(),
+            acme_challenges: Challenges::default(),
+            container_logs: ContainerLogs::new(Arc::new(FakeDockerClient::default())),
+        })
     }
 
     #[tokio::test]
     async fn can_cause_reconciliation() -> Result<()> {
-        let (service_registry, rng, client, reconciliation_path, message_bus) = get_dependencies();
+        let context = get_context();
 
         let req = Request::builder()
             .method("PUT")
-            .uri(format!("http://example.com{}", reconciliation_path))
+            .uri(format!("http://example.com{}", RECONCILIATION_PATH))
             .body(Empty::<Bytes>::new())
             .unwrap();
 
-        let response = handle_request(
-            service_registry,
-            rng,
-            client,
-            reconciliation_path,
-            Arc::clone(&message_bus),
-            req,
-        )
-        .await?;
+        let response = handle_request(Arc::clone(&context), req).await?;
 
         assert_eq!(response.status(), 200, "expected a 200 OK response");
 
         let message = tokio::time::timeout(
             Duration::from_millis(1),
-            message_bus.receive_reconciliation_request(),
+            context.message_bus.receive_reconciliation_request(),
         )
         .await?;
 
@@ -208,7 +1498,7 @@ mod tests {
 
     #[tokio::test]
     async fn can_cause_certificate_updates() -> Result<()> {
-        let (service_registry, rng, client, reconciliation_path, message_bus) = get_dependencies();
+        let context = get_context();
 
         let req = Request::builder()
             .method("PUT")
@@ -216,21 +1506,13 @@ mod tests {
             .body(Empty::<Bytes>::new())
             .unwrap();
 
-        let response = handle_request(
-            service_registry,
-            rng,
-            client,
-            reconciliation_path,
-            Arc::clone(&message_bus),
-            req,
-        )
-        .await?;
+        let response = handle_request(Arc::clone(&context), req).await?;
 
         assert_eq!(response.status(), 200, "expected a 200 OK response");
 
         let message = tokio::time::timeout(
             Duration::from_millis(1),
-            message_bus.receive_certificate_update_request(),
+            context.message_bus.receive_certificate_update_request(),
         )
         .await?;
 
@@ -239,6 +1521,88 @@ mod tests {
         Ok(())
     }
 
+    #[tokio::test]
+    async fn certificate_updates_can_be_scoped_to_a_domain() -> Result<()> {
+        let context = get_context();
+
+        let req = Request::builder()
+            .method("PUT")
+            .uri("http://example.com/certificates?domain=example.org")
+            .body(Empty::<Bytes>::new())
+            .unwrap();
+
+        let response = handle_request(Arc::clone(&context), req).await?;
+
+        assert_eq!(response.status(), 200, "expected a 200 OK response");
+
+        let message = context
+            .message_bus
+            .receive_certificate_update_request()
+            .await?;
+
+        assert_eq!(
+            message.into_content().domain.as_deref(),
+            Some("example.org")
+        );
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn can_fetch_metrics() -> Result<()> {
+        let context = get_context();
+        let _connection = context.metrics.track_connection(Scheme::Http);
+
+        let req = Request::builder()
+            .method("GET")
+            .uri(format!("http://example.com{}", METRICS_PATH))
+            .body(Empty::<Bytes>::new())
+            .unwrap();
+
+        let response = handle_request(context, req).await?;
+
+        assert_eq!(response.status(), 200, "expected a 200 OK response");
+
+        let body = response.into_body().collect().await?.to_bytes();
+        let body = String::from_utf8(body.to_vec())?;
+
+        assert!(body.contains("f2_connections_active{scheme=\"http\"} 1"));
+
+        Ok(())
+    }
+
+    async fn get_status_for_path(context: &Arc<Context<Empty<Bytes>>>, path: &str) -> Result<u16> {
+        let req = Request::builder()
+            .method("GET")
+            .uri(format!("http://example.com{path}"))
+            .header("Host", "example.com")
+            .body(Empty::<Bytes>::new())?;
+
+        let response = handle_request(Arc::clone(context), req).await?;
+
+        Ok(response.status().as_u16())
+    }
+
+    #[tokio::test]
+    async fn paths_exceeding_limits_are_rejected() -> Result<()> {
+        let context = get_configured_context(|alb| {
+            alb.path_limits = Some(PathLimits {
+                max_length: Some(32),
+                max_segments: Some(3),
+            });
+        });
+
+        let long_path = format!("/{}", "a".repeat(32));
+
+        assert_eq!(get_status_for_path(&context, &long_path).await?, 414);
+        assert_eq!(get_status_for_path(&context, "/a/b/c/d").await?, 400);
+
+        // paths within the limits fall through to routing, which finds nothing
+        assert_eq!(get_status_for_path(&context, "/a/b/c").await?, 404);
+
+        Ok(())
+    }
+
     #[test]
     fn can_extract_hosts_for_http_11() -> Result<()> {
         let req = Request::builder()
@@ -286,7 +1650,7 @@ mod tests {
             .header(&header_name, &header_value)
             .body(Empty::<Bytes>::new())?;
 
-        let mapped = map_request(req)?;
+        let mapped = map_request(req, UpstreamProtocol::Http1)?;
 
         assert_eq!(mapped.method(), method);
         assert_eq!(mapped.uri(), &uri);
@@ -295,4 +1659,59 @@ mod tests {
 
         Ok(())
     }
+
+    #[test]
+    fn connection_headers_are_only_kept_for_upgrades() -> Result<()> {
+        let build = || {
+            Request::builder()
+                .uri("http://example.com/ws")
+                .header(CONNECTION, "keep-alive, Upgrade")
+                .header(UPGRADE, "websocket")
+                .body(Empty::<Bytes>::new())
+        };
+
+        assert!(is_upgrade_request(build()?.headers()));
+        assert!(!is_upgrade_request(&HeaderMap::new()));
+
+        let mapped = map_request(build()?, UpstreamProtocol::Http2)?;
+        assert!(mapped.headers().get(CONNECTION).is_none());
+
+        let mapped = map_request(build()?, UpstreamProtocol::Http1)?;
+        assert!(mapped.headers().get(CONNECTION).is_some());
+
+        Ok(())
+    }
+
+    #[test]
+    fn grpc_requests_are_mapped_to_http2() -> Result<()> {
+        let req = Request::builder()
+            .uri("http://example.com/package.Service/Method")
+            .version(Version::HTTP_2)
+            .header(CONTENT_TYPE, "application/grpc+proto")
+            .header(TE, "trailers")
+            .body(Empty::<Bytes>::new())?;
+
+        assert!(is_grpc_request(req.headers()));
+
+        let mapped = map_request(req, UpstreamProtocol::Http2)?;
+
+        assert_eq!(mapped.version(), Version::HTTP_2);
+        assert_eq!(
+            mapped.headers().get(TE),
+            Some(&HeaderValue::from_static("trailers"))
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn path_prefixes_can_be_stripped() {
+        assert_eq!(
+            strip_path_prefix("/api/users?page=2", "/api"),
+            "/users?page=2"
+        );
+        assert_eq!(strip_path_prefix("/api/users", "/api/"), "/users");
+        assert_eq!(strip_path_prefix("/api", "/api"), "/");
+        assert_eq!(strip_path_prefix("/api?page=2", "/api"), "/?page=2");
+    }
 }
@@ -0,0 +1,127 @@
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use crate::config::{RateLimit, Route};
+
+#[derive(Debug)]
+struct Bucket {
+    tokens: f64,
+    updated: Instant,
+}
+
+/// Token buckets for each rate limited route, keyed by host and prefix so that they carry over
+/// when the configuration is reloaded.
+#[derive(Debug, Default)]
+pub struct RateLimiters {
+    buckets: Mutex<HashMap<(String, Option<String>), Bucket>>,
+}
+
+impl RateLimiters {
+    /// Takes a token for a request to the route, returning how long until one will be available
+    /// if there are none left.
+    pub fn acquire(&self, route: &Route, limit: &RateLimit) -> Result<(), Duration> {
+        let rate = f64::from(limit.requests_per_second.get());
+        let capacity = f64::from(limit.burst.unwrap_or(limit.requests_per_second).get());
+
+        let mut buckets = self.buckets.lock().unwrap();
+        let now = Instant::now();
+
+        let bucket = buckets
+            .entry((route.host.clone(), route.prefix.clone()))
+            .or_insert(Bucket {
+                tokens: capacity,
+                updated: now,
+            });
+
+        let elapsed = now.duration_since(bucket.updated).as_secs_f64();
+
+        bucket.tokens = (bucket.tokens + elapsed * rate).min(capacity);
+        bucket.updated = now;
+
+        if bucket.tokens >= 1.0 {
+            bucket.tokens -= 1.0;
+            return Ok(());
+        }
+
+        Err(Duration::from_secs_f64((1.0 - bucket.tokens) / rate))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::num::NonZeroU32;
+    use std::time::Duration;
+
+    use crate::config::{RateLimit, Route};
+    use crate::load_balancer::rate_limit::RateLimiters;
+
+    fn route(host: &str) -> Route {
+        Route {
+            host: host.to_owned(),
+            ..Default::default()
+        }
+    }
+
+    fn limit(requests_per_second: u32, burst: Option<u32>) -> RateLimit {
+        RateLimit {
+            requests_per_second: NonZeroU32::new(requests_per_second).unwrap(),
+            burst: burst.and_then(NonZeroU32::new),
+        }
+    }
+
+    #[test]
+    fn requests_are_accepted_up_to_the_burst() {
+        let limiters = RateLimiters::default();
+        let route = route("opentracker.app");
+        let limit = limit(1, Some(3));
+
+        for _ in 0..3 {
+            assert!(limiters.acquire(&route, &limit).is_ok());
+        }
+
+        let retry_after = limiters.acquire(&route, &limit).unwrap_err();
+
+        assert!(retry_after > Duration::ZERO);
+        assert!(retry_after <= Duration::from_secs(1));
+    }
+
+    #[test]
+    fn tokens_are_refilled_over_time() {
+        let limiters = RateLimiters::default();
+        let route = route("opentracker.app");
+        let limit = limit(100, Some(1));
+
+        assert!(limiters.acquire(&route, &limit).is_ok());
+        assert!(limiters.acquire(&route, &limit).is_err());
+
+        std::thread::sleep(Duration::from_millis(20));
+
+        assert!(limiters.acquire(&route, &limit).is_ok());
+    }
+
+    #[test]
+    fn routes_are_limited_independently() {
+        let limiters = RateLimiters::default();
+        let limit = limit(1, None);
+
+        assert!(limiters.acquire(&route("opentracker.app"), &limit).is_ok());
+        assert!(limiters.acquire(&route("opentracker.app"), &limit).is_err());
+        assert!(limiters.acquire(&route("blackboards.pl"), &limit).is_ok());
+    }
+
+    #[test]
+    fn limits_carry_over_when_routes_are_reloaded() {
+        let limiters = RateLimiters::default();
+        let limit = limit(1, None);
+
+        let original = route("opentracker.app");
+        let reloaded = Route {
+            port: 8080,
+            ..original.clone()
+        };
+
+        assert!(limiters.acquire(&original, &limit).is_ok());
+        assert!(limiters.acquire(&reloaded, &limit).is_err());
+    }
+}
@@ -0,0 +1,140 @@
+use color_eyre::eyre::Result;
+use http::{HeaderName, HeaderValue, Request};
+use uuid::Uuid;
+
+use crate::config::{RequestIdConfig, Route};
+
+/// The headers used to identify requests as they pass through the proxy.
+#[derive(Debug)]
+pub struct RequestIdHeaders {
+    header: HeaderName,
+    aliases: Vec<HeaderName>,
+}
+
+impl RequestIdHeaders {
+    /// Resolves the headers to use for a request, preferring any override on the matched route.
+    pub fn resolve(config: &RequestIdConfig, route: &Route) -> Result<Self> {
+        let header = route.request_id_header.as_ref().unwrap_or(&config.header);
+        let header = HeaderName::try_from(header)?;
+
+        let aliases: Vec<_> = config
+            .aliases
+            .iter()
+            .map(HeaderName::try_from)
+            .collect::<Result<_, _>>()?;
+
+        // The primary header may also be listed as an alias if a route overrides it
+        let aliases = aliases
+            .into_iter()
+            .filter(|alias| *alias != header)
+            .collect();
+
+        Ok(Self { header, aliases })
+    }
+
+    pub fn header(&self) -> &HeaderName {
+        &self.header
+    }
+
+    /// Ensures the request carries an identifier in the primary header, taking it from an alias
+    /// or generating a new one if needed, and returns it so it can be echoed to the client.
+    pub fn assign<B>(&self, req: &mut Request<B>) -> HeaderValue {
+        let headers = req.headers_mut();
+        let mut value = headers.get(&self.header).cloned();
+
+        // Aliases are always removed so downstreams only ever see the primary header
+        for alias in &self.aliases {
+            if let Some(aliased) = headers.remove(alias) {
+                value.get_or_insert(aliased);
+            }
+        }
+
+        let value = value.unwrap_or_else(generate_request_id);
+        headers.insert(self.header.clone(), value.clone());
+
+        value
+    }
+}
+
+fn generate_request_id() -> HeaderValue {
+    let identifier = Uuid::new_v4().to_string();
+
+    HeaderValue::from_str(&identifier).expect("uuids are always valid header values")
+}
+
+#[cfg(test)]
+mod tests {
+    use color_eyre::eyre::Result;
+    use http::{HeaderValue, Request};
+
+    use crate::config::{RequestIdConfig, Route};
+    use crate::load_balancer::request_id::RequestIdHeaders;
+
+    fn config(header: &str, aliases: &[&str]) -> RequestIdConfig {
+        RequestIdConfig {
+            header: header.to_owned(),
+            aliases: aliases.iter().map(|alias| alias.to_string()).collect(),
+        }
+    }
+
+    #[test]
+    fn existing_identifiers_are_preserved() -> Result<()> {
+        let headers = RequestIdHeaders::resolve(&config("X-Request-Id", &[]), &Route::default())?;
+
+        let mut req = Request::builder()
+            .header("X-Request-Id", "abc123")
+            .body(())?;
+
+        let value = headers.assign(&mut req);
+
+        assert_eq!(value, "abc123");
+        assert_eq!(req.headers().get("X-Request-Id"), Some(&value));
+
+        Ok(())
+    }
+
+    #[test]
+    fn identifiers_are_generated_if_missing() -> Result<()> {
+        let headers = RequestIdHeaders::resolve(&config("X-Request-Id", &[]), &Route::default())?;
+
+        let mut req = Request::builder().body(())?;
+        let value = headers.assign(&mut req);
+
+        assert!(!value.is_empty());
+        assert_eq!(req.headers().get("X-Request-Id"), Some(&value));
+
+        Ok(())
+    }
+
+    #[test]
+    fn aliases_are_normalised_to_the_primary_header() -> Result<()> {
+        let config = config("X-Request-Id", &["X-Correlation-Id", "Request-Id"]);
+        let headers = RequestIdHeaders::resolve(&config, &Route::default())?;
+
+        let mut req = Request::builder().header("Request-Id", "abc123").body(())?;
+
+        headers.assign(&mut req);
+
+        assert_eq!(
+            req.headers().get("X-Request-Id"),
+            Some(&HeaderValue::from_static("abc123"))
+        );
+        assert!(req.headers().get("Request-Id").is_none());
+
+        Ok(())
+    }
+
+    #[test]
+    fn routes_can_override_the_header() -> Result<()> {
+        let route = Route {
+            request_id_header: Some(String::from("X-Correlation-Id")),
+            ..Default::default()
+        };
+
+        let headers = RequestIdHeaders::resolve(&config("X-Request-Id", &[]), &route)?;
+
+        assert_eq!(headers.header(), "x-correlation-id");
+
+        Ok(())
+    }
+}
@@ -0,0 +1,308 @@
+use std::collections::VecDeque;
+use std::error::Error;
+use std::fmt;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+
+use http::HeaderMap;
+use http_body_util::BodyExt;
+use hyper::body::{Body, Buf, Bytes, Frame, SizeHint};
+
+use crate::config::{RetryCondition, RetryConfig};
+use crate::load_balancer::upstream_error::UpstreamError;
+
+/// Raised while streaming a request body once it grows beyond its limit.
+#[derive(Debug)]
+pub struct BodyTooLarge;
+
+impl fmt::Display for BodyTooLarge {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "request body exceeds the limit")
+    }
+}
+
+impl Error for BodyTooLarge {}
+
+impl BodyTooLarge {
+    /// Checks whether a failed request was caused by its body exceeding the limit.
+    pub fn caused(error: &(dyn Error + 'static)) -> bool {
+        let mut source = Some(error);
+
+        while let Some(error) = source {
+            if error.is::<Self>() {
+                return true;
+            }
+
+            source = error.source();
+        }
+
+        false
+    }
+}
+
+/// A request body that replays anything read ahead of time before continuing with the rest.
+#[derive(Debug)]
+pub struct ReplayBody<B> {
+    buffered: VecDeque<Bytes>,
+    trailers: Option<HeaderMap>,
+    rest: Option<B>,
+    /// How much of the body has been read from the client so far.
+    length: usize,
+    limit: Option<usize>,
+}
+
+impl<B> ReplayBody<B>
+where
+    B: Body + Unpin,
+{
+    /// Passes the body through without holding on to any of it.
+    pub fn streaming(body: B) -> Self {
+        Self {
+            buffered: VecDeque::new(),
+            trailers: None,
+            rest: Some(body),
+            length: 0,
+            limit: None,
+        }
+    }
+
+    /// Reads the body frame by frame until it ends or exceeds the limit, at which point it can
+    /// no longer be replayed and the remainder is streamed after what has already been read.
+    pub async fn buffer(mut body: B, limit: usize) -> Result<Self, B::Error> {
+        let mut buffered = VecDeque::new();
+        let mut length = 0;
+
+        while let Some(frame) = body.frame().await {
+            let frame = match frame?.into_data() {
+                Ok(mut data) => {
+                    length += data.remaining();
+                    buffered.push_back(data.copy_to_bytes(data.remaining()));
+
+                    if length > limit {
+                        return Ok(Self {
+                            buffered,
+                            trailers: None,
+                            rest: Some(body),
+                            length,
+                            limit: None,
+                        });
+                    }
+
+                    continue;
+                }
+                Err(frame) => frame,
+            };
+
+            if let Ok(trailers) = frame.into_trailers() {
+                return Ok(Self {
+                    buffered,
+                    trailers: Some(trailers),
+                    rest: None,
+                    length,
+                    limit: None,
+                });
+            }
+        }
+
+        Ok(Self {
+            buffered,
+            trailers: None,
+            rest: None,
+            length,
+            limit: None,
+        })
+    }
+
+    /// Fails the body with [`BodyTooLarge`] if more than `max` bytes are streamed in total.
+    pub fn limit(self, max: usize) -> Self {
+        Self {
+            limit: Some(max),
+            ..self
+        }
+    }
+
+    /// Checks whether what has already been read is over the limit.
+    pub fn exceeds_limit(&self) -> bool {
+        self.limit.is_some_and(|max| self.length > max)
+    }
+
+    /// Checks whether all of the body has been read.
+    pub fn is_complete(&self) -> bool {
+        self.rest.is_none()
+    }
+
+    /// Creates a copy of the body to send, if all of it has been read.
+    pub fn try_clone(&self) -> Option<Self> {
+        self.is_complete().then(|| Self {
+            buffered: self.buffered.clone(),
+            trailers: self.trailers.clone(),
+            rest: None,
+            length: self.length,
+            limit: self.limit,
+        })
+    }
+}
+
+impl<B> Body for ReplayBody<B>
+where
+    B: Body + Unpin,
+    B::Error: Into<Box<dyn Error + Send + Sync>>,
+{
+    type Data = Bytes;
+    type Error = Box<dyn Error + Send + Sync>;
+
+    fn poll_frame(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<Frame<Self::Data>, Self::Error>>> {
+        if let Some(data) = self.buffered.pop_front() {
+            return Poll::Ready(Some(Ok(Frame::data(data))));
+        }
+
+        if let Some(rest) = self.rest.as_mut() {
+            let frame = match Pin::new(rest).poll_frame(cx) {
+                Poll::Ready(Some(Ok(frame))) => frame,
+                Poll::Ready(Some(Err(e))) => return Poll::Ready(Some(Err(e.into()))),
+                Poll::Ready(None) => return Poll::Ready(None),
+                Poll::Pending => return Poll::Pending,
+            };
+
+            let frame = frame.map_data(|mut data| data.copy_to_bytes(data.remaining()));
+
+            if let Some(data) = frame.data_ref() {
+                self.length += data.len();
+
+                if self.exceeds_limit() {
+                    return Poll::Ready(Some(Err(Box::new(BodyTooLarge))));
+                }
+            }
+
+            return Poll::Ready(Some(Ok(frame)));
+        }
+
+        Poll::Ready(
+            self.trailers
+                .take()
+                .map(|trailers| Ok(Frame::trailers(trailers))),
+        )
+    }
+
+    fn is_end_stream(&self) -> bool {
+        self.buffered.is_empty()
+            && self.trailers.is_none()
+            && self.rest.as_ref().is_none_or(Body::is_end_stream)
+    }
+
+    fn size_hint(&self) -> SizeHint {
+        let buffered = self.buffered.iter().map(|data| data.len() as u64).sum();
+
+        match &self.rest {
+            Some(rest) => {
+                let hint = rest.size_hint();
+                let mut combined = SizeHint::new();
+
+                combined.set_lower(hint.lower() + buffered);
+
+                if let Some(upper) = hint.upper() {
+                    combined.set_upper(upper + buffered);
+                }
+
+                combined
+            }
+            None => SizeHint::with_exact(buffered),
+        }
+    }
+}
+
+/// Checks whether a failed request should be retried against another downstream.
+pub fn is_retryable(config: &RetryConfig, error: &hyper_util::client::legacy::Error) -> bool {
+    config.on.iter().any(|condition| match condition {
+        RetryCondition::ConnectFailure => error.is_connect(),
+        RetryCondition::ConnectionReset => {
+            UpstreamError::classify(error) == UpstreamError::ConnectionReset
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::convert::Infallible;
+
+    use http::HeaderMap;
+    use http_body_util::{BodyExt, StreamBody};
+    use hyper::body::{Bytes, Frame};
+
+    use crate::load_balancer::retry::{BodyTooLarge, ReplayBody};
+
+    /// Builds a body without a known length, as a chunked request would have.
+    fn chunked(
+        chunks: &[&'static [u8]],
+        trailers: bool,
+    ) -> StreamBody<impl futures::Stream<Item = Result<Frame<Bytes>, Infallible>> + Unpin> {
+        let mut frames: Vec<_> = chunks
+            .iter()
+            .map(|chunk| Ok(Frame::data(Bytes::from_static(chunk))))
+            .collect();
+
+        if trailers {
+            let mut map = HeaderMap::new();
+            map.insert("x-checksum", "abc".parse().unwrap());
+            frames.push(Ok(Frame::trailers(map)));
+        }
+
+        StreamBody::new(futures::stream::iter(frames))
+    }
+
+    #[tokio::test]
+    async fn chunked_bodies_under_the_limit_can_be_replayed() {
+        let body = chunked(&[b"hello ", b"world"], true);
+        let buffered = ReplayBody::buffer(body, 64).await.unwrap();
+
+        for _ in 0..2 {
+            let replay = buffered.try_clone().expect("body should be replayable");
+            let collected = replay.collect().await.unwrap();
+
+            assert_eq!(
+                collected.trailers().and_then(|t| t.get("x-checksum")),
+                Some(&"abc".parse().unwrap())
+            );
+            assert_eq!(collected.to_bytes(), Bytes::from_static(b"hello world"));
+        }
+    }
+
+    #[tokio::test]
+    async fn chunked_bodies_over_the_limit_are_still_sent_in_full() {
+        let body = chunked(&[b"hello ", b"world", b"!"], true);
+        let buffered = ReplayBody::buffer(body, 8).await.unwrap();
+
+        assert!(buffered.try_clone().is_none());
+
+        let collected = buffered.collect().await.unwrap();
+
+        assert!(collected.trailers().is_some());
+        assert_eq!(collected.to_bytes(), Bytes::from_static(b"hello world!"));
+    }
+
+    #[tokio::test]
+    async fn limited_bodies_fail_once_streamed_past_the_limit() {
+        let body = chunked(&[b"hello ", b"world"], false);
+        let buffered = ReplayBody::buffer(body, 4).await.unwrap().limit(8);
+
+        assert!(!buffered.exceeds_limit());
+
+        let error = buffered.collect().await.unwrap_err();
+
+        assert!(BodyTooLarge::caused(&*error));
+    }
+
+    #[tokio::test]
+    async fn streaming_bodies_are_passed_through() {
+        let body = ReplayBody::streaming(chunked(&[b"hello"], false));
+
+        assert!(body.try_clone().is_none());
+        assert_eq!(
+            body.collect().await.unwrap().to_bytes(),
+            Bytes::from_static(b"hello")
+        );
+    }
+}
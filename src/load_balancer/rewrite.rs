@@ -0,0 +1,106 @@
+use color_eyre::eyre::Result;
+use http::{HeaderMap, HeaderName, HeaderValue};
+
+use crate::config::{HeaderRewrite, HeaderRules};
+
+/// The parsed form of a route's header rules, ready to apply to requests and responses.
+#[derive(Debug)]
+pub struct HeaderRewriters {
+    pub request: HeaderRewriter,
+    pub response: HeaderRewriter,
+}
+
+impl HeaderRewriters {
+    pub fn resolve(rules: &HeaderRules) -> Result<Self> {
+        Ok(Self {
+            request: HeaderRewriter::resolve(&rules.request)?,
+            response: HeaderRewriter::resolve(&rules.response)?,
+        })
+    }
+}
+
+#[derive(Debug)]
+pub struct HeaderRewriter {
+    add: Vec<(HeaderName, HeaderValue)>,
+    set: Vec<(HeaderName, HeaderValue)>,
+    remove: Vec<HeaderName>,
+}
+
+impl HeaderRewriter {
+    pub fn resolve(rewrite: &HeaderRewrite) -> Result<Self> {
+        let parse = |(name, value): (&String, &String)| -> Result<_> {
+            Ok((HeaderName::try_from(name)?, HeaderValue::try_from(value)?))
+        };
+
+        Ok(Self {
+            add: rewrite.add.iter().map(parse).collect::<Result<_>>()?,
+            set: rewrite.set.iter().map(parse).collect::<Result<_>>()?,
+            remove: rewrite
+                .remove
+                .iter()
+                .map(HeaderName::try_from)
+                .collect::<Result<_, _>>()?,
+        })
+    }
+
+    /// Removes headers first, so a header can be both removed and then set or added again.
+    pub fn apply(&self, headers: &mut HeaderMap) {
+        for name in &self.remove {
+            headers.remove(name);
+        }
+
+        for (name, value) in &self.set {
+            headers.insert(name.clone(), value.clone());
+        }
+
+        for (name, value) in &self.add {
+            headers.append(name.clone(), value.clone());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+
+    use color_eyre::eyre::Result;
+    use http::header::{CACHE_CONTROL, SERVER, VARY};
+    use http::{HeaderMap, HeaderValue};
+
+    use crate::config::HeaderRewrite;
+    use crate::load_balancer::rewrite::HeaderRewriter;
+
+    #[test]
+    fn headers_can_be_added_set_and_removed() -> Result<()> {
+        let rewrite = HeaderRewrite {
+            add: BTreeMap::from([(String::from("Vary"), String::from("Origin"))]),
+            set: BTreeMap::from([(String::from("Cache-Control"), String::from("max-age=3600"))]),
+            remove: vec![String::from("Server")],
+        };
+
+        let mut headers = HeaderMap::new();
+        headers.insert(SERVER, HeaderValue::from_static("nginx"));
+        headers.insert(CACHE_CONTROL, HeaderValue::from_static("no-store"));
+        headers.insert(VARY, HeaderValue::from_static("Accept-Encoding"));
+
+        HeaderRewriter::resolve(&rewrite)?.apply(&mut headers);
+
+        assert!(headers.get(SERVER).is_none());
+        assert_eq!(headers[CACHE_CONTROL], "max-age=3600");
+
+        let vary: Vec<_> = headers.get_all(VARY).iter().collect();
+        assert_eq!(vary, ["Accept-Encoding", "Origin"]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn invalid_header_names_are_rejected() {
+        let rewrite = HeaderRewrite {
+            remove: vec![String::from("not a header")],
+            ..Default::default()
+        };
+
+        assert!(HeaderRewriter::resolve(&rewrite).is_err());
+    }
+}
@@ -0,0 +1,205 @@
+use std::collections::HashMap;
+use std::sync::Mutex;
+
+use indexmap::IndexSet;
+
+use crate::docker::api::StartedContainerDetails;
+use crate::docker::models::ContainerId;
+
+/// Spreads requests across downstreams in proportion to their weights, interleaving them rather
+/// than sending runs of requests to the heaviest downstream.
+#[derive(Debug, Default)]
+pub struct WeightedRoundRobin {
+    current: Mutex<HashMap<ContainerId, i64>>,
+}
+
+impl WeightedRoundRobin {
+    /// Forgets the position of every downstream, such as after a deployment replaces them.
+    pub fn clear(&self) {
+        self.current.lock().unwrap().clear();
+    }
+
+    /// Picks the next downstream, treating them all equally if none of them have any weight.
+    pub fn choose<'a, F>(
+        &self,
+        downstreams: &'a IndexSet<StartedContainerDetails>,
+        weight: F,
+    ) -> Option<&'a StartedContainerDetails>
+    where
+        F: Fn(&StartedContainerDetails) -> u32,
+    {
+        self.choose_preferring(downstreams, weight, |_| false)
+    }
+
+    /// Picks the next downstream like [`Self::choose`], breaking ties in favour of those matching
+    /// `preferred` so the spread of traffic is unchanged.
+    pub fn choose_preferring<'a, F, P>(
+        &self,
+        downstreams: &'a IndexSet<StartedContainerDetails>,
+        weight: F,
+        preferred: P,
+    ) -> Option<&'a StartedContainerDetails>
+    where
+        F: Fn(&StartedContainerDetails) -> u32,
+        P: Fn(&StartedContainerDetails) -> bool,
+    {
+        let weights: Vec<_> = downstreams
+            .iter()
+            .map(|downstream| i64::from(weight(downstream)))
+            .collect();
+
+        let total: i64 = weights.iter().sum();
+        let (weights, total) = match total {
+            0 => (vec![1; downstreams.len()], downstreams.len() as i64),
+            _ => (weights, total),
+        };
+
+        let mut current = self.current.lock().unwrap();
+        let mut selected: Option<(&StartedContainerDetails, i64, bool)> = None;
+
+        for (downstream, weight) in downstreams.iter().zip(weights) {
+            if weight == 0 {
+                continue;
+            }
+
+            let entry = current.entry(downstream.id.clone()).or_default();
+            *entry += weight;
+
+            let is_preferred = preferred(downstream);
+            let better = selected.is_none_or(|(_, best, best_preferred)| {
+                *entry > best || (*entry == best && is_preferred && !best_preferred)
+            });
+
+            if better {
+                selected = Some((downstream, *entry, is_preferred));
+            }
+        }
+
+        let (downstream, _, _) = selected?;
+
+        if let Some(entry) = current.get_mut(&downstream.id) {
+            *entry -= total;
+        }
+
+        Some(downstream)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use std::net::{IpAddr, Ipv4Addr};
+
+    use indexmap::IndexSet;
+
+    use crate::docker::api::StartedContainerDetails;
+    use crate::docker::models::ContainerId;
+    use crate::load_balancer::round_robin::WeightedRoundRobin;
+
+    fn downstreams(count: u8) -> IndexSet<StartedContainerDetails> {
+        (0..count)
+            .map(|i| StartedContainerDetails {
+                id: ContainerId(format!("container-{i}")),
+                addr: Ipv4Addr::new(172, 17, 0, i).into(),
+            })
+            .collect()
+    }
+
+    /// Which of the downstreams created by `downstreams` this is.
+    fn index(downstream: &StartedContainerDetails) -> u8 {
+        match downstream.addr {
+            IpAddr::V4(addr) => addr.octets()[3],
+            IpAddr::V6(addr) => addr.octets()[15],
+        }
+    }
+
+    fn selections<F>(
+        round_robin: &WeightedRoundRobin,
+        downstreams: &IndexSet<StartedContainerDetails>,
+        count: usize,
+        weight: F,
+    ) -> Vec<u8>
+    where
+        F: Fn(&StartedContainerDetails) -> u32,
+    {
+        (0..count)
+            .map(|_| index(round_robin.choose(downstreams, &weight).unwrap()))
+            .collect()
+    }
+
+    #[test]
+    fn equal_weights_rotate_through_every_downstream() {
+        let round_robin = WeightedRoundRobin::default();
+        let downstreams = downstreams(3);
+
+        let selected = selections(&round_robin, &downstreams, 6, |_| 1);
+
+        assert_eq!(selected, [0, 1, 2, 0, 1, 2]);
+    }
+
+    #[test]
+    fn traffic_is_split_in_proportion_to_weight() {
+        let round_robin = WeightedRoundRobin::default();
+        let downstreams = downstreams(2);
+
+        let selected = selections(&round_robin, &downstreams, 40, |downstream| {
+            match index(downstream) {
+                0 => 3,
+                _ => 1,
+            }
+        });
+
+        let mut counts: HashMap<u8, usize> = HashMap::new();
+
+        for index in selected {
+            *counts.entry(index).or_default() += 1;
+        }
+
+        assert_eq!(counts[&0], 30);
+        assert_eq!(counts[&1], 10);
+    }
+
+    #[test]
+    fn heavier_downstreams_are_interleaved_with_lighter_ones() {
+        let round_robin = WeightedRoundRobin::default();
+        let downstreams = downstreams(2);
+
+        let selected = selections(&round_robin, &downstreams, 4, |downstream| {
+            match index(downstream) {
+                0 => 3,
+                _ => 1,
+            }
+        });
+
+        assert!(selected.contains(&1));
+    }
+
+    #[test]
+    fn downstreams_without_weight_receive_no_traffic() {
+        let round_robin = WeightedRoundRobin::default();
+        let downstreams = downstreams(3);
+
+        let selected = selections(&round_robin, &downstreams, 10, |downstream| {
+            u32::from(index(downstream) != 1)
+        });
+
+        assert!(!selected.contains(&1));
+    }
+
+    #[test]
+    fn downstreams_are_treated_equally_if_none_have_weight() {
+        let round_robin = WeightedRoundRobin::default();
+        let downstreams = downstreams(2);
+
+        let selected = selections(&round_robin, &downstreams, 4, |_| 0);
+
+        assert_eq!(selected, [0, 1, 0, 1]);
+    }
+
+    #[test]
+    fn no_downstream_is_selected_from_an_empty_set() {
+        let round_robin = WeightedRoundRobin::default();
+
+        assert_eq!(round_robin.choose(&IndexSet::new(), |_| 1), None);
+    }
+}
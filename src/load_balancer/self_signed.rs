@@ -0,0 +1,107 @@
+//! Generating self-signed certificates in memory, so HTTPS can be tried out locally without
+//! provisioning real certificates.
+
+use chrono::{TimeDelta, Utc};
+use color_eyre::eyre::Result;
+use ring::rand::{SecureRandom, SystemRandom};
+use ring::signature::{EcdsaKeyPair, KeyPair, ECDSA_P256_SHA256_ASN1_SIGNING};
+use rustls::crypto::ring::sign::any_supported_type;
+use rustls::pki_types::{CertificateDer, PrivateKeyDer, PrivatePkcs8KeyDer};
+use rustls::sign::CertifiedKey;
+
+use crate::load_balancer::der;
+
+/// How long generated certificates are valid for, which only needs to outlast a single run.
+const VALIDITY_DAYS: i64 = 365;
+
+/// Generates a key and a certificate for `domain` signed by that same key.
+pub fn generate(domain: &str) -> Result<CertifiedKey> {
+    let rng = SystemRandom::new();
+    let pkcs8 = EcdsaKeyPair::generate_pkcs8(&ECDSA_P256_SHA256_ASN1_SIGNING, &rng)?;
+    let key = EcdsaKeyPair::from_pkcs8(&ECDSA_P256_SHA256_ASN1_SIGNING, pkcs8.as_ref(), &rng)?;
+
+    // Serial numbers should be unpredictable, but must also be positive and minimally encoded
+    let mut serial_number = [0; 16];
+    rng.fill(&mut serial_number)?;
+    serial_number[0] = (serial_number[0] & 0x7f) | 0x40;
+
+    // Allow for clients whose clocks are slightly behind
+    let not_before = Utc::now() - TimeDelta::hours(1);
+    let not_after = not_before + TimeDelta::days(VALIDITY_DAYS);
+
+    let info = der::self_signed_certificate_info(
+        domain,
+        key.public_key().as_ref(),
+        &serial_number,
+        not_before,
+        not_after,
+    );
+    let signature = key.sign(&rng, &info)?;
+    let certificate = der::certificate(&info, signature.as_ref());
+
+    let key = PrivateKeyDer::Pkcs8(PrivatePkcs8KeyDer::from(pkcs8.as_ref().to_vec()));
+
+    Ok(CertifiedKey::new(
+        vec![CertificateDer::from(certificate)],
+        any_supported_type(&key)?,
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use chrono::{TimeDelta, Utc};
+    use color_eyre::eyre::Result;
+    use rustls::client::danger::ServerCertVerifier;
+    use rustls::client::WebPkiServerVerifier;
+    use rustls::crypto::ring::default_provider;
+    use rustls::pki_types::{ServerName, UnixTime};
+    use rustls::RootCertStore;
+
+    use crate::load_balancer::der;
+    use crate::load_balancer::self_signed::generate;
+
+    const DOMAIN: &str = "localhost.example.com";
+
+    #[test]
+    fn certificates_are_valid_for_their_domain() -> Result<()> {
+        let certified_key = generate(DOMAIN)?;
+        let certificate = &certified_key.cert[0];
+
+        // Trusting the certificate directly lets it be verified as if it came from an authority
+        let mut roots = RootCertStore::empty();
+        roots.add(certificate.clone())?;
+
+        let verifier = WebPkiServerVerifier::builder_with_provider(
+            Arc::new(roots),
+            Arc::new(default_provider()),
+        )
+        .build()?;
+
+        let verify = |domain: &'static str| {
+            verifier.verify_server_cert(
+                certificate,
+                &[],
+                &ServerName::try_from(domain).unwrap(),
+                &[],
+                UnixTime::now(),
+            )
+        };
+
+        assert!(verify(DOMAIN).is_ok());
+        assert!(verify("other.example.com").is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn certificates_expire_after_a_year() -> Result<()> {
+        let certified_key = generate(DOMAIN)?;
+        let remaining = der::not_after(&certified_key.cert[0])? - Utc::now();
+
+        assert!(remaining > TimeDelta::days(364) && remaining <= TimeDelta::days(365));
+
+        Ok(())
+    }
+}
@@ -0,0 +1,326 @@
+use std::io::ErrorKind;
+
+use color_eyre::eyre::Result;
+use http::header::{ALLOW, CACHE_CONTROL, CONTENT_LENGTH, CONTENT_TYPE, ETAG, IF_NONE_MATCH};
+use http::{HeaderMap, HeaderValue, Method, Response, StatusCode};
+use hyper::body::Bytes;
+
+use crate::config::{ExternalBytes, StaticRoute, StaticSource};
+
+/// Finds the static route for a request, preferring the longest matching prefix.
+pub fn find_route<'a>(
+    routes: &'a [StaticRoute],
+    host: &str,
+    path: &str,
+) -> Option<&'a StaticRoute> {
+    routes
+        .iter()
+        .filter(|route| route.host == host)
+        .filter(|route| {
+            route
+                .prefix
+                .as_deref()
+                .is_none_or(|prefix| path.starts_with(prefix))
+        })
+        .max_by_key(|route| route.prefix.as_deref().map_or(0, str::len))
+}
+
+/// Serves the file a request refers to from the route's source.
+pub async fn serve(
+    route: &StaticRoute,
+    method: &Method,
+    path: &str,
+    headers: &HeaderMap,
+) -> Result<Response<Bytes>> {
+    if method != Method::GET && method != Method::HEAD {
+        return Ok(Response::builder()
+            .status(StatusCode::METHOD_NOT_ALLOWED)
+            .header(ALLOW, "GET, HEAD")
+            .body(Bytes::new())?);
+    }
+
+    let Some(file) = resolve_file(route, path) else {
+        return Ok(Response::builder()
+            .status(StatusCode::NOT_FOUND)
+            .body(Bytes::new())?);
+    };
+
+    let (file, contents) = match read(&route.source, &file).await? {
+        Some(contents) => (file, contents),
+        None if route.spa_fallback => {
+            let Some(contents) = read(&route.source, &route.index).await? else {
+                return Ok(Response::builder()
+                    .status(StatusCode::NOT_FOUND)
+                    .body(Bytes::new())?);
+            };
+
+            (route.index.clone(), contents)
+        }
+        None => {
+            return Ok(Response::builder()
+                .status(StatusCode::NOT_FOUND)
+                .body(Bytes::new())?)
+        }
+    };
+
+    let etag = format!("\"{:08x}\"", crc32fast::hash(&contents));
+
+    let mut response = Response::builder()
+        .header(CONTENT_TYPE, content_type(&file))
+        .header(ETAG, &etag);
+
+    if let Some(cache_control) = &route.cache_control {
+        response = response.header(CACHE_CONTROL, cache_control);
+    }
+
+    let not_modified = headers
+        .get_all(IF_NONE_MATCH)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .any(|tag| tag.trim() == etag || tag.trim() == "*");
+
+    if not_modified {
+        return Ok(response
+            .status(StatusCode::NOT_MODIFIED)
+            .body(Bytes::new())?);
+    }
+
+    let response = response.header(CONTENT_LENGTH, contents.len());
+
+    let body = match *method {
+        Method::HEAD => Bytes::new(),
+        _ => contents,
+    };
+
+    Ok(response.status(StatusCode::OK).body(body)?)
+}
+
+/// Works out which file a path refers to relative to the route's source, rejecting any attempt to
+/// escape it.
+fn resolve_file(route: &StaticRoute, path: &str) -> Option<String> {
+    let relative = route
+        .prefix
+        .as_deref()
+        .and_then(|prefix| path.strip_prefix(prefix))
+        .unwrap_or(path)
+        .trim_start_matches('/');
+
+    let escapes = relative
+        .split('/')
+        .any(|segment| segment == ".." || segment.contains('\\'));
+
+    if escapes {
+        return None;
+    }
+
+    match relative.is_empty() || relative.ends_with('/') {
+        true => Some(format!("{relative}{}", route.index)),
+        false => Some(relative.to_owned()),
+    }
+}
+
+/// Reads a file from the source, returning `None` if it does not exist.
+async fn read(source: &StaticSource, file: &str) -> Result<Option<Bytes>> {
+    match source {
+        StaticSource::Filesystem { path } => match tokio::fs::read(path.join(file)).await {
+            Ok(contents) => Ok(Some(Bytes::from(contents))),
+            Err(e) if matches!(e.kind(), ErrorKind::NotFound | ErrorKind::IsADirectory) => Ok(None),
+            Err(e) => Err(e.into()),
+        },
+        StaticSource::S3 { bucket, prefix } => {
+            let location = ExternalBytes::S3 {
+                bucket: bucket.clone(),
+                key: format!("{}/{file}", prefix.trim_end_matches('/')),
+            };
+
+            // Missing objects cannot be told apart from other failures here
+            match location.resolve().await {
+                Ok(contents) => Ok(Some(Bytes::from(contents))),
+                Err(e) => {
+                    tracing::debug!(%e, ?location, "failed to fetch static file");
+
+                    Ok(None)
+                }
+            }
+        }
+    }
+}
+
+fn content_type(file: &str) -> HeaderValue {
+    let extension = file
+        .rsplit_once('.')
+        .map(|(_, extension)| extension.to_ascii_lowercase());
+
+    let content_type = match extension.as_deref() {
+        Some("html" | "htm") => "text/html; charset=utf-8",
+        Some("css") => "text/css; charset=utf-8",
+        Some("js" | "mjs") => "text/javascript; charset=utf-8",
+        Some("json" | "map") => "application/json",
+        Some("txt") => "text/plain; charset=utf-8",
+        Some("xml") => "application/xml",
+        Some("svg") => "image/svg+xml",
+        Some("png") => "image/png",
+        Some("jpg" | "jpeg") => "image/jpeg",
+        Some("gif") => "image/gif",
+        Some("webp") => "image/webp",
+        Some("ico") => "image/x-icon",
+        Some("woff") => "font/woff",
+        Some("woff2") => "font/woff2",
+        Some("wasm") => "application/wasm",
+        Some("pdf") => "application/pdf",
+        _ => "application/octet-stream",
+    };
+
+    HeaderValue::from_static(content_type)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use color_eyre::eyre::Result;
+    use http::header::{CACHE_CONTROL, CONTENT_TYPE, ETAG, IF_NONE_MATCH};
+    use http::{HeaderMap, Method, Response, StatusCode};
+    use hyper::body::Bytes;
+    use tempfile::TempDir;
+
+    use crate::config::{StaticRoute, StaticSource};
+    use crate::load_balancer::static_files::{find_route, serve};
+
+    fn build_route(root: &Path) -> StaticRoute {
+        StaticRoute {
+            host: String::from("opentracker.app"),
+            prefix: None,
+            source: StaticSource::Filesystem {
+                path: root.to_owned(),
+            },
+            index: String::from("index.html"),
+            spa_fallback: false,
+            cache_control: Some(String::from("max-age=60")),
+        }
+    }
+
+    async fn create_site() -> Result<TempDir> {
+        let dir = tempfile::tempdir()?;
+
+        tokio::fs::create_dir(dir.path().join("docs")).await?;
+        tokio::fs::write(dir.path().join("index.html"), "<h1>Home</h1>").await?;
+        tokio::fs::write(dir.path().join("docs/index.html"), "<h1>Docs</h1>").await?;
+        tokio::fs::write(dir.path().join("app.js"), "console.log(1);").await?;
+
+        Ok(dir)
+    }
+
+    async fn get(route: &StaticRoute, path: &str) -> Result<Response<Bytes>> {
+        serve(route, &Method::GET, path, &HeaderMap::new()).await
+    }
+
+    #[tokio::test]
+    async fn files_are_served_with_their_content_type() -> Result<()> {
+        let dir = create_site().await?;
+        let route = build_route(dir.path());
+
+        let response = get(&route, "/app.js").await?;
+
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(
+            response.headers()[CONTENT_TYPE],
+            "text/javascript; charset=utf-8"
+        );
+        assert_eq!(response.headers()[CACHE_CONTROL], "max-age=60");
+        assert_eq!(response.body(), "console.log(1);");
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn directory_paths_serve_the_index_document() -> Result<()> {
+        let dir = create_site().await?;
+        let route = build_route(dir.path());
+
+        assert_eq!(get(&route, "/").await?.body(), "<h1>Home</h1>");
+        assert_eq!(get(&route, "/docs/").await?.body(), "<h1>Docs</h1>");
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn missing_files_are_not_found() -> Result<()> {
+        let dir = create_site().await?;
+        let route = build_route(dir.path());
+
+        assert_eq!(
+            get(&route, "/missing.css").await?.status(),
+            StatusCode::NOT_FOUND
+        );
+        assert_eq!(get(&route, "/docs").await?.status(), StatusCode::NOT_FOUND);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn spa_fallback_serves_the_index_for_deep_paths() -> Result<()> {
+        let dir = create_site().await?;
+        let route = StaticRoute {
+            spa_fallback: true,
+            ..build_route(dir.path())
+        };
+
+        let response = get(&route, "/users/42/settings").await?;
+
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.headers()[CONTENT_TYPE], "text/html; charset=utf-8");
+        assert_eq!(response.body(), "<h1>Home</h1>");
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn paths_cannot_escape_the_source() -> Result<()> {
+        let dir = create_site().await?;
+        let route = build_route(&dir.path().join("docs"));
+
+        let response = get(&route, "/../app.js").await?;
+
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn unchanged_files_are_not_resent() -> Result<()> {
+        let dir = create_site().await?;
+        let route = build_route(dir.path());
+
+        let etag = get(&route, "/app.js").await?.headers()[ETAG].clone();
+
+        let mut headers = HeaderMap::new();
+        headers.insert(IF_NONE_MATCH, etag);
+
+        let response = serve(&route, &Method::GET, "/app.js", &headers).await?;
+
+        assert_eq!(response.status(), StatusCode::NOT_MODIFIED);
+        assert!(response.body().is_empty());
+
+        Ok(())
+    }
+
+    #[test]
+    fn the_longest_matching_prefix_is_preferred() {
+        let root = Path::new("/srv");
+        let routes = vec![
+            build_route(root),
+            StaticRoute {
+                prefix: Some(String::from("/docs")),
+                ..build_route(root)
+            },
+        ];
+
+        let matched = |path| find_route(&routes, "opentracker.app", path).map(|r| &r.prefix);
+
+        assert_eq!(matched("/docs/intro"), Some(&Some(String::from("/docs"))));
+        assert_eq!(matched("/about"), Some(&None));
+        assert!(find_route(&routes, "example.com", "/").is_none());
+    }
+}
@@ -1,28 +1,86 @@
-use std::collections::{HashMap, HashSet};
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::convert::Infallible;
+use std::io::Write;
 use std::net::{Ipv4Addr, SocketAddr, SocketAddrV4};
+use std::num::NonZeroU32;
+use std::sync::atomic::{AtomicUsize, Ordering};
 use std::sync::Arc;
+use std::time::Duration;
 
 use arc_swap::ArcSwap;
-use color_eyre::eyre::Result;
-use http_body_util::{BodyExt, Full};
-use hyper::body::{Bytes, Incoming};
-use hyper::header::HOST;
+use color_eyre::eyre::{eyre, Result};
+use http_body_util::{BodyExt, Full, StreamBody};
+use hyper::body::{Bytes, Frame, Incoming};
+use hyper::header::{
+    HeaderValue, ACCEPT, ACCEPT_ENCODING, AUTHORIZATION, CACHE_CONTROL, CONTENT_ENCODING,
+    CONTENT_TYPE, HOST, LOCATION, RETRY_AFTER, TE,
+};
+use hyper::http::request::Parts;
 use hyper::service::service_fn;
-use hyper::{Request, Response, StatusCode};
+use hyper::{HeaderMap, Request, Response, StatusCode};
 use hyper_util::client::legacy::connect::HttpConnector;
 use hyper_util::client::legacy::Client;
 use hyper_util::rt::{TokioExecutor, TokioIo};
 use hyper_util::server::conn::auto::Builder;
-use tokio::net::TcpListener;
-use tokio::sync::RwLock;
-
-use crate::config::{AlbConfig, Config, Route, Scheme, Service};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream, UdpSocket};
+use tokio::sync::{mpsc, oneshot, Mutex, RwLock};
+use tokio::task::JoinHandle;
+
+use crate::config::{
+    AccessControl, AlbConfig, AlternateService, BusyResponse, CircuitBreakerConfig,
+    ClientCertificateRules, CompressionConfig, Config, ConnectionPool, ContainerLogsConfig,
+    CorsConfig, DockerRetryConfig, ErrorPage, ErrorPageSource, ExternalBytes, HeaderRewrite,
+    HeaderRules, HttpProtocol, HttpsOnly, IpNetwork, Mirror, OtlpConfig, PlainHttpAction,
+    PortRoute, RateLimit, RequestCondition, RequestIdConfig, RetryCondition, RetryConfig, Route,
+    Scheme, Service, ShutdownConfig, StaticRoute, StaticSource, TlsConfig, TlsSecrets,
+    TrafficSplit, UpstreamProtocol, VariantHeader,
+};
 use crate::docker::api::StartedContainerDetails;
+use crate::docker::logs::{LogFrame, OutputStream};
 use crate::docker::models::ContainerId;
 use crate::ipc::MessageBus;
+use crate::load_balancer::filters::{FilterAction, RequestFilter};
 use crate::load_balancer::LoadBalancer;
+use crate::metrics::Metrics;
+use crate::reconciler::tests::FakeDockerClient;
 use crate::service_registry::ServiceRegistry;
 
+/// Collects formatted log lines so tests can assert on diagnostics.
+#[derive(Clone, Default)]
+pub struct CapturedLogs(Arc<std::sync::Mutex<Vec<u8>>>);
+
+impl CapturedLogs {
+    /// Creates a subscriber that writes everything it logs into the returned buffer.
+    pub fn subscriber() -> (Self, impl tracing::Subscriber + Send + Sync) {
+        let logs = Self::default();
+        let subscriber = tracing_subscriber::fmt()
+            .with_writer({
+                let logs = logs.clone();
+                move || logs.clone()
+            })
+            .with_ansi(false)
+            .finish();
+
+        (logs, subscriber)
+    }
+
+    pub fn contents(&self) -> String {
+        String::from_utf8_lossy(&self.0.lock().unwrap()).into_owned()
+    }
+}
+
+impl Write for CapturedLogs {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.0.lock().unwrap().extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
 fn create_service<T: Into<Option<&'static str>>>(
     host: &'static str,
     port: u16,
@@ -33,6 +91,7 @@ fn create_service<T: Into<Option<&'static str>>>(
             host: String::from(host),
             prefix: path_prefix.into().map(ToOwned::to_owned),
             port,
+            ..Default::default()
         }]),
         ..Default::default()
     }
@@ -41,7 +100,7 @@ fn create_service<T: Into<Option<&'static str>>>(
 fn add_container(service_registry: &mut ServiceRegistry, name: &str) {
     let details = StartedContainerDetails {
         id: ContainerId(String::from("6cd915f16ab3")),
-        addr: Ipv4Addr::LOCALHOST,
+        addr: Ipv4Addr::LOCALHOST.into(),
     };
 
     service_registry.add_container(name, details);
@@ -81,41 +140,136 @@ async fn spawn_fixed_response_server(response: &'static str) -> Result<SocketAdd
     Ok(resolved_addr)
 }
 
+/// A running load balancer, along with the handles tests use to observe and drive it.
+struct SpawnedLoadBalancer {
+    addr: SocketAddr,
+    metrics: Arc<Metrics>,
+    message_bus: Arc<MessageBus>,
+    service_registry: Arc<RwLock<ServiceRegistry>>,
+    docker_client: FakeDockerClient,
+    shutdown: oneshot::Sender<()>,
+    handle: JoinHandle<()>,
+}
+
 async fn spawn_load_balancer(service_registry: ServiceRegistry) -> Result<SocketAddr> {
+    let spawned = spawn_configured_load_balancer(service_registry, |_| {}).await?;
+
+    Ok(spawned.addr)
+}
+
+/// Answers requests without an `Authorization` header itself, marking the rest as checked.
+struct CheckAuthorization;
+
+impl RequestFilter for CheckAuthorization {
+    fn filter(&self, parts: &mut Parts) -> FilterAction {
+        if !parts.headers.contains_key(AUTHORIZATION) {
+            let response = Response::builder()
+                .status(StatusCode::UNAUTHORIZED)
+                .body(Bytes::from("missing credentials"))
+                .expect("response should be valid");
+
+            return FilterAction::Respond(response);
+        }
+
+        parts
+            .headers
+            .insert("X-Authorization-Checked", HeaderValue::from_static("true"));
+
+        FilterAction::Continue
+    }
+}
+
+/// Spawns a load balancer, allowing the configuration to be adjusted before it starts.
+async fn spawn_configured_load_balancer<F: FnOnce(&mut AlbConfig)>(
+    service_registry: ServiceRegistry,
+    configure: F,
+) -> Result<SpawnedLoadBalancer> {
     let addr = SocketAddrV4::new(Ipv4Addr::LOCALHOST, 0);
     let listener = TcpListener::bind(&addr).await?;
 
     let resolved_addr = listener.local_addr()?;
     let service_registry = Arc::new(RwLock::new(service_registry));
 
-    let config = Config {
+    let mut config = Config {
         alb: AlbConfig {
             addr: Ipv4Addr::LOCALHOST,
             ports: HashMap::from([(Scheme::Http, resolved_addr.port())]),
+            protocols: HashMap::new(),
             reconciliation: String::from("/reconciliation"),
+            metrics: None,
+            maintenance: None,
+            container_logs: None,
+            request_id: None,
+            evict_connections_on_deploy: false,
+            path_limits: None,
+            empty_service_wait_ms: None,
+            prefer_warm_connections: false,
+            connection_pool: ConnectionPool::default(),
+            max_buffered_body_size: 1024 * 1024,
+            access_log: false,
+            busy_response: None,
+            retry_after_jitter_secs: 0,
+            error_pages: HashMap::new(),
+            maintenance_response: None,
+            static_routes: Vec::new(),
+            otlp: None,
             tls: None,
             mtls: None,
         },
         secrets: None,
+        shutdown: ShutdownConfig::default(),
+        deploy_events: false,
+        registries: HashMap::new(),
+        garbage_collection: None,
+        network: None,
+        docker_retries: DockerRetryConfig::default(),
         services: HashMap::new(),
     };
 
+    configure(&mut config.alb);
+
     let config = Arc::new(ArcSwap::from_pointee(config));
     let message_bus = MessageBus::new();
+    let metrics = Metrics::new();
+    let (shutdown, receiver) = oneshot::channel();
 
-    tokio::spawn(async move {
-        let message_bus = Arc::clone(&message_bus);
-        let load_balancer = LoadBalancer::new(service_registry, config, message_bus);
+    // only shut down when explicitly asked to, rather than when the sender is dropped
+    let shutdown_signal = async move {
+        if receiver.await.is_err() {
+            std::future::pending::<()>().await;
+        }
+    };
+
+    let docker_client = FakeDockerClient::default();
+
+    let load_balancer = LoadBalancer::new(
+        Arc::clone(&service_registry),
+        config,
+        Arc::clone(&message_bus),
+        Arc::clone(&metrics),
+        Arc::new(docker_client.clone()),
+    );
+
+    load_balancer.register_filter("check-authorization", Arc::new(CheckAuthorization));
 
+    let handle = tokio::spawn(async move {
         let listeners = HashMap::from([(Scheme::Http, listener)]);
 
         load_balancer
-            .run(listeners, None, None)
+            .run(listeners, None, None, shutdown_signal)
             .await
             .expect("Failed to run load balancer");
     });
 
-    Ok(resolved_addr)
+    Ok(SpawnedLoadBalancer {
+        addr: resolved_addr,
+        metrics,
+        message_bus,
+        service_registry,
+        docker_client,
+        shutdown,
+        handle,
+    })
 }
 
 #[tokio::test]
@@ -168,7 +322,7 @@ async fn request_paths_are_proxied_downstream() -> Result<()> {
             let io = TokioIo::new(stream);
 
             Builder::new(TokioExecutor::new())
-                .serve_connection(io, service_fn(move |req| handle_health_checks(req)))
+                .serve_connection(io, service_fn(handle_health_checks))
                 .await
                 .unwrap();
         }
@@ -303,11 +457,13 @@ async fn can_proxy_to_different_ports_based_on_route_configuration() -> Result<(
                 host: String::from(internal_host),
                 prefix: None,
                 port: internal_addr.port(),
+                ..Default::default()
             },
             Route {
                 host: String::from(external_host),
                 prefix: None,
                 port: external_addr.port(),
+                ..Default::default()
             },
         ]),
         ..Default::default()
@@ -336,3 +492,2537 @@ async fn can_proxy_to_different_ports_based_on_route_configuration() -> Result<(
 
     Ok(())
 }
+
+/// Spawns a server that streams a `text/event-stream` response, emitting a frame for each value
+/// sent on the returned channel.
+async fn spawn_streaming_server() -> Result<(SocketAddr, mpsc::Sender<Bytes>)> {
+    let addr = SocketAddrV4::new(Ipv4Addr::LOCALHOST, 0);
+    let listener = TcpListener::bind(&addr).await?;
+
+    let resolved_addr = listener.local_addr()?;
+
+    let (sender, receiver) = mpsc::channel::<Bytes>(1);
+    let receiver = Arc::new(Mutex::new(Some(receiver)));
+
+    tokio::spawn(async move {
+        loop {
+            let (stream, _) = listener.accept().await.unwrap();
+            let io = TokioIo::new(stream);
+            let receiver = Arc::clone(&receiver);
+
+            tokio::spawn(async move {
+                Builder::new(TokioExecutor::new())
+                    .serve_connection(
+                        io,
+                        service_fn(move |_| {
+                            let receiver = Arc::clone(&receiver);
+
+                            async move {
+                                let receiver = receiver.lock().await.take().unwrap();
+                                let frames = futures::stream::unfold(receiver, |mut rx| async {
+                                    let data = rx.recv().await?;
+                                    Some((Ok::<_, Infallible>(Frame::data(data)), rx))
+                                });
+
+                                Response::builder()
+                                    .header("Content-Type", "text/event-stream")
+                                    .body(StreamBody::new(frames))
+                            }
+                        }),
+                    )
+                    .await
+                    .unwrap();
+            });
+        }
+    });
+
+    Ok((resolved_addr, sender))
+}
+
+#[tokio::test]
+async fn streamed_responses_are_forwarded_incrementally() -> Result<()> {
+    let host = "events.opentracker.app";
+    let (downstream_addr, sender) = spawn_streaming_server().await?;
+
+    let mut service_registry = ServiceRegistry::new();
+
+    service_registry.define("events", create_service(host, downstream_addr.port(), None));
+    add_container(&mut service_registry, "events");
+
+    let addr = spawn_load_balancer(service_registry).await?;
+    let client = Client::builder(TokioExecutor::new()).build_http();
+
+    let request = Request::builder()
+        .uri(format!("http://{}/events", addr))
+        .header(HOST, host)
+        .body(Full::<Bytes>::default())?;
+
+    let response = client.request(request).await?;
+    let mut body = response.into_body();
+
+    // each event is only sent once the previous one has been received, so any buffering in the
+    // proxy would cause this to stall until the timeout
+    for event in ["data: first\n\n", "data: second\n\n", "data: third\n\n"] {
+        sender.send(Bytes::from(event)).await?;
+
+        let frame = tokio::time::timeout(Duration::from_secs(1), body.frame())
+            .await?
+            .ok_or_else(|| eyre!("response body ended early"))??;
+
+        let data = frame
+            .into_data()
+            .map_err(|_| eyre!("expected a data frame"))?;
+
+        assert_eq!(data, event);
+    }
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn saturated_containers_shed_requests() -> Result<()> {
+    let host = "events.opentracker.app";
+    let (downstream_addr, _sender) = spawn_streaming_server().await?;
+
+    let mut service_registry = ServiceRegistry::new();
+
+    let service = Service {
+        max_in_flight: Some(1),
+        ..create_service(host, downstream_addr.port(), None)
+    };
+
+    service_registry.define("events", service);
+    add_container(&mut service_registry, "events");
+
+    let addr = spawn_load_balancer(service_registry).await?;
+    let client = Client::builder(TokioExecutor::new()).build_http();
+
+    let build_request = || {
+        Request::builder()
+            .uri(format!("http://{}/events", addr))
+            .header(HOST, host)
+            .body(Full::<Bytes>::default())
+    };
+
+    // the first response stays open until the stream ends, occupying the only slot
+    let streaming = client.request(build_request()?).await?;
+    assert_eq!(streaming.status(), StatusCode::OK);
+
+    let shed = client.request(build_request()?).await?;
+    assert_eq!(shed.status(), StatusCode::SERVICE_UNAVAILABLE);
+    assert!(shed.headers().contains_key(RETRY_AFTER));
+
+    Ok(())
+}
+
+/// Polls the active connection gauge until it reaches the expected value.
+async fn wait_for_active_connections(metrics: &Metrics, expected: u64) -> Result<()> {
+    let poll = async {
+        while metrics.connections(Scheme::Http).active() != expected {
+            tokio::time::sleep(Duration::from_millis(1)).await;
+        }
+    };
+
+    tokio::time::timeout(Duration::from_secs(1), poll)
+        .await
+        .map_err(|_| eyre!("active connections never reached {expected}"))
+}
+
+#[tokio::test]
+async fn active_connections_are_tracked_for_their_lifetime() -> Result<()> {
+    let SpawnedLoadBalancer { addr, metrics, .. } =
+        spawn_configured_load_balancer(ServiceRegistry::new(), |_| {}).await?;
+
+    wait_for_active_connections(&metrics, 0).await?;
+
+    let stream = tokio::net::TcpStream::connect(addr).await?;
+
+    wait_for_active_connections(&metrics, 1).await?;
+
+    drop(stream);
+
+    wait_for_active_connections(&metrics, 0).await?;
+
+    assert_eq!(metrics.connections(Scheme::Http).accepted(), 1);
+
+    Ok(())
+}
+
+/// Responds with the value of the given request header, or an empty body if it is missing.
+async fn spawn_header_echo_server(header: &'static str) -> Result<SocketAddr> {
+    let addr = SocketAddrV4::new(Ipv4Addr::LOCALHOST, 0);
+    let listener = TcpListener::bind(&addr).await?;
+
+    let resolved_addr = listener.local_addr()?;
+
+    tokio::spawn(async move {
+        loop {
+            let (stream, _) = listener.accept().await.unwrap();
+            let io = TokioIo::new(stream);
+
+            tokio::spawn(async move {
+                Builder::new(TokioExecutor::new())
+                    .serve_connection(
+                        io,
+                        service_fn(move |req: Request<Incoming>| async move {
+                            let value = req
+                                .headers()
+                                .get(header)
+                                .map(|value| Bytes::copy_from_slice(value.as_bytes()))
+                                .unwrap_or_default();
+
+                            Ok::<_, Infallible>(Response::new(Full::new(value)))
+                        }),
+                    )
+                    .await
+                    .unwrap();
+            });
+        }
+    });
+
+    Ok(resolved_addr)
+}
+
+#[tokio::test]
+async fn request_identifiers_use_the_configured_header() -> Result<()> {
+    let host = "opentracker.app";
+    let header = "X-Correlation-Id";
+
+    let downstream_addr = spawn_header_echo_server(header).await?;
+
+    let mut service_registry = ServiceRegistry::new();
+
+    service_registry.define(
+        "service",
+        create_service(host, downstream_addr.port(), None),
+    );
+    add_container(&mut service_registry, "service");
+
+    let SpawnedLoadBalancer { addr, .. } =
+        spawn_configured_load_balancer(service_registry, |alb| {
+            alb.request_id = Some(RequestIdConfig {
+                header: String::from(header),
+                aliases: Vec::new(),
+            });
+        })
+        .await?;
+
+    let client = Client::builder(TokioExecutor::new()).build_http();
+
+    let request = Request::builder()
+        .uri(format!("http://{}/", addr))
+        .header(HOST, host)
+        .body(Full::<Bytes>::default())?;
+
+    let response = client.request(request).await?;
+
+    let echoed = response
+        .headers()
+        .get(header)
+        .cloned()
+        .ok_or_else(|| eyre!("expected the request identifier to be echoed"))?;
+
+    // the downstream saw the same generated identifier that was echoed to the client
+    let received = response.into_body().collect().await?.to_bytes();
+
+    assert!(!echoed.is_empty());
+    assert_eq!(echoed.as_bytes(), received);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn client_addresses_are_forwarded_instead_of_spoofed_values() -> Result<()> {
+    let host = "opentracker.app";
+
+    let downstream_addr = spawn_header_echo_server("X-Forwarded-For").await?;
+
+    let mut service_registry = ServiceRegistry::new();
+
+    service_registry.define(
+        "service",
+        create_service(host, downstream_addr.port(), None),
+    );
+    add_container(&mut service_registry, "service");
+
+    let addr = spawn_load_balancer(service_registry).await?;
+    let client = Client::builder(TokioExecutor::new()).build_http();
+
+    let request = Request::builder()
+        .uri(format!("http://{}/", addr))
+        .header(HOST, host)
+        .header("X-Forwarded-For", "203.0.113.7")
+        .body(Full::<Bytes>::default())?;
+
+    let response = client.request(request).await?;
+    let received = response.into_body().collect().await?.to_bytes();
+
+    assert_eq!(received, "127.0.0.1");
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn services_can_rewrite_the_upstream_host() -> Result<()> {
+    let host = "opentracker.app";
+
+    let downstream_addr = spawn_header_echo_server("Host").await?;
+
+    let mut service_registry = ServiceRegistry::new();
+
+    let service = Service {
+        upstream_host: Some(String::from("backend.internal")),
+        ..create_service(host, downstream_addr.port(), None)
+    };
+
+    service_registry.define("service", service);
+    add_container(&mut service_registry, "service");
+
+    let addr = spawn_load_balancer(service_registry).await?;
+    let client = Client::builder(TokioExecutor::new()).build_http();
+
+    let request = Request::builder()
+        .uri(format!("http://{}/", addr))
+        .header(HOST, host)
+        .body(Full::<Bytes>::default())?;
+
+    let response = client.request(request).await?;
+    let received = response.into_body().collect().await?.to_bytes();
+
+    assert_eq!(received, "backend.internal");
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn route_filters_can_modify_and_answer_requests() -> Result<()> {
+    let host = "opentracker.app";
+
+    let downstream_addr = spawn_header_echo_server("X-Authorization-Checked").await?;
+
+    let mut service_registry = ServiceRegistry::new();
+
+    let service = Service {
+        routes: HashSet::from([Route {
+            host: String::from(host),
+            port: downstream_addr.port(),
+            filters: vec![String::from("check-authorization")],
+            ..Default::default()
+        }]),
+        ..Default::default()
+    };
+
+    service_registry.define("service", service);
+    add_container(&mut service_registry, "service");
+
+    let addr = spawn_load_balancer(service_registry).await?;
+    let client = Client::builder(TokioExecutor::new()).build_http();
+
+    let request = Request::builder()
+        .uri(format!("http://{}/", addr))
+        .header(HOST, host)
+        .body(Full::<Bytes>::default())?;
+
+    let response = client.request(request).await?;
+
+    assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+    assert_eq!(
+        response.into_body().collect().await?.to_bytes(),
+        "missing credentials"
+    );
+
+    let request = Request::builder()
+        .uri(format!("http://{}/", addr))
+        .header(HOST, host)
+        .header(AUTHORIZATION, "Bearer token")
+        .body(Full::<Bytes>::default())?;
+
+    let response = client.request(request).await?;
+
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(response.into_body().collect().await?.to_bytes(), "true");
+
+    Ok(())
+}
+
+/// Responds to every request, counting the number of connections accepted.
+async fn spawn_connection_counting_server() -> Result<(SocketAddr, Arc<AtomicUsize>)> {
+    let addr = SocketAddrV4::new(Ipv4Addr::LOCALHOST, 0);
+    let listener = TcpListener::bind(&addr).await?;
+
+    let resolved_addr = listener.local_addr()?;
+    let connections = Arc::new(AtomicUsize::new(0));
+
+    tokio::spawn({
+        let connections = Arc::clone(&connections);
+
+        async move {
+            loop {
+                let (stream, _) = listener.accept().await.unwrap();
+                let io = TokioIo::new(stream);
+
+                connections.fetch_add(1, Ordering::SeqCst);
+
+                tokio::spawn(async move {
+                    let _ = Builder::new(TokioExecutor::new())
+                        .serve_connection(io, service_fn(move |_| handler("Hello World!")))
+                        .await;
+                });
+            }
+        }
+    });
+
+    Ok((resolved_addr, connections))
+}
+
+async fn request_downstream_connections(
+    addr: SocketAddr,
+    host: &'static str,
+    connections: &AtomicUsize,
+) -> Result<usize> {
+    let client = Client::builder(TokioExecutor::new()).build_http();
+
+    let request = Request::builder()
+        .uri(format!("http://{}/", addr))
+        .header(HOST, host)
+        .body(Full::<Bytes>::default())?;
+
+    let response = client.request(request).await?;
+    response.into_body().collect().await?;
+
+    Ok(connections.load(Ordering::SeqCst))
+}
+
+#[tokio::test]
+async fn pooled_connections_are_evicted_on_deploy_when_configured() -> Result<()> {
+    let host = "opentracker.app";
+
+    let (downstream_addr, connections) = spawn_connection_counting_server().await?;
+
+    let mut service_registry = ServiceRegistry::new();
+
+    service_registry.define(
+        "service",
+        create_service(host, downstream_addr.port(), None),
+    );
+    add_container(&mut service_registry, "service");
+
+    let SpawnedLoadBalancer {
+        addr, message_bus, ..
+    } = spawn_configured_load_balancer(service_registry, |alb| {
+        alb.evict_connections_on_deploy = true;
+    })
+    .await?;
+
+    assert_eq!(
+        request_downstream_connections(addr, host, &connections).await?,
+        1
+    );
+
+    // the pooled connection is reused while nothing has been deployed
+    assert_eq!(
+        request_downstream_connections(addr, host, &connections).await?,
+        1
+    );
+
+    message_bus.send_pool_eviction_request()?;
+
+    // give the load balancer a chance to process the eviction
+    tokio::time::sleep(Duration::from_millis(50)).await;
+
+    assert_eq!(
+        request_downstream_connections(addr, host, &connections).await?,
+        2
+    );
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn connections_remain_usable_after_sending_bodies_to_reserved_endpoints() -> Result<()> {
+    let SpawnedLoadBalancer { addr, .. } =
+        spawn_configured_load_balancer(ServiceRegistry::new(), |_| {}).await?;
+
+    let stream = tokio::net::TcpStream::connect(addr).await?;
+    let (mut sender, connection) =
+        hyper::client::conn::http1::handshake(TokioIo::new(stream)).await?;
+
+    tokio::spawn(connection);
+
+    for body in [Bytes::from(vec![b'a'; 16 * 1024]), Bytes::new()] {
+        let request = Request::builder()
+            .method("PUT")
+            .uri("/reconciliation")
+            .header(HOST, "localhost")
+            .body(Full::new(body))?;
+
+        let response = sender.send_request(request).await?;
+
+        assert_eq!(response.status(), StatusCode::OK);
+    }
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn oversized_bodies_are_rejected_by_reserved_endpoints() -> Result<()> {
+    let addr = spawn_load_balancer(ServiceRegistry::new()).await?;
+
+    let client = Client::builder(TokioExecutor::new()).build_http();
+
+    let request = Request::builder()
+        .method("PUT")
+        .uri(format!("http://{}/reconciliation", addr))
+        .body(Full::new(Bytes::from(vec![b'a'; 1024 * 1024])))?;
+
+    let response = client.request(request).await?;
+
+    assert_eq!(response.status(), StatusCode::PAYLOAD_TOO_LARGE);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn in_flight_requests_complete_after_listeners_stop_on_shutdown() -> Result<()> {
+    let host = "events.opentracker.app";
+    let (downstream_addr, sender) = spawn_streaming_server().await?;
+
+    let mut service_registry = ServiceRegistry::new();
+
+    service_registry.define("events", create_service(host, downstream_addr.port(), None));
+    add_container(&mut service_registry, "events");
+
+    let SpawnedLoadBalancer {
+        addr,
+        shutdown,
+        handle,
+        ..
+    } = spawn_configured_load_balancer(service_registry, |_| {}).await?;
+
+    let client = Client::builder(TokioExecutor::new()).build_http();
+
+    let request = Request::builder()
+        .uri(format!("http://{}/events", addr))
+        .header(HOST, host)
+        .body(Full::<Bytes>::default())?;
+
+    let response = client.request(request).await?;
+    let mut body = response.into_body();
+
+    sender.send(Bytes::from("data: first\n\n")).await?;
+    body.frame().await;
+
+    shutdown
+        .send(())
+        .map_err(|_| eyre!("load balancer stopped early"))?;
+
+    // new connections are refused while the existing request is still in flight
+    let refused = async {
+        while tokio::net::TcpStream::connect(addr).await.is_ok() {
+            tokio::time::sleep(Duration::from_millis(1)).await;
+        }
+    };
+
+    tokio::time::timeout(Duration::from_secs(1), refused).await?;
+    assert!(!handle.is_finished());
+
+    sender.send(Bytes::from("data: last\n\n")).await?;
+    drop(sender);
+
+    let remaining = body.collect().await?.to_bytes();
+    assert_eq!(remaining, "data: last\n\n");
+
+    // the load balancer only completes once the request has drained
+    tokio::time::timeout(Duration::from_secs(1), handle).await??;
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn http2_clients_are_rejected_by_http1_only_listeners() -> Result<()> {
+    let SpawnedLoadBalancer { addr, .. } =
+        spawn_configured_load_balancer(ServiceRegistry::new(), |alb| {
+            alb.protocols.insert(Scheme::Http, HttpProtocol::Http1);
+        })
+        .await?;
+
+    let request = || {
+        Request::builder()
+            .method("PUT")
+            .uri(format!("http://{}/reconciliation", addr))
+            .body(Full::<Bytes>::default())
+    };
+
+    let http1 = Client::builder(TokioExecutor::new()).build_http();
+    let response = http1.request(request()?).await?;
+
+    assert_eq!(response.status(), StatusCode::OK);
+
+    let http2 = Client::builder(TokioExecutor::new())
+        .http2_only(true)
+        .build_http();
+
+    assert!(http2.request(request()?).await.is_err());
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn requests_to_services_without_containers_are_rejected() -> Result<()> {
+    let host = "opentracker.app";
+
+    let mut service_registry = ServiceRegistry::new();
+    service_registry.define("service", create_service(host, 80, None));
+
+    let addr = spawn_load_balancer(service_registry).await?;
+    let client = Client::builder(TokioExecutor::new()).build_http();
+
+    let request = Request::builder()
+        .uri(format!("http://{}/", addr))
+        .header(HOST, host)
+        .body(Full::<Bytes>::default())?;
+
+    let response = client.request(request).await?;
+
+    assert_eq!(response.status(), StatusCode::SERVICE_UNAVAILABLE);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn requests_wait_briefly_for_containers_to_appear() -> Result<()> {
+    let host = "opentracker.app";
+    let reply = "Hello World!";
+
+    let downstream_addr = spawn_fixed_response_server(reply).await?;
+
+    let mut service_registry = ServiceRegistry::new();
+    service_registry.define(
+        "service",
+        create_service(host, downstream_addr.port(), None),
+    );
+
+    let SpawnedLoadBalancer {
+        addr,
+        service_registry,
+        ..
+    } = spawn_configured_load_balancer(service_registry, |alb| {
+        alb.empty_service_wait_ms = Some(1000);
+    })
+    .await?;
+
+    let client = Client::builder(TokioExecutor::new()).build_http();
+
+    let request = Request::builder()
+        .uri(format!("http://{}/", addr))
+        .header(HOST, host)
+        .body(Full::<Bytes>::default())?;
+
+    let response = tokio::spawn(async move { get_response_body(&client, request).await });
+
+    // the deploy adds a container shortly after the request arrives
+    tokio::time::sleep(Duration::from_millis(50)).await;
+    add_container(&mut *service_registry.write().await, "service");
+
+    assert_eq!(response.await??, reply);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn access_logs_respect_per_service_overrides() -> Result<()> {
+    let logged_host = "logged.opentracker.app";
+    let quiet_host = "quiet.opentracker.app";
+
+    let downstream_addr = spawn_fixed_response_server("Hello World!").await?;
+
+    let mut service_registry = ServiceRegistry::new();
+
+    let mut logged = create_service(logged_host, downstream_addr.port(), None);
+    logged.access_log = Some(true);
+
+    service_registry.define("logged", logged);
+    service_registry.define(
+        "quiet",
+        create_service(quiet_host, downstream_addr.port(), None),
+    );
+
+    add_container(&mut service_registry, "logged");
+    add_container(&mut service_registry, "quiet");
+
+    let (logs, subscriber) = CapturedLogs::subscriber();
+    let _guard = tracing::subscriber::set_default(subscriber);
+
+    let addr = spawn_load_balancer(service_registry).await?;
+    let client = Client::builder(TokioExecutor::new()).build_http();
+
+    for host in [logged_host, quiet_host] {
+        let request = Request::builder()
+            .uri(format!("http://{}/", addr))
+            .header(HOST, host)
+            .body(Full::<Bytes>::default())?;
+
+        get_response_body(&client, request).await?;
+    }
+
+    let logs = logs.contents();
+
+    assert!(logs.contains(logged_host));
+    assert!(!logs.contains(quiet_host));
+
+    Ok(())
+}
+
+/// Accepts a single upgrade request, switches protocols and then echoes whatever it receives.
+async fn spawn_upgrading_echo_server() -> Result<SocketAddr> {
+    let addr = SocketAddrV4::new(Ipv4Addr::LOCALHOST, 0);
+    let listener = TcpListener::bind(&addr).await?;
+
+    let resolved_addr = listener.local_addr()?;
+
+    tokio::spawn(async move {
+        let (mut stream, _) = listener.accept().await.unwrap();
+
+        read_until_headers_end(&mut stream).await.unwrap();
+
+        stream
+            .write_all(
+                b"HTTP/1.1 101 Switching Protocols\r\nConnection: upgrade\r\nUpgrade: websocket\r\n\r\n",
+            )
+            .await
+            .unwrap();
+
+        let (mut reader, mut writer) = stream.split();
+        tokio::io::copy(&mut reader, &mut writer).await.unwrap();
+    });
+
+    Ok(resolved_addr)
+}
+
+async fn read_until_headers_end(stream: &mut TcpStream) -> Result<String> {
+    let mut received = Vec::new();
+
+    while !received.ends_with(b"\r\n\r\n") {
+        let mut byte = [0; 1];
+
+        if stream.read(&mut byte).await? == 0 {
+            return Err(eyre!("connection closed before the headers ended"));
+        }
+
+        received.push(byte[0]);
+    }
+
+    Ok(String::from_utf8(received)?)
+}
+
+#[tokio::test]
+async fn upgraded_connections_are_proxied_in_both_directions() -> Result<()> {
+    let host = "opentracker.app";
+    let downstream_addr = spawn_upgrading_echo_server().await?;
+
+    let mut service_registry = ServiceRegistry::new();
+    service_registry.define(
+        "opentracker",
+        create_service(host, downstream_addr.port(), None),
+    );
+    add_container(&mut service_registry, "opentracker");
+
+    let addr = spawn_load_balancer(service_registry).await?;
+    let mut stream = TcpStream::connect(addr).await?;
+
+    let request = format!(
+        "GET /ws HTTP/1.1\r\nHost: {host}\r\nConnection: Upgrade\r\nUpgrade: websocket\r\n\r\n"
+    );
+    stream.write_all(request.as_bytes()).await?;
+
+    let response = read_until_headers_end(&mut stream).await?;
+    assert!(response.starts_with("HTTP/1.1 101"));
+
+    stream.write_all(b"ping").await?;
+
+    let mut echoed = [0; 4];
+    tokio::time::timeout(Duration::from_secs(1), stream.read_exact(&mut echoed)).await??;
+
+    assert_eq!(&echoed, b"ping");
+
+    Ok(())
+}
+
+/// Serves HTTP/2 only, replying with the version of each request and counting connections.
+async fn spawn_http2_only_server() -> Result<(SocketAddr, Arc<AtomicUsize>)> {
+    let addr = SocketAddrV4::new(Ipv4Addr::LOCALHOST, 0);
+    let listener = TcpListener::bind(&addr).await?;
+
+    let resolved_addr = listener.local_addr()?;
+    let connections = Arc::new(AtomicUsize::new(0));
+
+    tokio::spawn({
+        let connections = Arc::clone(&connections);
+
+        async move {
+            loop {
+                let (stream, _) = listener.accept().await.unwrap();
+                let io = TokioIo::new(stream);
+
+                connections.fetch_add(1, Ordering::SeqCst);
+
+                tokio::spawn(async move {
+                    let service = service_fn(|req: Request<Incoming>| async move {
+                        let version = format!("{:?}", req.version());
+                        Ok::<_, Infallible>(Response::new(Full::<Bytes>::from(version)))
+                    });
+
+                    let _ = Builder::new(TokioExecutor::new())
+                        .http2_only()
+                        .serve_connection(io, service)
+                        .await;
+                });
+            }
+        }
+    });
+
+    Ok((resolved_addr, connections))
+}
+
+#[tokio::test]
+async fn services_can_be_proxied_to_over_http2() -> Result<()> {
+    let host = "opentracker.app";
+    let (downstream_addr, connections) = spawn_http2_only_server().await?;
+
+    let mut service = create_service(host, downstream_addr.port(), None);
+    service.upstream_protocol = UpstreamProtocol::Http2;
+
+    let mut service_registry = ServiceRegistry::new();
+    service_registry.define("opentracker", service);
+    add_container(&mut service_registry, "opentracker");
+
+    let addr = spawn_load_balancer(service_registry).await?;
+    let client = Client::builder(TokioExecutor::new()).build_http();
+
+    for _ in 0..3 {
+        let request = Request::builder()
+            .uri(format!("http://{}/", addr))
+            .header(HOST, host)
+            .body(Full::<Bytes>::default())?;
+
+        assert_eq!(get_response_body(&client, request).await?, "HTTP/2.0");
+    }
+
+    // every request shares the one multiplexed connection
+    assert_eq!(connections.load(Ordering::SeqCst), 1);
+
+    Ok(())
+}
+
+/// Replies to gRPC-style requests over HTTP/2 with a message followed by a status trailer.
+async fn spawn_grpc_server() -> Result<SocketAddr> {
+    let addr = SocketAddrV4::new(Ipv4Addr::LOCALHOST, 0);
+    let listener = TcpListener::bind(&addr).await?;
+
+    let resolved_addr = listener.local_addr()?;
+
+    tokio::spawn(async move {
+        loop {
+            let (stream, _) = listener.accept().await.unwrap();
+            let io = TokioIo::new(stream);
+
+            tokio::spawn(async move {
+                let service = service_fn(|_: Request<Incoming>| async move {
+                    let mut trailers = HeaderMap::new();
+                    trailers.insert("grpc-status", HeaderValue::from_static("0"));
+
+                    let frames = futures::stream::iter([
+                        Ok::<_, Infallible>(Frame::data(Bytes::from_static(b"message"))),
+                        Ok(Frame::trailers(trailers)),
+                    ]);
+
+                    Response::builder()
+                        .header(CONTENT_TYPE, "application/grpc")
+                        .body(StreamBody::new(frames))
+                });
+
+                let _ = Builder::new(TokioExecutor::new())
+                    .http2_only()
+                    .serve_connection(io, service)
+                    .await;
+            });
+        }
+    });
+
+    Ok(resolved_addr)
+}
+
+#[tokio::test]
+async fn grpc_requests_are_proxied_with_trailers() -> Result<()> {
+    let host = "grpc.opentracker.app";
+    let downstream_addr = spawn_grpc_server().await?;
+
+    let mut service_registry = ServiceRegistry::new();
+    service_registry.define("grpc", create_service(host, downstream_addr.port(), None));
+    add_container(&mut service_registry, "grpc");
+
+    let addr = spawn_load_balancer(service_registry).await?;
+
+    // connect directly so the authority can be the host the route is matched on
+    let stream = TcpStream::connect(addr).await?;
+    let (mut sender, connection) =
+        hyper::client::conn::http2::handshake(TokioExecutor::new(), TokioIo::new(stream)).await?;
+
+    tokio::spawn(connection);
+
+    let request = Request::builder()
+        .uri(format!("http://{host}/package.Service/Method"))
+        .header(CONTENT_TYPE, "application/grpc")
+        .header(TE, "trailers")
+        .body(Full::new(Bytes::from_static(b"request")))?;
+
+    let response = sender.send_request(request).await?;
+    let collected = response.into_body().collect().await?;
+
+    let trailers = collected
+        .trailers()
+        .cloned()
+        .ok_or_else(|| eyre!("no trailers were received"))?;
+
+    assert_eq!(
+        trailers.get("grpc-status"),
+        Some(&HeaderValue::from_static("0"))
+    );
+    assert_eq!(collected.to_bytes(), Bytes::from_static(b"message"));
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn refused_connections_are_retried_against_other_downstreams() -> Result<()> {
+    let host = "opentracker.app";
+
+    // only the second loopback address has anything listening on the port
+    let listener = TcpListener::bind(SocketAddrV4::new(Ipv4Addr::new(127, 0, 0, 2), 0)).await?;
+    let port = listener.local_addr()?.port();
+
+    tokio::spawn(async move {
+        loop {
+            let (stream, _) = listener.accept().await.unwrap();
+
+            let io = TokioIo::new(stream);
+
+            tokio::spawn(async move {
+                let _ = Builder::new(TokioExecutor::new())
+                    .serve_connection(io, service_fn(move |_| handler("Hello World!")))
+                    .await;
+            });
+        }
+    });
+
+    let mut service = create_service(host, port, None);
+    service.retry = Some(RetryConfig {
+        attempts: 1,
+        on: HashSet::from([RetryCondition::ConnectFailure]),
+    });
+
+    let mut service_registry = ServiceRegistry::new();
+    service_registry.define("service", service);
+
+    for (id, addr) in [
+        ("refusing", Ipv4Addr::LOCALHOST),
+        ("listening", Ipv4Addr::new(127, 0, 0, 2)),
+    ] {
+        let details = StartedContainerDetails {
+            id: ContainerId(String::from(id)),
+            addr: addr.into(),
+        };
+
+        service_registry.add_container("service", details);
+    }
+
+    let addr = spawn_load_balancer(service_registry).await?;
+    let client = Client::builder(TokioExecutor::new()).build_http();
+
+    for _ in 0..2 {
+        let request = Request::builder()
+            .method("POST")
+            .uri(format!("http://{}/", addr))
+            .header(HOST, host)
+            .body(Full::new(Bytes::from_static(b"payload")))?;
+
+        assert_eq!(get_response_body(&client, request).await?, "Hello World!");
+    }
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn failing_services_are_rejected_once_their_circuit_opens() -> Result<()> {
+    let host = "opentracker.app";
+
+    let addr = SocketAddrV4::new(Ipv4Addr::LOCALHOST, 0);
+    let listener = TcpListener::bind(&addr).await?;
+    let downstream_addr = listener.local_addr()?;
+    let requests = Arc::new(AtomicUsize::new(0));
+
+    tokio::spawn({
+        let requests = Arc::clone(&requests);
+
+        async move {
+            loop {
+                let (stream, _) = listener.accept().await.unwrap();
+                let io = TokioIo::new(stream);
+                let requests = Arc::clone(&requests);
+
+                tokio::spawn(async move {
+                    let service = service_fn(move |_| {
+                        requests.fetch_add(1, Ordering::SeqCst);
+
+                        async move {
+                            Response::builder()
+                                .status(StatusCode::INTERNAL_SERVER_ERROR)
+                                .body(Full::<Bytes>::default())
+                        }
+                    });
+
+                    let _ = Builder::new(TokioExecutor::new())
+                        .serve_connection(io, service)
+                        .await;
+                });
+            }
+        }
+    });
+
+    let mut service = create_service(host, downstream_addr.port(), None);
+    service.circuit_breaker = Some(CircuitBreakerConfig {
+        failure_threshold: 2,
+        open_ms: 60_000,
+    });
+
+    let mut service_registry = ServiceRegistry::new();
+    service_registry.define("service", service);
+    add_container(&mut service_registry, "service");
+
+    let addr = spawn_load_balancer(service_registry).await?;
+    let client = Client::builder(TokioExecutor::new()).build_http();
+
+    let mut statuses = Vec::new();
+
+    for _ in 0..3 {
+        let request = Request::builder()
+            .uri(format!("http://{}/", addr))
+            .header(HOST, host)
+            .body(Full::<Bytes>::default())?;
+
+        statuses.push(client.request(request).await?.status());
+    }
+
+    assert_eq!(
+        statuses,
+        [
+            StatusCode::INTERNAL_SERVER_ERROR,
+            StatusCode::INTERNAL_SERVER_ERROR,
+            StatusCode::SERVICE_UNAVAILABLE
+        ]
+    );
+    assert_eq!(requests.load(Ordering::SeqCst), 2);
+
+    Ok(())
+}
+
+/// Defines a service whose route only accepts a single request before rate limiting.
+fn rate_limited_registry(host: &'static str, port: u16) -> ServiceRegistry {
+    let mut service = create_service(host, port, None);
+    service.routes = service
+        .routes
+        .into_iter()
+        .map(|route| Route {
+            rate_limit: Some(RateLimit {
+                requests_per_second: NonZeroU32::MIN,
+                burst: None,
+            }),
+            ..route
+        })
+        .collect();
+
+    let mut service_registry = ServiceRegistry::new();
+    service_registry.define("service", service);
+    add_container(&mut service_registry, "service");
+
+    service_registry
+}
+
+#[tokio::test]
+async fn rate_limited_requests_receive_the_busy_response() -> Result<()> {
+    let host = "opentracker.app";
+    let downstream_addr = spawn_fixed_response_server("Hello World!").await?;
+
+    let SpawnedLoadBalancer { addr, .. } = spawn_configured_load_balancer(
+        rate_limited_registry(host, downstream_addr.port()),
+        |alb| {
+            alb.busy_response = Some(BusyResponse {
+                status: None,
+                body: String::from("We're busy, please try again shortly"),
+                content_type: String::from("text/plain"),
+            });
+        },
+    )
+    .await?;
+
+    let client = Client::builder(TokioExecutor::new()).build_http();
+    let request = || {
+        Request::builder()
+            .uri(format!("http://{}/", addr))
+            .header(HOST, host)
+            .body(Full::<Bytes>::default())
+    };
+
+    assert_eq!(
+        get_response_body(&client, request()?).await?,
+        "Hello World!"
+    );
+
+    let response = client.request(request()?).await?;
+
+    assert_eq!(response.status(), StatusCode::TOO_MANY_REQUESTS);
+    assert_eq!(
+        response.headers().get(RETRY_AFTER),
+        Some(&HeaderValue::from_static("1"))
+    );
+
+    let body = response.into_body().collect().await?.to_bytes();
+
+    assert_eq!(body, "We're busy, please try again shortly");
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn retry_after_values_are_jittered() -> Result<()> {
+    let host = "opentracker.app";
+    let downstream_addr = spawn_fixed_response_server("Hello World!").await?;
+
+    let SpawnedLoadBalancer { addr, .. } = spawn_configured_load_balancer(
+        rate_limited_registry(host, downstream_addr.port()),
+        |alb| alb.retry_after_jitter_secs = 30,
+    )
+    .await?;
+
+    let client = Client::builder(TokioExecutor::new()).build_http();
+    let mut retry_afters = HashSet::new();
+
+    for _ in 0..20 {
+        let request = Request::builder()
+            .uri(format!("http://{}/", addr))
+            .header(HOST, host)
+            .body(Full::<Bytes>::default())?;
+
+        let response = client.request(request).await?;
+
+        if let Some(value) = response.headers().get(RETRY_AFTER) {
+            retry_afters.insert(value.to_str()?.parse::<u64>()?);
+        }
+    }
+
+    assert!(retry_afters.len() > 1);
+    assert!(retry_afters.iter().all(|value| (1..=31).contains(value)));
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn oversized_request_bodies_are_rejected_before_reaching_downstreams() -> Result<()> {
+    let host = "opentracker.app";
+    let (downstream_addr, connections) = spawn_connection_counting_server().await?;
+
+    let mut service = create_service(host, downstream_addr.port(), None);
+    service.routes = service
+        .routes
+        .into_iter()
+        .map(|route| Route {
+            max_body_size: Some(16),
+            ..route
+        })
+        .collect();
+
+    let mut service_registry = ServiceRegistry::new();
+    service_registry.define("service", service);
+    add_container(&mut service_registry, "service");
+
+    let addr = spawn_load_balancer(service_registry).await?;
+    let client = Client::builder(TokioExecutor::new()).build_http();
+
+    let request = Request::builder()
+        .method("POST")
+        .uri(format!("http://{}/", addr))
+        .header(HOST, host)
+        .body(Full::new(Bytes::from(vec![b'a'; 64])))?;
+
+    assert_eq!(
+        client.request(request).await?.status(),
+        StatusCode::PAYLOAD_TOO_LARGE
+    );
+
+    // bodies without a declared length are read up to the limit before being rejected
+    let chunks = vec![Bytes::from(vec![b'a'; 12]); 2];
+    let frames = futures::stream::iter(
+        chunks
+            .into_iter()
+            .map(|chunk| Ok::<_, Infallible>(Frame::data(chunk))),
+    );
+
+    let request = Request::builder()
+        .method("POST")
+        .uri(format!("http://{}/", addr))
+        .header(HOST, host)
+        .body(StreamBody::new(frames))?;
+
+    let chunked_client = Client::builder(TokioExecutor::new()).build_http();
+
+    assert_eq!(
+        chunked_client.request(request).await?.status(),
+        StatusCode::PAYLOAD_TOO_LARGE
+    );
+
+    assert_eq!(connections.load(Ordering::SeqCst), 0);
+
+    let request = Request::builder()
+        .method("POST")
+        .uri(format!("http://{}/", addr))
+        .header(HOST, host)
+        .body(Full::new(Bytes::from_static(b"small")))?;
+
+    assert_eq!(get_response_body(&client, request).await?, "Hello World!");
+
+    Ok(())
+}
+
+/// Reads request bodies frame by frame, reporting how many bytes have arrived so far and responding
+/// with the total.
+async fn spawn_upload_progress_server() -> Result<(SocketAddr, mpsc::UnboundedReceiver<usize>)> {
+    let addr = SocketAddrV4::new(Ipv4Addr::LOCALHOST, 0);
+    let listener = TcpListener::bind(&addr).await?;
+
+    let resolved_addr = listener.local_addr()?;
+    let (sender, receiver) = mpsc::unbounded_channel();
+
+    tokio::spawn(async move {
+        loop {
+            let (stream, _) = listener.accept().await.unwrap();
+            let io = TokioIo::new(stream);
+            let sender = sender.clone();
+
+            tokio::spawn(async move {
+                Builder::new(TokioExecutor::new())
+                    .serve_connection(
+                        io,
+                        service_fn(move |req: Request<Incoming>| {
+                            let sender = sender.clone();
+
+                            async move {
+                                let mut body = req.into_body();
+                                let mut received = 0;
+
+                                while let Some(frame) = body.frame().await {
+                                    if let Ok(data) = frame?.into_data() {
+                                        received += data.len();
+                                        let _ = sender.send(received);
+                                    }
+                                }
+
+                                Ok::<_, hyper::Error>(Response::new(Full::new(Bytes::from(
+                                    received.to_string(),
+                                ))))
+                            }
+                        }),
+                    )
+                    .await
+                    .unwrap();
+            });
+        }
+    });
+
+    Ok((resolved_addr, receiver))
+}
+
+#[tokio::test]
+async fn uploads_beyond_the_buffer_size_are_streamed() -> Result<()> {
+    let host = "opentracker.app";
+    let (downstream_addr, mut progress) = spawn_upload_progress_server().await?;
+
+    let mut service = create_service(host, downstream_addr.port(), None);
+    service.retry = Some(RetryConfig {
+        attempts: 1,
+        on: HashSet::from([RetryCondition::ConnectFailure]),
+    });
+
+    let mut service_registry = ServiceRegistry::new();
+    service_registry.define("service", service);
+    add_container(&mut service_registry, "service");
+
+    let SpawnedLoadBalancer { addr, .. } =
+        spawn_configured_load_balancer(service_registry, |alb| {
+            alb.max_buffered_body_size = 1024;
+        })
+        .await?;
+
+    let (sender, receiver) = mpsc::channel::<Bytes>(1);
+    let frames = Box::pin(futures::stream::unfold(receiver, |mut rx| async {
+        let data = rx.recv().await?;
+        Some((Ok::<_, Infallible>(Frame::data(data)), rx))
+    }));
+
+    let request = Request::builder()
+        .method("POST")
+        .uri(format!("http://{}/", addr))
+        .header(HOST, host)
+        .body(StreamBody::new(frames))?;
+
+    let client = Client::builder(TokioExecutor::new()).build_http();
+    let response = tokio::spawn(async move { client.request(request).await });
+
+    // each chunk is only sent once the last has reached the downstream, so holding on to the whole
+    // body would stall until the timeout
+    let chunk = Bytes::from(vec![b'a'; 64 * 1024]);
+    let mut sent = 0;
+
+    for _ in 0..16 {
+        sender.send(chunk.clone()).await?;
+        sent += chunk.len();
+
+        let poll = async {
+            while progress
+                .recv()
+                .await
+                .is_some_and(|received| received < sent)
+            {}
+        };
+
+        tokio::time::timeout(Duration::from_secs(1), poll).await?;
+    }
+
+    drop(sender);
+
+    let response = response.await??;
+    let received = response.into_body().collect().await?.to_bytes();
+
+    assert_eq!(received, sent.to_string());
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn streamed_uploads_are_cut_off_once_over_the_route_limit() -> Result<()> {
+    let host = "opentracker.app";
+    let (downstream_addr, _progress) = spawn_upload_progress_server().await?;
+
+    let mut service = create_service(host, downstream_addr.port(), None);
+    service.routes = service
+        .routes
+        .into_iter()
+        .map(|route| Route {
+            max_body_size: Some(4096),
+            ..route
+        })
+        .collect();
+
+    let mut service_registry = ServiceRegistry::new();
+    service_registry.define("service", service);
+    add_container(&mut service_registry, "service");
+
+    let SpawnedLoadBalancer { addr, .. } =
+        spawn_configured_load_balancer(service_registry, |alb| {
+            alb.max_buffered_body_size = 1024;
+        })
+        .await?;
+
+    let chunks = vec![Bytes::from(vec![b'a'; 2048]); 3];
+    let frames = futures::stream::iter(
+        chunks
+            .into_iter()
+            .map(|chunk| Ok::<_, Infallible>(Frame::data(chunk))),
+    );
+
+    let request = Request::builder()
+        .method("POST")
+        .uri(format!("http://{}/", addr))
+        .header(HOST, host)
+        .body(StreamBody::new(frames))?;
+
+    let client = Client::builder(TokioExecutor::new()).build_http();
+
+    assert_eq!(
+        client.request(request).await?.status(),
+        StatusCode::PAYLOAD_TOO_LARGE
+    );
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn responses_are_compressed_for_clients_that_accept_it() -> Result<()> {
+    let host = "opentracker.app";
+    let text = "Hello World! ".repeat(200);
+
+    let addr = SocketAddrV4::new(Ipv4Addr::LOCALHOST, 0);
+    let listener = TcpListener::bind(&addr).await?;
+    let downstream_addr = listener.local_addr()?;
+
+    tokio::spawn({
+        let text = text.clone();
+
+        async move {
+            loop {
+                let (stream, _) = listener.accept().await.unwrap();
+                let io = TokioIo::new(stream);
+                let text = text.clone();
+
+                tokio::spawn(async move {
+                    let service = service_fn(move |_| {
+                        let response = Response::builder()
+                            .header(CONTENT_TYPE, "text/plain")
+                            .body(Full::<Bytes>::from(text.clone()));
+
+                        async move { response }
+                    });
+
+                    let _ = Builder::new(TokioExecutor::new())
+                        .serve_connection(io, service)
+                        .await;
+                });
+            }
+        }
+    });
+
+    let mut service = create_service(host, downstream_addr.port(), None);
+    service.compression = Some(CompressionConfig { min_size: 1024 });
+
+    let mut service_registry = ServiceRegistry::new();
+    service_registry.define("service", service);
+    add_container(&mut service_registry, "service");
+
+    let addr = spawn_load_balancer(service_registry).await?;
+    let client = Client::builder(TokioExecutor::new()).build_http();
+
+    let request = Request::builder()
+        .uri(format!("http://{}/", addr))
+        .header(HOST, host)
+        .header(ACCEPT_ENCODING, "deflate")
+        .body(Full::<Bytes>::default())?;
+
+    let response = client.request(request).await?;
+
+    assert_eq!(
+        response.headers().get(CONTENT_ENCODING),
+        Some(&HeaderValue::from_static("deflate"))
+    );
+
+    let body = response.into_body().collect().await?.to_bytes();
+    let decompressed = miniz_oxide::inflate::decompress_to_vec_zlib(&body)
+        .map_err(|e| eyre!("failed to decompress the response: {e:?}"))?;
+
+    assert!(body.len() < text.len());
+    assert_eq!(decompressed, text.as_bytes());
+
+    // clients that don't ask for compression receive the response untouched
+    let request = Request::builder()
+        .uri(format!("http://{}/", addr))
+        .header(HOST, host)
+        .body(Full::<Bytes>::default())?;
+
+    assert_eq!(get_response_body(&client, request).await?, text);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn routes_can_rewrite_request_and_response_headers() -> Result<()> {
+    let host = "opentracker.app";
+    let downstream_addr = spawn_header_echo_server("X-Tenant").await?;
+
+    let rules = HeaderRules {
+        request: HeaderRewrite {
+            set: BTreeMap::from([(String::from("X-Tenant"), String::from("acme"))]),
+            ..Default::default()
+        },
+        response: HeaderRewrite {
+            set: BTreeMap::from([(String::from("Cache-Control"), String::from("max-age=3600"))]),
+            ..Default::default()
+        },
+    };
+
+    let mut service = create_service(host, downstream_addr.port(), None);
+    service.routes = service
+        .routes
+        .into_iter()
+        .map(|route| Route {
+            headers: Some(rules.clone()),
+            ..route
+        })
+        .collect();
+
+    let mut service_registry = ServiceRegistry::new();
+    service_registry.define("service", service);
+    add_container(&mut service_registry, "service");
+
+    let addr = spawn_load_balancer(service_registry).await?;
+    let client = Client::builder(TokioExecutor::new()).build_http();
+
+    let request = Request::builder()
+        .uri(format!("http://{}/", addr))
+        .header(HOST, host)
+        .header("X-Tenant", "someone-else")
+        .body(Full::<Bytes>::default())?;
+
+    let response = client.request(request).await?;
+
+    assert_eq!(response.headers()[CACHE_CONTROL], "max-age=3600");
+
+    let received = response.into_body().collect().await?.to_bytes();
+    assert_eq!(received, "acme");
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn plain_http_requests_can_be_redirected_to_https() -> Result<()> {
+    let redirected = "opentracker.app";
+    let proxied = "blackboards.pl";
+
+    let downstream_addr = spawn_fixed_response_server("Hello World!").await?;
+
+    let mut service_registry = ServiceRegistry::new();
+    service_registry.define(
+        "service",
+        create_service(proxied, downstream_addr.port(), None),
+    );
+    add_container(&mut service_registry, "service");
+
+    let secrets = || {
+        TlsSecrets::new(
+            ExternalBytes::Filesystem {
+                path: "cert.pem".into(),
+            },
+            ExternalBytes::Filesystem {
+                path: "key.pem".into(),
+            },
+        )
+    };
+
+    let mut redirecting = secrets();
+    redirecting.redirect_http = true;
+
+    let SpawnedLoadBalancer { addr, .. } =
+        spawn_configured_load_balancer(service_registry, |alb| {
+            alb.ports.insert(Scheme::Https, 8443);
+            alb.tls = Some(TlsConfig {
+                domains: HashMap::from([
+                    (String::from(redirected), redirecting),
+                    (String::from(proxied), secrets()),
+                ]),
+                default_certificate: None,
+                acme: None,
+                poll_interval_secs: 300,
+                min_version: None,
+                max_version: None,
+                cipher_suites: None,
+                expiry_warning_days: 14,
+                reject_expired_certificates: false,
+                self_signed: false,
+            });
+        })
+        .await?;
+
+    let client = Client::builder(TokioExecutor::new()).build_http();
+
+    let request = Request::builder()
+        .uri(format!("http://{}/login?next=%2F", addr))
+        .header(HOST, format!("{redirected}:{}", addr.port()))
+        .body(Full::<Bytes>::default())?;
+
+    let response = client.request(request).await?;
+
+    assert_eq!(response.status(), StatusCode::MOVED_PERMANENTLY);
+    assert_eq!(
+        response.headers()[LOCATION],
+        "https://opentracker.app:8443/login?next=%2F"
+    );
+
+    // domains without the option are still proxied as normal
+    let request = Request::builder()
+        .uri(format!("http://{}/", addr))
+        .header(HOST, proxied)
+        .body(Full::<Bytes>::default())?;
+
+    let response = client.request(request).await?;
+
+    assert_eq!(response.status(), StatusCode::OK);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn https_only_routes_are_not_served_over_plain_http() -> Result<()> {
+    let host = "opentracker.app";
+
+    let downstream_addr = spawn_fixed_response_server("Hello World!").await?;
+
+    let route = |prefix: &str, plain_http: Option<PlainHttpAction>| Route {
+        host: String::from(host),
+        prefix: Some(String::from(prefix)),
+        port: downstream_addr.port(),
+        https_only: plain_http.map(|plain_http| HttpsOnly {
+            plain_http,
+            hsts: None,
+        }),
+        ..Default::default()
+    };
+
+    let service = Service {
+        routes: HashSet::from([
+            route("/", None),
+            route("/login", Some(PlainHttpAction::Redirect)),
+            route("/admin", Some(PlainHttpAction::Refuse)),
+        ]),
+        ..Default::default()
+    };
+
+    let mut service_registry = ServiceRegistry::new();
+    service_registry.define("service", service);
+    add_container(&mut service_registry, "service");
+
+    let addr = spawn_load_balancer(service_registry).await?;
+    let client = Client::builder(TokioExecutor::new()).build_http();
+
+    let get = |path: &str| {
+        Request::builder()
+            .uri(format!("http://{addr}{path}"))
+            .header(HOST, host)
+            .body(Full::<Bytes>::default())
+    };
+
+    let response = client.request(get("/login?next=%2F")?).await?;
+
+    assert_eq!(response.status(), StatusCode::MOVED_PERMANENTLY);
+    assert_eq!(
+        response.headers()[LOCATION],
+        "https://opentracker.app/login?next=%2F"
+    );
+
+    let response = client.request(get("/admin")?).await?;
+    assert_eq!(response.status(), StatusCode::FORBIDDEN);
+
+    let response = client.request(get("/about")?).await?;
+    assert_eq!(response.status(), StatusCode::OK);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn hosts_can_configure_error_pages() -> Result<()> {
+    let host = "opentracker.app";
+    let unknown = "blackboards.pl";
+
+    // nothing is listening on the downstream port, so proxying to it fails
+    let unused = TcpListener::bind(SocketAddrV4::new(Ipv4Addr::LOCALHOST, 0)).await?;
+    let downstream_port = unused.local_addr()?.port();
+    drop(unused);
+
+    let mut service_registry = ServiceRegistry::new();
+    service_registry.define("service", create_service(host, downstream_port, None));
+    add_container(&mut service_registry, "service");
+
+    let page = |body: &str| ErrorPage {
+        source: ErrorPageSource::Inline {
+            body: String::from(body),
+        },
+        content_type: String::from("text/html"),
+    };
+
+    let SpawnedLoadBalancer { addr, .. } =
+        spawn_configured_load_balancer(service_registry, |alb| {
+            alb.error_pages = HashMap::from([
+                (
+                    String::from(host),
+                    HashMap::from([(502, page("<h1>Oops</h1>"))]),
+                ),
+                (
+                    String::from(unknown),
+                    HashMap::from([(404, page("<h1>Not Found</h1>"))]),
+                ),
+            ]);
+        })
+        .await?;
+
+    let client = Client::builder(TokioExecutor::new()).build_http();
+
+    for (host, status, body) in [
+        (unknown, StatusCode::NOT_FOUND, "<h1>Not Found</h1>"),
+        (host, StatusCode::BAD_GATEWAY, "<h1>Oops</h1>"),
+    ] {
+        let request = Request::builder()
+            .uri(format!("http://{}/", addr))
+            .header(HOST, host)
+            .body(Full::<Bytes>::default())?;
+
+        let response = client.request(request).await?;
+
+        assert_eq!(response.status(), status);
+        assert_eq!(response.headers()[CONTENT_TYPE], "text/html");
+        assert_eq!(response.into_body().collect().await?.to_bytes(), body);
+    }
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn upstream_failures_are_described_to_clients() -> Result<()> {
+    let host = "opentracker.app";
+
+    let unused = TcpListener::bind(SocketAddrV4::new(Ipv4Addr::LOCALHOST, 0)).await?;
+    let downstream_port = unused.local_addr()?.port();
+    drop(unused);
+
+    let mut service_registry = ServiceRegistry::new();
+    service_registry.define("service", create_service(host, downstream_port, None));
+    add_container(&mut service_registry, "service");
+
+    let addr = spawn_load_balancer(service_registry).await?;
+    let client = Client::builder(TokioExecutor::new()).build_http();
+
+    let request = Request::builder()
+        .uri(format!("http://{}/", addr))
+        .header(HOST, host)
+        .header(ACCEPT, "application/json")
+        .body(Full::<Bytes>::default())?;
+
+    let response = client.request(request).await?;
+
+    assert_eq!(response.status(), StatusCode::BAD_GATEWAY);
+    assert_eq!(response.headers()[CONTENT_TYPE], "application/json");
+
+    let body = response.into_body().collect().await?.to_bytes();
+    let body: serde_json::Value = serde_json::from_slice(&body)?;
+
+    assert_eq!(body["code"], "upstream_connection_refused");
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn cors_preflights_are_answered_by_the_load_balancer() -> Result<()> {
+    let host = "opentracker.app";
+    let origin = "https://app.opentracker.app";
+    let (downstream_addr, connections) = spawn_connection_counting_server().await?;
+
+    let mut service = create_service(host, downstream_addr.port(), None);
+    service.routes = service
+        .routes
+        .into_iter()
+        .map(|route| Route {
+            cors: Some(CorsConfig {
+                allowed_origins: vec![String::from(origin)],
+                ..Default::default()
+            }),
+            ..route
+        })
+        .collect();
+
+    let mut service_registry = ServiceRegistry::new();
+    service_registry.define("service", service);
+    add_container(&mut service_registry, "service");
+
+    let addr = spawn_load_balancer(service_registry).await?;
+    let client = Client::builder(TokioExecutor::new()).build_http();
+
+    let request = Request::builder()
+        .method("OPTIONS")
+        .uri(format!("http://{}/", addr))
+        .header(HOST, host)
+        .header("Origin", origin)
+        .header("Access-Control-Request-Method", "PUT")
+        .body(Full::<Bytes>::default())?;
+
+    let response = client.request(request).await?;
+
+    assert_eq!(response.status(), StatusCode::NO_CONTENT);
+    assert_eq!(response.headers()["Access-Control-Allow-Origin"], origin);
+    assert_eq!(response.headers()["Access-Control-Allow-Methods"], "PUT");
+    assert_eq!(connections.load(Ordering::SeqCst), 0);
+
+    let request = Request::builder()
+        .uri(format!("http://{}/", addr))
+        .header(HOST, host)
+        .header("Origin", origin)
+        .body(Full::<Bytes>::default())?;
+
+    let response = client.request(request).await?;
+
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(response.headers()["Access-Control-Allow-Origin"], origin);
+    assert_eq!(connections.load(Ordering::SeqCst), 1);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn trace_context_is_propagated_to_downstreams() -> Result<()> {
+    let host = "opentracker.app";
+    let trace_id = "4bf92f3577b34da6a3ce929d0e0e4736";
+
+    let downstream_addr = spawn_header_echo_server("traceparent").await?;
+
+    let mut service_registry = ServiceRegistry::new();
+    service_registry.define(
+        "service",
+        create_service(host, downstream_addr.port(), None),
+    );
+    add_container(&mut service_registry, "service");
+
+    let SpawnedLoadBalancer { addr, .. } =
+        spawn_configured_load_balancer(service_registry, |alb| {
+            alb.otlp = Some(OtlpConfig {
+                endpoint: String::from("http://localhost:4318"),
+                service_name: String::from("f2"),
+            });
+        })
+        .await?;
+
+    let client = Client::builder(TokioExecutor::new()).build_http();
+
+    let request = Request::builder()
+        .uri(format!("http://{}/", addr))
+        .header(HOST, host)
+        .header("traceparent", format!("00-{trace_id}-00f067aa0ba902b7-01"))
+        .body(Full::<Bytes>::default())?;
+
+    let response = client.request(request).await?;
+    let received = response.into_body().collect().await?.to_bytes();
+    let received = std::str::from_utf8(&received)?;
+
+    // the downstream continues the same trace, with the load balancer's span as its parent
+    let parts: Vec<_> = received.split('-').collect();
+
+    assert_eq!(parts.len(), 4);
+    assert_eq!(parts[1], trace_id);
+    assert_ne!(parts[2], "00f067aa0ba902b7");
+    assert_eq!(parts[3], "01");
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn services_can_be_put_into_maintenance_mode() -> Result<()> {
+    let host = "opentracker.app";
+    let (downstream_addr, connections) = spawn_connection_counting_server().await?;
+
+    let mut service_registry = ServiceRegistry::new();
+    service_registry.define(
+        "service",
+        create_service(host, downstream_addr.port(), None),
+    );
+    add_container(&mut service_registry, "service");
+
+    let SpawnedLoadBalancer { addr, .. } =
+        spawn_configured_load_balancer(service_registry, |alb| {
+            alb.maintenance = Some(String::from("/maintenance"));
+            alb.maintenance_response = Some(BusyResponse {
+                status: None,
+                body: String::from("Back soon"),
+                content_type: String::from("text/plain"),
+            });
+        })
+        .await?;
+
+    let client = Client::builder(TokioExecutor::new()).build_http();
+
+    let set_maintenance = |enabled: bool| {
+        Request::builder()
+            .method("PUT")
+            .uri(format!(
+                "http://{}/maintenance?service=service&enabled={enabled}",
+                addr
+            ))
+            .body(Full::<Bytes>::default())
+    };
+
+    let request = || {
+        Request::builder()
+            .uri(format!("http://{}/", addr))
+            .header(HOST, host)
+            .body(Full::<Bytes>::default())
+    };
+
+    let response = client.request(set_maintenance(true)?).await?;
+    assert_eq!(response.status(), StatusCode::OK);
+
+    let response = client.request(request()?).await?;
+
+    assert_eq!(response.status(), StatusCode::SERVICE_UNAVAILABLE);
+    assert_eq!(
+        response.into_body().collect().await?.to_bytes(),
+        "Back soon"
+    );
+    assert_eq!(connections.load(Ordering::SeqCst), 0);
+
+    client.request(set_maintenance(false)?).await?;
+
+    let response = client.request(request()?).await?;
+
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(connections.load(Ordering::SeqCst), 1);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn maintenance_mode_cannot_be_set_unless_a_path_is_configured() -> Result<()> {
+    let host = "opentracker.app";
+    let (downstream_addr, connections) = spawn_connection_counting_server().await?;
+
+    let mut service_registry = ServiceRegistry::new();
+    service_registry.define(
+        "service",
+        create_service(host, downstream_addr.port(), None),
+    );
+    add_container(&mut service_registry, "service");
+
+    let addr = spawn_load_balancer(service_registry).await?;
+    let client = Client::builder(TokioExecutor::new()).build_http();
+
+    let request = Request::builder()
+        .method("PUT")
+        .uri(format!(
+            "http://{}/maintenance?service=service&enabled=true",
+            addr
+        ))
+        .body(Full::<Bytes>::default())?;
+
+    let response = client.request(request).await?;
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+
+    let request = Request::builder()
+        .uri(format!("http://{}/", addr))
+        .header(HOST, host)
+        .body(Full::<Bytes>::default())?;
+
+    let response = client.request(request).await?;
+
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(connections.load(Ordering::SeqCst), 1);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn container_logs_are_only_served_when_enabled() -> Result<()> {
+    let downstream_addr = spawn_fixed_response_server("Hello from the backend").await?;
+
+    let spawn = |allow: Option<&'static str>| {
+        let mut service_registry = ServiceRegistry::new();
+        service_registry.define(
+            "backend",
+            create_service("opentracker.app", downstream_addr.port(), None),
+        );
+        add_container(&mut service_registry, "backend");
+
+        spawn_configured_load_balancer(service_registry, move |alb| {
+            alb.container_logs = allow.map(|allow| ContainerLogsConfig {
+                path: String::from("/services"),
+                access: AccessControl {
+                    allow: vec![IpNetwork::try_from(allow.to_owned()).unwrap()],
+                    deny: Vec::new(),
+                },
+            });
+        })
+    };
+
+    let client = Client::builder(TokioExecutor::new()).build_http();
+    let request = |addr: SocketAddr, service: &str| {
+        Request::builder()
+            .uri(format!("http://{addr}/services/{service}/logs?follow=true"))
+            .header(HOST, "opentracker.app")
+            .body(Full::<Bytes>::default())
+    };
+
+    let spawned = spawn(Some("127.0.0.0/8")).await?;
+
+    spawned
+        .docker_client
+        .add_logs(
+            &ContainerId(String::from("6cd915f16ab3")),
+            vec![LogFrame {
+                stream: OutputStream::Stdout,
+                data: Bytes::from("listening on port 5000\n"),
+            }],
+        )
+        .await;
+
+    let response = client.request(request(spawned.addr, "backend")?).await?;
+
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(
+        response.into_body().collect().await?.to_bytes(),
+        "6cd915f16ab3 stdout listening on port 5000\n"
+    );
+
+    let response = client.request(request(spawned.addr, "frontend")?).await?;
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+
+    // Without the endpoint enabled, the request is proxied like any other
+    let spawned = spawn(None).await?;
+    let body = get_response_body(&client, request(spawned.addr, "backend")?).await?;
+
+    assert_eq!(body, "Hello from the backend");
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn container_logs_are_refused_outside_of_allowed_networks() -> Result<()> {
+    let downstream_addr = spawn_fixed_response_server("Hello from the backend").await?;
+
+    let mut service_registry = ServiceRegistry::new();
+    service_registry.define(
+        "backend",
+        create_service("opentracker.app", downstream_addr.port(), None),
+    );
+    add_container(&mut service_registry, "backend");
+
+    let spawned = spawn_configured_load_balancer(service_registry, |alb| {
+        alb.container_logs = Some(ContainerLogsConfig {
+            path: String::from("/services"),
+            access: AccessControl {
+                allow: vec![IpNetwork::try_from(String::from("10.0.0.0/8")).unwrap()],
+                deny: Vec::new(),
+            },
+        });
+    })
+    .await?;
+
+    spawned
+        .docker_client
+        .add_logs(
+            &ContainerId(String::from("6cd915f16ab3")),
+            vec![LogFrame {
+                stream: OutputStream::Stdout,
+                data: Bytes::from("listening on port 5000\n"),
+            }],
+        )
+        .await;
+
+    let client = Client::builder(TokioExecutor::new()).build_http();
+    let request = Request::builder()
+        .uri(format!("http://{}/services/backend/logs", spawned.addr))
+        .header(HOST, "opentracker.app")
+        .body(Full::<Bytes>::default())?;
+
+    let response = client.request(request).await?;
+
+    assert_eq!(response.status(), StatusCode::FORBIDDEN);
+    assert!(response.into_body().collect().await?.to_bytes().is_empty());
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn static_routes_are_served_without_containers() -> Result<()> {
+    let host = "opentracker.app";
+    let dir = tempfile::tempdir()?;
+
+    tokio::fs::write(dir.path().join("index.html"), "<h1>Home</h1>").await?;
+
+    let root = dir.path().to_owned();
+
+    let SpawnedLoadBalancer { addr, .. } =
+        spawn_configured_load_balancer(ServiceRegistry::new(), |alb| {
+            alb.static_routes = vec![StaticRoute {
+                host: String::from(host),
+                prefix: None,
+                source: StaticSource::Filesystem { path: root },
+                index: String::from("index.html"),
+                spa_fallback: true,
+                cache_control: None,
+            }];
+        })
+        .await?;
+
+    let client = Client::builder(TokioExecutor::new()).build_http();
+
+    let request = Request::builder()
+        .uri(format!("http://{}/dashboard/settings", addr))
+        .header(HOST, host)
+        .body(Full::<Bytes>::default())?;
+
+    let response = client.request(request).await?;
+
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(
+        response.into_body().collect().await?.to_bytes(),
+        "<h1>Home</h1>"
+    );
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn tcp_connections_are_streamed_to_containers() -> Result<()> {
+    let downstream = TcpListener::bind(SocketAddrV4::new(Ipv4Addr::LOCALHOST, 0)).await?;
+    let downstream_port = downstream.local_addr()?.port();
+
+    // echoes everything it receives back to the client
+    tokio::spawn(async move {
+        loop {
+            let (mut stream, _) = downstream.accept().await.unwrap();
+
+            tokio::spawn(async move {
+                let (mut reader, mut writer) = stream.split();
+                let _ = tokio::io::copy(&mut reader, &mut writer).await;
+            });
+        }
+    });
+
+    let unused = TcpListener::bind(SocketAddrV4::new(Ipv4Addr::LOCALHOST, 0)).await?;
+    let listen = unused.local_addr()?.port();
+    drop(unused);
+
+    let service = Service {
+        tcp_routes: HashSet::from([PortRoute {
+            listen,
+            port: downstream_port,
+        }]),
+        ..Default::default()
+    };
+
+    let mut service_registry = ServiceRegistry::new();
+    service_registry.define("database", service);
+    add_container(&mut service_registry, "database");
+
+    let _load_balancer = spawn_load_balancer(service_registry).await?;
+
+    // the listener is bound once the load balancer starts running
+    let connect = async {
+        loop {
+            match TcpStream::connect(SocketAddrV4::new(Ipv4Addr::LOCALHOST, listen)).await {
+                Ok(stream) => break stream,
+                Err(_) => tokio::time::sleep(Duration::from_millis(10)).await,
+            }
+        }
+    };
+
+    let mut stream = tokio::time::timeout(Duration::from_secs(5), connect).await?;
+
+    stream.write_all(b"PING").await?;
+
+    let mut buffer = [0; 4];
+    stream.read_exact(&mut buffer).await?;
+
+    assert_eq!(&buffer, b"PING");
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn udp_datagrams_are_forwarded_to_containers() -> Result<()> {
+    let downstream = UdpSocket::bind(SocketAddrV4::new(Ipv4Addr::LOCALHOST, 0)).await?;
+    let downstream_port = downstream.local_addr()?.port();
+
+    // echoes every datagram back to its sender
+    tokio::spawn(async move {
+        let mut buffer = [0; 1024];
+
+        loop {
+            let (len, peer) = downstream.recv_from(&mut buffer).await.unwrap();
+            downstream.send_to(&buffer[..len], peer).await.unwrap();
+        }
+    });
+
+    let unused = UdpSocket::bind(SocketAddrV4::new(Ipv4Addr::LOCALHOST, 0)).await?;
+    let listen = unused.local_addr()?.port();
+    drop(unused);
+
+    let service = Service {
+        udp_routes: HashSet::from([PortRoute {
+            listen,
+            port: downstream_port,
+        }]),
+        ..Default::default()
+    };
+
+    let mut service_registry = ServiceRegistry::new();
+    service_registry.define("dns", service);
+    add_container(&mut service_registry, "dns");
+
+    let _load_balancer = spawn_load_balancer(service_registry).await?;
+
+    let client = UdpSocket::bind(SocketAddrV4::new(Ipv4Addr::LOCALHOST, 0)).await?;
+    client
+        .connect(SocketAddrV4::new(Ipv4Addr::LOCALHOST, listen))
+        .await?;
+
+    // datagrams sent before the socket is bound are lost, so keep asking until one is answered
+    let exchange = async {
+        let mut buffer = [0; 1024];
+
+        loop {
+            client.send(b"PING").await?;
+
+            match tokio::time::timeout(Duration::from_millis(100), client.recv(&mut buffer)).await {
+                Ok(Ok(len)) => break Ok::<_, std::io::Error>(buffer[..len].to_vec()),
+                _ => continue,
+            }
+        }
+    };
+
+    let reply = tokio::time::timeout(Duration::from_secs(5), exchange).await??;
+
+    assert_eq!(reply, b"PING");
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn clients_outside_of_allowed_networks_are_forbidden() -> Result<()> {
+    let downstream_addr = spawn_fixed_response_server("Hello from OpenTracker").await?;
+
+    let access = |allow: &str, deny: &str| -> Result<AccessControl> {
+        Ok(AccessControl {
+            allow: vec![IpNetwork::try_from(allow.to_owned())?],
+            deny: vec![IpNetwork::try_from(deny.to_owned())?],
+        })
+    };
+
+    let routes = HashSet::from([
+        Route {
+            host: String::from("opentracker.app"),
+            port: downstream_addr.port(),
+            access: Some(access("127.0.0.0/8", "10.0.0.0/8")?),
+            ..Default::default()
+        },
+        Route {
+            host: String::from("admin.opentracker.app"),
+            port: downstream_addr.port(),
+            access: Some(access("10.0.0.0/8", "192.168.0.0/16")?),
+            ..Default::default()
+        },
+        Route {
+            host: String::from("internal.opentracker.app"),
+            port: downstream_addr.port(),
+            access: Some(access("0.0.0.0/0", "127.0.0.1/32")?),
+            ..Default::default()
+        },
+    ]);
+
+    let mut service_registry = ServiceRegistry::new();
+    service_registry.define(
+        "opentracker",
+        Service {
+            routes,
+            ..Default::default()
+        },
+    );
+    add_container(&mut service_registry, "opentracker");
+
+    let addr = spawn_load_balancer(service_registry).await?;
+    let client = Client::builder(TokioExecutor::new()).build_http();
+
+    let status = |host: &'static str| {
+        let client = client.clone();
+
+        async move {
+            let request = Request::builder()
+                .uri(format!("http://{addr}/"))
+                .header(HOST, host)
+                .body(Full::<Bytes>::default())?;
+
+            Ok::<_, color_eyre::Report>(client.request(request).await?.status())
+        }
+    };
+
+    assert_eq!(status("opentracker.app").await?, StatusCode::OK);
+    assert_eq!(
+        status("admin.opentracker.app").await?,
+        StatusCode::FORBIDDEN
+    );
+    assert_eq!(
+        status("internal.opentracker.app").await?,
+        StatusCode::FORBIDDEN
+    );
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn clients_without_authorized_certificates_are_forbidden() -> Result<()> {
+    let host = "opentracker.app";
+    let downstream_addr = spawn_fixed_response_server("Hello from OpenTracker").await?;
+
+    let mut service = create_service(host, downstream_addr.port(), None);
+    service.routes = service
+        .routes
+        .into_iter()
+        .map(|route| Route {
+            client_certificates: Some(ClientCertificateRules {
+                common_names: vec![String::from("billing-service")],
+            }),
+            ..route
+        })
+        .collect();
+
+    let mut service_registry = ServiceRegistry::new();
+    service_registry.define("opentracker", service);
+    add_container(&mut service_registry, "opentracker");
+
+    let addr = spawn_load_balancer(service_registry).await?;
+    let client = Client::builder(TokioExecutor::new()).build_http();
+
+    // Plain HTTP connections never carry a verified certificate, whatever headers are sent
+    let request = Request::builder()
+        .uri(format!("http://{addr}/"))
+        .header(HOST, host)
+        .header("X-Client-Cert-CN", "billing-service")
+        .body(Full::<Bytes>::default())?;
+
+    let response = client.request(request).await?;
+
+    assert_eq!(response.status(), StatusCode::FORBIDDEN);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn requests_are_mirrored_to_shadow_services() -> Result<()> {
+    let host = "opentracker.app";
+    let downstream_addr = spawn_fixed_response_server("Hello from OpenTracker").await?;
+    let (shadow_addr, shadow_connections) = spawn_connection_counting_server().await?;
+
+    let mut service = create_service(host, downstream_addr.port(), None);
+    service.routes = service
+        .routes
+        .into_iter()
+        .map(|route| Route {
+            mirror: Some(Mirror {
+                service: String::from("shadow"),
+                port: shadow_addr.port(),
+                percentage: 100,
+            }),
+            ..route
+        })
+        .collect();
+
+    let mut service_registry = ServiceRegistry::new();
+    service_registry.define("opentracker", service);
+    service_registry.define("shadow", Service::default());
+    add_container(&mut service_registry, "opentracker");
+    add_container(&mut service_registry, "shadow");
+
+    let addr = spawn_load_balancer(service_registry).await?;
+    let client = Client::builder(TokioExecutor::new()).build_http();
+
+    let request = Request::builder()
+        .method("POST")
+        .uri(format!("http://{addr}/"))
+        .header(HOST, host)
+        .body(Full::<Bytes>::from("payload"))?;
+
+    let response = client.request(request).await?;
+
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(
+        response.into_body().collect().await?.to_bytes(),
+        "Hello from OpenTracker"
+    );
+
+    // the copy is sent in the background, so may arrive after the response
+    let mirrored = async {
+        while shadow_connections.load(Ordering::SeqCst) == 0 {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+    };
+
+    tokio::time::timeout(Duration::from_secs(5), mirrored).await?;
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn split_routes_send_requests_to_the_other_service() -> Result<()> {
+    let host = "opentracker.app";
+    let stable_addr = spawn_fixed_response_server("Hello from the stable service").await?;
+    let canary_addr = spawn_fixed_response_server("Hello from the canary").await?;
+
+    let mut service = create_service(host, stable_addr.port(), None);
+    service.routes = service
+        .routes
+        .into_iter()
+        .map(|route| Route {
+            split: Some(TrafficSplit {
+                service: String::from("canary"),
+                percentage: 100,
+            }),
+            ..route
+        })
+        .collect();
+
+    // the canary listens on a port of its own, so must be sent requests on that port
+    let mut service_registry = ServiceRegistry::new();
+    service_registry.define("stable", service);
+    service_registry.define(
+        "canary",
+        create_service("canary.opentracker.app", canary_addr.port(), None),
+    );
+    add_container(&mut service_registry, "stable");
+    add_container(&mut service_registry, "canary");
+
+    let addr = spawn_load_balancer(service_registry).await?;
+    let client = Client::builder(TokioExecutor::new()).build_http();
+
+    let request = Request::builder()
+        .uri(format!("http://{addr}/"))
+        .header(HOST, host)
+        .body(Full::<Bytes>::default())?;
+
+    let response = client.request(request).await?;
+
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(
+        response.into_body().collect().await?.to_bytes(),
+        "Hello from the canary"
+    );
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn alternate_services_are_reached_on_their_own_port() -> Result<()> {
+    let host = "opentracker.app";
+    let stable_addr = spawn_fixed_response_server("Hello from the stable service").await?;
+    let beta_addr = spawn_fixed_response_server("Hello from the beta").await?;
+
+    let service = Service {
+        routes: HashSet::from([Route {
+            host: String::from(host),
+            port: stable_addr.port(),
+            alternates: vec![
+                AlternateService {
+                    service: String::from("beta"),
+                    when: RequestCondition::Header {
+                        name: String::from("X-Beta"),
+                        value: String::from("true"),
+                    },
+                },
+                AlternateService {
+                    service: String::from("beta"),
+                    when: RequestCondition::Cookie {
+                        name: String::from("beta"),
+                        value: String::from("true"),
+                    },
+                },
+            ],
+            ..Default::default()
+        }]),
+        ..Default::default()
+    };
+
+    let mut service_registry = ServiceRegistry::new();
+    service_registry.define("stable", service);
+    service_registry.define(
+        "beta",
+        create_service("beta.opentracker.app", beta_addr.port(), None),
+    );
+    add_container(&mut service_registry, "stable");
+    add_container(&mut service_registry, "beta");
+
+    let addr = spawn_load_balancer(service_registry).await?;
+    let client = Client::builder(TokioExecutor::new()).build_http();
+
+    let request = |header: Option<(&'static str, &'static str)>| {
+        let builder = Request::builder()
+            .uri(format!("http://{addr}/"))
+            .header(HOST, host);
+
+        match header {
+            Some((name, value)) => builder.header(name, value),
+            None => builder,
+        }
+        .body(Full::<Bytes>::default())
+    };
+
+    let by_header = request(Some(("X-Beta", "true")))?;
+    let by_cookie = request(Some(("Cookie", "theme=dark; beta=true")))?;
+
+    assert_eq!(
+        get_response_body(&client, by_header).await?,
+        "Hello from the beta"
+    );
+    assert_eq!(
+        get_response_body(&client, by_cookie).await?,
+        "Hello from the beta"
+    );
+    assert_eq!(
+        get_response_body(&client, request(None)?).await?,
+        "Hello from the stable service"
+    );
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn variant_headers_pin_requests_to_known_variants() -> Result<()> {
+    let host = "opentracker.app";
+    let canary_addr = spawn_fixed_response_server("Hello from the canary").await?;
+
+    let service = Service {
+        routes: HashSet::from([Route {
+            host: String::from(host),
+            port: canary_addr.port(),
+            split: Some(TrafficSplit {
+                service: String::from("canary"),
+                percentage: 0,
+            }),
+            ..Default::default()
+        }]),
+        variant_header: Some(VariantHeader {
+            name: String::from("X-F2-Variant"),
+            trusted: Vec::new(),
+        }),
+        ..Default::default()
+    };
+
+    // the stable service has no containers listening, so only the canary can respond
+    let mut service_registry = ServiceRegistry::new();
+    service_registry.define("stable", service);
+    service_registry.define(
+        "canary",
+        create_service("canary.opentracker.app", canary_addr.port(), None),
+    );
+    service_registry.add_container(
+        "stable",
+        StartedContainerDetails {
+            id: ContainerId(String::from("b4f3c2d1e0a9")),
+            addr: Ipv4Addr::new(127, 0, 0, 2).into(),
+        },
+    );
+    add_container(&mut service_registry, "canary");
+
+    let addr = spawn_load_balancer(service_registry).await?;
+    let client = Client::builder(TokioExecutor::new()).build_http();
+
+    let request = |variant: &'static str| {
+        Request::builder()
+            .uri(format!("http://{addr}/"))
+            .header(HOST, host)
+            .header("X-F2-Variant", variant)
+            .body(Full::<Bytes>::default())
+    };
+
+    let response = client.request(request("canary")?).await?;
+
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(
+        response.into_body().collect().await?.to_bytes(),
+        "Hello from the canary"
+    );
+
+    let response = client.request(request("missing")?).await?;
+
+    assert_eq!(response.status(), StatusCode::BAD_GATEWAY);
+
+    Ok(())
+}
@@ -1,18 +1,42 @@
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap, HashSet};
 use std::io::Cursor;
-use std::sync::Arc;
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
 
 use arc_swap::ArcSwap;
+use chrono::{TimeDelta, Utc};
 use color_eyre::eyre::{eyre, Result};
 use itertools::Itertools;
 use mutual_tls::{AuthenticationLevel, AuthenticationLevelResolver};
+use rustls::client::danger::HandshakeSignatureValid;
+use rustls::crypto::ring::default_provider;
 use rustls::crypto::ring::sign::any_supported_type;
-use rustls::pki_types::PrivateKeyDer;
-use rustls::server::{ClientHello, ResolvesServerCert};
+use rustls::crypto::CryptoProvider;
+use rustls::pki_types::{CertificateDer, CertificateRevocationListDer, PrivateKeyDer, UnixTime};
+use rustls::server::danger::{ClientCertVerified, ClientCertVerifier};
+use rustls::server::{ClientHello, ResolvesServerCert, WebPkiClientVerifier};
 use rustls::sign::CertifiedKey;
+use rustls::{
+    CertificateError, DigitallySignedStruct, DistinguishedName, ProtocolVersion, RootCertStore,
+    SignatureScheme, SupportedCipherSuite,
+};
+use tokio::time::Instant;
 
-use crate::config::{Config, TlsSecrets};
+use crate::config::{Config, ExternalBytes, MtlsConfig, TlsConfig, TlsSecrets, TlsVersion};
 use crate::ipc::MessageBus;
+use crate::load_balancer::der;
+use crate::load_balancer::ocsp::{self, HttpClient};
+use crate::load_balancer::self_signed;
+use crate::metrics::{CertificateExpiry, Metrics};
+
+/// How long to wait for further certificate update requests before refreshing.
+const UPDATE_DEBOUNCE: Duration = Duration::from_millis(50);
+
+/// How often to check whether certificates are close to expiring.
+const EXPIRY_CHECK_INTERVAL: Duration = Duration::from_secs(12 * 60 * 60);
+
+/// The name the default certificate is stored under, which clients cannot ask for themselves.
+pub const DEFAULT_CERTIFICATE: &str = "*";
 
 type Configuration = HashMap<String, TlsSecrets>;
 type Domains = HashMap<String, Arc<CertifiedKey>>;
@@ -20,14 +44,40 @@ type Domains = HashMap<String, Arc<CertifiedKey>>;
 #[derive(Debug, Default)]
 pub struct CertificateResolver {
     domains: Arc<ArcSwap<Domains>>,
+    metrics: Arc<Metrics>,
+}
+
+/// The secrets for each domain, along with the default certificate so that it is refreshed in the
+/// same way.
+pub fn certificate_secrets(config: &TlsConfig) -> Configuration {
+    let mut secrets = config.domains.clone();
+
+    if let Some(default_certificate) = &config.default_certificate {
+        secrets.insert(
+            String::from(DEFAULT_CERTIFICATE),
+            default_certificate.clone(),
+        );
+    }
+
+    secrets
 }
 
 async fn resolve_and_parse_certificates(config: &Configuration) -> Result<Domains> {
+    let http = ocsp::http_client()?;
     let mut domains = HashMap::new();
 
     for (domain, secrets) in config {
-        let (cert, key) = secrets.resolve_files().await?;
-        let certified_key = parse_certified_key(&cert, &key)?;
+        let (cert, key) = match secrets.resolve_files().await {
+            Ok(files) => files,
+            // Managed certificates may not have been issued yet
+            Err(error) if secrets.acme => {
+                tracing::warn!(%domain, %error, "no certificate has been issued over acme yet");
+                continue;
+            }
+            Err(error) => return Err(error),
+        };
+        let mut certified_key = parse_certified_key(&cert, &key)?;
+        ocsp::staple(&http, domain, &mut certified_key).await;
 
         domains.insert(domain.to_owned(), Arc::new(certified_key));
     }
@@ -35,31 +85,105 @@ async fn resolve_and_parse_certificates(config: &Configuration) -> Result<Domain
     Ok(domains)
 }
 
+/// What a batch of certificate update requests asked to be refreshed.
+#[derive(Debug, Eq, PartialEq)]
+enum UpdateScope {
+    All,
+    Domains(HashSet<String>),
+}
+
+impl UpdateScope {
+    fn include(&mut self, domain: Option<String>) {
+        match (self, domain) {
+            (Self::Domains(domains), Some(domain)) => {
+                domains.insert(domain);
+            }
+            (scope, None) => *scope = Self::All,
+            (Self::All, Some(_)) => {}
+        }
+    }
+}
+
+/// Gathers any further requests arriving shortly after the first, so that bulk renewals only
+/// cause a single refresh.
+async fn collect_update_requests(message_bus: &MessageBus, first: Option<String>) -> UpdateScope {
+    let mut scope = UpdateScope::Domains(HashSet::new());
+    scope.include(first);
+
+    let deadline = Instant::now() + UPDATE_DEBOUNCE;
+
+    while let Ok(Ok(message)) =
+        tokio::time::timeout_at(deadline, message_bus.receive_certificate_update_request()).await
+    {
+        scope.include(message.into_content().domain);
+    }
+
+    scope
+}
+
+async fn refresh_certificates(
+    scope: &UpdateScope,
+    config: &Configuration,
+    domains: &ArcSwap<Domains>,
+) -> Result<()> {
+    let UpdateScope::Domains(requested) = scope else {
+        domains.store(Arc::new(resolve_and_parse_certificates(config).await?));
+        return Ok(());
+    };
+
+    for domain in requested
+        .iter()
+        .filter(|domain| !config.contains_key(*domain))
+    {
+        tracing::warn!(%domain, "ignoring certificate update for an unknown domain");
+    }
+
+    let scoped: Configuration = config
+        .iter()
+        .filter(|(domain, _)| requested.contains(*domain))
+        .map(|(domain, secrets)| (domain.clone(), secrets.clone()))
+        .collect();
+
+    let refreshed = resolve_and_parse_certificates(&scoped).await?;
+
+    let mut updated = Domains::clone(&domains.load());
+    updated.extend(refreshed);
+
+    domains.store(Arc::new(updated));
+
+    Ok(())
+}
+
 async fn poll_for_certificate_updates(
     message_bus: Arc<MessageBus>,
     config: &Configuration,
     domains: Arc<ArcSwap<Domains>>,
+    client_cert_verifier: Option<Arc<RevocationCheckingClientCertVerifier>>,
+    expiry_monitor: Arc<ExpiryMonitor>,
 ) -> Result<()> {
-    while message_bus
-        .receive_certificate_update_request()
-        .await
-        .is_ok()
-    {
+    while let Ok(message) = message_bus.receive_certificate_update_request().await {
         let span = tracing::info_span!("certificate_update");
         let _enter = span.enter();
 
-        tracing::info!("processing certificate update request");
+        let scope = collect_update_requests(&message_bus, message.into_content().domain).await;
 
-        match resolve_and_parse_certificates(config).await {
-            Ok(new_domains) => {
-                domains.store(Arc::new(new_domains));
+        tracing::info!(?scope, "processing certificate update request");
 
+        match refresh_certificates(&scope, config, &domains).await {
+            Ok(()) => {
                 tracing::info!("successfully updated the certificate");
+                expiry_monitor.check(&domains.load());
             }
             Err(error) => {
                 tracing::error!(%error, "failed to update certificate");
             }
         }
+
+        if let Some(verifier) = &client_cert_verifier {
+            if let Err(error) = verifier.reload_revocation_lists().await {
+                tracing::error!(%error, "failed to reload certificate revocation lists");
+            }
+        }
     }
 
     tracing::info!("certificate update request receiver closed, stopping resolver");
@@ -67,27 +191,189 @@ async fn poll_for_certificate_updates(
     Ok(())
 }
 
+/// Reports certificates that are close to expiring, through both logs and metrics.
+#[derive(Debug)]
+pub struct ExpiryMonitor {
+    metrics: Arc<Metrics>,
+    warning_window: Duration,
+    reject_expired: bool,
+}
+
+impl ExpiryMonitor {
+    pub fn new(metrics: Arc<Metrics>, config: &TlsConfig) -> Self {
+        Self {
+            metrics,
+            warning_window: config.expiry_warning_window(),
+            reject_expired: config.reject_expired_certificates,
+        }
+    }
+
+    /// Warns about any certificates expiring within the window, returning the domains whose
+    /// certificates have already expired.
+    fn check(&self, domains: &Domains) -> Vec<String> {
+        let now = Utc::now();
+        let window = TimeDelta::from_std(self.warning_window).unwrap_or(TimeDelta::MAX);
+
+        let mut certificates = BTreeMap::new();
+        let mut expired = Vec::new();
+
+        for (domain, certified_key) in domains {
+            let Some(certificate) = certified_key.cert.first() else {
+                continue;
+            };
+
+            let not_after = match der::not_after(certificate) {
+                Ok(not_after) => not_after,
+                Err(error) => {
+                    tracing::warn!(%domain, %error, "failed to read when the certificate expires");
+                    continue;
+                }
+            };
+
+            let remaining = not_after - now;
+
+            if remaining <= TimeDelta::zero() {
+                tracing::error!(%domain, %not_after, "certificate has expired");
+                expired.push(domain.clone());
+            } else if remaining < window {
+                let days = remaining.num_days();
+                tracing::warn!(%domain, %not_after, %days, "certificate expires soon");
+            }
+
+            let expiry = CertificateExpiry {
+                not_after: not_after.timestamp(),
+                expiring: remaining < window,
+            };
+
+            certificates.insert(domain.clone(), expiry);
+        }
+
+        self.metrics.record_certificate_expiry(certificates);
+
+        expired.sort();
+        expired
+    }
+}
+
+/// Periodically checks for certificates close to expiring, as they are otherwise only checked
+/// when loaded.
+async fn monitor_expiry(expiry_monitor: Arc<ExpiryMonitor>, domains: Arc<ArcSwap<Domains>>) {
+    loop {
+        tokio::time::sleep(EXPIRY_CHECK_INTERVAL).await;
+
+        expiry_monitor.check(&domains.load());
+    }
+}
+
+/// Periodically replaces the stapled OCSP responses, leaving alone any certificates that were
+/// updated while fetching them.
+async fn refresh_ocsp_responses(http: HttpClient, domains: Arc<ArcSwap<Domains>>) {
+    loop {
+        tokio::time::sleep(ocsp::REFRESH_INTERVAL).await;
+
+        let mut refreshed = HashMap::new();
+
+        for (domain, certified_key) in domains.load_full().iter() {
+            let mut certified_key = CertifiedKey::clone(certified_key);
+
+            if ocsp::staple(&http, domain, &mut certified_key).await {
+                refreshed.insert(domain.clone(), Arc::new(certified_key));
+            }
+        }
+
+        domains.rcu(|current| {
+            let mut updated = Domains::clone(current);
+
+            for (domain, certified_key) in &refreshed {
+                if current
+                    .get(domain)
+                    .is_some_and(|existing| existing.cert == certified_key.cert)
+                {
+                    updated.insert(domain.clone(), Arc::clone(certified_key));
+                }
+            }
+
+            updated
+        });
+    }
+}
+
 impl CertificateResolver {
-    pub async fn new(config: Arc<Configuration>, message_bus: Arc<MessageBus>) -> Result<Self> {
+    /// Resolves the certificates for each domain, refreshing them whenever an update is requested
+    /// along with the revocation lists used by `client_cert_verifier`.
+    pub async fn new(
+        config: Arc<Configuration>,
+        message_bus: Arc<MessageBus>,
+        client_cert_verifier: Option<Arc<RevocationCheckingClientCertVerifier>>,
+        expiry_monitor: ExpiryMonitor,
+    ) -> Result<Self> {
         let domains = resolve_and_parse_certificates(&config).await?;
+        let expired = expiry_monitor.check(&domains);
+
+        if expiry_monitor.reject_expired && !expired.is_empty() {
+            return Err(eyre!(
+                "refusing to start with expired certificates for {}",
+                expired.join(", ")
+            ));
+        }
+
         let domains = Arc::new(ArcSwap::from_pointee(domains));
+        let metrics = Arc::clone(&expiry_monitor.metrics);
+        let expiry_monitor = Arc::new(expiry_monitor);
 
         let resolver = Self {
             domains: Arc::clone(&domains),
+            metrics,
         };
 
+        tokio::spawn(monitor_expiry(
+            Arc::clone(&expiry_monitor),
+            Arc::clone(&domains),
+        ));
+
         tokio::spawn({
+            let domains = Arc::clone(&domains);
+
             async move {
-                poll_for_certificate_updates(message_bus, &config, domains)
-                    .await
-                    .unwrap_or_else(|error| {
-                        tracing::error!(%error, "failed to poll for certificate updates");
-                    });
+                poll_for_certificate_updates(
+                    message_bus,
+                    &config,
+                    domains,
+                    client_cert_verifier,
+                    expiry_monitor,
+                )
+                .await
+                .unwrap_or_else(|error| {
+                    tracing::error!(%error, "failed to poll for certificate updates");
+                });
             }
         });
 
+        tokio::spawn(refresh_ocsp_responses(ocsp::http_client()?, domains));
+
         Ok(resolver)
     }
+
+    /// Generates a self-signed certificate for each of the hosts, which only exist in memory and
+    /// so are never refreshed.
+    pub fn self_signed<'a>(
+        hosts: impl IntoIterator<Item = &'a str>,
+        metrics: Arc<Metrics>,
+    ) -> Result<Self> {
+        let domains = hosts
+            .into_iter()
+            .map(|host| {
+                tracing::info!(%host, "generating a self-signed certificate");
+
+                Ok((host.to_owned(), Arc::new(self_signed::generate(host)?)))
+            })
+            .collect::<Result<Domains>>()?;
+
+        Ok(Self {
+            domains: Arc::new(ArcSwap::from_pointee(domains)),
+            metrics,
+        })
+    }
 }
 
 fn parse_certified_key(cert: &[u8], key: &[u8]) -> Result<CertifiedKey> {
@@ -112,12 +398,372 @@ fn parse_certified_key(cert: &[u8], key: &[u8]) -> Result<CertifiedKey> {
     Ok(certified_key)
 }
 
+fn version_of(suite: &SupportedCipherSuite) -> TlsVersion {
+    match suite.version().version {
+        ProtocolVersion::TLSv1_2 => TlsVersion::Tls12,
+        _ => TlsVersion::Tls13,
+    }
+}
+
+/// Builds a crypto provider offering only the configured versions and cipher suites, if any
+/// restrictions are configured.
+///
+/// Versions are restricted by removing their cipher suites, as none can be negotiated without one.
+fn restricted_crypto_provider(config: &TlsConfig) -> Result<Option<CryptoProvider>> {
+    if config.min_version.is_none()
+        && config.max_version.is_none()
+        && config.cipher_suites.is_none()
+    {
+        return Ok(None);
+    }
+
+    let mut provider = default_provider();
+
+    if let Some(names) = &config.cipher_suites {
+        for name in names {
+            if !provider
+                .cipher_suites
+                .iter()
+                .any(|suite| suite.suite().as_str() == Some(name.as_str()))
+            {
+                return Err(eyre!("unsupported cipher suite `{name}`"));
+            }
+        }
+    }
+
+    provider.cipher_suites.retain(|suite| {
+        let version = version_of(suite);
+        let name = suite.suite().as_str().unwrap_or_default();
+
+        config.min_version.is_none_or(|min| version >= min)
+            && config.max_version.is_none_or(|max| version <= max)
+            && config
+                .cipher_suites
+                .as_ref()
+                .is_none_or(|names| names.iter().any(|allowed| allowed == name))
+    });
+
+    if provider.cipher_suites.is_empty() {
+        return Err(eyre!(
+            "no cipher suites are allowed by the configured versions and cipher suites"
+        ));
+    }
+
+    Ok(Some(provider))
+}
+
+/// Applies any configured restrictions on the TLS versions and cipher suites negotiated with
+/// clients.
+///
+/// The TLS server builds its configuration from the process-wide crypto provider, so this must be
+/// called before it starts. Outbound connections, such as those made over ACME, are restricted in
+/// the same way.
+pub fn install_crypto_provider(config: &TlsConfig) -> Result<()> {
+    let Some(provider) = restricted_crypto_provider(config)? else {
+        return Ok(());
+    };
+
+    let suites = provider
+        .cipher_suites
+        .iter()
+        .filter_map(|suite| suite.suite().as_str())
+        .join(", ");
+
+    tracing::info!(%suites, "restricting the negotiated cipher suites");
+
+    provider
+        .install_default()
+        .map_err(|_| eyre!("a crypto provider has already been installed"))
+}
+
+impl CertificateResolver {
+    /// Finds the certificate for the requested server name, logging why the handshake will fail
+    /// if there is not one.
+    ///
+    /// Wildcard certificates such as `*.example.com` cover a single label in place of the `*`,
+    /// unless there is a certificate for the exact server name.
+    fn certificate_for(&self, server_name: Option<&str>) -> Option<Arc<CertifiedKey>> {
+        let domains = self.domains.load();
+        let default_certificate = domains.get(DEFAULT_CERTIFICATE).cloned();
+
+        let Some(server_name) = server_name else {
+            if default_certificate.is_none() {
+                tracing::warn!("rejecting tls handshake without a server name indication");
+            }
+
+            return default_certificate;
+        };
+
+        let certificate = domains.get(server_name).cloned().or_else(|| {
+            let (_, parent) = server_name.split_once('.')?;
+
+            domains.get(&format!("*.{parent}")).cloned()
+        });
+
+        if certificate.is_some() {
+            return certificate;
+        }
+
+        self.metrics.record_unmatched_server_name();
+
+        if default_certificate.is_some() {
+            tracing::debug!(%server_name, "no certificate for server name, using the default");
+        } else {
+            tracing::warn!(%server_name, "rejecting tls handshake, no certificate for server name");
+        }
+
+        default_certificate
+    }
+}
+
 impl ResolvesServerCert for CertificateResolver {
     fn resolve(&self, client_hello: ClientHello) -> Option<Arc<CertifiedKey>> {
-        let server_name = client_hello.server_name()?;
-        let domains = self.domains.load();
+        self.certificate_for(client_hello.server_name())
+    }
+}
+
+/// Resolves the certificate revocation lists, which may each hold several PEM encoded lists.
+async fn resolve_revocation_lists(
+    files: &[ExternalBytes],
+) -> Result<Vec<CertificateRevocationListDer<'static>>> {
+    let mut crls = Vec::new();
+
+    for file in files {
+        let mut cursor = Cursor::new(file.resolve().await?);
+        let parsed: Vec<_> = rustls_pemfile::crls(&mut cursor).try_collect()?;
+
+        crls.extend(parsed);
+    }
+
+    Ok(crls)
+}
+
+/// Verifies client certificates against the trust anchor, rejecting any that have been revoked or
+/// lack a SPIFFE ID from one of the trusted domains, if there are any.
+///
+/// The revocation lists can be reloaded while running, whereas the trust anchor is only resolved
+/// once.
+#[derive(Debug)]
+pub struct RevocationCheckingClientCertVerifier {
+    roots: Arc<RootCertStore>,
+    crls: Vec<ExternalBytes>,
+    spiffe_trust_domains: HashSet<String>,
+    inner: RwLock<Arc<dyn ClientCertVerifier>>,
+    root_hint_subjects: Vec<DistinguishedName>,
+}
+
+/// Finds the trust domain of a SPIFFE ID given the URI subject alternative names of a
+/// certificate, which must hold exactly one URI for it to be an SVID.
+fn spiffe_trust_domain(uri_names: &[String]) -> Option<&str> {
+    let [uri] = uri_names else {
+        return None;
+    };
+
+    let id = uri.strip_prefix("spiffe://")?;
+    let trust_domain = id
+        .split_once('/')
+        .map_or(id, |(trust_domain, _)| trust_domain);
+
+    (!trust_domain.is_empty()).then_some(trust_domain)
+}
+
+/// Checks whether a DER encoded certificate has a SPIFFE ID from one of the trust domains.
+fn has_trusted_spiffe_id(certificate: &[u8], trust_domains: &HashSet<String>) -> bool {
+    let uri_names = match der::uri_names(certificate) {
+        Ok(uri_names) => uri_names,
+        Err(error) => {
+            tracing::warn!(%error, "failed to read the spiffe id of a client certificate");
+            return false;
+        }
+    };
+
+    match spiffe_trust_domain(&uri_names) {
+        Some(trust_domain) if trust_domains.contains(trust_domain) => true,
+        _ => {
+            tracing::debug!(
+                ?uri_names,
+                "client certificate is not from a trusted spiffe domain"
+            );
+            false
+        }
+    }
+}
 
-        domains.get(server_name).cloned()
+impl RevocationCheckingClientCertVerifier {
+    pub async fn new(config: &MtlsConfig) -> Result<Arc<Self>> {
+        let bytes = config.anchor.resolve().await?;
+        let mut cursor = Cursor::new(bytes);
+
+        let mut store = RootCertStore::empty();
+        let certs = rustls_pemfile::certs(&mut cursor).filter_map(Result::ok);
+        let (added, ignored) = store.add_parsable_certificates(certs);
+
+        tracing::info!(%added, %ignored, "set up the trust store");
+
+        let roots = Arc::new(store);
+        let inner = Self::build(&roots, &config.crls).await?;
+        let root_hint_subjects = inner.root_hint_subjects().to_vec();
+
+        Ok(Arc::new(Self {
+            roots,
+            crls: config.crls.clone(),
+            spiffe_trust_domains: config.spiffe_trust_domains.clone(),
+            inner: RwLock::new(inner),
+            root_hint_subjects,
+        }))
+    }
+
+    async fn build(
+        roots: &Arc<RootCertStore>,
+        files: &[ExternalBytes],
+    ) -> Result<Arc<dyn ClientCertVerifier>> {
+        let crls = resolve_revocation_lists(files).await?;
+
+        if !files.is_empty() {
+            tracing::info!(count = crls.len(), "loaded certificate revocation lists");
+        }
+
+        // Only signature verification comes from the provider, so cipher restrictions don't apply
+        let provider = Arc::new(default_provider());
+        let verifier = WebPkiClientVerifier::builder_with_provider(Arc::clone(roots), provider)
+            .with_crls(crls)
+            .build()?;
+
+        Ok(verifier)
+    }
+
+    /// Resolves the revocation lists again, so that newly revoked certificates are rejected.
+    pub async fn reload_revocation_lists(&self) -> Result<()> {
+        if self.crls.is_empty() {
+            return Ok(());
+        }
+
+        let verifier = Self::build(&self.roots, &self.crls).await?;
+        *self.inner.write().unwrap() = verifier;
+
+        Ok(())
+    }
+
+    fn inner(&self) -> Arc<dyn ClientCertVerifier> {
+        Arc::clone(&self.inner.read().unwrap())
+    }
+}
+
+impl ClientCertVerifier for RevocationCheckingClientCertVerifier {
+    fn offer_client_auth(&self) -> bool {
+        self.inner().offer_client_auth()
+    }
+
+    fn client_auth_mandatory(&self) -> bool {
+        self.inner().client_auth_mandatory()
+    }
+
+    fn root_hint_subjects(&self) -> &[DistinguishedName] {
+        &self.root_hint_subjects
+    }
+
+    fn verify_client_cert(
+        &self,
+        end_entity: &CertificateDer<'_>,
+        intermediates: &[CertificateDer<'_>],
+        now: UnixTime,
+    ) -> Result<ClientCertVerified, rustls::Error> {
+        let verified = self
+            .inner()
+            .verify_client_cert(end_entity, intermediates, now)?;
+
+        if !self.spiffe_trust_domains.is_empty()
+            && !has_trusted_spiffe_id(end_entity, &self.spiffe_trust_domains)
+        {
+            return Err(rustls::Error::InvalidCertificate(
+                CertificateError::ApplicationVerificationFailure,
+            ));
+        }
+
+        Ok(verified)
+    }
+
+    fn verify_tls12_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        self.inner().verify_tls12_signature(message, cert, dss)
+    }
+
+    fn verify_tls13_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        self.inner().verify_tls13_signature(message, cert, dss)
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+        self.inner().supported_verify_schemes()
+    }
+}
+
+/// Wraps a client certificate verifier to log the reason for any rejected certificates.
+#[derive(Debug)]
+pub struct LoggingClientCertVerifier {
+    inner: Arc<dyn ClientCertVerifier>,
+}
+
+impl LoggingClientCertVerifier {
+    pub fn new(inner: Arc<dyn ClientCertVerifier>) -> Arc<Self> {
+        Arc::new(Self { inner })
+    }
+}
+
+impl ClientCertVerifier for LoggingClientCertVerifier {
+    fn offer_client_auth(&self) -> bool {
+        self.inner.offer_client_auth()
+    }
+
+    fn client_auth_mandatory(&self) -> bool {
+        self.inner.client_auth_mandatory()
+    }
+
+    fn root_hint_subjects(&self) -> &[DistinguishedName] {
+        self.inner.root_hint_subjects()
+    }
+
+    fn verify_client_cert(
+        &self,
+        end_entity: &CertificateDer<'_>,
+        intermediates: &[CertificateDer<'_>],
+        now: UnixTime,
+    ) -> Result<ClientCertVerified, rustls::Error> {
+        self.inner
+            .verify_client_cert(end_entity, intermediates, now)
+            .inspect_err(|error| {
+                tracing::warn!(%error, "rejecting tls handshake, client certificate is invalid");
+            })
+    }
+
+    fn verify_tls12_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        self.inner.verify_tls12_signature(message, cert, dss)
+    }
+
+    fn verify_tls13_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        self.inner.verify_tls13_signature(message, cert, dss)
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+        self.inner.supported_verify_schemes()
     }
 }
 
@@ -160,11 +806,23 @@ mod tests {
     use color_eyre::eyre::{eyre, Result};
     use mutual_tls::{AuthenticationLevel, AuthenticationLevelResolver};
     use rustls::pki_types::pem::PemObject;
-    use rustls::pki_types::CertificateDer;
+    use rustls::pki_types::{CertificateDer, UnixTime};
+    use rustls::server::danger::ClientCertVerifier;
+    use rustls::sign::CertifiedKey;
 
-    use crate::config::{AlbConfig, Config, ExternalBytes, MtlsConfig, Scheme, TlsSecrets};
+    use crate::config::{
+        AlbConfig, Config, ConnectionPool, DockerRetryConfig, ExternalBytes, MtlsConfig, Scheme,
+        ShutdownConfig, TlsConfig, TlsSecrets, TlsVersion,
+    };
     use crate::ipc::MessageBus;
-    use crate::load_balancer::tls::{CertificateResolver, DynamicAuthenticationLevelResolver};
+    use crate::load_balancer::tests::CapturedLogs;
+    use crate::load_balancer::tls::{
+        collect_update_requests, parse_certified_key, restricted_crypto_provider,
+        spiffe_trust_domain, version_of, CertificateResolver, DynamicAuthenticationLevelResolver,
+        ExpiryMonitor, RevocationCheckingClientCertVerifier, UpdateScope, DEFAULT_CERTIFICATE,
+        UPDATE_DEBOUNCE,
+    };
+    use crate::metrics::Metrics;
 
     const PRIMARY_DOMAIN: &str = "primary.example.com";
     const SECONDARY_DOMAIN: &str = "secondary.example.com";
@@ -177,19 +835,45 @@ mod tests {
         let alb = AlbConfig {
             addr: Ipv4Addr::LOCALHOST,
             ports: HashMap::from([(Scheme::Http, 5000)]),
+            protocols: HashMap::new(),
             reconciliation: String::new(),
+            metrics: None,
+            maintenance: None,
+            container_logs: None,
+            request_id: None,
+            evict_connections_on_deploy: false,
+            path_limits: None,
+            empty_service_wait_ms: None,
+            prefer_warm_connections: false,
+            connection_pool: ConnectionPool::default(),
+            max_buffered_body_size: 1024 * 1024,
+            access_log: false,
+            busy_response: None,
+            retry_after_jitter_secs: 0,
+            error_pages: HashMap::new(),
+            maintenance_response: None,
+            static_routes: Vec::new(),
+            otlp: None,
             tls: None,
             mtls: Some(MtlsConfig {
                 anchor: ExternalBytes::Filesystem {
                     path: PathBuf::new(),
                 },
                 domains: HashSet::from([domain1.to_string()]),
+                crls: Vec::new(),
+                spiffe_trust_domains: HashSet::new(),
             }),
         };
 
         let mut original_config = Config {
             alb,
             secrets: None,
+            shutdown: ShutdownConfig::default(),
+            deploy_events: false,
+            registries: HashMap::new(),
+            garbage_collection: None,
+            network: None,
+            docker_retries: DockerRetryConfig::default(),
             services: HashMap::new(),
         };
 
@@ -197,11 +881,11 @@ mod tests {
         let resolver = DynamicAuthenticationLevelResolver::new(Arc::clone(&config));
 
         assert!(matches!(
-            resolver.resolve(&domain1),
+            resolver.resolve(domain1),
             Some(AuthenticationLevel::Mutual)
         ));
         assert!(matches!(
-            resolver.resolve(&domain2),
+            resolver.resolve(domain2),
             Some(AuthenticationLevel::Standard)
         ));
 
@@ -216,11 +900,11 @@ mod tests {
         config.store(Arc::new(original_config));
 
         assert!(matches!(
-            resolver.resolve(&domain1),
+            resolver.resolve(domain1),
             Some(AuthenticationLevel::Mutual)
         ));
         assert!(matches!(
-            resolver.resolve(&domain2),
+            resolver.resolve(domain2),
             Some(AuthenticationLevel::Mutual)
         ));
     }
@@ -286,13 +970,28 @@ mod tests {
         Ok(())
     }
 
+    /// Builds an `ExpiryMonitor` with the default warning window.
+    fn expiry_monitor(reject_expired: bool) -> ExpiryMonitor {
+        ExpiryMonitor {
+            metrics: Metrics::new(),
+            warning_window: Duration::from_secs(14 * 24 * 60 * 60),
+            reject_expired,
+        }
+    }
+
     /// Builds a `CertificateResolver` instance with the given configuration, returning a sender
     /// for certificate update requests and the resolver itself.
     async fn build_resolver(
         config: HashMap<String, TlsSecrets>,
     ) -> Result<(Arc<MessageBus>, CertificateResolver)> {
         let message_bus = MessageBus::new();
-        let resolver = CertificateResolver::new(Arc::new(config), Arc::clone(&message_bus)).await?;
+        let resolver = CertificateResolver::new(
+            Arc::new(config),
+            Arc::clone(&message_bus),
+            None,
+            expiry_monitor(false),
+        )
+        .await?;
 
         Ok((message_bus, resolver))
     }
@@ -327,10 +1026,10 @@ mod tests {
         tokio::fs::copy("resources/certificates/new.key", &key_path).await?;
 
         // inform the resolver about a certificate update
-        message_bus.send_certificate_update_request()?;
+        message_bus.send_certificate_update_request(None)?;
 
         // check that the resolver returns the new certificate, after a little bit of time
-        tokio::time::sleep(Duration::from_millis(5)).await;
+        tokio::time::sleep(UPDATE_DEBOUNCE * 2).await;
 
         verify_certificate_matches(&resolver, PRIMARY_DOMAIN, "certificates/new.crt")?;
 
@@ -368,14 +1067,441 @@ mod tests {
         tokio::fs::copy("resources/certificates/new.key", &second_key_path).await?;
 
         // inform the resolver about a certificate update
-        message_bus.send_certificate_update_request()?;
+        message_bus.send_certificate_update_request(None)?;
 
         // check that the resolver returns the new certificates
-        tokio::time::sleep(Duration::from_millis(5)).await;
+        tokio::time::sleep(UPDATE_DEBOUNCE * 2).await;
 
         verify_certificate_matches(&resolver, PRIMARY_DOMAIN, "certificates/new.crt")?;
         verify_certificate_matches(&resolver, SECONDARY_DOMAIN, "certificates/new.crt")?;
 
         Ok(())
     }
+
+    #[tokio::test]
+    async fn rapid_update_requests_are_coalesced() -> Result<()> {
+        let message_bus = MessageBus::new();
+
+        message_bus.send_certificate_update_request(Some(PRIMARY_DOMAIN.to_owned()))?;
+        message_bus.send_certificate_update_request(Some(SECONDARY_DOMAIN.to_owned()))?;
+
+        let first = message_bus.receive_certificate_update_request().await?;
+        let scope = collect_update_requests(&message_bus, first.into_content().domain).await;
+
+        let expected = HashSet::from([PRIMARY_DOMAIN.to_owned(), SECONDARY_DOMAIN.to_owned()]);
+        assert_eq!(scope, UpdateScope::Domains(expected));
+
+        // any unscoped request widens the refresh to every domain
+        message_bus.send_certificate_update_request(Some(PRIMARY_DOMAIN.to_owned()))?;
+        message_bus.send_certificate_update_request(None)?;
+
+        let first = message_bus.receive_certificate_update_request().await?;
+        let scope = collect_update_requests(&message_bus, first.into_content().domain).await;
+
+        assert_eq!(scope, UpdateScope::All);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn domain_scoped_updates_only_reload_that_domain() -> Result<()> {
+        let temp_dir = tempfile::tempdir()?;
+
+        let first_certificate_path = temp_dir.path().join("first-cert.pem");
+        let first_key_path = temp_dir.path().join("first-key.pem");
+
+        let second_certificate_path = temp_dir.path().join("second-cert.pem");
+        let second_key_path = temp_dir.path().join("second-key.pem");
+
+        for (cert, key) in [
+            (&first_certificate_path, &first_key_path),
+            (&second_certificate_path, &second_key_path),
+        ] {
+            tokio::fs::copy("resources/certificates/old.crt", cert).await?;
+            tokio::fs::copy("resources/certificates/old.key", key).await?;
+        }
+
+        let config = build_resolver_config(&[
+            (PRIMARY_DOMAIN, &first_certificate_path, &first_key_path),
+            (SECONDARY_DOMAIN, &second_certificate_path, &second_key_path),
+        ]);
+
+        let (message_bus, resolver) = build_resolver(config).await?;
+
+        for (cert, key) in [
+            (&first_certificate_path, &first_key_path),
+            (&second_certificate_path, &second_key_path),
+        ] {
+            tokio::fs::copy("resources/certificates/new.crt", cert).await?;
+            tokio::fs::copy("resources/certificates/new.key", key).await?;
+        }
+
+        message_bus.send_certificate_update_request(Some(PRIMARY_DOMAIN.to_owned()))?;
+
+        tokio::time::sleep(UPDATE_DEBOUNCE * 2).await;
+
+        verify_certificate_matches(&resolver, PRIMARY_DOMAIN, "certificates/new.crt")?;
+        verify_certificate_matches(&resolver, SECONDARY_DOMAIN, "certificates/old.crt")?;
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn expired_certificates_can_be_refused() -> Result<()> {
+        let temp_dir = tempfile::tempdir()?;
+
+        // the certificates in `resources` are only valid for a year
+        let certificate_path = stage_resource(temp_dir.path(), "certificates/new.crt").await?;
+        let key_path = stage_resource(temp_dir.path(), "certificates/new.key").await?;
+
+        let config = build_resolver_config(&[(PRIMARY_DOMAIN, &certificate_path, &key_path)]);
+        let resolver = CertificateResolver::new(
+            Arc::new(config),
+            MessageBus::new(),
+            None,
+            expiry_monitor(true),
+        )
+        .await;
+
+        let error = resolver
+            .err()
+            .ok_or_else(|| eyre!("expired certificate was accepted"))?;
+
+        assert!(error.to_string().contains(PRIMARY_DOMAIN));
+
+        Ok(())
+    }
+
+    #[test]
+    fn expiring_certificates_are_reported() -> Result<()> {
+        let certificate = |name: &str| -> Result<Arc<CertifiedKey>> {
+            let cert = std::fs::read(format!("resources/certificates/{name}.crt"))?;
+            let key = std::fs::read(format!("resources/certificates/{name}.key"))?;
+
+            Ok(Arc::new(parse_certified_key(&cert, &key)?))
+        };
+
+        let domains = HashMap::from([
+            (PRIMARY_DOMAIN.to_owned(), certificate("new")?),
+            (SECONDARY_DOMAIN.to_owned(), certificate("stapled")?),
+        ]);
+
+        // the stapled certificate is valid for ten years
+        let monitor = ExpiryMonitor {
+            warning_window: Duration::from_secs(20 * 365 * 24 * 60 * 60),
+            ..expiry_monitor(false)
+        };
+
+        assert_eq!(monitor.check(&domains), vec![PRIMARY_DOMAIN.to_owned()]);
+
+        let rendered = monitor.metrics.render();
+
+        assert!(rendered.contains(&format!(
+            "f2_certificate_expiring{{domain=\"{SECONDARY_DOMAIN}\"}} 1"
+        )));
+        assert!(rendered.contains(&format!(
+            "f2_certificate_expiry_timestamp_seconds{{domain=\"{PRIMARY_DOMAIN}\"}} 1782401553"
+        )));
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn wildcard_certificates_cover_a_single_label() -> Result<()> {
+        let temp_dir = tempfile::tempdir()?;
+
+        let old_certificate_path = stage_resource(temp_dir.path(), "certificates/old.crt").await?;
+        let old_key_path = stage_resource(temp_dir.path(), "certificates/old.key").await?;
+        let new_certificate_path = stage_resource(temp_dir.path(), "certificates/new.crt").await?;
+        let new_key_path = stage_resource(temp_dir.path(), "certificates/new.key").await?;
+
+        let config = build_resolver_config(&[
+            ("*.example.com", &old_certificate_path, &old_key_path),
+            (PRIMARY_DOMAIN, &new_certificate_path, &new_key_path),
+        ]);
+
+        let (_, resolver) = build_resolver(config).await?;
+
+        let certificate = |server_name| {
+            resolver
+                .certificate_for(Some(server_name))
+                .map(|certified_key| certified_key.cert[0].clone())
+        };
+
+        let old = CertificateDer::from_pem_file("resources/certificates/old.crt")?;
+        let new = CertificateDer::from_pem_file("resources/certificates/new.crt")?;
+
+        assert_eq!(certificate("tenant.example.com"), Some(old));
+        assert_eq!(certificate(PRIMARY_DOMAIN), Some(new));
+        assert_eq!(certificate("example.com"), None);
+        assert_eq!(certificate("nested.tenant.example.com"), None);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn unknown_server_names_are_logged() -> Result<()> {
+        let temp_dir = tempfile::tempdir()?;
+
+        let certificate_path = stage_resource(temp_dir.path(), "certificates/old.crt").await?;
+        let key_path = stage_resource(temp_dir.path(), "certificates/old.key").await?;
+
+        let config = build_resolver_config(&[(PRIMARY_DOMAIN, &certificate_path, &key_path)]);
+        let (_, resolver) = build_resolver(config).await?;
+
+        let (logs, subscriber) = CapturedLogs::subscriber();
+
+        let certificate = tracing::subscriber::with_default(subscriber, || {
+            resolver.certificate_for(Some("unknown.example.com"))
+        });
+
+        assert!(certificate.is_none());
+
+        let logs = logs.contents();
+
+        assert!(logs.contains("no certificate for server name"));
+        assert!(logs.contains("unknown.example.com"));
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn unmatched_server_names_fall_back_to_the_default_certificate() -> Result<()> {
+        let temp_dir = tempfile::tempdir()?;
+
+        let old_certificate_path = stage_resource(temp_dir.path(), "certificates/old.crt").await?;
+        let old_key_path = stage_resource(temp_dir.path(), "certificates/old.key").await?;
+        let new_certificate_path = stage_resource(temp_dir.path(), "certificates/new.crt").await?;
+        let new_key_path = stage_resource(temp_dir.path(), "certificates/new.key").await?;
+
+        let config = build_resolver_config(&[
+            (DEFAULT_CERTIFICATE, &old_certificate_path, &old_key_path),
+            (PRIMARY_DOMAIN, &new_certificate_path, &new_key_path),
+        ]);
+
+        let (_, resolver) = build_resolver(config).await?;
+
+        let certificate = |server_name| {
+            resolver
+                .certificate_for(server_name)
+                .map(|certified_key| certified_key.cert[0].clone())
+        };
+
+        let old = CertificateDer::from_pem_file("resources/certificates/old.crt")?;
+        let new = CertificateDer::from_pem_file("resources/certificates/new.crt")?;
+
+        assert_eq!(certificate(Some(PRIMARY_DOMAIN)), Some(new));
+        assert_eq!(resolver.metrics.unmatched_server_names(), 0);
+
+        assert_eq!(certificate(Some("unknown.example.com")), Some(old.clone()));
+        assert_eq!(certificate(None), Some(old));
+        assert_eq!(resolver.metrics.unmatched_server_names(), 1);
+
+        Ok(())
+    }
+
+    #[test]
+    fn self_signed_certificates_are_generated_for_each_host() -> Result<()> {
+        let resolver = CertificateResolver::self_signed(
+            [PRIMARY_DOMAIN, "other.example.com"],
+            Metrics::new(),
+        )?;
+
+        let primary = resolver.certificate_for(Some(PRIMARY_DOMAIN));
+        let other = resolver.certificate_for(Some("other.example.com"));
+
+        assert!(primary.is_some() && other.is_some());
+        assert_ne!(primary.unwrap().cert, other.unwrap().cert);
+        assert!(resolver
+            .certificate_for(Some("unknown.example.com"))
+            .is_none());
+
+        Ok(())
+    }
+
+    fn restricted_tls_config(
+        min_version: Option<TlsVersion>,
+        max_version: Option<TlsVersion>,
+        cipher_suites: Option<&[&str]>,
+    ) -> TlsConfig {
+        TlsConfig {
+            domains: HashMap::new(),
+            default_certificate: None,
+            acme: None,
+            poll_interval_secs: 300,
+            min_version,
+            max_version,
+            cipher_suites: cipher_suites
+                .map(|names| names.iter().map(|name| name.to_string()).collect()),
+            expiry_warning_days: 14,
+            reject_expired_certificates: false,
+            self_signed: false,
+        }
+    }
+
+    #[test]
+    fn providers_are_only_restricted_when_configured() -> Result<()> {
+        assert!(restricted_crypto_provider(&restricted_tls_config(None, None, None))?.is_none());
+
+        Ok(())
+    }
+
+    #[test]
+    fn versions_outside_the_configured_range_have_no_cipher_suites() -> Result<()> {
+        let config = restricted_tls_config(Some(TlsVersion::Tls13), None, None);
+        let provider = restricted_crypto_provider(&config)?.unwrap();
+
+        assert!(!provider.cipher_suites.is_empty());
+        assert!(provider
+            .cipher_suites
+            .iter()
+            .all(|suite| version_of(suite) == TlsVersion::Tls13));
+
+        Ok(())
+    }
+
+    #[test]
+    fn only_the_configured_cipher_suites_are_offered() -> Result<()> {
+        let names = ["TLS13_AES_256_GCM_SHA384", "TLS13_CHACHA20_POLY1305_SHA256"];
+        let config = restricted_tls_config(None, None, Some(&names));
+        let provider = restricted_crypto_provider(&config)?.unwrap();
+
+        let offered: Vec<_> = provider
+            .cipher_suites
+            .iter()
+            .filter_map(|suite| suite.suite().as_str())
+            .collect();
+
+        assert_eq!(offered, names);
+
+        Ok(())
+    }
+
+    #[test]
+    fn unsupported_or_conflicting_restrictions_are_rejected() {
+        let unknown = restricted_tls_config(None, None, Some(&["TLS_RSA_WITH_RC4_128_MD5"]));
+        assert!(restricted_crypto_provider(&unknown).is_err());
+
+        let conflicting = restricted_tls_config(
+            None,
+            Some(TlsVersion::Tls12),
+            Some(&["TLS13_AES_256_GCM_SHA384"]),
+        );
+        assert!(restricted_crypto_provider(&conflicting).is_err());
+    }
+
+    /// Builds an mTLS configuration trusting the client authority in `resources`, along with the
+    /// revocation list at `crl`.
+    fn revocation_checking_config(crl: &Path) -> MtlsConfig {
+        MtlsConfig {
+            anchor: ExternalBytes::Filesystem {
+                path: PathBuf::from("resources/certificates/client-ca.crt"),
+            },
+            domains: HashSet::from([PRIMARY_DOMAIN.to_owned()]),
+            crls: vec![ExternalBytes::Filesystem {
+                path: crl.to_owned(),
+            }],
+            spiffe_trust_domains: HashSet::new(),
+        }
+    }
+
+    /// Checks whether the verifier accepts the named client certificate in `resources`.
+    fn accepts(verifier: &RevocationCheckingClientCertVerifier, name: &str) -> bool {
+        let path = Path::new("resources/certificates").join(name);
+        let cert = CertificateDer::from_pem_file(path).unwrap();
+
+        verifier
+            .verify_client_cert(&cert, &[], UnixTime::now())
+            .is_ok()
+    }
+
+    #[tokio::test]
+    async fn revoked_client_certificates_are_rejected() -> Result<()> {
+        let config = revocation_checking_config(Path::new("resources/certificates/client-ca.crl"));
+        let verifier = RevocationCheckingClientCertVerifier::new(&config).await?;
+
+        assert!(accepts(&verifier, "trusted-client.crt"));
+        assert!(!accepts(&verifier, "revoked-client.crt"));
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn client_certificates_must_hold_a_trusted_spiffe_id() -> Result<()> {
+        let config = revocation_checking_config(Path::new("resources/certificates/client-ca.crl"));
+        let verifier = RevocationCheckingClientCertVerifier::new(&config).await?;
+
+        assert!(accepts(&verifier, "workload-client.crt"));
+
+        for (trust_domain, workload_accepted) in [("example.com", true), ("example.org", false)] {
+            let config = MtlsConfig {
+                spiffe_trust_domains: HashSet::from([trust_domain.to_owned()]),
+                ..config.clone()
+            };
+            let verifier = RevocationCheckingClientCertVerifier::new(&config).await?;
+
+            assert_eq!(accepts(&verifier, "workload-client.crt"), workload_accepted);
+            assert!(!accepts(&verifier, "trusted-client.crt"));
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn spiffe_ids_must_be_the_only_uri_name() {
+        let uris =
+            |uris: &[&str]| -> Vec<String> { uris.iter().map(|uri| uri.to_string()).collect() };
+
+        let workload = uris(&["spiffe://example.com/billing"]);
+        let trust_domain = uris(&["spiffe://example.com"]);
+
+        assert_eq!(spiffe_trust_domain(&workload), Some("example.com"));
+        assert_eq!(spiffe_trust_domain(&trust_domain), Some("example.com"));
+
+        for invalid in [
+            uris(&[]),
+            uris(&["spiffe:///billing"]),
+            uris(&["https://example.com/billing"]),
+            uris(&[
+                "spiffe://example.com/billing",
+                "spiffe://example.org/billing",
+            ]),
+        ] {
+            assert_eq!(spiffe_trust_domain(&invalid), None);
+        }
+    }
+
+    #[tokio::test]
+    async fn revocation_lists_are_reloaded_on_certificate_updates() -> Result<()> {
+        let temp_dir = tempfile::tempdir()?;
+        let crl_path = temp_dir.path().join("client-ca.crl");
+
+        // nothing has been revoked yet
+        tokio::fs::write(&crl_path, "").await?;
+
+        let verifier =
+            RevocationCheckingClientCertVerifier::new(&revocation_checking_config(&crl_path))
+                .await?;
+
+        assert!(accepts(&verifier, "revoked-client.crt"));
+
+        let message_bus = MessageBus::new();
+        let _resolver = CertificateResolver::new(
+            Arc::new(HashMap::new()),
+            Arc::clone(&message_bus),
+            Some(Arc::clone(&verifier)),
+            expiry_monitor(false),
+        )
+        .await?;
+
+        tokio::fs::copy("resources/certificates/client-ca.crl", &crl_path).await?;
+        message_bus.send_certificate_update_request(None)?;
+
+        tokio::time::sleep(UPDATE_DEBOUNCE * 2).await;
+
+        assert!(!accepts(&verifier, "revoked-client.crt"));
+        assert!(accepts(&verifier, "trusted-client.crt"));
+
+        Ok(())
+    }
 }
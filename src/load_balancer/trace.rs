@@ -0,0 +1,453 @@
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use color_eyre::eyre::{eyre, Result};
+use http::header::CONTENT_TYPE;
+use http::{HeaderMap, HeaderName, HeaderValue, Method, Request, Uri};
+use http_body_util::Full;
+use hyper::body::{Bytes, Incoming};
+use hyper_util::client::legacy::connect::HttpConnector;
+use hyper_util::client::legacy::Client;
+use hyper_util::rt::TokioExecutor;
+use rand::Rng;
+use serde::Serialize;
+
+use crate::load_balancer::proxy::Context;
+
+pub const TRACEPARENT: HeaderName = HeaderName::from_static("traceparent");
+
+/// How often buffered spans are sent to the collector.
+const EXPORT_INTERVAL: Duration = Duration::from_secs(5);
+
+/// The most spans to hold between exports, beyond which new spans are dropped.
+const MAX_BUFFERED_SPANS: usize = 4096;
+
+/// The `SERVER` span kind in OTLP.
+const SPAN_KIND_SERVER: u8 = 2;
+
+/// The `ERROR` status code in OTLP.
+const STATUS_CODE_ERROR: u8 = 2;
+
+/// The parts of a W3C `traceparent` header.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub struct TraceParent {
+    pub trace_id: u128,
+    pub parent_id: u64,
+    pub sampled: bool,
+}
+
+impl TraceParent {
+    pub fn parse(value: &HeaderValue) -> Option<Self> {
+        let mut parts = value.to_str().ok()?.trim().split('-');
+
+        let version = parts.next().filter(|version| version.len() == 2)?;
+        let trace_id = parts.next().filter(|id| id.len() == 32)?;
+        let parent_id = parts.next().filter(|id| id.len() == 16)?;
+        let flags = parts.next().filter(|flags| flags.len() == 2)?;
+
+        // Future versions may add fields, but the current one has exactly four
+        if version == "ff" || (version == "00" && parts.next().is_some()) {
+            return None;
+        }
+
+        let trace_id = u128::from_str_radix(trace_id, 16)
+            .ok()
+            .filter(|id| *id != 0)?;
+        let parent_id = u64::from_str_radix(parent_id, 16)
+            .ok()
+            .filter(|id| *id != 0)?;
+        let flags = u8::from_str_radix(flags, 16).ok()?;
+
+        Some(Self {
+            trace_id,
+            parent_id,
+            sampled: flags & 1 == 1,
+        })
+    }
+
+    pub fn to_header_value(self) -> HeaderValue {
+        let value = format!(
+            "00-{:032x}-{:016x}-{:02x}",
+            self.trace_id,
+            self.parent_id,
+            u8::from(self.sampled)
+        );
+
+        HeaderValue::from_str(&value).expect("traceparent headers are always valid header values")
+    }
+}
+
+/// A span covering a request's hop through the proxy, continuing any trace the client started.
+#[derive(Debug)]
+pub struct ProxySpan {
+    trace_id: u128,
+    span_id: u64,
+    parent_id: Option<u64>,
+    sampled: bool,
+    start: SystemTime,
+}
+
+impl ProxySpan {
+    pub fn start<R: Rng>(headers: &HeaderMap, rng: &mut R) -> Self {
+        let parent = headers.get(TRACEPARENT).and_then(TraceParent::parse);
+
+        Self {
+            trace_id: parent.map_or_else(|| rng.gen_range(1..=u128::MAX), |p| p.trace_id),
+            span_id: rng.gen_range(1..=u64::MAX),
+            parent_id: parent.map(|parent| parent.parent_id),
+            sampled: parent.is_none_or(|parent| parent.sampled),
+            start: SystemTime::now(),
+        }
+    }
+
+    /// Sets the `traceparent` header so the downstream continues the trace from this span.
+    pub fn inject(&self, headers: &mut HeaderMap) {
+        let traceparent = TraceParent {
+            trace_id: self.trace_id,
+            parent_id: self.span_id,
+            sampled: self.sampled,
+        };
+
+        headers.insert(TRACEPARENT, traceparent.to_header_value());
+    }
+
+    pub fn finish(self, details: SpanDetails) -> Option<FinishedSpan> {
+        if !self.sampled {
+            return None;
+        }
+
+        let end = SystemTime::now();
+
+        Some(FinishedSpan {
+            trace_id: self.trace_id,
+            span_id: self.span_id,
+            parent_id: self.parent_id,
+            start_unix_nanos: unix_nanos(self.start),
+            end_unix_nanos: unix_nanos(end),
+            details,
+        })
+    }
+}
+
+/// Describes the request a span covered.
+#[derive(Debug)]
+pub struct SpanDetails {
+    pub method: Method,
+    pub host: String,
+    pub path: String,
+    pub status: u16,
+    pub downstream: String,
+}
+
+#[derive(Debug)]
+pub struct FinishedSpan {
+    trace_id: u128,
+    span_id: u64,
+    parent_id: Option<u64>,
+    start_unix_nanos: u128,
+    end_unix_nanos: u128,
+    details: SpanDetails,
+}
+
+/// Holds finished spans until they are next exported.
+#[derive(Debug, Default)]
+pub struct SpanBuffer {
+    spans: Mutex<Vec<FinishedSpan>>,
+}
+
+impl SpanBuffer {
+    pub fn record(&self, span: FinishedSpan) {
+        let mut spans = self.spans.lock().unwrap();
+
+        if spans.len() < MAX_BUFFERED_SPANS {
+            spans.push(span);
+        }
+    }
+
+    fn take(&self) -> Vec<FinishedSpan> {
+        std::mem::take(&mut *self.spans.lock().unwrap())
+    }
+}
+
+/// Periodically sends buffered spans to the configured OTLP collector.
+pub async fn export_spans(context: Arc<Context<Incoming>>) {
+    let client = Client::builder(TokioExecutor::new()).build(HttpConnector::new());
+    let mut interval = tokio::time::interval(EXPORT_INTERVAL);
+
+    loop {
+        interval.tick().await;
+
+        let spans = context.spans.take();
+        let config = context.config.load();
+
+        let Some(otlp) = config.alb.otlp.as_ref() else {
+            continue;
+        };
+
+        if spans.is_empty() {
+            continue;
+        }
+
+        if let Err(e) = send(&client, &otlp.endpoint, &otlp.service_name, spans).await {
+            tracing::warn!(%e, "failed to export spans");
+        }
+    }
+}
+
+/// Sends spans to an OTLP/HTTP collector using the JSON encoding.
+async fn send(
+    client: &Client<HttpConnector, Full<Bytes>>,
+    endpoint: &str,
+    service_name: &str,
+    spans: Vec<FinishedSpan>,
+) -> Result<()> {
+    let uri: Uri = format!("{}/v1/traces", endpoint.trim_end_matches('/')).parse()?;
+    let body = serde_json::to_vec(&encode(service_name, spans))?;
+
+    let request = Request::post(uri)
+        .header(CONTENT_TYPE, "application/json")
+        .body(Full::new(Bytes::from(body)))?;
+
+    let response = client.request(request).await?;
+
+    if !response.status().is_success() {
+        return Err(eyre!("collector responded with {}", response.status()));
+    }
+
+    Ok(())
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct ExportRequest {
+    resource_spans: Vec<ResourceSpans>,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct ResourceSpans {
+    resource: Resource,
+    scope_spans: Vec<ScopeSpans>,
+}
+
+#[derive(Serialize)]
+struct Resource {
+    attributes: Vec<KeyValue>,
+}
+
+#[derive(Serialize)]
+struct ScopeSpans {
+    scope: Scope,
+    spans: Vec<Span>,
+}
+
+#[derive(Serialize)]
+struct Scope {
+    name: &'static str,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct Span {
+    trace_id: String,
+    span_id: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    parent_span_id: Option<String>,
+    name: String,
+    kind: u8,
+    start_time_unix_nano: String,
+    end_time_unix_nano: String,
+    attributes: Vec<KeyValue>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    status: Option<Status>,
+}
+
+#[derive(Serialize)]
+struct Status {
+    code: u8,
+}
+
+#[derive(Serialize)]
+struct KeyValue {
+    key: &'static str,
+    value: AnyValue,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+enum AnyValue {
+    StringValue(String),
+    IntValue(String),
+}
+
+fn encode(service_name: &str, spans: Vec<FinishedSpan>) -> ExportRequest {
+    let string = |key, value: String| KeyValue {
+        key,
+        value: AnyValue::StringValue(value),
+    };
+
+    let spans = spans
+        .into_iter()
+        .map(|span| {
+            let SpanDetails {
+                method,
+                host,
+                path,
+                status,
+                downstream,
+            } = span.details;
+
+            Span {
+                trace_id: format!("{:032x}", span.trace_id),
+                span_id: format!("{:016x}", span.span_id),
+                parent_span_id: span.parent_id.map(|id| format!("{id:016x}")),
+                name: method.to_string(),
+                kind: SPAN_KIND_SERVER,
+                // 64-bit integers are encoded as strings in OTLP JSON
+                start_time_unix_nano: span.start_unix_nanos.to_string(),
+                end_time_unix_nano: span.end_unix_nanos.to_string(),
+                attributes: vec![
+                    string("http.request.method", method.to_string()),
+                    string("server.address", host),
+                    string("url.path", path),
+                    string("f2.downstream", downstream),
+                    KeyValue {
+                        key: "http.response.status_code",
+                        value: AnyValue::IntValue(status.to_string()),
+                    },
+                ],
+                status: (status >= 500).then_some(Status {
+                    code: STATUS_CODE_ERROR,
+                }),
+            }
+        })
+        .collect();
+
+    ExportRequest {
+        resource_spans: vec![ResourceSpans {
+            resource: Resource {
+                attributes: vec![string("service.name", service_name.to_owned())],
+            },
+            scope_spans: vec![ScopeSpans {
+                scope: Scope { name: "f2" },
+                spans,
+            }],
+        }],
+    }
+}
+
+fn unix_nanos(time: SystemTime) -> u128 {
+    time.duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_nanos()
+}
+
+#[cfg(test)]
+mod tests {
+    use http::{HeaderMap, HeaderValue, Method};
+    use rand::rngs::SmallRng;
+    use rand::SeedableRng;
+
+    use crate::load_balancer::trace::{encode, ProxySpan, SpanDetails, TraceParent, TRACEPARENT};
+
+    const TRACEPARENT_VALUE: &str = "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01";
+
+    fn build_details() -> SpanDetails {
+        SpanDetails {
+            method: Method::GET,
+            host: String::from("opentracker.app"),
+            path: String::from("/"),
+            status: 502,
+            downstream: String::from("127.0.0.1:8080"),
+        }
+    }
+
+    #[test]
+    fn traceparent_headers_can_be_parsed() {
+        let traceparent = TraceParent::parse(&HeaderValue::from_static(TRACEPARENT_VALUE));
+
+        assert_eq!(
+            traceparent,
+            Some(TraceParent {
+                trace_id: 0x4bf92f3577b34da6a3ce929d0e0e4736,
+                parent_id: 0x00f067aa0ba902b7,
+                sampled: true,
+            })
+        );
+
+        assert_eq!(traceparent.unwrap().to_header_value(), TRACEPARENT_VALUE);
+    }
+
+    #[test]
+    fn invalid_traceparent_headers_are_ignored() {
+        for value in [
+            "00-00000000000000000000000000000000-00f067aa0ba902b7-01",
+            "00-4bf92f3577b34da6a3ce929d0e0e4736-0000000000000000-01",
+            "ff-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01",
+            "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01-extra",
+            "00-4bf92f35-00f067aa0ba902b7-01",
+            "not a traceparent",
+        ] {
+            assert_eq!(TraceParent::parse(&HeaderValue::from_static(value)), None);
+        }
+    }
+
+    #[test]
+    fn spans_continue_incoming_traces() {
+        let mut rng = SmallRng::seed_from_u64(0);
+
+        let mut headers = HeaderMap::new();
+        headers.insert(TRACEPARENT, HeaderValue::from_static(TRACEPARENT_VALUE));
+
+        let span = ProxySpan::start(&headers, &mut rng);
+        span.inject(&mut headers);
+
+        let injected = TraceParent::parse(&headers[TRACEPARENT]).unwrap();
+
+        assert_eq!(injected.trace_id, 0x4bf92f3577b34da6a3ce929d0e0e4736);
+        assert_ne!(injected.parent_id, 0x00f067aa0ba902b7);
+        assert_eq!(span.parent_id, Some(0x00f067aa0ba902b7));
+    }
+
+    #[test]
+    fn unsampled_traces_are_propagated_but_not_recorded() {
+        let mut rng = SmallRng::seed_from_u64(0);
+
+        let mut headers = HeaderMap::new();
+        headers.insert(
+            TRACEPARENT,
+            HeaderValue::from_static("00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-00"),
+        );
+
+        let span = ProxySpan::start(&headers, &mut rng);
+        span.inject(&mut headers);
+
+        assert!(headers[TRACEPARENT].to_str().unwrap().ends_with("-00"));
+        assert!(span.finish(build_details()).is_none());
+    }
+
+    #[test]
+    fn spans_are_encoded_as_otlp_json() {
+        let mut rng = SmallRng::seed_from_u64(0);
+
+        let mut headers = HeaderMap::new();
+        headers.insert(TRACEPARENT, HeaderValue::from_static(TRACEPARENT_VALUE));
+
+        let span = ProxySpan::start(&headers, &mut rng)
+            .finish(build_details())
+            .unwrap();
+
+        let encoded = serde_json::to_value(encode("f2", vec![span])).unwrap();
+        let resource = &encoded["resourceSpans"][0];
+        let span = &resource["scopeSpans"][0]["spans"][0];
+
+        assert_eq!(
+            resource["resource"]["attributes"][0]["value"]["stringValue"],
+            "f2"
+        );
+        assert_eq!(span["traceId"], "4bf92f3577b34da6a3ce929d0e0e4736");
+        assert_eq!(span["parentSpanId"], "00f067aa0ba902b7");
+        assert_eq!(span["name"], "GET");
+        assert_eq!(span["status"]["code"], 2);
+    }
+}
@@ -0,0 +1,152 @@
+use std::error::Error;
+use std::io::ErrorKind;
+
+use http::header::ACCEPT;
+use http::{HeaderMap, StatusCode};
+use hyper::body::Bytes;
+use serde_json::json;
+
+/// Why a request to a container failed, which decides what the client is told.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum UpstreamError {
+    ConnectionRefused,
+    ConnectionReset,
+    Timeout,
+    Other,
+}
+
+impl UpstreamError {
+    /// Works out why a request failed by searching the error's sources.
+    pub fn classify(error: &(dyn Error + 'static)) -> Self {
+        let mut source = Some(error);
+
+        while let Some(error) = source {
+            if let Some(error) = error.downcast_ref::<std::io::Error>() {
+                match error.kind() {
+                    ErrorKind::ConnectionRefused => return Self::ConnectionRefused,
+                    ErrorKind::ConnectionReset => return Self::ConnectionReset,
+                    ErrorKind::TimedOut => return Self::Timeout,
+                    _ => {}
+                }
+            }
+
+            if let Some(error) = error.downcast_ref::<hyper::Error>() {
+                if error.is_incomplete_message() {
+                    return Self::ConnectionReset;
+                }
+
+                if error.is_timeout() {
+                    return Self::Timeout;
+                }
+            }
+
+            source = error.source();
+        }
+
+        Self::Other
+    }
+
+    /// The status to respond with, leaving `503 Service Unavailable` for when there is no container
+    /// to try at all.
+    pub fn status(self) -> StatusCode {
+        match self {
+            Self::ConnectionRefused | Self::ConnectionReset | Self::Other => {
+                StatusCode::BAD_GATEWAY
+            }
+            Self::Timeout => StatusCode::GATEWAY_TIMEOUT,
+        }
+    }
+
+    /// A stable identifier for the failure, so clients can tell them apart.
+    pub fn code(self) -> &'static str {
+        match self {
+            Self::ConnectionRefused => "upstream_connection_refused",
+            Self::ConnectionReset => "upstream_connection_reset",
+            Self::Timeout => "upstream_timeout",
+            Self::Other => "upstream_error",
+        }
+    }
+
+    /// Describes the failure as JSON if the client accepts it, or as HTML otherwise, returning the
+    /// content type alongside the body.
+    pub fn render(self, headers: &HeaderMap) -> (&'static str, Bytes) {
+        let status = self.status();
+        let reason = status.canonical_reason().unwrap_or_default();
+
+        let wants_json = headers
+            .get_all(ACCEPT)
+            .iter()
+            .filter_map(|value| value.to_str().ok())
+            .any(|value| value.contains("application/json"));
+
+        if wants_json {
+            let body = json!({
+                "status": status.as_u16(),
+                "error": reason,
+                "code": self.code(),
+            });
+
+            return ("application/json", Bytes::from(body.to_string()));
+        }
+
+        let body = format!(
+            "<html><head><title>{status}</title></head><body><h1>{status}</h1><p>{}</p></body></html>",
+            self.code()
+        );
+
+        ("text/html; charset=utf-8", Bytes::from(body))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{Error, ErrorKind};
+
+    use color_eyre::eyre::Result;
+    use http::header::ACCEPT;
+    use http::{HeaderMap, HeaderValue, StatusCode};
+
+    use crate::load_balancer::upstream_error::UpstreamError;
+
+    #[test]
+    fn failures_are_classified_by_their_sources() {
+        let classify = |kind| UpstreamError::classify(&Error::new(kind, "failed"));
+
+        assert_eq!(
+            classify(ErrorKind::ConnectionRefused),
+            UpstreamError::ConnectionRefused
+        );
+        assert_eq!(
+            classify(ErrorKind::ConnectionReset),
+            UpstreamError::ConnectionReset
+        );
+        assert_eq!(classify(ErrorKind::TimedOut), UpstreamError::Timeout);
+        assert_eq!(classify(ErrorKind::Other), UpstreamError::Other);
+
+        assert_eq!(
+            UpstreamError::ConnectionRefused.status(),
+            StatusCode::BAD_GATEWAY
+        );
+        assert_eq!(UpstreamError::Timeout.status(), StatusCode::GATEWAY_TIMEOUT);
+    }
+
+    #[test]
+    fn json_is_rendered_for_clients_that_accept_it() -> Result<()> {
+        let mut headers = HeaderMap::new();
+        headers.insert(ACCEPT, HeaderValue::from_static("application/json"));
+
+        let (content_type, body) = UpstreamError::Timeout.render(&headers);
+        let body: serde_json::Value = serde_json::from_slice(&body)?;
+
+        assert_eq!(content_type, "application/json");
+        assert_eq!(body["status"], 504);
+        assert_eq!(body["code"], "upstream_timeout");
+
+        let (content_type, body) = UpstreamError::Timeout.render(&HeaderMap::new());
+
+        assert_eq!(content_type, "text/html; charset=utf-8");
+        assert!(String::from_utf8_lossy(&body).contains("upstream_timeout"));
+
+        Ok(())
+    }
+}
@@ -0,0 +1,198 @@
+//! Connecting to containers over TLS, for backends that require traffic to be encrypted on every
+//! hop.
+
+use std::io::Cursor;
+use std::sync::Arc;
+
+use color_eyre::eyre::{eyre, Result};
+use hyper_rustls::{FixedServerNameResolver, HttpsConnector, HttpsConnectorBuilder};
+use hyper_util::client::legacy::connect::HttpConnector;
+use itertools::Itertools;
+use rustls::crypto::ring::default_provider;
+use rustls::pki_types::ServerName;
+use rustls::{ClientConfig, RootCertStore};
+
+use crate::config::{ExternalBytes, UpstreamProtocol};
+
+/// Builds the store of authorities to verify containers against, only trusting those in `ca` if
+/// it is given and the public roots otherwise.
+async fn root_store(ca: Option<&ExternalBytes>) -> Result<RootCertStore> {
+    let Some(ca) = ca else {
+        return Ok(RootCertStore {
+            roots: webpki_roots::TLS_SERVER_ROOTS.to_vec(),
+        });
+    };
+
+    let bytes = ca.resolve().await?;
+    let certs: Vec<_> = rustls_pemfile::certs(&mut Cursor::new(bytes)).try_collect()?;
+
+    let mut roots = RootCertStore::empty();
+    let (added, _) = roots.add_parsable_certificates(certs);
+
+    if added == 0 {
+        return Err(eyre!("no certificate authorities found in {ca:?}"));
+    }
+
+    Ok(roots)
+}
+
+/// Builds a connector that verifies containers against `ca`, expecting them to present a
+/// certificate for `server_name` regardless of the address being connected to.
+///
+/// The ring provider is used directly, so any restrictions placed on the listeners do not apply
+/// to connections to containers.
+pub async fn connector(
+    mut http: HttpConnector,
+    protocol: UpstreamProtocol,
+    server_name: &str,
+    ca: Option<&ExternalBytes>,
+) -> Result<HttpsConnector<HttpConnector>> {
+    let config = ClientConfig::builder_with_provider(Arc::new(default_provider()))
+        .with_safe_default_protocol_versions()?
+        .with_root_certificates(root_store(ca).await?)
+        .with_no_client_auth();
+
+    let server_name = ServerName::try_from(server_name.to_owned())?;
+
+    // The TLS connector passes `https` addresses through to the underlying connector
+    http.enforce_http(false);
+
+    let builder = HttpsConnectorBuilder::new()
+        .with_tls_config(config)
+        .https_only()
+        .with_server_name_resolver(FixedServerNameResolver::new(server_name));
+
+    let connector = match protocol {
+        UpstreamProtocol::Http1 => builder.enable_http1().wrap_connector(http),
+        UpstreamProtocol::Http2 => builder.enable_http2().wrap_connector(http),
+    };
+
+    Ok(connector)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::{Ipv4Addr, SocketAddr, SocketAddrV4};
+    use std::path::PathBuf;
+    use std::sync::Arc;
+
+    use color_eyre::eyre::Result;
+    use http::{Response, StatusCode};
+    use http_body_util::{BodyExt, Empty, Full};
+    use hyper::body::Bytes;
+    use hyper::service::service_fn;
+    use hyper_util::client::legacy::connect::HttpConnector;
+    use hyper_util::client::legacy::Client;
+    use hyper_util::rt::{TokioExecutor, TokioIo};
+    use hyper_util::server::conn::auto::Builder;
+    use itertools::Itertools;
+    use rustls::crypto::ring::default_provider;
+    use rustls::pki_types::pem::PemObject;
+    use rustls::pki_types::{CertificateDer, PrivateKeyDer};
+    use rustls::ServerConfig;
+    use tokio::net::TcpListener;
+    use tokio_rustls::TlsAcceptor;
+
+    use crate::config::{ExternalBytes, UpstreamProtocol};
+    use crate::load_balancer::upstream_tls::{connector, root_store};
+
+    const SERVER_NAME: &str = "stapled.example.com";
+
+    fn pinned_ca() -> ExternalBytes {
+        ExternalBytes::Filesystem {
+            path: PathBuf::from("resources/certificates/stapled.crt"),
+        }
+    }
+
+    /// Spawns a container that only accepts connections over TLS, presenting a certificate for
+    /// `SERVER_NAME` issued by a private authority.
+    async fn spawn_tls_container() -> Result<SocketAddr> {
+        let chain: Vec<_> =
+            CertificateDer::pem_file_iter("resources/certificates/stapled.crt")?.try_collect()?;
+        let key = PrivateKeyDer::from_pem_file("resources/certificates/stapled.key")?;
+
+        let config = ServerConfig::builder_with_provider(Arc::new(default_provider()))
+            .with_safe_default_protocol_versions()?
+            .with_no_client_auth()
+            .with_single_cert(chain, key)?;
+
+        let acceptor = TlsAcceptor::from(Arc::new(config));
+        let listener = TcpListener::bind(SocketAddrV4::new(Ipv4Addr::LOCALHOST, 0)).await?;
+        let addr = listener.local_addr()?;
+
+        tokio::spawn(async move {
+            loop {
+                let (stream, _) = listener.accept().await.unwrap();
+                let acceptor = acceptor.clone();
+
+                tokio::spawn(async move {
+                    // Clients that do not trust the container abandon the handshake
+                    let Ok(stream) = acceptor.accept(stream).await else {
+                        return;
+                    };
+
+                    let service = service_fn(|_| async {
+                        Ok::<_, hyper::Error>(Response::new(Full::new(Bytes::from("encrypted"))))
+                    });
+
+                    let _ = Builder::new(TokioExecutor::new())
+                        .serve_connection(TokioIo::new(stream), service)
+                        .await;
+                });
+            }
+        });
+
+        Ok(addr)
+    }
+
+    async fn request(
+        addr: SocketAddr,
+        server_name: &str,
+        ca: Option<&ExternalBytes>,
+    ) -> Result<String> {
+        let connector = connector(
+            HttpConnector::new(),
+            UpstreamProtocol::Http1,
+            server_name,
+            ca,
+        )
+        .await?;
+        let client: Client<_, Empty<Bytes>> =
+            Client::builder(TokioExecutor::new()).build(connector);
+
+        let response = client.get(format!("https://{addr}/").parse()?).await?;
+
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let body = response.into_body().collect().await?.to_bytes();
+
+        Ok(String::from_utf8(body.to_vec())?)
+    }
+
+    #[tokio::test]
+    async fn containers_are_verified_against_the_pinned_authority() -> Result<()> {
+        let addr = spawn_tls_container().await?;
+        let ca = pinned_ca();
+
+        assert_eq!(request(addr, SERVER_NAME, Some(&ca)).await?, "encrypted");
+
+        // Neither the public roots nor a different server name match the container's certificate
+        assert!(request(addr, SERVER_NAME, None).await.is_err());
+        assert!(request(addr, "other.example.com", Some(&ca)).await.is_err());
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn pinned_authorities_must_hold_certificates() -> Result<()> {
+        assert_eq!(root_store(Some(&pinned_ca())).await?.len(), 2);
+
+        let key = ExternalBytes::Filesystem {
+            path: PathBuf::from("resources/certificates/stapled.key"),
+        };
+
+        assert!(root_store(Some(&key)).await.is_err());
+
+        Ok(())
+    }
+}
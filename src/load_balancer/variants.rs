@@ -0,0 +1,191 @@
+use std::net::IpAddr;
+
+use http::header::COOKIE;
+use http::HeaderMap;
+
+use crate::config::{RequestCondition, Route, VariantHeader};
+
+/// Finds the variant a client asked for through the variant header, if it is trusted to pick one.
+pub fn requested<'a>(
+    header: &VariantHeader,
+    headers: &'a HeaderMap,
+    addr: Option<IpAddr>,
+) -> Option<&'a str> {
+    let trusted = header.trusted.is_empty()
+        || addr.is_some_and(|addr| header.trusted.iter().any(|network| network.contains(addr)));
+
+    if !trusted {
+        return None;
+    }
+
+    headers.get(header.name.as_str())?.to_str().ok()
+}
+
+/// Checks whether a route can send requests to the given service instead of its own.
+pub fn is_known(route: &Route, service: &str) -> bool {
+    route
+        .split
+        .iter()
+        .map(|split| &split.service)
+        .chain(route.alternates.iter().map(|alternate| &alternate.service))
+        .any(|variant| variant == service)
+}
+
+/// Picks the service a request should be sent to instead of the one its route belongs to, if any.
+///
+/// `roll` is a random number below 100 used to decide whether the request falls in a split.
+pub fn choose<'a>(route: &'a Route, headers: &HeaderMap, roll: u8) -> Option<&'a str> {
+    let alternate = route
+        .alternates
+        .iter()
+        .find(|alternate| is_met(&alternate.when, headers))
+        .map(|alternate| alternate.service.as_str());
+
+    alternate.or_else(|| {
+        route
+            .split
+            .as_ref()
+            .filter(|split| roll < split.percentage)
+            .map(|split| split.service.as_str())
+    })
+}
+
+fn is_met(condition: &RequestCondition, headers: &HeaderMap) -> bool {
+    match condition {
+        RequestCondition::Header { name, value } => headers
+            .get_all(name.as_str())
+            .iter()
+            .any(|header| header.as_bytes() == value.as_bytes()),
+        RequestCondition::Cookie { name, value } => headers
+            .get_all(COOKIE)
+            .iter()
+            .filter_map(|header| header.to_str().ok())
+            .flat_map(|header| header.split(';'))
+            .filter_map(|cookie| cookie.trim().split_once('='))
+            .any(|cookie| cookie == (name.as_str(), value.as_str())),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use color_eyre::eyre::Result;
+    use http::header::COOKIE;
+    use http::{HeaderMap, HeaderValue};
+
+    use crate::config::{
+        AlternateService, IpNetwork, RequestCondition, Route, TrafficSplit, VariantHeader,
+    };
+    use crate::load_balancer::variants::{choose, is_known, requested};
+
+    fn build_route() -> Route {
+        Route {
+            split: Some(TrafficSplit {
+                service: String::from("canary"),
+                percentage: 10,
+            }),
+            alternates: vec![
+                AlternateService {
+                    service: String::from("beta"),
+                    when: RequestCondition::Header {
+                        name: String::from("X-Beta"),
+                        value: String::from("true"),
+                    },
+                },
+                AlternateService {
+                    service: String::from("preview"),
+                    when: RequestCondition::Cookie {
+                        name: String::from("preview"),
+                        value: String::from("1"),
+                    },
+                },
+            ],
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn splits_receive_their_percentage_of_requests() {
+        let route = build_route();
+        let headers = HeaderMap::new();
+
+        let chosen = (0..100).filter(|roll| choose(&route, &headers, *roll).is_some());
+
+        assert_eq!(chosen.count(), 10);
+        assert_eq!(choose(&route, &headers, 0), Some("canary"));
+        assert_eq!(choose(&route, &headers, 10), None);
+    }
+
+    #[test]
+    fn routes_without_splits_keep_their_service() {
+        assert_eq!(choose(&Route::default(), &HeaderMap::new(), 0), None);
+    }
+
+    #[test]
+    fn alternates_take_precedence_over_splits() {
+        let route = build_route();
+
+        let mut headers = HeaderMap::new();
+        headers.insert("x-beta", HeaderValue::from_static("true"));
+
+        assert_eq!(choose(&route, &headers, 0), Some("beta"));
+        assert_eq!(choose(&route, &headers, 99), Some("beta"));
+
+        headers.insert("x-beta", HeaderValue::from_static("false"));
+
+        assert_eq!(choose(&route, &headers, 99), None);
+    }
+
+    #[test]
+    fn alternates_can_match_on_cookies() {
+        let route = build_route();
+
+        let mut headers = HeaderMap::new();
+        headers.insert(COOKIE, HeaderValue::from_static("session=abc; preview=1"));
+
+        assert_eq!(choose(&route, &headers, 99), Some("preview"));
+
+        headers.insert(COOKIE, HeaderValue::from_static("preview=10"));
+
+        assert_eq!(choose(&route, &headers, 99), None);
+    }
+
+    #[test]
+    fn variants_are_only_requested_by_trusted_clients() -> Result<()> {
+        let header = VariantHeader {
+            name: String::from("X-F2-Variant"),
+            trusted: vec![IpNetwork::try_from(String::from("10.0.0.0/8"))?],
+        };
+
+        let mut headers = HeaderMap::new();
+        headers.insert("x-f2-variant", HeaderValue::from_static("canary"));
+
+        assert_eq!(
+            requested(&header, &headers, Some("10.0.0.1".parse()?)),
+            Some("canary")
+        );
+        assert_eq!(
+            requested(&header, &headers, Some("192.168.0.1".parse()?)),
+            None
+        );
+        assert_eq!(requested(&header, &headers, None), None);
+
+        let anyone = VariantHeader {
+            trusted: Vec::new(),
+            ..header
+        };
+
+        assert_eq!(requested(&anyone, &headers, None), Some("canary"));
+
+        Ok(())
+    }
+
+    #[test]
+    fn only_the_route_variants_are_known() {
+        let route = build_route();
+
+        assert!(is_known(&route, "canary"));
+        assert!(is_known(&route, "beta"));
+        assert!(!is_known(&route, "missing"));
+        assert!(!is_known(&Route::default(), "canary"));
+    }
+}
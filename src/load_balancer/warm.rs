@@ -0,0 +1,139 @@
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// Tracks which downstreams are likely to have an idle pooled connection, since the client does
+/// not expose its pool.
+#[derive(Debug, Default)]
+pub struct WarmDownstreams {
+    last_used: Mutex<HashMap<SocketAddr, Instant>>,
+}
+
+impl WarmDownstreams {
+    /// Records a completed request to a downstream, leaving its connection in the pool for up to
+    /// `idle_timeout`.
+    pub fn mark(&self, addr: SocketAddr, idle_timeout: Duration) {
+        let mut last_used = self.last_used.lock().unwrap();
+
+        last_used.retain(|_, used| used.elapsed() < idle_timeout);
+        last_used.insert(addr, Instant::now());
+    }
+
+    pub fn clear(&self) {
+        self.last_used.lock().unwrap().clear();
+    }
+
+    /// Whether a downstream is likely to still have an idle connection in the pool.
+    pub fn is_warm(&self, addr: SocketAddr, idle_timeout: Duration) -> bool {
+        self.last_used
+            .lock()
+            .unwrap()
+            .get(&addr)
+            .is_some_and(|used| used.elapsed() < idle_timeout)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use std::net::{Ipv4Addr, SocketAddr};
+    use std::time::Duration;
+
+    use indexmap::IndexSet;
+
+    use crate::docker::api::StartedContainerDetails;
+    use crate::docker::models::ContainerId;
+    use crate::load_balancer::round_robin::WeightedRoundRobin;
+    use crate::load_balancer::warm::WarmDownstreams;
+
+    const PORT: u16 = 8080;
+    const IDLE_TIMEOUT: Duration = Duration::from_secs(90);
+
+    fn downstreams(count: u8) -> IndexSet<StartedContainerDetails> {
+        (0..count)
+            .map(|i| StartedContainerDetails {
+                id: ContainerId(format!("container-{i}")),
+                addr: Ipv4Addr::new(172, 17, 0, i).into(),
+            })
+            .collect()
+    }
+
+    fn choose<'a>(
+        round_robin: &WeightedRoundRobin,
+        warm: &WarmDownstreams,
+        downstreams: &'a IndexSet<StartedContainerDetails>,
+    ) -> &'a StartedContainerDetails {
+        round_robin
+            .choose_preferring(
+                downstreams,
+                |_| 1,
+                |downstream| warm.is_warm(SocketAddr::new(downstream.addr, PORT), IDLE_TIMEOUT),
+            )
+            .unwrap()
+    }
+
+    #[test]
+    fn traffic_still_spreads_across_replicas_when_one_is_warm() {
+        let downstreams = downstreams(5);
+        let round_robin = WeightedRoundRobin::default();
+        let warm = WarmDownstreams::default();
+
+        let preferred = downstreams.get_index(3).unwrap();
+        warm.mark(SocketAddr::new(preferred.addr, PORT), IDLE_TIMEOUT);
+
+        let mut counts: HashMap<ContainerId, usize> = HashMap::new();
+
+        for _ in 0..20 {
+            let downstream = choose(&round_robin, &warm, &downstreams);
+            *counts.entry(downstream.id.clone()).or_default() += 1;
+        }
+
+        assert_eq!(counts.len(), 5);
+        assert!(counts.values().all(|count| *count == 4));
+    }
+
+    #[test]
+    fn warm_downstreams_break_ties() {
+        let downstreams = downstreams(3);
+        let round_robin = WeightedRoundRobin::default();
+        let warm = WarmDownstreams::default();
+
+        assert_eq!(
+            choose(&round_robin, &warm, &downstreams),
+            downstreams.get_index(0).unwrap()
+        );
+
+        // the other two downstreams are now tied, so the warm one goes first
+        let preferred = downstreams.get_index(2).unwrap();
+        warm.mark(SocketAddr::new(preferred.addr, PORT), IDLE_TIMEOUT);
+
+        assert_eq!(choose(&round_robin, &warm, &downstreams), preferred);
+    }
+
+    #[test]
+    fn connections_are_only_warm_on_the_port_they_were_made_to() {
+        let downstream = downstreams(1).pop().unwrap();
+        let warm = WarmDownstreams::default();
+
+        warm.mark(SocketAddr::new(downstream.addr, PORT + 1), IDLE_TIMEOUT);
+
+        assert!(!warm.is_warm(SocketAddr::new(downstream.addr, PORT), IDLE_TIMEOUT));
+
+        warm.mark(SocketAddr::new(downstream.addr, PORT), IDLE_TIMEOUT);
+        assert!(warm.is_warm(SocketAddr::new(downstream.addr, PORT), IDLE_TIMEOUT));
+
+        warm.clear();
+        assert!(!warm.is_warm(SocketAddr::new(downstream.addr, PORT), IDLE_TIMEOUT));
+    }
+
+    #[test]
+    fn connections_idle_for_longer_than_the_timeout_are_cold() {
+        let downstream = downstreams(1).pop().unwrap();
+        let warm = WarmDownstreams::default();
+
+        warm.mark(SocketAddr::new(downstream.addr, PORT), IDLE_TIMEOUT);
+
+        assert!(!warm.is_warm(SocketAddr::new(downstream.addr, PORT), Duration::ZERO));
+    }
+}
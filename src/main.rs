@@ -1,10 +1,12 @@
 use std::collections::HashMap;
+use std::env;
 use std::net::SocketAddrV4;
+use std::path::PathBuf;
 use std::sync::Arc;
 
 use arc_swap::ArcSwap;
-use color_eyre::eyre::{eyre, Result};
-use docker::client::{Client, DockerClient};
+use color_eyre::eyre::Result;
+use docker::client::{Client, DockerClient, DOCKER_HOST};
 use rsa::RsaPrivateKey;
 use service_registry::ServiceRegistry;
 use tokio::net::TcpListener;
@@ -17,10 +19,14 @@ use tracing_subscriber::EnvFilter;
 
 use crate::args::Args;
 use crate::common::Container;
-use crate::config::{Config, Service};
-use crate::docker::api::create_and_start_container;
+use crate::config::{Config, Scheme, TlsConfig};
+use crate::docker::api::{create_and_start_container, ensure_network};
+use crate::docker::tls::DOCKER_CERT_PATH;
+use crate::events::log_deploy_events;
+use crate::garbage_collector::collect_garbage;
 use crate::ipc::MessageBus;
 use crate::load_balancer::LoadBalancer;
+use crate::metrics::Metrics;
 use crate::reconciler::Reconciler;
 
 mod args;
@@ -28,9 +34,12 @@ mod common;
 mod config;
 mod crypto;
 mod docker;
+mod events;
+mod garbage_collector;
 mod health;
 mod ipc;
 mod load_balancer;
+mod metrics;
 mod reconciler;
 mod service_registry;
 
@@ -62,18 +71,43 @@ async fn main() -> Result<()> {
     let alb_config = &config.load().alb;
 
     let addr = alb_config.addr;
-    let tls = alb_config.tls.clone();
+    let mut tls = alb_config.tls.clone();
     let mtls = alb_config.mtls.clone();
 
+    // Generated certificates replace any that are configured, so nothing needs provisioning
+    if args.dev_tls {
+        tls.get_or_insert_with(TlsConfig::default).self_signed = true;
+
+        if !alb_config.ports.contains_key(&Scheme::Https) {
+            tracing::warn!("no https port is configured, so self-signed certificates are unused");
+        }
+    }
+
     let mut service_registry = ServiceRegistry::new();
     let private_key = config.load().get_private_key().await?;
 
-    let docker_client = Client::default();
-    let services = &config.load().services;
+    // Follow Docker's own tools in reading the daemon's address from the environment
+    let docker_host = args
+        .docker_host
+        .clone()
+        .or_else(|| env::var(DOCKER_HOST).ok());
+    let docker_cert_path = args
+        .docker_cert_path
+        .clone()
+        .or_else(|| env::var_os(DOCKER_CERT_PATH).map(PathBuf::from));
+
+    let mut docker_client = match docker_host {
+        Some(host) => Client::from_host(&host, docker_cert_path.as_deref())?,
+        None => Client::default(),
+    }
+    .with_retries(config.load().docker_retries.clone());
+
+    docker_client.negotiate_version().await?;
+    ensure_network(&docker_client, config.load().network.as_ref()).await?;
 
     start_services(
         &docker_client,
-        services,
+        &config.load(),
         &mut service_registry,
         private_key.as_ref(),
     )
@@ -82,11 +116,15 @@ async fn main() -> Result<()> {
     let service_registry = Arc::new(RwLock::new(service_registry));
     let message_bus = MessageBus::new();
 
+    // Always consume deploy events, since they can be enabled by a later reconciliation
+    tokio::spawn(log_deploy_events(Arc::clone(&message_bus)));
+    tokio::spawn(collect_garbage(docker_client.clone(), Arc::clone(&config)));
+
     let reconciler = Reconciler::new(
         Arc::clone(&service_registry),
         args.config_location.clone(),
         Arc::clone(&config),
-        docker_client,
+        docker_client.clone(),
         Arc::clone(&message_bus),
     );
 
@@ -94,24 +132,37 @@ async fn main() -> Result<()> {
 
     for (protocol, port) in alb_config.ports.iter() {
         let listener = TcpListener::bind(SocketAddrV4::new(addr, *port)).await?;
-        listeners.insert(protocol.clone(), listener);
+        listeners.insert(*protocol, listener);
     }
 
-    let load_balancer = LoadBalancer::new(service_registry, config, message_bus);
+    let metrics = Metrics::new();
+    let load_balancer = LoadBalancer::new(
+        service_registry,
+        Arc::clone(&config),
+        message_bus,
+        metrics,
+        Arc::new(docker_client),
+    );
     let shutdown_signal = handle_shutdown_signal();
 
-    tokio::try_join!(
-        load_balancer.run(listeners, tls, mtls),
-        reconciler.run(),
-        shutdown_signal
-    )?;
+    // Stop listening and drain in-flight requests before touching any containers
+    tokio::select! {
+        result = load_balancer.run(listeners, tls, mtls, shutdown_signal) => result?,
+        result = reconciler.run() => result?,
+        () = reconciler.watch_containers() => {}
+    }
+
+    if config.load().shutdown.stop_containers {
+        tracing::info!("stopping managed containers");
+        reconciler.stop_all_containers().await?;
+    }
 
     tracing::info!("shutting down gracefully, all components have completed their tasks");
 
     Ok(())
 }
 
-async fn handle_shutdown_signal() -> Result<()> {
+async fn handle_shutdown_signal() {
     let ctrl_c = async {
         tokio::signal::ctrl_c()
             .await
@@ -130,25 +181,40 @@ async fn handle_shutdown_signal() -> Result<()> {
         _ = terminate => {},
     }
 
-    Err(eyre!("shutdown signal received, exiting..."))
+    tracing::info!("shutdown signal received, exiting...");
 }
 
 async fn start_services<C: DockerClient>(
     client: &C,
-    services: &HashMap<String, Service>,
+    config: &Config,
     service_registry: &mut ServiceRegistry,
     private_key: Option<&RsaPrivateKey>,
 ) -> Result<()> {
+    let Config {
+        services,
+        registries,
+        ..
+    } = config;
+
+    if services.is_empty() {
+        tracing::info!(
+            "no services are configured, waiting for them to be added by reconciliation"
+        );
+    }
+
     for (name, service) in services {
         service_registry.define(name, service.clone());
 
         let tag = &service.tag;
-        let container = Container::from(service);
+        let container = Container::from(service).with_registries(registries);
 
         tracing::info!(%name, %tag, "starting service");
 
-        for _ in 0..service.replicas.get() {
-            let details = create_and_start_container(client, &container, tag, private_key).await?;
+        for replica in 0..usize::from(service.replicas.get()) {
+            let details =
+                create_and_start_container(client, name, replica, &container, tag, private_key)
+                    .await?;
+            service_registry.set_replica(&details.id, replica);
             service_registry.add_container(name, details);
         }
     }
@@ -0,0 +1,265 @@
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+
+use crate::config::Scheme;
+
+/// Counters describing the connections handled by a single listener.
+#[derive(Debug, Default)]
+pub struct ConnectionMetrics {
+    accepted: AtomicU64,
+    active: AtomicU64,
+    errors: AtomicU64,
+}
+
+impl ConnectionMetrics {
+    pub fn accepted(&self) -> u64 {
+        self.accepted.load(Ordering::Relaxed)
+    }
+
+    pub fn active(&self) -> u64 {
+        self.active.load(Ordering::Relaxed)
+    }
+
+    pub fn errors(&self) -> u64 {
+        self.errors.load(Ordering::Relaxed)
+    }
+}
+
+/// When the certificate for a domain expires, and whether that is within the warning window.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub struct CertificateExpiry {
+    /// The Unix timestamp the certificate stops being valid at.
+    pub not_after: i64,
+    pub expiring: bool,
+}
+
+/// Metrics collected by the load balancer, rendered in the Prometheus text format.
+#[derive(Debug, Default)]
+pub struct Metrics {
+    http: ConnectionMetrics,
+    https: ConnectionMetrics,
+    certificates: Mutex<BTreeMap<String, CertificateExpiry>>,
+    unmatched_server_names: AtomicU64,
+}
+
+impl Metrics {
+    pub fn new() -> Arc<Self> {
+        Arc::new(Self::default())
+    }
+
+    pub fn connections(&self, scheme: Scheme) -> &ConnectionMetrics {
+        match scheme {
+            Scheme::Http => &self.http,
+            Scheme::Https => &self.https,
+        }
+    }
+
+    /// Records a newly accepted connection, returning a guard that marks it as active until it is
+    /// dropped.
+    pub fn track_connection(self: &Arc<Self>, scheme: Scheme) -> ActiveConnection {
+        let connections = self.connections(scheme);
+
+        connections.accepted.fetch_add(1, Ordering::Relaxed);
+        connections.active.fetch_add(1, Ordering::Relaxed);
+
+        ActiveConnection {
+            metrics: Arc::clone(self),
+            scheme,
+        }
+    }
+
+    pub fn record_connection_error(&self, scheme: Scheme) {
+        self.connections(scheme)
+            .errors
+            .fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Replaces the expiry of every loaded certificate, so removed domains stop being reported.
+    pub fn record_certificate_expiry(&self, certificates: BTreeMap<String, CertificateExpiry>) {
+        *self.certificates.lock().unwrap() = certificates;
+    }
+
+    /// Records a TLS handshake for a server name without a certificate of its own.
+    pub fn record_unmatched_server_name(&self) {
+        self.unmatched_server_names.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn unmatched_server_names(&self) -> u64 {
+        self.unmatched_server_names.load(Ordering::Relaxed)
+    }
+
+    pub fn render(&self) -> String {
+        let mut output = String::new();
+
+        self.render_family(
+            &mut output,
+            "f2_connections_accepted_total",
+            "counter",
+            ConnectionMetrics::accepted,
+        );
+        self.render_family(
+            &mut output,
+            "f2_connections_active",
+            "gauge",
+            ConnectionMetrics::active,
+        );
+        self.render_family(
+            &mut output,
+            "f2_connection_errors_total",
+            "counter",
+            ConnectionMetrics::errors,
+        );
+
+        let name = "f2_tls_unmatched_server_names_total";
+        let _ = writeln!(output, "# TYPE {name} counter");
+        let _ = writeln!(output, "{name} {}", self.unmatched_server_names());
+
+        self.render_certificates(&mut output);
+
+        output
+    }
+
+    fn render_certificates(&self, output: &mut String) {
+        let certificates = self.certificates.lock().unwrap();
+
+        if certificates.is_empty() {
+            return;
+        }
+
+        let name = "f2_certificate_expiry_timestamp_seconds";
+        let _ = writeln!(output, "# TYPE {name} gauge");
+
+        for (domain, expiry) in certificates.iter() {
+            let _ = writeln!(output, "{name}{{domain=\"{domain}\"}} {}", expiry.not_after);
+        }
+
+        let name = "f2_certificate_expiring";
+        let _ = writeln!(output, "# TYPE {name} gauge");
+
+        for (domain, expiry) in certificates.iter() {
+            let _ = writeln!(
+                output,
+                "{name}{{domain=\"{domain}\"}} {}",
+                u8::from(expiry.expiring)
+            );
+        }
+    }
+
+    fn render_family<F>(&self, output: &mut String, name: &str, kind: &str, value: F)
+    where
+        F: Fn(&ConnectionMetrics) -> u64,
+    {
+        let _ = writeln!(output, "# TYPE {name} {kind}");
+
+        for scheme in [Scheme::Http, Scheme::Https] {
+            let value = value(self.connections(scheme));
+            let _ = writeln!(output, "{name}{{scheme=\"{scheme}\"}} {value}");
+        }
+    }
+}
+
+/// Marks a connection as active for as long as it is held.
+#[derive(Debug)]
+pub struct ActiveConnection {
+    metrics: Arc<Metrics>,
+    scheme: Scheme,
+}
+
+impl Drop for ActiveConnection {
+    fn drop(&mut self) {
+        self.metrics
+            .connections(self.scheme)
+            .active
+            .fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+
+    use crate::config::Scheme;
+    use crate::metrics::{CertificateExpiry, Metrics};
+
+    #[test]
+    fn active_connections_are_released_on_drop() {
+        let metrics = Metrics::new();
+
+        let first = metrics.track_connection(Scheme::Http);
+        let second = metrics.track_connection(Scheme::Http);
+
+        assert_eq!(metrics.connections(Scheme::Http).active(), 2);
+
+        drop(first);
+
+        assert_eq!(metrics.connections(Scheme::Http).active(), 1);
+
+        drop(second);
+
+        let connections = metrics.connections(Scheme::Http);
+
+        assert_eq!(connections.active(), 0);
+        assert_eq!(connections.accepted(), 2);
+    }
+
+    #[test]
+    fn metrics_are_tracked_per_scheme() {
+        let metrics = Metrics::new();
+
+        let _connection = metrics.track_connection(Scheme::Https);
+        metrics.record_connection_error(Scheme::Http);
+
+        assert_eq!(metrics.connections(Scheme::Http).accepted(), 0);
+        assert_eq!(metrics.connections(Scheme::Http).errors(), 1);
+        assert_eq!(metrics.connections(Scheme::Https).active(), 1);
+    }
+
+    #[test]
+    fn can_render_metrics() {
+        let metrics = Metrics::new();
+
+        let _connection = metrics.track_connection(Scheme::Http);
+        let rendered = metrics.render();
+
+        assert!(rendered.contains("# TYPE f2_connections_active gauge"));
+        assert!(rendered.contains("f2_connections_active{scheme=\"http\"} 1"));
+        assert!(rendered.contains("f2_connections_accepted_total{scheme=\"https\"} 0"));
+    }
+
+    #[test]
+    fn certificate_expiry_is_rendered_per_domain() {
+        let metrics = Metrics::new();
+
+        assert!(!metrics.render().contains("f2_certificate_expiring"));
+
+        metrics.record_certificate_expiry(BTreeMap::from([(
+            String::from("example.com"),
+            CertificateExpiry {
+                not_after: 1_800_000_000,
+                expiring: true,
+            },
+        )]));
+
+        let rendered = metrics.render();
+
+        assert!(rendered.contains(
+            "f2_certificate_expiry_timestamp_seconds{domain=\"example.com\"} 1800000000"
+        ));
+        assert!(rendered.contains("f2_certificate_expiring{domain=\"example.com\"} 1"));
+    }
+
+    #[test]
+    fn unmatched_server_names_are_counted() {
+        let metrics = Metrics::new();
+
+        metrics.record_unmatched_server_name();
+        metrics.record_unmatched_server_name();
+
+        assert_eq!(metrics.unmatched_server_names(), 2);
+        assert!(metrics
+            .render()
+            .contains("f2_tls_unmatched_server_names_total 2"));
+    }
+}
@@ -1,17 +1,28 @@
+use std::collections::HashMap;
 use std::sync::Arc;
+use std::time::Duration;
 
 use arc_swap::ArcSwap;
 use color_eyre::eyre::{eyre, Result};
+use futures::StreamExt;
 use indexmap::IndexSet;
-use tokio::sync::RwLock;
+use tokio::sync::{Mutex, RwLock};
 
 use crate::common::Container;
-use crate::config::{Config, Diff, ExternalBytes, ReplicaCount, Service, ShutdownMode};
-use crate::docker::api::{create_and_start_container, StartedContainerDetails};
+use crate::config::{
+    Config, Diff, ExternalBytes, ReplicaCount, RestartPolicy, Service, ShutdownMode,
+};
+use crate::docker::api::{create_and_start_container, run_hook, StartedContainerDetails};
 use crate::docker::client::DockerClient;
+use crate::docker::events::{ContainerAction, ContainerEvent};
+use crate::docker::models::ContainerId;
+use crate::events::{DeployEvent, DeployTrigger};
 use crate::ipc::MessageBus;
 use crate::service_registry::ServiceRegistry;
 
+/// How long to wait before subscribing to container events again after losing the connection.
+const EVENTS_RECONNECT_DELAY: Duration = Duration::from_secs(5);
+
 #[derive(Debug)]
 pub struct Reconciler<C: DockerClient> {
     registry: Arc<RwLock<ServiceRegistry>>,
@@ -19,6 +30,9 @@ pub struct Reconciler<C: DockerClient> {
     config: Arc<ArcSwap<Config>>,
     docker_client: C,
     message_bus: Arc<MessageBus>,
+    /// Containers that died and are expected to be restarted by the daemon, with their service and
+    /// replica index if known.
+    restarting: Mutex<HashMap<ContainerId, (String, Option<usize>)>>,
 }
 
 impl<C: DockerClient> Reconciler<C> {
@@ -35,38 +49,289 @@ impl<C: DockerClient> Reconciler<C> {
             config,
             docker_client,
             message_bus,
+            restarting: Mutex::default(),
         }
     }
 
     pub async fn run(&self) -> Result<()> {
-        while self
-            .message_bus
-            .receive_reconciliation_request()
-            .await
-            .is_ok()
-        {
+        while let Ok(message) = self.message_bus.receive_reconciliation_request().await {
             tracing::info!("received signal to reconcile");
-            self.reconcile().await?;
+
+            let trigger = DeployTrigger::new(message.identifier(), "reconciliation-request");
+
+            // A failed reconciliation should not take the load balancer down with it
+            if let Err(error) = self.reconcile(&trigger).await {
+                tracing::error!(?error, "failed to reconcile, waiting for the next request");
+            }
         }
 
         Ok(())
     }
 
-    async fn reconcile(&self) -> Result<()> {
-        let new_config = Config::from_location(&self.config_location).await?;
-        let old_config = self.config.load();
+    /// Watches for managed containers dying without f2 stopping them, taking them out of rotation
+    /// until the daemon restarts them or replacing them if their service asks for it.
+    ///
+    /// The subscription is renewed whenever the connection to the daemon is lost, so this never
+    /// returns.
+    pub async fn watch_containers(&self) {
+        loop {
+            if let Err(error) = self.follow_container_events().await {
+                tracing::warn!(?error, "lost the subscription to container events");
+            }
+
+            tokio::time::sleep(EVENTS_RECONNECT_DELAY).await;
+        }
+    }
+
+    async fn follow_container_events(&self) -> Result<()> {
+        let mut events = self.docker_client.stream_events().await?;
+
+        while let Some(event) = events.next().await {
+            // A container that cannot be replaced should not stop the others being watched
+            if let Err(error) = self.handle_container_event(event?).await {
+                tracing::error!(?error, "failed to handle a container event");
+            }
+        }
+
+        Ok(())
+    }
+
+    #[tracing::instrument(skip(self))]
+    async fn handle_container_event(&self, event: ContainerEvent) -> Result<()> {
+        match event.action {
+            ContainerAction::OutOfMemory => {
+                tracing::warn!(id = %event.id, "container ran out of memory");
+            }
+            ContainerAction::Died { exit_code } => {
+                self.handle_death(&event.id, exit_code, event.replica)
+                    .await?
+            }
+            ContainerAction::Started => self.handle_restart(&event.id).await?,
+        }
+
+        Ok(())
+    }
+
+    async fn handle_death(
+        &self,
+        id: &ContainerId,
+        exit_code: Option<i64>,
+        replica: Option<usize>,
+    ) -> Result<()> {
+        let mut write_lock = self.registry.write().await;
+
+        // Containers f2 stops itself are taken out of the registry first, so are ignored here
+        let Some(name) = write_lock.find_service_for_container(id).map(str::to_owned) else {
+            return Ok(());
+        };
+
+        write_lock.remove_container_by_id(&name, id);
+        let definition = write_lock.get_definition(&name).cloned();
+
+        drop(write_lock);
+
+        tracing::warn!(%name, %id, ?exit_code, "container died, removing it from the load balancer");
+
+        let Some(definition) = definition else {
+            return Ok(());
+        };
+
+        // The daemon gives up once a container has used its retries, so f2 has to take over
+        let restart_count = match definition.restart_policy {
+            RestartPolicy::OnFailure {
+                max_retries: Some(_),
+            } => self.docker_client.get_restart_count(id).await?,
+            _ => 0,
+        };
+
+        if restarted_by_daemon(definition.restart_policy, exit_code, restart_count) {
+            self.restarting
+                .lock()
+                .await
+                .insert(id.clone(), (name, replica));
+            return Ok(());
+        }
+
+        if definition.replace_dead_containers {
+            let replica = match replica {
+                Some(replica) => replica,
+                None => self
+                    .get_running_containers(&name)
+                    .await
+                    .map_or(0, |c| c.len()),
+            };
+
+            self.replace_container(&name, &definition, replica).await?;
+            self.docker_client.remove_container(id).await?;
+        }
+
+        Ok(())
+    }
+
+    async fn handle_restart(&self, id: &ContainerId) -> Result<()> {
+        let Some((name, replica)) = self.restarting.lock().await.remove(id) else {
+            return Ok(());
+        };
+
+        let addr = self.docker_client.get_container_ip(id).await?;
+        let mut write_lock = self.registry.write().await;
+
+        // The service may have been removed or redeployed while the container was down
+        let Some(definition) = write_lock.get_definition(&name) else {
+            return Ok(());
+        };
+
+        // Removing the container when it died also removed its weight and replica
+        if let Some(replica) = replica {
+            if let Some(weight) = definition.weights.get(replica).copied() {
+                write_lock.set_weight(id, weight);
+            }
+
+            write_lock.set_replica(id, replica);
+        }
+
+        tracing::info!(%name, %id, "container was restarted, adding it back to the load balancer");
+
+        let details = StartedContainerDetails {
+            id: id.clone(),
+            addr,
+        };
+
+        write_lock.add_container(&name, details);
+
+        Ok(())
+    }
+
+    /// Removes the containers of a service that are waiting to be restarted by the daemon, which
+    /// would otherwise be added back to the load balancer once they start.
+    async fn remove_restarting_containers(&self, name: &str) -> Result<()> {
+        let mut restarting = self.restarting.lock().await;
+        let ids: Vec<_> = restarting
+            .iter()
+            .filter(|(_, (service, _))| *service == name)
+            .map(|(id, _)| id.clone())
+            .collect();
+
+        restarting.retain(|_, (service, _)| service != name);
+        drop(restarting);
+
+        for id in &ids {
+            self.docker_client.remove_container(id).await?;
+        }
+
+        Ok(())
+    }
+
+    async fn replace_container(
+        &self,
+        name: &str,
+        definition: &Service,
+        replica: usize,
+    ) -> Result<()> {
+        let config = self.config.load_full();
+        let private_key = config.get_private_key().await?;
+        let container = Container::from(definition).with_registries(&config.registries);
+
+        let details = create_and_start_container(
+            &self.docker_client,
+            name,
+            replica,
+            &container,
+            &definition.tag,
+            private_key.as_ref(),
+        )
+        .await?;
+
+        tracing::info!(%name, id = %details.id, "started a container to replace a dead one");
+
+        let mut write_lock = self.registry.write().await;
+
+        if let Some(weight) = definition.weights.get(replica) {
+            write_lock.set_weight(&details.id, *weight);
+        }
 
-        if let Some(diff) = old_config.diff(&new_config) {
-            self.config.store(Arc::new(new_config.clone()));
+        write_lock.set_replica(&details.id, replica);
+        write_lock.add_container(name, details);
 
-            for event in diff {
-                self.handle_diff(event).await?;
+        Ok(())
+    }
+
+    /// Stops every container managed by the registry, respecting each service's shutdown mode.
+    pub async fn stop_all_containers(&self) -> Result<()> {
+        let config = self.config.load();
+
+        for (name, service) in &config.services {
+            let Some(containers) = self.get_running_containers(name).await else {
+                continue;
+            };
+
+            self.run_pre_stop_hooks(service, &containers).await;
+            self.registry.write().await.remove_all_containers(name);
+
+            tracing::info!(%name, count = %containers.len(), "stopping containers for service");
+
+            for details in &containers {
+                match service.shutdown_mode {
+                    ShutdownMode::Graceful => {
+                        self.docker_client.stop_container(&details.id).await?
+                    }
+                    ShutdownMode::Forceful => {
+                        self.docker_client.remove_container(&details.id).await?
+                    }
+                }
             }
+
+            self.remove_restarting_containers(name).await?;
         }
 
         Ok(())
     }
 
+    async fn reconcile(&self, trigger: &DeployTrigger) -> Result<()> {
+        let new_config = Config::from_location(&self.config_location).await?;
+        let old_config = self.config.load_full();
+
+        let Some(diff) = old_config.diff(&new_config) else {
+            return Ok(());
+        };
+
+        // Containers are started with the new registries and secrets, so those are needed first
+        self.config.store(Arc::new(new_config.clone()));
+
+        let mut failed = Vec::new();
+
+        for event in diff {
+            let name = event.name().to_owned();
+
+            if let Err(error) = self.handle_diff(event, trigger).await {
+                tracing::error!(?error, %name, "failed to apply the changes to a service");
+                failed.push(name);
+            }
+        }
+
+        if failed.is_empty() {
+            return Ok(());
+        }
+
+        // Keep the previous definitions of services that failed, so the next reconciliation finds
+        // their changes again and retries them
+        let mut applied = new_config;
+
+        for name in &failed {
+            match old_config.services.get(name) {
+                Some(definition) => applied.services.insert(name.clone(), definition.clone()),
+                None => applied.services.remove(name),
+            };
+        }
+
+        self.config.store(Arc::new(applied));
+
+        Err(eyre!(
+            "failed to apply the changes to {}",
+            failed.join(", ")
+        ))
+    }
+
     async fn get_running_containers(
         &self,
         name: &str,
@@ -86,12 +351,15 @@ impl<C: DockerClient> Reconciler<C> {
         // Keep the locks short, create everything then add to the LB
         let mut started_containers = Vec::new();
 
-        let private_key = self.config.load().get_private_key().await?;
-        let container = Container::from(&new_definition);
+        let config = self.config.load_full();
+        let private_key = config.get_private_key().await?;
+        let container = Container::from(&new_definition).with_registries(&config.registries);
 
-        for _ in 0..replicas.get() {
+        for replica in 0..usize::from(replicas.get()) {
             let details = create_and_start_container(
                 &self.docker_client,
+                name,
+                replica,
                 &container,
                 &new_definition.tag,
                 private_key.as_ref(),
@@ -102,11 +370,17 @@ impl<C: DockerClient> Reconciler<C> {
         }
 
         let mut write_lock = self.registry.write().await;
-        write_lock.define(name, new_definition);
 
-        started_containers
-            .into_iter()
-            .for_each(|details| write_lock.add_container(name, details));
+        for (index, details) in started_containers.into_iter().enumerate() {
+            if let Some(weight) = new_definition.weights.get(index) {
+                write_lock.set_weight(&details.id, *weight);
+            }
+
+            write_lock.set_replica(&details.id, index);
+            write_lock.add_container(name, details);
+        }
+
+        write_lock.define(name, new_definition);
 
         Ok(())
     }
@@ -118,6 +392,18 @@ impl<C: DockerClient> Reconciler<C> {
         old_definition: Service,
         new_definition: Service,
     ) -> Result<()> {
+        if old_definition.only_routing_differs(&new_definition) {
+            tracing::info!(
+                %name,
+                maintenance = %new_definition.maintenance,
+                "updating routing without replacing containers"
+            );
+
+            self.registry.write().await.define(name, new_definition);
+
+            return Ok(());
+        }
+
         let running_containers = self
             .get_running_containers(name)
             .await
@@ -125,19 +411,45 @@ impl<C: DockerClient> Reconciler<C> {
 
         let replicas = new_definition.replicas;
 
-        self.start_multiple_containers(name, new_definition, replicas)
-            .await?;
+        // Containers publishing ports on the host can't run alongside their replacements, so those
+        // have to be stopped before the new ones start
+        if old_definition.published_ports.is_empty() {
+            self.start_multiple_containers(name, new_definition, replicas)
+                .await?;
+            self.retire_containers(name, &running_containers, &old_definition)
+                .await?;
+        } else {
+            self.retire_containers(name, &running_containers, &old_definition)
+                .await?;
+            self.start_multiple_containers(name, new_definition, replicas)
+                .await?;
+        }
+
+        self.remove_restarting_containers(name).await?;
+        self.message_bus.send_pool_eviction_request()?;
+
+        Ok(())
+    }
+
+    /// Takes containers out of the load balancer before stopping them.
+    async fn retire_containers(
+        &self,
+        name: &str,
+        containers: &IndexSet<StartedContainerDetails>,
+        definition: &Service,
+    ) -> Result<()> {
+        self.run_pre_stop_hooks(definition, containers).await;
 
         let mut write_lock = self.registry.write().await;
 
-        for details in &running_containers {
+        for details in containers {
             write_lock.remove_container_by_id(name, &details.id);
         }
 
         drop(write_lock);
 
-        for details in &running_containers {
-            match old_definition.shutdown_mode {
+        for details in containers {
+            match definition.shutdown_mode {
                 ShutdownMode::Graceful => {
                     self.docker_client.stop_container(&details.id).await?;
                 }
@@ -150,6 +462,26 @@ impl<C: DockerClient> Reconciler<C> {
         Ok(())
     }
 
+    /// Runs the service's pre-stop hook in each container while it is still in rotation, stopping
+    /// the containers regardless of whether it succeeds.
+    async fn run_pre_stop_hooks(
+        &self,
+        definition: &Service,
+        containers: &IndexSet<StartedContainerDetails>,
+    ) {
+        let Some(command) = &definition.pre_stop else {
+            return;
+        };
+
+        for details in containers {
+            if let Err(error) =
+                run_hook(&self.docker_client, &details.id, "pre-stop", command).await
+            {
+                tracing::warn!(?error, id = %details.id, "pre-stop hook failed, stopping anyway");
+            }
+        }
+    }
+
     #[tracing::instrument(skip(self))]
     async fn handle_addition(&self, name: String, definition: Service) -> Result<()> {
         let replicas = definition.replicas;
@@ -166,6 +498,12 @@ impl<C: DockerClient> Reconciler<C> {
 
         // Remove them from the LB
         if let Some(containers) = running_containers {
+            let definition = self.registry.read().await.get_definition(&name).cloned();
+
+            if let Some(definition) = &definition {
+                self.run_pre_stop_hooks(definition, &containers).await;
+            }
+
             let mut write_lock = self.registry.write().await;
 
             write_lock.undefine(&name);
@@ -176,13 +514,29 @@ impl<C: DockerClient> Reconciler<C> {
             for details in &containers {
                 self.docker_client.remove_container(&details.id).await?;
             }
+
+            self.message_bus.send_pool_eviction_request()?;
         }
 
+        self.remove_restarting_containers(&name).await?;
+
         Ok(())
     }
 
     #[tracing::instrument(skip(self))]
-    async fn handle_diff(&self, diff: Diff) -> Result<()> {
+    async fn handle_diff(&self, diff: Diff, trigger: &DeployTrigger) -> Result<()> {
+        let event = if self.config.load().deploy_events {
+            let read_lock = self.registry.read().await;
+            let previous = match &diff {
+                Diff::Removal { name } => read_lock.get_definition(name),
+                _ => None,
+            };
+
+            Some(DeployEvent::new(&diff, previous, trigger.clone()))
+        } else {
+            None
+        };
+
         match diff {
             Diff::Alteration {
                 name,
@@ -196,26 +550,59 @@ impl<C: DockerClient> Reconciler<C> {
             Diff::Removal { name } => self.handle_removal(name).await?,
         }
 
+        if let Some(event) = event {
+            self.message_bus.send_deploy_event(event)?;
+        }
+
         Ok(())
     }
 }
 
+/// Whether the daemon will start a container again by itself after it exits with `exit_code`,
+/// having already restarted it `restart_count` times.
+fn restarted_by_daemon(
+    restart_policy: RestartPolicy,
+    exit_code: Option<i64>,
+    restart_count: u32,
+) -> bool {
+    match restart_policy {
+        RestartPolicy::No => false,
+        RestartPolicy::OnFailure { max_retries } => {
+            exit_code != Some(0) && max_retries.is_none_or(|max| restart_count < max)
+        }
+        RestartPolicy::UnlessStopped => true,
+    }
+}
+
 #[cfg(test)]
 pub mod tests {
-    use std::collections::HashMap;
-    use std::net::Ipv4Addr;
+    use std::collections::{HashMap, HashSet};
+    use std::net::{IpAddr, Ipv4Addr};
     use std::path::PathBuf;
     use std::sync::Arc;
+    use std::time::Duration;
 
     use arc_swap::ArcSwap;
-    use color_eyre::eyre::Result;
+    use color_eyre::eyre::{eyre, Result};
+    use futures::StreamExt;
+    use http::Method;
     use tokio::sync::RwLock;
-
-    use crate::common::Environment;
-    use crate::config::{AlbConfig, Config, Diff, ExternalBytes, ReplicaCount, Scheme, Service};
+    use uuid::Uuid;
+
+    use crate::common::{Environment, Mounts, RuntimeOptions};
+    use crate::config::{
+        AlbConfig, Config, ConnectionPool, Diff, DockerRetryConfig, ExternalBytes, NetworkConfig,
+        PublishedPort, ReplicaCount, RestartPolicy, Route, Scheme, Service, ShutdownConfig,
+        ShutdownMode, TrafficSplit,
+    };
     use crate::docker::api::StartedContainerDetails;
     use crate::docker::client::DockerClient;
-    use crate::docker::models::{ContainerId, ImageSummary, NetworkId};
+    use crate::docker::events::{ContainerAction, ContainerEvent, EventStream};
+    use crate::docker::logs::{LogFrame, LogStream};
+    use crate::docker::models::{
+        ContainerId, HealthStatus, ImageSummary, NetworkId, PruneReport, RegistryAuth,
+    };
+    use crate::events::{DeployEventKind, DeployTrigger};
     use crate::ipc::MessageBus;
     use crate::reconciler::Reconciler;
     use crate::service_registry::ServiceRegistry;
@@ -224,6 +611,21 @@ pub mod tests {
     struct DockerState {
         images: Vec<ImageSummary>,
         containers: Vec<(ContainerId, String)>,
+        labels: HashMap<ContainerId, HashMap<String, String>>,
+        host_ports: HashMap<ContainerId, Vec<u16>>,
+        connections: HashMap<ContainerId, Vec<(NetworkId, Vec<String>)>>,
+        execs: Vec<(ContainerId, Vec<String>)>,
+        missing_networks: HashSet<String>,
+        created_networks: Vec<(String, NetworkConfig)>,
+        exec_exit_code: i64,
+        container_prunes: Vec<(String, Duration)>,
+        image_prunes: Vec<Duration>,
+        logs: HashMap<ContainerId, Vec<LogFrame>>,
+        health: Vec<HealthStatus>,
+        pulls: usize,
+        last_pull_auth: Option<RegistryAuth>,
+        failing_creates: usize,
+        restart_counts: HashMap<ContainerId, u32>,
     }
 
     #[derive(Clone, Default)]
@@ -231,6 +633,88 @@ pub mod tests {
         state: Arc<RwLock<DockerState>>,
     }
 
+    impl FakeDockerClient {
+        pub async fn add_image(&self, image_and_tag: &str) {
+            let mut lock = self.state.write().await;
+
+            lock.images.push(ImageSummary {
+                repo_tags: vec![image_and_tag.to_owned()],
+            });
+        }
+
+        pub async fn pull_count(&self) -> usize {
+            self.state.read().await.pulls
+        }
+
+        pub async fn last_pull_auth(&self) -> Option<RegistryAuth> {
+            self.state.read().await.last_pull_auth.clone()
+        }
+
+        pub async fn labels(&self, id: &ContainerId) -> HashMap<String, String> {
+            self.state.read().await.labels[id].clone()
+        }
+
+        pub async fn set_restart_count(&self, id: &ContainerId, count: u32) {
+            self.state
+                .write()
+                .await
+                .restart_counts
+                .insert(id.clone(), count);
+        }
+
+        /// Makes the next `count` container creations fail.
+        pub async fn fail_creates(&self, count: usize) {
+            self.state.write().await.failing_creates = count;
+        }
+
+        /// Makes health checks report each of `statuses` in turn, then pass.
+        pub async fn set_health(&self, statuses: Vec<HealthStatus>) {
+            self.state.write().await.health = statuses;
+        }
+
+        pub async fn container_count(&self) -> usize {
+            self.state.read().await.containers.len()
+        }
+
+        pub async fn connections(&self, id: &ContainerId) -> Vec<(NetworkId, Vec<String>)> {
+            let lock = self.state.read().await;
+            lock.connections.get(id).cloned().unwrap_or_default()
+        }
+
+        pub async fn remove_network(&self, name: &str) {
+            self.state
+                .write()
+                .await
+                .missing_networks
+                .insert(name.to_owned());
+        }
+
+        pub async fn created_networks(&self) -> Vec<(String, NetworkConfig)> {
+            self.state.read().await.created_networks.clone()
+        }
+
+        pub async fn execs(&self) -> Vec<(ContainerId, Vec<String>)> {
+            self.state.read().await.execs.clone()
+        }
+
+        pub async fn set_exec_exit_code(&self, exit_code: i64) {
+            self.state.write().await.exec_exit_code = exit_code;
+        }
+
+        pub async fn container_prunes(&self) -> Vec<(String, Duration)> {
+            self.state.read().await.container_prunes.clone()
+        }
+
+        pub async fn image_prunes(&self) -> Vec<Duration> {
+            self.state.read().await.image_prunes.clone()
+        }
+
+        pub async fn add_logs(&self, id: &ContainerId, frames: Vec<LogFrame>) {
+            let mut lock = self.state.write().await;
+            lock.logs.entry(id.clone()).or_default().extend(frames);
+        }
+    }
+
     #[async_trait::async_trait]
     impl DockerClient for FakeDockerClient {
         async fn fetch_images(&self) -> Result<Vec<ImageSummary>> {
@@ -239,7 +723,16 @@ pub mod tests {
             Ok(lock.images.clone())
         }
 
-        async fn pull_image(&self, _image: &str, _tag: &str) -> Result<()> {
+        async fn pull_image(
+            &self,
+            _image: &str,
+            _tag: &str,
+            auth: Option<&RegistryAuth>,
+        ) -> Result<()> {
+            let mut lock = self.state.write().await;
+            lock.pulls += 1;
+            lock.last_pull_auth = auth.cloned();
+
             Ok(())
         }
 
@@ -247,24 +740,81 @@ pub mod tests {
             &self,
             image: &str,
             _environment: &Option<Environment>,
-            _docker_volumes: &HashMap<String, String>,
+            _mounts: &Mounts,
+            runtime: &RuntimeOptions,
             _network: Option<(&NetworkId, &str)>,
         ) -> Result<ContainerId> {
             let container_id = ContainerId::random();
+            let host_ports: Vec<_> = runtime
+                .published_ports
+                .iter()
+                .map(|port| port.host_port)
+                .collect();
 
             let mut lock = self.state.write().await;
+
+            if lock.failing_creates > 0 {
+                lock.failing_creates -= 1;
+                return Err(eyre!("failed to create the container"));
+            }
+
+            // Like Docker, refuse to publish a port another container is still using
+            let allocated = lock.containers.iter().any(|(id, _)| {
+                lock.host_ports
+                    .get(id)
+                    .is_some_and(|ports| ports.iter().any(|port| host_ports.contains(port)))
+            });
+
+            if allocated {
+                return Err(eyre!("port is already allocated"));
+            }
+
+            lock.host_ports.insert(container_id.clone(), host_ports);
             lock.containers
                 .push((container_id.clone(), image.to_owned()));
+            lock.labels
+                .insert(container_id.clone(), runtime.labels.clone());
 
             Ok(container_id)
         }
 
+        async fn connect_network(
+            &self,
+            network: &NetworkId,
+            id: &ContainerId,
+            aliases: &[String],
+        ) -> Result<()> {
+            let mut lock = self.state.write().await;
+            lock.connections
+                .entry(id.clone())
+                .or_default()
+                .push((network.clone(), aliases.to_vec()));
+
+            Ok(())
+        }
+
         async fn start_container(&self, _id: &ContainerId) -> Result<()> {
             Ok(())
         }
 
-        async fn get_container_ip(&self, _id: &ContainerId) -> Result<Ipv4Addr> {
-            Ok(Ipv4Addr::LOCALHOST)
+        async fn get_container_ip(&self, _id: &ContainerId) -> Result<IpAddr> {
+            Ok(Ipv4Addr::LOCALHOST.into())
+        }
+
+        async fn get_container_health(&self, _id: &ContainerId) -> Result<Option<HealthStatus>> {
+            let mut lock = self.state.write().await;
+
+            if lock.health.is_empty() {
+                return Ok(None);
+            }
+
+            Ok(Some(lock.health.remove(0)))
+        }
+
+        async fn get_restart_count(&self, id: &ContainerId) -> Result<u32> {
+            let lock = self.state.read().await;
+
+            Ok(lock.restart_counts.get(id).copied().unwrap_or_default())
         }
 
         async fn stop_container(&self, id: &ContainerId) -> Result<()> {
@@ -281,34 +831,130 @@ pub mod tests {
             Ok(())
         }
 
-        async fn get_network_by_name(&self, _name: &str) -> Result<Option<NetworkId>> {
+        async fn get_network_by_name(&self, name: &str) -> Result<Option<NetworkId>> {
+            if self.state.read().await.missing_networks.contains(name) {
+                return Ok(None);
+            }
+
             Ok(Some(NetworkId("mesh".to_owned())))
         }
+
+        async fn create_network(&self, name: &str, config: &NetworkConfig) -> Result<NetworkId> {
+            let mut lock = self.state.write().await;
+            lock.missing_networks.remove(name);
+            lock.created_networks
+                .push((name.to_owned(), config.clone()));
+
+            Ok(NetworkId("mesh".to_owned()))
+        }
+
+        async fn stream_logs(&self, id: &ContainerId, _follow: bool) -> Result<LogStream> {
+            let lock = self.state.read().await;
+            let frames = lock
+                .logs
+                .get(id)
+                .ok_or_else(|| eyre!("no such container: {id}"))?
+                .clone();
+
+            Ok(futures::stream::iter(frames.into_iter().map(Ok)).boxed())
+        }
+
+        async fn exec(&self, id: &ContainerId, command: &[String]) -> Result<i64> {
+            let mut lock = self.state.write().await;
+            lock.execs.push((id.clone(), command.to_vec()));
+
+            Ok(lock.exec_exit_code)
+        }
+
+        async fn prune_containers(&self, label: &str, older_than: Duration) -> Result<PruneReport> {
+            let mut lock = self.state.write().await;
+            lock.container_prunes.push((label.to_owned(), older_than));
+
+            Ok(PruneReport::default())
+        }
+
+        async fn prune_images(&self, older_than: Duration) -> Result<PruneReport> {
+            let mut lock = self.state.write().await;
+            lock.image_prunes.push(older_than);
+
+            Ok(PruneReport::default())
+        }
+
+        async fn stream_events(&self) -> Result<EventStream> {
+            Ok(futures::stream::empty().boxed())
+        }
+    }
+
+    fn test_trigger() -> DeployTrigger {
+        DeployTrigger::new(Uuid::new_v4(), "test")
     }
 
     fn create_reconciler<C: DockerClient>(
         registry: ServiceRegistry,
         docker_client: C,
+    ) -> Reconciler<C> {
+        create_reconciler_with_services(registry, docker_client, HashMap::new())
+    }
+
+    fn create_reconciler_with_services<C: DockerClient>(
+        registry: ServiceRegistry,
+        docker_client: C,
+        services: HashMap<String, Service>,
+    ) -> Reconciler<C> {
+        let config_location = ExternalBytes::Filesystem {
+            path: PathBuf::new(),
+        };
+
+        create_reconciler_from(registry, docker_client, services, config_location)
+    }
+
+    fn create_reconciler_from<C: DockerClient>(
+        registry: ServiceRegistry,
+        docker_client: C,
+        services: HashMap<String, Service>,
+        config_location: ExternalBytes,
     ) -> Reconciler<C> {
         let config = Config {
             alb: AlbConfig {
                 addr: Ipv4Addr::LOCALHOST,
                 ports: HashMap::from([(Scheme::Http, 5000)]),
+                protocols: HashMap::new(),
                 reconciliation: String::new(),
+                metrics: None,
+                maintenance: None,
+                container_logs: None,
+                request_id: None,
+                evict_connections_on_deploy: false,
+                path_limits: None,
+                empty_service_wait_ms: None,
+                prefer_warm_connections: false,
+                connection_pool: ConnectionPool::default(),
+                max_buffered_body_size: 1024 * 1024,
+                access_log: false,
+                busy_response: None,
+                retry_after_jitter_secs: 0,
+                error_pages: HashMap::new(),
+                maintenance_response: None,
+                static_routes: Vec::new(),
+                otlp: None,
                 tls: None,
                 mtls: None,
             },
             secrets: None,
-            services: HashMap::new(),
+            shutdown: ShutdownConfig::default(),
+            deploy_events: false,
+            registries: HashMap::new(),
+            garbage_collection: None,
+            network: None,
+            docker_retries: DockerRetryConfig::default(),
+            services,
         };
 
         let config = ArcSwap::from_pointee(config);
 
         Reconciler::new(
             Arc::new(RwLock::new(registry)),
-            ExternalBytes::Filesystem {
-                path: PathBuf::new(),
-            },
+            config_location,
             Arc::new(config),
             docker_client,
             MessageBus::new(),
@@ -331,12 +977,12 @@ pub mod tests {
         let docker_client = FakeDockerClient::default();
         let reconciler = create_reconciler(registry, docker_client.clone());
 
-        reconciler
-            .handle_diff(Diff::Addition {
-                name: "foobar".to_owned(),
-                definition: service,
-            })
-            .await?;
+        let diff = Diff::Addition {
+            name: "foobar".to_owned(),
+            definition: service,
+        };
+
+        reconciler.handle_diff(diff, &test_trigger()).await?;
 
         // Check we now have some containers in the Docker state
         let lock = docker_client.state.read().await;
@@ -351,6 +997,39 @@ pub mod tests {
         Ok(())
     }
 
+    #[tokio::test]
+    async fn replicas_are_given_their_configured_weights() -> Result<()> {
+        let service = Service {
+            image: "alexanderjackson/f2".to_owned(),
+            tag: "latest".to_owned(),
+            replicas: ReplicaCount::try_from(3)?,
+            weights: vec![5, 2],
+            ..Default::default()
+        };
+
+        let reconciler = create_reconciler(ServiceRegistry::new(), FakeDockerClient::default());
+
+        let diff = Diff::Addition {
+            name: "foobar".to_owned(),
+            definition: service,
+        };
+
+        reconciler.handle_diff(diff, &test_trigger()).await?;
+
+        let registry = reconciler.registry.read().await;
+
+        let weights: Vec<_> = registry
+            .get_running_containers("foobar")
+            .ok_or_else(|| eyre!("no containers were started"))?
+            .iter()
+            .map(|details| registry.get_weight(&details.id))
+            .collect();
+
+        assert_eq!(weights, [5, 2, 1]);
+
+        Ok(())
+    }
+
     #[tokio::test]
     async fn can_handle_removal_of_service() -> Result<()> {
         let mut registry = ServiceRegistry::new();
@@ -365,7 +1044,8 @@ pub mod tests {
             .create_container(
                 &format!("{image}:{tag}"),
                 &None,
-                &HashMap::new(),
+                &Mounts::default(),
+                &RuntimeOptions::default(),
                 Some((&NetworkId("mesh".to_owned()), "foobar.local")),
             )
             .await?;
@@ -374,7 +1054,7 @@ pub mod tests {
             service,
             StartedContainerDetails {
                 id,
-                addr: Ipv4Addr::LOCALHOST,
+                addr: Ipv4Addr::LOCALHOST.into(),
             },
         );
 
@@ -384,7 +1064,7 @@ pub mod tests {
             name: service.to_owned(),
         };
 
-        reconciler.handle_diff(diff).await?;
+        reconciler.handle_diff(diff, &test_trigger()).await?;
 
         // Check the state is now empty
         let lock = docker_client.state.read().await;
@@ -419,7 +1099,8 @@ pub mod tests {
             .create_container(
                 &image_and_tag,
                 &None,
-                &HashMap::new(),
+                &Mounts::default(),
+                &RuntimeOptions::default(),
                 Some((&NetworkId("mesh".to_owned()), "foobar.local")),
             )
             .await?;
@@ -429,7 +1110,7 @@ pub mod tests {
             service,
             StartedContainerDetails {
                 id: id.clone(),
-                addr: Ipv4Addr::LOCALHOST,
+                addr: Ipv4Addr::LOCALHOST.into(),
             },
         );
 
@@ -441,7 +1122,7 @@ pub mod tests {
             new_definition: altered_definition,
         };
 
-        reconciler.handle_diff(diff).await?;
+        reconciler.handle_diff(diff, &test_trigger()).await?;
 
         // Check we now have 2 containers for this image and tag
         let lock = docker_client.state.read().await;
@@ -456,4 +1137,610 @@ pub mod tests {
 
         Ok(())
     }
+
+    /// Alters a running service, returning whether its original container was kept along with its
+    /// definition afterwards.
+    async fn alter_running_service<F>(alter: F) -> Result<(bool, Service)>
+    where
+        F: FnOnce(Service) -> Service,
+    {
+        let mut registry = ServiceRegistry::new();
+
+        let service = "foobar";
+        let image = "alexanderjackson/f2";
+        let tag = "latest";
+
+        let docker_client = FakeDockerClient::default();
+
+        let service_definition = Service {
+            image: image.to_owned(),
+            tag: tag.to_owned(),
+            ..Default::default()
+        };
+
+        let altered_definition = alter(service_definition.clone());
+
+        let id = docker_client
+            .create_container(
+                &format!("{image}:{tag}"),
+                &None,
+                &Mounts::default(),
+                &RuntimeOptions::default(),
+                Some((&NetworkId("mesh".to_owned()), "foobar.local")),
+            )
+            .await?;
+
+        registry.define(service, service_definition.clone());
+        registry.add_container(
+            service,
+            StartedContainerDetails {
+                id: id.clone(),
+                addr: Ipv4Addr::LOCALHOST.into(),
+            },
+        );
+
+        let reconciler = create_reconciler(registry, docker_client.clone());
+
+        let diff = Diff::Alteration {
+            name: service.to_owned(),
+            old_definition: service_definition,
+            new_definition: altered_definition,
+        };
+
+        reconciler.handle_diff(diff, &test_trigger()).await?;
+
+        let containers = &docker_client.state.read().await.containers;
+        let kept = containers.len() == 1 && containers.iter().any(|c| c.0 == id);
+
+        let registry = reconciler.registry.read().await;
+        let definition = registry
+            .get_definition(service)
+            .ok_or_else(|| eyre!("service was undefined"))?
+            .clone();
+
+        Ok((kept, definition))
+    }
+
+    #[tokio::test]
+    async fn maintenance_mode_changes_keep_existing_containers() -> Result<()> {
+        let (kept, definition) = alter_running_service(|service| Service {
+            maintenance: true,
+            ..service
+        })
+        .await?;
+
+        assert!(kept);
+        assert!(definition.maintenance);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn route_changes_keep_existing_containers() -> Result<()> {
+        let route = Route {
+            host: String::from("opentracker.app"),
+            port: 3000,
+            split: Some(TrafficSplit {
+                service: String::from("canary"),
+                percentage: 10,
+            }),
+            ..Default::default()
+        };
+
+        let (kept, definition) = alter_running_service(|service| Service {
+            routes: HashSet::from([route.clone()]),
+            ..service
+        })
+        .await?;
+
+        assert!(kept);
+        assert!(definition.routes.contains(&route));
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn can_stop_all_containers() -> Result<()> {
+        let mut registry = ServiceRegistry::new();
+        let docker_client = FakeDockerClient::default();
+
+        let mut services = HashMap::new();
+
+        for (name, shutdown_mode) in [
+            ("graceful", ShutdownMode::Graceful),
+            ("forceful", ShutdownMode::Forceful),
+        ] {
+            let service = Service {
+                image: format!("alexanderjackson/{name}"),
+                tag: String::from("latest"),
+                shutdown_mode,
+                ..Default::default()
+            };
+
+            let id = docker_client
+                .create_container(
+                    &service.image,
+                    &None,
+                    &Mounts::default(),
+                    &RuntimeOptions::default(),
+                    None,
+                )
+                .await?;
+
+            registry.define(name, service.clone());
+            registry.add_container(
+                name,
+                StartedContainerDetails {
+                    id,
+                    addr: Ipv4Addr::LOCALHOST.into(),
+                },
+            );
+
+            services.insert(name.to_owned(), service);
+        }
+
+        let reconciler = create_reconciler_with_services(registry, docker_client.clone(), services);
+
+        reconciler.stop_all_containers().await?;
+
+        assert!(docker_client.state.read().await.containers.is_empty());
+
+        let registry = reconciler.registry.read().await;
+
+        assert!(registry.get_running_containers("graceful").is_none());
+        assert!(registry.get_running_containers("forceful").is_none());
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn alterations_emit_deploy_events() -> Result<()> {
+        let mut registry = ServiceRegistry::new();
+        let docker_client = FakeDockerClient::default();
+
+        let service = "foobar";
+        let old_definition = Service {
+            image: String::from("alexanderjackson/f2"),
+            tag: String::from("1"),
+            ..Default::default()
+        };
+
+        let new_definition = Service {
+            tag: String::from("2"),
+            replicas: ReplicaCount::try_from(3)?,
+            ..old_definition.clone()
+        };
+
+        let id = docker_client
+            .create_container(
+                "alexanderjackson/f2:1",
+                &None,
+                &Mounts::default(),
+                &RuntimeOptions::default(),
+                None,
+            )
+            .await?;
+
+        registry.define(service, old_definition.clone());
+        registry.add_container(
+            service,
+            StartedContainerDetails {
+                id,
+                addr: Ipv4Addr::LOCALHOST.into(),
+            },
+        );
+
+        let reconciler = create_reconciler(registry, docker_client);
+        reconciler.config.rcu(|config| {
+            let mut config = Config::clone(config);
+            config.deploy_events = true;
+            config
+        });
+
+        let trigger = test_trigger();
+        let diff = Diff::Alteration {
+            name: service.to_owned(),
+            old_definition,
+            new_definition,
+        };
+
+        reconciler.handle_diff(diff, &trigger).await?;
+
+        let event = reconciler
+            .message_bus
+            .receive_deploy_event()
+            .await?
+            .into_content();
+
+        assert_eq!(event.service, service);
+        assert_eq!(event.kind, DeployEventKind::Alteration);
+        assert_eq!(event.old_image.as_deref(), Some("alexanderjackson/f2:1"));
+        assert_eq!(event.new_image.as_deref(), Some("alexanderjackson/f2:2"));
+        assert_eq!(event.old_replicas, Some(1));
+        assert_eq!(event.new_replicas, Some(3));
+        assert_eq!(event.trigger, trigger);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn reconciliation_continues_after_errors() -> Result<()> {
+        let temp_dir = tempfile::tempdir()?;
+        let path = temp_dir.path().join("config.yaml");
+
+        tokio::fs::write(&path, "not: [valid").await?;
+
+        let docker_client = FakeDockerClient::default();
+        let reconciler = Arc::new(create_reconciler_from(
+            ServiceRegistry::new(),
+            docker_client.clone(),
+            HashMap::new(),
+            ExternalBytes::Filesystem { path: path.clone() },
+        ));
+
+        let running = tokio::spawn({
+            let reconciler = Arc::clone(&reconciler);
+            async move { reconciler.run().await }
+        });
+
+        reconciler.message_bus.send_reconciliation_request()?;
+
+        // give the reconciler a chance to fail on the invalid configuration
+        tokio::time::sleep(Duration::from_millis(20)).await;
+
+        let config = r#"
+            alb:
+              addr: 127.0.0.1
+              ports:
+                http: 5000
+              reconciliation: /reconciliation
+            services:
+              backend:
+                image: alexanderjackson/f2
+                tag: latest
+                replicas: 1
+        "#;
+
+        tokio::fs::write(&path, config).await?;
+        reconciler.message_bus.send_reconciliation_request()?;
+
+        let started = async {
+            while docker_client.state.read().await.containers.is_empty() {
+                tokio::time::sleep(Duration::from_millis(1)).await;
+            }
+        };
+
+        tokio::time::timeout(Duration::from_secs(1), started).await?;
+
+        assert!(!running.is_finished());
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn services_can_be_added_after_starting_with_none() -> Result<()> {
+        let temp_dir = tempfile::tempdir()?;
+        let path = temp_dir.path().join("config.yaml");
+
+        let config = r#"
+            alb:
+              addr: 127.0.0.1
+              ports:
+                http: 5000
+              reconciliation: /reconciliation
+            services:
+              backend:
+                image: alexanderjackson/f2
+                tag: latest
+                replicas: 1
+                routes:
+                  - host: opentracker.app
+                    port: 8080
+        "#;
+
+        tokio::fs::write(&path, config).await?;
+
+        let reconciler = create_reconciler_from(
+            ServiceRegistry::new(),
+            FakeDockerClient::default(),
+            HashMap::new(),
+            ExternalBytes::Filesystem { path },
+        );
+
+        reconciler.reconcile(&test_trigger()).await?;
+
+        let registry = reconciler.registry.read().await;
+        let matched = registry
+            .find_downstreams("opentracker.app", "/", &Method::GET)
+            .ok_or_else(|| eyre!("expected the new service to be routable"))?;
+
+        assert_eq!(matched.downstreams.len(), 1);
+        assert_eq!(matched.route.port, 8080);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn failed_changes_are_retried_on_the_next_reconciliation() -> Result<()> {
+        let temp_dir = tempfile::tempdir()?;
+        let path = temp_dir.path().join("config.yaml");
+
+        let config = r#"
+            alb:
+              addr: 127.0.0.1
+              ports:
+                http: 5000
+              reconciliation: /reconciliation
+            services:
+              backend:
+                image: alexanderjackson/f2
+                tag: latest
+                replicas: 1
+                routes:
+                  - host: opentracker.app
+                    port: 8080
+        "#;
+
+        tokio::fs::write(&path, config).await?;
+
+        let docker_client = FakeDockerClient::default();
+        let reconciler = create_reconciler_from(
+            ServiceRegistry::new(),
+            docker_client.clone(),
+            HashMap::new(),
+            ExternalBytes::Filesystem { path },
+        );
+
+        docker_client.fail_creates(1).await;
+
+        assert!(reconciler.reconcile(&test_trigger()).await.is_err());
+        assert_eq!(docker_client.container_count().await, 0);
+        assert!(!reconciler.config.load().services.contains_key("backend"));
+
+        // the configuration hasn't changed, but the service still needs to be started
+        reconciler.reconcile(&test_trigger()).await?;
+
+        assert_eq!(docker_client.container_count().await, 1);
+
+        let registry = reconciler.registry.read().await;
+        let matched = registry
+            .find_downstreams("opentracker.app", "/", &Method::GET)
+            .ok_or_else(|| eyre!("expected the service to be routable after the retry"))?;
+
+        assert_eq!(matched.downstreams.len(), 1);
+
+        Ok(())
+    }
+
+    async fn running_container_ids(reconciler: &Reconciler<FakeDockerClient>) -> Vec<ContainerId> {
+        reconciler
+            .get_running_containers("backend")
+            .await
+            .map(|containers| containers.into_iter().map(|c| c.id).collect())
+            .unwrap_or_default()
+    }
+
+    async fn start_backend(
+        definition: Service,
+    ) -> Result<(Reconciler<FakeDockerClient>, FakeDockerClient, ContainerId)> {
+        let docker_client = FakeDockerClient::default();
+        let reconciler = create_reconciler(ServiceRegistry::new(), docker_client.clone());
+
+        let diff = Diff::Addition {
+            name: "backend".to_owned(),
+            definition,
+        };
+
+        reconciler.handle_diff(diff, &test_trigger()).await?;
+
+        let id = running_container_ids(&reconciler)
+            .await
+            .pop()
+            .ok_or_else(|| eyre!("no containers were started"))?;
+
+        Ok((reconciler, docker_client, id))
+    }
+
+    fn died(id: &ContainerId, exit_code: i64) -> ContainerEvent {
+        ContainerEvent {
+            id: id.clone(),
+            action: ContainerAction::Died {
+                exit_code: Some(exit_code),
+            },
+            replica: Some(0),
+        }
+    }
+
+    #[tokio::test]
+    async fn dead_containers_are_removed_from_the_load_balancer() -> Result<()> {
+        let (reconciler, docker_client, id) = start_backend(Service {
+            image: "alexanderjackson/f2".to_owned(),
+            tag: "latest".to_owned(),
+            ..Default::default()
+        })
+        .await?;
+
+        reconciler.handle_container_event(died(&id, 137)).await?;
+
+        assert!(running_container_ids(&reconciler).await.is_empty());
+        assert_eq!(docker_client.state.read().await.containers.len(), 1);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn dead_containers_are_replaced_if_configured() -> Result<()> {
+        let (reconciler, docker_client, id) = start_backend(Service {
+            image: "alexanderjackson/f2".to_owned(),
+            tag: "latest".to_owned(),
+            replace_dead_containers: true,
+            ..Default::default()
+        })
+        .await?;
+
+        reconciler.handle_container_event(died(&id, 1)).await?;
+
+        let running = running_container_ids(&reconciler).await;
+
+        assert_eq!(running.len(), 1);
+        assert_ne!(running[0], id);
+
+        let lock = docker_client.state.read().await;
+
+        assert!(lock
+            .containers
+            .iter()
+            .all(|(container, _)| *container != id));
+        assert_eq!(lock.labels[&running[0]]["f2.replica"], "0");
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn containers_restarted_by_the_daemon_are_added_back() -> Result<()> {
+        let (reconciler, docker_client, id) = start_backend(Service {
+            image: "alexanderjackson/f2".to_owned(),
+            tag: "latest".to_owned(),
+            restart_policy: RestartPolicy::UnlessStopped,
+            replace_dead_containers: true,
+            weights: vec![3],
+            ..Default::default()
+        })
+        .await?;
+
+        reconciler.handle_container_event(died(&id, 137)).await?;
+
+        // The daemon restarts the container itself, so no replacement should be started
+        assert!(running_container_ids(&reconciler).await.is_empty());
+        assert_eq!(docker_client.state.read().await.containers.len(), 1);
+
+        let started = ContainerEvent {
+            id: id.clone(),
+            action: ContainerAction::Started,
+            replica: Some(0),
+        };
+
+        reconciler.handle_container_event(started).await?;
+
+        assert_eq!(running_container_ids(&reconciler).await, vec![id.clone()]);
+        assert_eq!(reconciler.registry.read().await.get_weight(&id), 3);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn containers_are_replaced_once_the_daemon_runs_out_of_retries() -> Result<()> {
+        let (reconciler, docker_client, id) = start_backend(Service {
+            image: "alexanderjackson/f2".to_owned(),
+            tag: "latest".to_owned(),
+            restart_policy: RestartPolicy::OnFailure {
+                max_retries: Some(3),
+            },
+            replace_dead_containers: true,
+            ..Default::default()
+        })
+        .await?;
+
+        // With retries left, the daemon restarts the container itself
+        docker_client.set_restart_count(&id, 2).await;
+        reconciler.handle_container_event(died(&id, 1)).await?;
+
+        assert!(running_container_ids(&reconciler).await.is_empty());
+        assert_eq!(docker_client.container_count().await, 1);
+
+        let started = ContainerEvent {
+            id: id.clone(),
+            action: ContainerAction::Started,
+            replica: Some(0),
+        };
+
+        reconciler.handle_container_event(started).await?;
+
+        // Having used every retry, the daemon leaves it stopped so a replacement is started
+        docker_client.set_restart_count(&id, 3).await;
+        reconciler.handle_container_event(died(&id, 1)).await?;
+
+        let running = running_container_ids(&reconciler).await;
+
+        assert_eq!(running.len(), 1);
+        assert_ne!(running[0], id);
+        assert_eq!(docker_client.container_count().await, 1);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn containers_stopped_by_f2_are_ignored() -> Result<()> {
+        let (reconciler, docker_client, id) = start_backend(Service {
+            image: "alexanderjackson/f2".to_owned(),
+            tag: "latest".to_owned(),
+            replace_dead_containers: true,
+            ..Default::default()
+        })
+        .await?;
+
+        let stopped = ContainerId::random();
+
+        reconciler.handle_container_event(died(&stopped, 0)).await?;
+
+        assert_eq!(running_container_ids(&reconciler).await, vec![id]);
+        assert_eq!(docker_client.state.read().await.containers.len(), 1);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn containers_publishing_ports_are_replaced_after_stopping() -> Result<()> {
+        let definition = Service {
+            image: "alexanderjackson/f2".to_owned(),
+            tag: "latest".to_owned(),
+            published_ports: vec![PublishedPort::try_from(String::from("2222:22"))?],
+            ..Default::default()
+        };
+
+        let (reconciler, docker_client, id) = start_backend(definition.clone()).await?;
+
+        let diff = Diff::Alteration {
+            name: "backend".to_owned(),
+            old_definition: definition.clone(),
+            new_definition: Service {
+                tag: "v2".to_owned(),
+                ..definition
+            },
+        };
+
+        reconciler.handle_diff(diff, &test_trigger()).await?;
+
+        let running = running_container_ids(&reconciler).await;
+
+        assert_eq!(running.len(), 1);
+        assert_ne!(running[0], id);
+        assert_eq!(docker_client.container_count().await, 1);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn pre_stop_hooks_run_before_containers_are_removed() -> Result<()> {
+        let drain = vec![String::from("/bin/drain")];
+
+        let (reconciler, docker_client, id) = start_backend(Service {
+            image: "alexanderjackson/f2".to_owned(),
+            tag: "latest".to_owned(),
+            pre_stop: Some(drain.clone()),
+            ..Default::default()
+        })
+        .await?;
+
+        let diff = Diff::Removal {
+            name: "backend".to_owned(),
+        };
+
+        reconciler.handle_diff(diff, &test_trigger()).await?;
+
+        assert_eq!(docker_client.execs().await, vec![(id, drain)]);
+        assert_eq!(docker_client.container_count().await, 0);
+
+        Ok(())
+    }
 }
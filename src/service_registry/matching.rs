@@ -1,3 +1,36 @@
+use std::cmp::Reverse;
+
+use http::Method;
+use regex::Regex;
+
+/// How a route's host matched a request, ordered from most to least specific.
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Ord, PartialOrd)]
+pub enum HostMatch {
+    Exact,
+    /// Matched a pattern such as `*.example.com`, where longer suffixes are more specific.
+    Wildcard {
+        suffix_len: Reverse<usize>,
+    },
+}
+
+impl HostMatch {
+    /// Matches a host against a route's host, where a leading `*.` matches any single label.
+    pub fn compute(pattern: &str, host: &str) -> Option<Self> {
+        if pattern == host {
+            return Some(Self::Exact);
+        }
+
+        let suffix = pattern.strip_prefix('*')?;
+        let label = host.strip_suffix(suffix)?;
+
+        (suffix.starts_with('.') && !label.is_empty() && !label.contains('.')).then_some(
+            Self::Wildcard {
+                suffix_len: Reverse(suffix.len()),
+            },
+        )
+    }
+}
+
 pub struct PathMatchCalculator<'a> {
     pub path: &'a str,
     pub prefix: Option<&'a str>,
@@ -17,9 +50,40 @@ impl<'a> PathMatchCalculator<'a> {
     }
 }
 
+/// How a route's methods matched a request, ordered from most to least specific.
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Ord, PartialOrd)]
+pub enum MethodMatch {
+    /// The route only accepts a set of methods, including this one.
+    Restricted,
+    Any,
+}
+
+impl MethodMatch {
+    pub fn compute(methods: &[String], method: &Method) -> Option<Self> {
+        if methods.is_empty() {
+            return Some(Self::Any);
+        }
+
+        methods
+            .iter()
+            .any(|allowed| allowed.eq_ignore_ascii_case(method.as_str()))
+            .then_some(Self::Restricted)
+    }
+}
+
+/// Computes how much of a path is left over after a pattern matches it, or `None` if it does not.
+pub fn compute_pattern_match_length(path: &str, pattern: &Regex) -> Option<usize> {
+    pattern.find(path).map(|found| path.len() - found.len())
+}
+
 #[cfg(test)]
 mod tests {
-    use crate::service_registry::matching::PathMatchCalculator;
+    use http::Method;
+    use regex::Regex;
+
+    use crate::service_registry::matching::{
+        compute_pattern_match_length, HostMatch, MethodMatch, PathMatchCalculator,
+    };
 
     #[test]
     fn computes_correctly_for_matching_prefix() {
@@ -92,4 +156,53 @@ mod tests {
         let result = PathMatchCalculator::new(path, prefix).compute_match_length();
         assert_eq!(result, 10); // Length of "/resource/"
     }
+
+    #[test]
+    fn wildcards_match_a_single_label() {
+        let pattern = "*.example.com";
+
+        assert!(HostMatch::compute(pattern, "tenant.example.com").is_some());
+        assert!(HostMatch::compute(pattern, "a.tenant.example.com").is_none());
+        assert!(HostMatch::compute(pattern, "example.com").is_none());
+        assert!(HostMatch::compute(pattern, "tenantexample.com").is_none());
+    }
+
+    #[test]
+    fn more_specific_hosts_are_preferred() {
+        let exact = HostMatch::compute("api.example.com", "api.example.com");
+        let narrow = HostMatch::compute("*.eu.example.com", "api.eu.example.com");
+        let broad = HostMatch::compute("*.example.com", "api.example.com");
+
+        assert_eq!(exact, Some(HostMatch::Exact));
+        assert!(exact < narrow);
+        assert!(narrow < broad);
+    }
+
+    #[test]
+    fn patterns_only_match_paths_they_find() {
+        let pattern = Regex::new(r"^/users/\d+").unwrap();
+
+        assert_eq!(compute_pattern_match_length("/users/42", &pattern), Some(0));
+        assert_eq!(
+            compute_pattern_match_length("/users/42/settings", &pattern),
+            Some(9)
+        );
+        assert_eq!(compute_pattern_match_length("/users/me", &pattern), None);
+    }
+
+    #[test]
+    fn restricted_methods_are_preferred() {
+        let methods = vec![String::from("post")];
+
+        assert_eq!(
+            MethodMatch::compute(&methods, &Method::POST),
+            Some(MethodMatch::Restricted)
+        );
+        assert_eq!(MethodMatch::compute(&methods, &Method::GET), None);
+        assert_eq!(
+            MethodMatch::compute(&[], &Method::GET),
+            Some(MethodMatch::Any)
+        );
+        assert!(MethodMatch::Restricted < MethodMatch::Any);
+    }
 }
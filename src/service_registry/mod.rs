@@ -1,19 +1,42 @@
 use std::collections::HashMap;
+use std::time::{Duration, Instant};
 
+use http::Method;
 use indexmap::IndexSet;
+use regex::Regex;
 
-use crate::config::Service;
+use crate::config::{PortRoute, Route, Service, Transport};
 use crate::docker::api::StartedContainerDetails;
 use crate::docker::models::ContainerId;
-use crate::service_registry::matching::PathMatchCalculator;
+use crate::service_registry::matching::{
+    compute_pattern_match_length, HostMatch, MethodMatch, PathMatchCalculator,
+};
 
 mod matching;
 
+/// How much finer weights become while containers are in their slow start window.
+const SLOW_START_SCALE: u64 = 100;
+
+/// A service matched for a request, along with the route it matched on.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct MatchedService<'a> {
+    pub name: &'a str,
+    pub service: &'a Service,
+    pub route: &'a Route,
+    pub downstreams: &'a IndexSet<StartedContainerDetails>,
+}
+
 /// Registry of all of the running services.
 #[derive(Debug, Default)]
 pub struct ServiceRegistry {
     definitions: HashMap<String, Service>,
     containers: HashMap<String, IndexSet<StartedContainerDetails>>,
+    weights: HashMap<ContainerId, u32>,
+    /// The replica each container was started as, which outlives the container itself.
+    replicas: HashMap<ContainerId, usize>,
+    added: HashMap<ContainerId, Instant>,
+    /// Compiled path patterns, kept across definitions so reloads only compile new ones.
+    patterns: HashMap<String, Regex>,
 }
 
 impl ServiceRegistry {
@@ -22,11 +45,47 @@ impl ServiceRegistry {
     }
 
     pub fn define(&mut self, service: &str, definition: Service) {
+        for pattern in definition
+            .routes
+            .iter()
+            .filter_map(|route| route.path_pattern.as_ref())
+        {
+            if self.patterns.contains_key(pattern.as_str()) {
+                continue;
+            }
+
+            match Regex::new(pattern.as_str()) {
+                Ok(regex) => {
+                    self.patterns.insert(pattern.as_str().to_owned(), regex);
+                }
+                Err(e) => tracing::warn!(%e, ?pattern, "failed to compile path pattern"),
+            }
+        }
+
         self.definitions.insert(service.to_string(), definition);
+        self.prune_patterns();
+    }
+
+    pub fn get_definition(&self, service: &str) -> Option<&Service> {
+        self.definitions.get(service)
     }
 
     pub fn undefine(&mut self, service: &str) {
         self.definitions.remove(service);
+        self.prune_patterns();
+    }
+
+    /// Forgets any compiled patterns that no route uses anymore.
+    fn prune_patterns(&mut self) {
+        let definitions = &self.definitions;
+
+        self.patterns.retain(|pattern, _| {
+            definitions
+                .values()
+                .flat_map(|service| &service.routes)
+                .filter_map(|route| route.path_pattern.as_ref())
+                .any(|used| used.as_str() == pattern)
+        });
     }
 
     pub fn get_running_containers(
@@ -42,6 +101,7 @@ impl ServiceRegistry {
     pub fn add_container(&mut self, service: &str, details: StartedContainerDetails) {
         tracing::info!("adding a downstream container");
 
+        self.added.insert(details.id.clone(), Instant::now());
         self.containers
             .entry(service.to_string())
             .or_default()
@@ -49,48 +109,215 @@ impl ServiceRegistry {
     }
 
     pub fn remove_all_containers(&mut self, service: &str) {
-        self.containers.remove(service);
+        if let Some(containers) = self.containers.remove(service) {
+            for details in containers {
+                self.weights.remove(&details.id);
+                self.replicas.remove(&details.id);
+                self.added.remove(&details.id);
+            }
+        }
     }
 
     pub fn remove_container_by_id(&mut self, service: &str, id: &ContainerId) {
         if let Some(containers) = self.containers.get_mut(service) {
             containers.retain(|c| c.id != *id);
         }
+
+        self.weights.remove(id);
+        self.replicas.remove(id);
+        self.added.remove(id);
     }
 
-    pub fn find_downstreams(
+    /// Finds the service a container is routed to as part of, if any.
+    pub fn find_service_for_container(&self, id: &ContainerId) -> Option<&str> {
+        self.containers
+            .iter()
+            .find(|(_, containers)| containers.iter().any(|c| c.id == *id))
+            .map(|(service, _)| service.as_str())
+    }
+
+    /// Sets the share of traffic a container receives relative to the others in its service.
+    pub fn set_weight(&mut self, id: &ContainerId, weight: u32) {
+        self.weights.insert(id.clone(), weight);
+    }
+
+    /// Gets the weight of a container, which defaults to 1 if it has not been set.
+    pub fn get_weight(&self, id: &ContainerId) -> u32 {
+        self.weights.get(id).copied().unwrap_or(1)
+    }
+
+    /// Records which replica of its service a container was started as.
+    pub fn set_replica(&mut self, id: &ContainerId, replica: usize) {
+        self.replicas.insert(id.clone(), replica);
+    }
+
+    pub fn get_replica(&self, id: &ContainerId) -> Option<usize> {
+        self.replicas.get(id).copied()
+    }
+
+    /// Gets the weight of a container, scaled down in proportion to how far through the slow
+    /// start window it is since being added.
+    pub fn get_ramped_weight(&self, id: &ContainerId, slow_start: Option<Duration>) -> u32 {
+        let weight = self.get_weight(id);
+
+        let Some(window) = slow_start.filter(|window| !window.is_zero()) else {
+            return weight;
+        };
+
+        // Scale every weight up so that partially ramped containers keep some precision
+        let full = u64::from(weight) * SLOW_START_SCALE;
+        let elapsed = self.added.get(id).map_or(window, Instant::elapsed);
+
+        if elapsed >= window || weight == 0 {
+            return u32::try_from(full).unwrap_or(u32::MAX);
+        }
+
+        let ramped = full * elapsed.as_millis() as u64 / window.as_millis() as u64;
+
+        u32::try_from(ramped.max(1)).unwrap_or(u32::MAX)
+    }
+
+    /// Finds the service and route that best match the given host, path and method, where routes
+    /// whose prefix matches the path are preferred over those whose prefix does not, then exact
+    /// hosts over wildcards and routes restricted to the method over those that are not.
+    pub fn find_route(
         &self,
         host: &str,
         path: &str,
-    ) -> Option<(&IndexSet<StartedContainerDetails>, u16)> {
-        tracing::debug!(host, path, "finding downstream containers");
+        method: &Method,
+    ) -> Option<(&str, &Service, &Route)> {
+        self.find_route_among(self.definitions.iter(), Some(host), path, method)
+    }
 
-        self.definitions
-            .iter()
-            .filter_map(|(name, service)| {
-                service
-                    .routes
-                    .iter()
-                    .find(|route| route.host == host)
-                    .map(|route| {
-                        let calculator = PathMatchCalculator::new(path, route.prefix.as_deref());
-                        (name, calculator.compute_match_length(), route.port)
-                    })
+    /// Finds the route that best matches a request out of those belonging to `services`, where
+    /// routes for any host match if `host` is `None`.
+    fn find_route_among<'a>(
+        &'a self,
+        services: impl Iterator<Item = (&'a String, &'a Service)>,
+        host: Option<&str>,
+        path: &str,
+        method: &Method,
+    ) -> Option<(&'a str, &'a Service, &'a Route)> {
+        services
+            .flat_map(|(name, service)| {
+                service.routes.iter().filter_map(move |route| {
+                    let host_match = match host {
+                        Some(host) => HostMatch::compute(&route.host, host)?,
+                        None => HostMatch::Exact,
+                    };
+                    let method_match = MethodMatch::compute(&route.methods, method)?;
+
+                    let path_match = match &route.path_pattern {
+                        Some(pattern) => {
+                            let regex = self.patterns.get(pattern.as_str())?;
+                            compute_pattern_match_length(path, regex)?
+                        }
+                        None => PathMatchCalculator::new(path, route.prefix.as_deref())
+                            .compute_match_length(),
+                    };
+
+                    // A route whose prefix doesn't match is only a fallback, however exact its host
+                    let prefix_missed = path_match == usize::MAX;
+
+                    Some((
+                        name,
+                        service,
+                        route,
+                        (prefix_missed, host_match, path_match, method_match),
+                    ))
+                })
             })
-            .min_by_key(|(_, match_length, _)| *match_length)
-            .and_then(|(name, _, port)| {
+            .min_by_key(|(_, _, _, specificity)| *specificity)
+            .map(|(name, service, route, _)| (name.as_str(), service, route))
+    }
+
+    /// Lists the ports any service accepts traffic on over the given transport.
+    pub fn listen_ports(&self, transport: Transport) -> Vec<u16> {
+        self.definitions
+            .values()
+            .flat_map(|service| service.port_routes(transport))
+            .map(|route| route.listen)
+            .collect()
+    }
+
+    /// Finds the service accepting traffic on the given port, along with its containers.
+    pub fn find_port_downstreams(
+        &self,
+        transport: Transport,
+        listen: u16,
+    ) -> Option<(&PortRoute, &IndexSet<StartedContainerDetails>)> {
+        self.definitions.iter().find_map(|(name, service)| {
+            let route = service
+                .port_routes(transport)
+                .iter()
+                .find(|route| route.listen == listen)?;
+
+            self.get_running_containers(name)
+                .map(|downstreams| (route, downstreams))
+        })
+    }
+
+    pub fn find_downstreams(
+        &self,
+        host: &str,
+        path: &str,
+        method: &Method,
+    ) -> Option<MatchedService<'_>> {
+        tracing::debug!(host, path, %method, "finding downstream containers");
+
+        self.find_route(host, path, method)
+            .and_then(|(name, service, route)| {
                 self.get_running_containers(name)
-                    .map(|downstreams| (downstreams, port))
+                    .map(|downstreams| MatchedService {
+                        name,
+                        service,
+                        route,
+                        downstreams,
+                    })
             })
     }
+
+    /// Sends a request to another service instead, using whichever of its own routes best matches
+    /// the path and method so it is sent to the port that service listens on.
+    ///
+    /// The host is ignored, since the variant is usually served under a host of its own such as
+    /// `canary.example.com` to avoid competing with the service it stands in for.
+    ///
+    /// Returns `None` if the service is unknown, has no route for the request or has no running
+    /// containers.
+    pub fn find_variant(
+        &self,
+        path: &str,
+        method: &Method,
+        variant: &str,
+    ) -> Option<MatchedService<'_>> {
+        let definition = self.definitions.get_key_value(variant)?;
+        let (name, service, route) =
+            self.find_route_among(std::iter::once(definition), None, path, method)?;
+
+        let downstreams = self
+            .get_running_containers(variant)
+            .filter(|downstreams| !downstreams.is_empty())?;
+
+        Some(MatchedService {
+            name,
+            service,
+            route,
+            downstreams,
+        })
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use std::collections::HashSet;
     use std::net::Ipv4Addr;
+    use std::time::{Duration, Instant};
 
-    use crate::config::{Route, Service};
+    use color_eyre::eyre::Result;
+    use http::Method;
+
+    use crate::config::{PathPattern, PortRoute, Route, Service, Transport};
     use crate::docker::api::StartedContainerDetails;
     use crate::docker::models::ContainerId;
     use crate::service_registry::ServiceRegistry;
@@ -116,11 +343,11 @@ mod tests {
 
         registry.define(service, definition);
 
-        assert!(registry.definitions.get(service).is_some());
+        assert!(registry.definitions.contains_key(service));
 
         registry.undefine(service);
 
-        assert!(registry.definitions.get(service).is_none());
+        assert!(!registry.definitions.contains_key(service));
     }
 
     #[test]
@@ -132,12 +359,12 @@ mod tests {
 
         let first = StartedContainerDetails {
             id: container1.clone(),
-            addr: Ipv4Addr::new(127, 0, 0, 3),
+            addr: Ipv4Addr::new(127, 0, 0, 3).into(),
         };
 
         let second = StartedContainerDetails {
             id: container2.clone(),
-            addr: Ipv4Addr::new(127, 0, 0, 4),
+            addr: Ipv4Addr::new(127, 0, 0, 4).into(),
         };
 
         registry.add_container("backend", first);
@@ -183,7 +410,7 @@ mod tests {
 
         let details = StartedContainerDetails {
             id: id.clone(),
-            addr: Ipv4Addr::LOCALHOST,
+            addr: Ipv4Addr::LOCALHOST.into(),
         };
 
         registry.add_container(name, details);
@@ -196,13 +423,15 @@ mod tests {
         host: &str,
         path: &str,
     ) -> Option<HashSet<ContainerId>> {
-        registry.find_downstreams(host, path).map(|value| {
-            value
-                .0
-                .into_iter()
-                .map(|details| details.id.clone())
-                .collect()
-        })
+        registry
+            .find_downstreams(host, path, &Method::GET)
+            .map(|matched| {
+                matched
+                    .downstreams
+                    .into_iter()
+                    .map(|details| details.id.clone())
+                    .collect()
+            })
     }
 
     #[test]
@@ -243,6 +472,121 @@ mod tests {
         assert_eq!(downstreams, Some(expected));
     }
 
+    #[test]
+    fn exact_hosts_are_preferred_over_wildcards() {
+        let mut registry = ServiceRegistry::new();
+
+        define_service(&mut registry, "tenants", "*.opentracker.app", None);
+        define_service(&mut registry, "admin", "admin.opentracker.app", None);
+
+        let tenants_id = add_container(&mut registry, "tenants");
+        let admin_id = add_container(&mut registry, "admin");
+
+        let tenant = find_matching_container_ids(&registry, "acme.opentracker.app", "/");
+        let admin = find_matching_container_ids(&registry, "admin.opentracker.app", "/");
+
+        assert_eq!(tenant, Some(HashSet::from([tenants_id])));
+        assert_eq!(admin, Some(HashSet::from([admin_id])));
+        assert_eq!(
+            find_matching_container_ids(&registry, "opentracker.app", "/"),
+            None
+        );
+    }
+
+    #[test]
+    fn matching_prefixes_are_preferred_over_exact_hosts() {
+        let mut registry = ServiceRegistry::new();
+
+        define_service(&mut registry, "tenants", "*.example.com", None);
+        define_service(
+            &mut registry,
+            "admin",
+            "api.example.com",
+            Some(String::from("/admin")),
+        );
+
+        let tenants_id = add_container(&mut registry, "tenants");
+        let admin_id = add_container(&mut registry, "admin");
+
+        assert_eq!(
+            find_matching_container_ids(&registry, "api.example.com", "/foo"),
+            Some(HashSet::from([tenants_id]))
+        );
+        assert_eq!(
+            find_matching_container_ids(&registry, "api.example.com", "/admin/users"),
+            Some(HashSet::from([admin_id]))
+        );
+    }
+
+    #[test]
+    fn path_patterns_only_match_the_paths_they_find() -> Result<()> {
+        let mut registry = ServiceRegistry::new();
+        let host = "opentracker.app";
+
+        define_service(&mut registry, "frontend", host, None);
+
+        let users = Service {
+            routes: HashSet::from([Route {
+                host: String::from(host),
+                path_pattern: Some(PathPattern::try_from(String::from(r"^/users/\d+$"))?),
+                ..Default::default()
+            }]),
+            ..Default::default()
+        };
+
+        registry.define("users", users);
+
+        let frontend_id = add_container(&mut registry, "frontend");
+        let users_id = add_container(&mut registry, "users");
+
+        assert_eq!(
+            find_matching_container_ids(&registry, host, "/users/42"),
+            Some(HashSet::from([users_id]))
+        );
+        assert_eq!(
+            find_matching_container_ids(&registry, host, "/users/me"),
+            Some(HashSet::from([frontend_id]))
+        );
+
+        registry.undefine("users");
+        assert!(registry.patterns.is_empty());
+
+        Ok(())
+    }
+
+    #[test]
+    fn routes_restricted_to_the_method_are_preferred() {
+        let mut registry = ServiceRegistry::new();
+        let host = "opentracker.app";
+        let prefix = Some(String::from("/api/webhooks"));
+
+        define_service(&mut registry, "backend", host, prefix.clone());
+
+        let ingestion = Service {
+            routes: HashSet::from([Route {
+                host: String::from(host),
+                prefix,
+                methods: vec![String::from("POST")],
+                ..Default::default()
+            }]),
+            ..Default::default()
+        };
+
+        registry.define("ingestion", ingestion);
+
+        let backend_id = add_container(&mut registry, "backend");
+        let ingestion_id = add_container(&mut registry, "ingestion");
+
+        let find = |method| {
+            registry
+                .find_downstreams(host, "/api/webhooks", &method)
+                .map(|matched| matched.downstreams[0].id.clone())
+        };
+
+        assert_eq!(find(Method::POST), Some(ingestion_id));
+        assert_eq!(find(Method::GET), Some(backend_id));
+    }
+
     #[test]
     fn produces_no_results_for_downstreams_if_no_matches() {
         let mut registry = ServiceRegistry::new();
@@ -296,6 +640,26 @@ mod tests {
         assert!(registry.get_running_containers(name).is_none());
     }
 
+    #[test]
+    fn can_find_the_service_a_container_belongs_to() {
+        let mut registry = ServiceRegistry::new();
+
+        define_service(&mut registry, "backend", "backend.com", None);
+        define_service(&mut registry, "frontend", "frontend.com", None);
+
+        add_container(&mut registry, "backend");
+        let container = add_container(&mut registry, "frontend");
+
+        assert_eq!(
+            registry.find_service_for_container(&container),
+            Some("frontend")
+        );
+
+        registry.remove_container_by_id("frontend", &container);
+
+        assert_eq!(registry.find_service_for_container(&container), None);
+    }
+
     #[test]
     fn can_find_downstream_by_multiple_hosts_if_configured() {
         let mut registry = ServiceRegistry::new();
@@ -322,15 +686,133 @@ mod tests {
         registry.define(name, service);
         let container_id = add_container(&mut registry, name);
 
-        let internal_downstreams = registry.find_downstreams(internal_host, path);
-        let external_downstreams = registry.find_downstreams(external_host, path);
+        let internal_downstreams = registry.find_downstreams(internal_host, path, &Method::GET);
+        let external_downstreams = registry.find_downstreams(external_host, path, &Method::GET);
 
-        assert_eq!(internal_downstreams, external_downstreams);
+        assert_eq!(
+            internal_downstreams.map(|matched| matched.downstreams),
+            external_downstreams.map(|matched| matched.downstreams)
+        );
 
-        assert!(internal_downstreams.is_some_and(|(containers, _)| {
-            containers
+        assert!(internal_downstreams.is_some_and(|matched| {
+            matched
+                .downstreams
                 .iter()
                 .any(|container| container.id == container_id)
         }));
     }
+
+    #[test]
+    fn container_weights_default_to_one_and_are_removed_with_the_container() {
+        let mut registry = ServiceRegistry::new();
+        let name = "backend";
+
+        let container_id = add_container(&mut registry, name);
+
+        assert_eq!(registry.get_weight(&container_id), 1);
+
+        registry.set_weight(&container_id, 5);
+
+        assert_eq!(registry.get_weight(&container_id), 5);
+
+        registry.remove_container_by_id(name, &container_id);
+
+        assert_eq!(registry.get_weight(&container_id), 1);
+    }
+
+    #[test]
+    fn new_containers_are_ramped_up_during_slow_start() {
+        let mut registry = ServiceRegistry::new();
+        let name = "backend";
+
+        define_service(&mut registry, name, "opentracker.app", None);
+
+        let old = add_container(&mut registry, name);
+        let new = add_container(&mut registry, name);
+
+        // pretend the first container was added long ago
+        registry
+            .added
+            .insert(old.clone(), Instant::now() - Duration::from_secs(60));
+
+        let window = Some(Duration::from_secs(30));
+
+        assert_eq!(registry.get_ramped_weight(&old, window), 100);
+        assert!(registry.get_ramped_weight(&new, window) < 10);
+        assert_eq!(registry.get_ramped_weight(&new, None), 1);
+    }
+
+    #[test]
+    fn port_routes_are_found_by_their_listen_port() {
+        let mut registry = ServiceRegistry::new();
+        let name = "database";
+
+        let route = PortRoute {
+            listen: 5432,
+            port: 5432,
+        };
+
+        let service = Service {
+            tcp_routes: HashSet::from([route]),
+            ..Default::default()
+        };
+
+        registry.define(name, service);
+        let container_id = add_container(&mut registry, name);
+
+        assert_eq!(registry.listen_ports(Transport::Tcp), [5432]);
+        assert!(registry.listen_ports(Transport::Udp).is_empty());
+        assert!(registry
+            .find_port_downstreams(Transport::Tcp, 6379)
+            .is_none());
+        assert!(registry
+            .find_port_downstreams(Transport::Udp, 5432)
+            .is_none());
+
+        let (found, downstreams) = registry
+            .find_port_downstreams(Transport::Tcp, 5432)
+            .expect("expected the tcp route to be found");
+
+        assert_eq!(found, &route);
+        assert!(downstreams
+            .iter()
+            .any(|container| container.id == container_id));
+    }
+
+    #[test]
+    fn variants_use_their_own_route() {
+        let mut registry = ServiceRegistry::new();
+
+        let service = |host: &str, port: u16| Service {
+            routes: HashSet::from([Route {
+                host: String::from(host),
+                port,
+                ..Default::default()
+            }]),
+            ..Default::default()
+        };
+
+        registry.define("stable", service("opentracker.app", 3000));
+        registry.define("canary", service("canary.opentracker.app", 4000));
+        registry.define("stopped", service("canary.opentracker.app", 4000));
+        registry.define("unrouted", Service::default());
+        add_container(&mut registry, "stable");
+        add_container(&mut registry, "unrouted");
+        let canary_id = add_container(&mut registry, "canary");
+
+        let find_variant = |variant| registry.find_variant("/", &Method::GET, variant);
+
+        let variant = find_variant("canary").expect("expected the canary to be found");
+
+        assert_eq!(variant.name, "canary");
+        assert_eq!(variant.route.port, 4000);
+        assert!(variant
+            .downstreams
+            .iter()
+            .any(|container| container.id == canary_id));
+
+        assert!(find_variant("stopped").is_none());
+        assert!(find_variant("unrouted").is_none());
+        assert!(find_variant("missing").is_none());
+    }
 }